@@ -0,0 +1,112 @@
+//! Throughput of `Node::handle_from_stdin`, in messages per second, for the hot paths: echo,
+//! broadcast of new and of already-seen values, and read of a large set.
+//!
+//! Run with `cargo bench`, or `cargo bench -- read` for the cases whose name contains `read`.
+//! Each case runs on its own node, set up before timing starts; criterion reports the time per
+//! message and the throughput.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tranquility::actor::NodeHandle;
+use tranquility::Node;
+
+const INIT: &str = r#"{"src": "c0", "dest": "n1", "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2", "n3"]}}"#;
+
+struct Case {
+    name: &'static str,
+    /// Sent before timing starts, e.g. to fill the store.
+    setup: Vec<String>,
+    /// The `i`th message timed. Numbering carries on across samples, so every message is new.
+    message: fn(u64) -> String,
+}
+
+fn echo(i: u64) -> String {
+    format!(
+        r#"{{"src": "c1", "dest": "n1", "body": {{"type": "echo", "echo": "Please echo {i}", "msg_id": {i}}}}}"#
+    )
+}
+
+fn broadcast(i: u64, value: u64) -> String {
+    format!(
+        r#"{{"src": "c1", "dest": "n1", "body": {{"type": "broadcast", "message": {value}, "msg_id": {i}}}}}"#
+    )
+}
+
+fn read(i: u64) -> String {
+    format!(r#"{{"src": "c2", "dest": "n1", "body": {{"type": "read", "msg_id": {i}}}}}"#)
+}
+
+fn cases() -> [Case; 4] {
+    [
+        Case {
+            name: "echo",
+            setup: vec![],
+            message: echo,
+        },
+        Case {
+            name: "broadcast_new",
+            setup: vec![],
+            message: |i| broadcast(i, i),
+        },
+        // The same value again and again, as a node sees it once gossip has spread it.
+        Case {
+            name: "broadcast_seen",
+            setup: vec![broadcast(0, 7)],
+            message: |i| broadcast(i + 1, 7),
+        },
+        Case {
+            name: "read_large_set",
+            setup: (0..10_000).map(|i| broadcast(i, i)).collect(),
+            message: read,
+        },
+    ]
+}
+
+async fn setup(case: &Case) -> NodeHandle {
+    let node = NodeHandle::spawn(Node::default());
+
+    for line in std::iter::once(INIT).chain(case.setup.iter().map(String::as_str)) {
+        Node::handle_from_stdin(node.clone(), line).await.unwrap();
+    }
+
+    node
+}
+
+/// How long the node took to handle `case`'s messages numbered `messages`.
+async fn run(node: NodeHandle, case: &Case, messages: Range<u64>) -> Duration {
+    let lines: Vec<String> = messages.map(case.message).collect();
+    let start = Instant::now();
+
+    for line in &lines {
+        Node::handle_from_stdin(node.clone(), line).await.unwrap();
+    }
+
+    start.elapsed()
+}
+
+fn handlers(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("handle_from_stdin");
+    group.throughput(Throughput::Elements(1));
+
+    for case in cases() {
+        let node = runtime.block_on(setup(&case));
+        let mut sent = 0;
+
+        group.bench_function(case.name, |b| {
+            b.to_async(&runtime).iter_custom(|iters| {
+                let messages = sent..sent + iters;
+                sent += iters;
+
+                run(node.clone(), &case, messages)
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, handlers);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+//! Arbitrary bytes, as a line or several from stdin, to a fresh node: anything it can't handle
+//! must come back as an error, not a panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+use tokio::runtime::Runtime;
+use tranquility::actor::NodeHandle;
+use tranquility::Node;
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    // Stdin is read as UTF-8 lines, so the node never sees anything else.
+    let input = String::from_utf8_lossy(data);
+
+    runtime().block_on(async {
+        let node = NodeHandle::spawn(Node::default());
+
+        let _ = Node::handle_from_stdin(node.clone(), &input).await;
+
+        // Wait for anything the node's task still had queued. libfuzzer's panic hook aborts on
+        // any panic, including one the node's task would catch.
+        let _ = node.call(|_| ()).await;
+    });
+});
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use tokio::sync::{mpsc, oneshot};
+use tracing::error;
+
+use crate::error::NodeError;
+use crate::node::Node;
+use crate::tasks::Tasks;
+
+pub(crate) type Command = Box<dyn FnOnce(&mut Node) + Send>;
+
+/// A handle to a node owned by a single task. Every access to the node's state is a command the
+/// task runs in turn, so there is no lock on the node to contend for or hold across an `await`.
+///
+/// Handles are cheap to clone; the task stops once every handle is dropped.
+#[derive(Clone)]
+pub struct NodeHandle {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl fmt::Debug for NodeHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NodeHandle")
+    }
+}
+
+/// A handle, and the commands sent through it for the node to run.
+pub(crate) fn channel() -> (NodeHandle, mpsc::UnboundedReceiver<Command>) {
+    let (commands, rx) = mpsc::unbounded_channel::<Command>();
+
+    (NodeHandle { commands }, rx)
+}
+
+impl NodeHandle {
+    /// Move the node into its own task, which also runs the node's `Tasks`.
+    pub fn spawn(mut node: Node) -> Self {
+        let (handle, mut rx) = channel();
+        node.tasks.attach(handle.commands.downgrade());
+
+        tokio::spawn(async move {
+            while let Some(command) = rx.recv().await {
+                // A panicking command fails only its own caller, with `NodeError::Panicked`; the node keeps
+                // serving everyone else.
+                if panic::catch_unwind(AssertUnwindSafe(|| command(&mut node))).is_err() {
+                    error!("A command panicked; the node carries on.");
+                }
+
+                if panic::catch_unwind(AssertUnwindSafe(|| Tasks::run(&mut node))).is_err() {
+                    error!("A task panicked; the node carries on.");
+                }
+            }
+        });
+
+        handle
+    }
+
+    /// Run `command` on the node and wait for its result. Commands run one at a time, in the
+    /// order they were sent. A command that panics returns `NodeError::Panicked`, and one sent
+    /// after the node's task stopped returns `NodeError::ChannelClosed`.
+    pub async fn call<R: Send + 'static>(
+        &self,
+        command: impl FnOnce(&mut Node) -> R + Send + 'static,
+    ) -> Result<R, NodeError> {
+        let (tx, rx) = oneshot::channel();
+
+        self.commands
+            .send(Box::new(move |node| {
+                // The caller may have stopped waiting.
+                let _ = tx.send(command(node));
+            }))
+            .map_err(|_| NodeError::ChannelClosed)?;
+
+        // While the task runs, the reply is only dropped unsent when the command panicked.
+        rx.await.map_err(|_| match self.commands.is_closed() {
+            true => NodeError::ChannelClosed,
+            false => NodeError::Panicked,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn runs_commands_in_order() {
+        let node = NodeHandle::spawn(Node::default());
+
+        let first = node.call(|node| node.next_message_id());
+        let second = node.call(|node| node.next_message_id());
+
+        assert_eq!((first.await, second.await), (Ok(1), Ok(2)));
+    }
+
+    #[tokio::test]
+    async fn survives_a_panicking_command() {
+        let node = NodeHandle::spawn(Node::default());
+
+        assert_eq!(
+            node.call(|_| panic!("boom")).await,
+            Err(NodeError::Panicked)
+        );
+        assert_eq!(node.call(|node| node.next_message_id()).await, Ok(1));
+    }
+}
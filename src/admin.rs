@@ -0,0 +1,143 @@
+//! Fault injection for trying out recovery paths locally, without Maelstrom's kill nemesis. A
+//! `crash` message either exits the process on the spot, or drops everything the node holds in
+//! memory as if it had crashed and been restarted. Only accepted with `--enable-admin`.
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+#[cfg(feature = "runtime")]
+use crate::correlation::Correlations;
+#[cfg(feature = "runtime")]
+use crate::failure::Liveness;
+#[cfg(feature = "runtime")]
+use crate::node::Node;
+#[cfg(feature = "runtime")]
+use crate::persist::Persistence;
+#[cfg(feature = "runtime")]
+use crate::retry::Retries;
+
+/// What a `crash` message does.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum CrashMode {
+    /// Exit the process without draining or replying.
+    #[default]
+    Exit,
+    /// Drop the node's state and recover what a restart would.
+    Reset,
+}
+
+/// Exit the process immediately, as a crash would.
+pub fn exit() -> ! {
+    info!("Crashing on request.");
+    std::process::exit(1)
+}
+
+/// Forget everything the node holds in memory, keeping its identity, configuration, and
+/// metrics, then recover what's on disk the way a restarted node does on `init`. Message ids and
+/// clocks keep counting, so a late reply to a request from before the crash isn't mistaken for
+/// the reply to a new one.
+#[cfg(feature = "runtime")]
+pub fn reset(node: &mut Node) {
+    info!("Dropping the node's state on request.");
+
+    #[cfg(feature = "broadcast")]
+    {
+        node.broadcast.messages.forget_in_memory();
+        node.broadcast.recently_seen.clear();
+        node.broadcast.topology.clear();
+        node.broadcast.overlay = Default::default();
+        node.broadcast.gossip_batch.take();
+        node.broadcast.causal = node.broadcast.causal.take().map(|_| Default::default());
+        node.broadcast.held_replies.clear();
+    }
+
+    node.correlations = Correlations::new(node.config.callback_ttl, node.config.callback_cap);
+    node.rpc_deadlines.clear();
+    node.unacknowledged = Default::default();
+    node.retries = Retries::new(node.retries.policy.clone());
+    node.peer_health = Default::default();
+    node.liveness = Liveness::new(
+        node.config.heartbeat_interval,
+        node.config.suspicion_threshold,
+        node.config.failure_detector,
+    );
+
+    #[cfg(feature = "counter")]
+    {
+        node.counter = Default::default();
+    }
+    #[cfg(feature = "kafka")]
+    {
+        node.kafka.logs = Default::default();
+    }
+    #[cfg(feature = "kv")]
+    {
+        node.kv.store = Default::default();
+        node.kv.lww = Default::default();
+        node.kv.sessions = Default::default();
+    }
+    #[cfg(feature = "raft")]
+    {
+        node.kv.raft = Default::default();
+    }
+    #[cfg(feature = "txn")]
+    {
+        node.txns = Default::default();
+    }
+    node.replies.clear();
+
+    if let Some(node_id) = node.id.clone() {
+        Persistence::recover(node, &node_id);
+    }
+}
+
+#[cfg(all(test, feature = "runtime", feature = "broadcast"))]
+mod test {
+    use super::*;
+    use crate::config::Config;
+    use crate::core::message::{ErrorCode, MessageBody};
+    use crate::core::state::parse;
+    use crate::node::Registry;
+    use crate::workload::Workload;
+
+    fn node(admin: bool) -> Node {
+        Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string()],
+            registry: Registry::for_workloads(&[Workload::Broadcast]),
+            config: Config {
+                admin,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    const BROADCAST: &str =
+        r#"{"src": "c1", "dest": "n1", "body": {"type": "broadcast", "message": 7, "msg_id": 1}}"#;
+    const RESET: &str =
+        r#"{"src": "c1", "dest": "n1", "body": {"type": "crash", "mode": "reset", "msg_id": 2}}"#;
+
+    #[test]
+    fn resets_the_nodes_state_only_when_enabled() {
+        let mut disabled = node(false);
+        disabled.dispatch(parse(BROADCAST).unwrap());
+
+        let refused = disabled.dispatch(parse(RESET).unwrap());
+        assert!(matches!(
+            &refused[0].body,
+            MessageBody::Error(body) if body.code == ErrorCode::NotSupported
+        ));
+        assert_eq!(disabled.broadcast.messages.len(), 1);
+
+        let mut enabled = node(true);
+        enabled.dispatch(parse(BROADCAST).unwrap());
+
+        let replies = enabled.dispatch(parse(RESET).unwrap());
+        assert_eq!(replies[0].body.kind(), "crash_ok");
+        assert!(enabled.broadcast.messages.is_empty());
+        assert_eq!(enabled.id.as_deref(), Some("n1"));
+    }
+}
@@ -0,0 +1,337 @@
+//! The binary's entry point, shared by `tranquility` and the per-challenge binaries in
+//! `src/bin`.
+
+use clap::Parser;
+use futures_util::StreamExt;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::level_filters::LevelFilter;
+use tracing::{error, warn};
+use tracing_subscriber::EnvFilter;
+
+use crate::actor::NodeHandle;
+#[cfg(feature = "broadcast")]
+use crate::bootstrap::Bootstrap;
+#[cfg(feature = "broadcast")]
+use crate::broadcast::BroadcastState;
+#[cfg(feature = "broadcast")]
+use crate::causal::CausalBroadcast;
+use crate::cli::{Args, Command};
+use crate::config::Config;
+#[cfg(feature = "broadcast")]
+use crate::core::state::BroadcastStore;
+use crate::correlation::Correlations;
+#[cfg(feature = "broadcast")]
+use crate::dedupe::DedupeCache;
+use crate::failure::Liveness;
+#[cfg(feature = "broadcast")]
+use crate::gossip::GossipBatch;
+use crate::idempotency::ReplyCache;
+#[cfg(feature = "kafka")]
+use crate::log::{KafkaState, Sink};
+use crate::memory::MemoryBounds;
+use crate::metrics::{self, MetricsReport};
+use crate::node::{Node, Registry};
+use crate::persist::Persistence;
+use crate::readiness::Readiness;
+use crate::record::Recorder;
+use crate::replay::{Direction, Trace};
+use crate::retry::Retries;
+use crate::rpc::RpcPermits;
+#[cfg(feature = "schema")]
+use crate::schema;
+use crate::selftest;
+use crate::shutdown::ShutdownReport;
+#[cfg(feature = "kafka")]
+use crate::sink::NdjsonSink;
+use crate::snowflake::Snowflake;
+#[cfg(feature = "broadcast")]
+use crate::spill::SpillSegment;
+use crate::tcp;
+use crate::tls::Tls;
+use crate::transport::{self, LineTransport};
+use crate::workload::{ReplyModes, Workload};
+
+/// Run a node from the command line, stdin, and stdout until stdin closes. `workloads` pins the
+/// workloads served, for a binary built for one challenge; `None` serves the configured ones.
+pub async fn main(
+    workloads: Option<&[Workload]>,
+) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let args = Args::parse();
+
+    match args.command {
+        // `tranquility schema` prints the wire format's JSON Schema instead of running a node.
+        #[cfg(feature = "schema")]
+        Some(Command::Schema) => {
+            println!("{}", serde_json::to_string_pretty(&schema::export())?);
+
+            return Ok(());
+        }
+        // `tranquility self-test` runs a scripted session against an in-process node.
+        Some(Command::SelfTest) => {
+            if !selftest::run().await {
+                std::process::exit(1);
+            }
+
+            return Ok(());
+        }
+        None => {}
+    }
+
+    // The config file, then environment variables, then command-line options.
+    let mut config = Config::load(args.config.as_deref())?;
+    args.apply(&mut config);
+    init_logging(config.log_level);
+
+    if let Some(workloads) = workloads {
+        config.workloads = workloads.to_vec();
+    }
+
+    let workloads = config.workloads.clone();
+
+    // With `spill_after` set, older broadcast values are spilled to disk.
+    #[cfg(feature = "broadcast")]
+    let messages = match config.spill_after {
+        Some(limit) => {
+            let dir = config.spill_dir.clone().unwrap_or_else(std::env::temp_dir);
+
+            BroadcastStore::with_overflow(limit, Box::new(SpillSegment::create(&dir)?))
+        }
+        None => BroadcastStore::default(),
+    };
+
+    // Outside of Maelstrom there's no `init` message; the cluster is discovered from a seed
+    // list or DNS instead.
+    let discovery = match (&config.discovery, &config.node_id) {
+        (Some(discovery), Some(node_id)) => Some((discovery.clone(), node_id.clone())),
+        (Some(_), None) => {
+            return Err("TRANQUILITY_NODE_ID is required when TRANQUILITY_SEEDS is set.".into())
+        }
+        (None, _) => None,
+    };
+
+    // With `kafka_sink` set, committed kafka records are mirrored to a file.
+    #[cfg(feature = "kafka")]
+    let log_sink = match &config.kafka_sink {
+        Some(path) => Some(NdjsonSink::open(path)?),
+        None => None,
+    };
+
+    let shutdown_report = config.shutdown_report.clone();
+    let dialing = config.dialing.clone();
+    // Read the certificates up front, so a bad one stops the node before it starts.
+    let tls = match &args.listen {
+        Some(_) => Tls::new(&config.tls)?,
+        None => None,
+    };
+
+    // Initialize the channel used to send messages from stdin to the node instance.
+    let (tx, rx) = mpsc::channel(config.stdin_capacity.max(1));
+
+    // Initialize the response channel;
+    let (response_tx, response_rx) = mpsc::channel(config.response_capacity.max(1));
+
+    // With `--record <path>`, every message in and out is appended to a file on its way past.
+    let (rx, response_rx) = match &args.record {
+        Some(path) => {
+            let recorder = Recorder::open(path)
+                .map_err(|err| format!("Unable to record to {}: {}", path.display(), err))?;
+
+            (
+                recorder
+                    .clone()
+                    .relay(Direction::In, rx, config.stdin_capacity),
+                recorder.relay(Direction::Out, response_rx, config.response_capacity),
+            )
+        }
+        None => (rx, response_rx),
+    };
+
+    let node = Node {
+        id: None,
+        #[cfg(feature = "broadcast")]
+        broadcast: BroadcastState {
+            messages,
+            bootstrap: Bootstrap::new(config.peer_bootstrap),
+            recently_seen: DedupeCache::new(config.dedupe),
+            gossip_batch: config
+                .gossip_batch
+                .map(GossipBatch::new)
+                .unwrap_or_default(),
+            overlay_strategy: config.topology,
+            causal: config.causal.then(CausalBroadcast::default),
+            anti_entropy: config.anti_entropy.clone(),
+            ..Default::default()
+        },
+        id_format: config.id_format,
+        snowflake: Snowflake::new(config.id_format.layout()),
+        memory_bounds: MemoryBounds {
+            max_bytes: config.max_memory_bytes,
+        },
+        registry: Registry::for_workloads(&workloads),
+        readiness: Readiness::new(&workloads, &config.readiness),
+        reply_modes: ReplyModes::strict(&config.strict_replies),
+        replies: ReplyCache::new(config.reply_cache),
+        retries: Retries::new(config.retry.clone()),
+        drain: config.drain.clone(),
+        startup_jitter: config.startup_jitter.clone(),
+        rpc_permits: Arc::new(RpcPermits::new(config.rpc_limits)),
+        persistence: Persistence::new(config.state_dir.clone(), config.wal_commit_window),
+        correlations: Correlations::new(config.callback_ttl, config.callback_cap),
+        liveness: Liveness::new(
+            config.heartbeat_interval,
+            config.suspicion_threshold,
+            config.failure_detector,
+        ),
+        #[cfg(feature = "kafka")]
+        kafka: KafkaState {
+            sink: log_sink.map(|sink| Box::new(sink) as Box<dyn Sink>),
+            ..Default::default()
+        },
+        config,
+        ..Default::default()
+    };
+
+    let tracker = TaskTracker::new();
+    let tracker_clone = tracker.clone();
+
+    // With `--replay <path>`, messages come from a recorded trace instead of stdin.
+    let replay = match &args.replay {
+        Some(path) => Some(
+            Trace::read(path)
+                .map_err(|err| format!("Unable to read {}: {}", path.display(), err))?,
+        ),
+        None => None,
+    };
+
+    // With `--listen <addr>`, the node is served over TCP instead of stdin/stdout.
+    let listener = match &args.listen {
+        Some(addr) => Some(TcpListener::bind(addr).await?),
+        None => None,
+    };
+
+    // From here on a single task owns the node; everything else reaches it through handles.
+    let node = NodeHandle::spawn(node);
+
+    if let Some((discovery, node_id)) = discovery {
+        discovery.bootstrap(&node, node_id).await?;
+    }
+
+    // With `--metrics-interval <secs>`, periodically log a metrics delta to stderr.
+    let metrics_handler = args
+        .metrics_interval
+        .map(|interval| tokio::spawn(metrics::report(node.clone(), interval)));
+
+    let shutdown_node = node.clone();
+
+    // `Node::run` must be executed in a thread; calling `.await` immediately blocks the execution
+    // of the main thread i.e. the code after it never executes -- there will be no listener on
+    // stdin.
+    //
+    // Alternatively, use `tracker.spawn` instead of `tokio::spawn` to ensure the threads finish
+    // execution after the channel is closed.
+    //
+    // Note, without `await`ing the join handler, a thread via `tokio::spawn` is invoked and closed
+    // immediately.
+    let node_handler = tokio::spawn(async move {
+        Node::run(node, rx, response_tx, &tracker_clone).await;
+    });
+
+    let (response_handler, stdin_handler) = match listener {
+        // Over TCP, the node runs until interrupted.
+        Some(listener) => {
+            let shutdown = CancellationToken::new();
+            let interrupted = shutdown.clone();
+
+            let interrupt_handler = tokio::spawn(async move {
+                let _ = tokio::signal::ctrl_c().await;
+
+                interrupted.cancel();
+            });
+
+            let server_handler = tokio::spawn(async move {
+                if let Err(err) =
+                    tcp::serve(listener, tx, response_rx, dialing, tls, shutdown).await
+                {
+                    error!("Unable to serve: {:?}", err);
+                }
+            });
+
+            (server_handler, interrupt_handler)
+        }
+        None => {
+            let (mut lines, stdout) = LineTransport::stdio().split();
+
+            // The only task that writes to stdout: every outbound message, from handlers,
+            // retries, and background tasks alike, goes through the response channel, so lines
+            // never interleave.
+            let response_handler = tokio::spawn(transport::forward(response_rx, stdout));
+
+            let stdin_handler = match replay {
+                Some(trace) => tokio::spawn(trace.feed(args.replay_speed.unwrap_or(1.0), tx)),
+                None => tokio::spawn(async move {
+                    while let Some(line) = lines.next().await {
+                        match line {
+                            Ok(data) => {
+                                if tx.send(data).await.is_err() {
+                                    warn!("The node stopped; no longer reading stdin.");
+                                    break;
+                                }
+                            }
+                            Err(err) => {
+                                error!("Unable to read from stdin: {:?}", err);
+                                break;
+                            }
+                        }
+                    }
+
+                    // NOTE: Call `drop` explicitly as this breaks the Node's `while` loop during
+                    // `run()`. Alternatively, the `tx` can get dropped automatically if the entire
+                    // loop is call in a separate tracker thread.
+                    drop(tx);
+                }),
+            };
+
+            (response_handler, stdin_handler)
+        }
+    };
+
+    let _ = node_handler.await;
+
+    if let Some(metrics_handler) = metrics_handler {
+        metrics_handler.abort();
+    }
+    let _ = response_handler.await;
+    let _ = stdin_handler.await;
+
+    tracker.close();
+    tracker.wait().await;
+
+    let (report, metrics) = shutdown_node
+        .call(|node| (ShutdownReport::capture(node), MetricsReport::take(node)))
+        .await?;
+
+    report.emit(shutdown_report.as_deref());
+    // Like the periodic deltas, not subject to the log level.
+    eprintln!("metrics {}", serde_json::to_string(&metrics)?);
+
+    Ok(())
+}
+
+/// Log to stderr at `level`, or as `RUST_LOG` directs for the targets it names. Lines carry the
+/// spans they were logged in, e.g. the message being handled or the peer being written to.
+fn init_logging(level: LevelFilter) {
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+
+    // Only the first node in the process installs its subscriber, e.g. in tests.
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(false)
+        .try_init();
+}
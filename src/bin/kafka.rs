@@ -0,0 +1,9 @@
+//! A node for the kafka challenges only.
+
+use tranquility::app;
+use tranquility::workload::Workload;
+
+#[tokio::main]
+async fn main() -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    app::main(Some(&[Workload::Kafka])).await
+}
@@ -0,0 +1,85 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+/// Peer-assisted recovery for a node that starts without any state: once it knows its neighbors,
+/// it reads their values and holds client reads until they reply, so a restarted node doesn't
+/// answer reads with an empty set.
+#[derive(Debug, Default)]
+pub struct Bootstrap {
+    pub enabled: bool,
+    /// Message ids of the reads sent to neighbors that haven't been answered.
+    pending: HashSet<u32>,
+    done: Arc<Notify>,
+}
+
+impl Bootstrap {
+    /// How long client reads wait for the neighbors before the bootstrap is abandoned.
+    pub const TIMEOUT: Duration = Duration::from_millis(1000);
+
+    pub fn new(enabled: bool) -> Self {
+        Bootstrap {
+            enabled,
+            ..Default::default()
+        }
+    }
+
+    pub fn start(&mut self, message_ids: impl IntoIterator<Item = u32>) {
+        self.pending.extend(message_ids);
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Record a neighbor's reply, returning `true` if it answers one of the bootstrap reads.
+    pub fn complete(&mut self, in_reply_to: u32) -> bool {
+        let completed = self.pending.remove(&in_reply_to);
+
+        if completed && self.pending.is_empty() {
+            info!("Bootstrap complete.");
+            self.done.notify_waiters();
+        }
+
+        completed
+    }
+
+    /// Stop waiting on neighbors that haven't replied.
+    pub fn abandon(&mut self) {
+        if self.is_pending() {
+            warn!("Abandoning bootstrap, no reply to: {:?}", self.pending);
+            self.pending.clear();
+            self.done.notify_waiters();
+        }
+    }
+
+    pub fn done(&self) -> Arc<Notify> {
+        self.done.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn notifies_once_every_neighbor_replies() {
+        let mut bootstrap = Bootstrap::default();
+        bootstrap.start([1, 2]);
+
+        let done = bootstrap.done();
+        let notified = done.notified();
+
+        assert!(bootstrap.complete(1));
+        assert!(!bootstrap.complete(1));
+        assert!(bootstrap.is_pending());
+        assert!(bootstrap.complete(2));
+        assert!(!bootstrap.is_pending());
+
+        tokio::time::timeout(Duration::from_millis(10), notified)
+            .await
+            .unwrap();
+    }
+}
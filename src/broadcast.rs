@@ -0,0 +1,101 @@
+//! The broadcast workload: clients broadcast values and read them back, and nodes gossip the
+//! values over the topology's overlay until every node has them.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::bootstrap::Bootstrap;
+use crate::causal::CausalBroadcast;
+use crate::core::message::{BroadcastValue, InternalBody, Message, MessageBody};
+use crate::core::state::BroadcastStore;
+use crate::dedupe::DedupeCache;
+use crate::gossip::{AntiEntropy, GossipBatch};
+use crate::handlers::{
+    self, BroadcastHandler, GossipDigestHandler, GossipHandler, HeldReply, ReadHandler,
+    ReadOkHandler, SyncHandler, TopologyHandler, TopologyReportHandler,
+};
+use crate::lifecycle::Workload;
+use crate::node::{Handler, Node};
+use crate::topology::{OverlayStrategy, Topology};
+
+/// The broadcast workload's state on the node.
+#[derive(Debug, Default)]
+pub struct BroadcastState {
+    pub messages: BroadcastStore,
+    /// Values seen recently, checked before the store, which may have to go to disk.
+    pub recently_seen: DedupeCache<BroadcastValue>,
+    /// The neighbors from the last `topology` message.
+    pub topology: Vec<String>,
+    pub gossip_batch: GossipBatch,
+    /// Set with `--causal`, which delivers values in causal order.
+    pub causal: Option<CausalBroadcast>,
+    pub anti_entropy: AntiEntropy,
+    /// The whole overlay from the last `topology` message, for diagnostics.
+    pub overlay: Topology,
+    pub overlay_strategy: OverlayStrategy,
+    pub bootstrap: Bootstrap,
+    /// Replies held back in strict mode, keyed by the reply's `msg_id`.
+    pub held_replies: HashMap<u32, HeldReply>,
+}
+
+pub struct BroadcastWorkload;
+
+impl BroadcastWorkload {
+    /// Send each neighbor the values batched for it.
+    fn flush(node: &mut Node) {
+        for (neighbor, values) in node.broadcast.gossip_batch.take() {
+            handlers::gossip(node, neighbor, values, None);
+        }
+    }
+}
+
+impl Workload for BroadcastWorkload {
+    fn name(&self) -> &'static str {
+        "broadcast"
+    }
+
+    fn message_types(&self) -> &'static [&'static str] {
+        &[
+            "broadcast",
+            "read",
+            "read_ok",
+            "internal_sync",
+            "internal_gossip_digest",
+            "internal_gossip",
+            "topology",
+            "topology_report",
+        ]
+    }
+
+    fn on_message(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        match &message.body {
+            MessageBody::Broadcast(_) => BroadcastHandler.handle(node, message),
+            MessageBody::Read(_) => ReadHandler.handle(node, message),
+            MessageBody::ReadOk(_) => ReadOkHandler.handle(node, message),
+            MessageBody::Internal(InternalBody::Sync(_)) => SyncHandler.handle(node, message),
+            MessageBody::Internal(InternalBody::GossipDigest(_)) => {
+                GossipDigestHandler.handle(node, message)
+            }
+            MessageBody::Internal(InternalBody::Gossip(_)) => GossipHandler.handle(node, message),
+            MessageBody::Topology(_) => TopologyHandler.handle(node, message),
+            MessageBody::TopologyReport(_) => TopologyReportHandler.handle(node, message),
+            _ => vec![],
+        }
+    }
+
+    /// The gossip batch window, when gossip is batched.
+    fn interval(&self, node: &Node) -> Option<Duration> {
+        node.broadcast.gossip_batch.window
+    }
+
+    fn on_tick(&self, node: &mut Node) -> Vec<Message<'static>> {
+        BroadcastWorkload::flush(node);
+        vec![]
+    }
+
+    /// Send the last batch.
+    fn on_shutdown(&self, node: &mut Node) -> Vec<Message<'static>> {
+        BroadcastWorkload::flush(node);
+        vec![]
+    }
+}
@@ -0,0 +1,95 @@
+//! Causal broadcast: a value is only delivered, i.e. stored and returned by `read`, once every
+//! value delivered before it on the node it was broadcast to has been delivered here too.
+
+use crate::clock::VectorClock;
+use crate::core::message::{BroadcastValue, CausalValue};
+
+#[derive(Debug, Default)]
+pub struct CausalBroadcast {
+    /// How many values broadcast to each node have been delivered here.
+    pub delivered: VectorClock,
+    /// Values received ahead of their dependencies.
+    pending: Vec<CausalValue>,
+}
+
+impl CausalBroadcast {
+    /// Deliver a value a client broadcast to this node, tagging it for gossip.
+    pub fn broadcast(&mut self, node_id: &str, value: BroadcastValue) -> CausalValue {
+        self.delivered.increment(node_id);
+
+        CausalValue {
+            value,
+            origin: node_id.to_string(),
+            clock: self.delivered.clone(),
+        }
+    }
+
+    /// Whether the value was delivered or is waiting to be.
+    pub fn has_seen(&self, value: &CausalValue) -> bool {
+        value.clock.get(&value.origin) <= self.delivered.get(&value.origin)
+            || self.pending.contains(value)
+    }
+
+    /// Take a value gossiped by another node, returning every value that can now be delivered,
+    /// in the order they're delivered.
+    pub fn receive(&mut self, value: CausalValue) -> Vec<CausalValue> {
+        if self.has_seen(&value) {
+            return vec![];
+        }
+
+        self.pending.push(value);
+
+        let mut delivered = vec![];
+
+        while let Some(index) = self.pending.iter().position(|value| self.is_ready(value)) {
+            let value = self.pending.remove(index);
+
+            self.delivered
+                .set(&value.origin, value.clock.get(&value.origin));
+            delivered.push(value);
+        }
+
+        delivered
+    }
+
+    /// The number of values waiting on their dependencies.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The next value from its origin, with everything it depends on from other nodes delivered.
+    fn is_ready(&self, value: &CausalValue) -> bool {
+        value
+            .clock
+            .0
+            .iter()
+            .all(|(node_id, time)| match node_id == &value.origin {
+                true => *time == self.delivered.get(node_id) + 1,
+                false => *time <= self.delivered.get(node_id),
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn holds_values_until_their_dependencies_arrive() {
+        let mut n1 = CausalBroadcast::default();
+        let first = n1.broadcast("n1", BroadcastValue::from(1));
+
+        let mut n2 = CausalBroadcast::default();
+        n2.receive(first.clone());
+        let second = n2.broadcast("n2", BroadcastValue::from(2));
+
+        // n3 hears about the second value before the first, which n2 had delivered before it.
+        let mut n3 = CausalBroadcast::default();
+
+        assert!(n3.receive(second.clone()).is_empty());
+        assert_eq!(n3.pending(), 1);
+        assert_eq!(n3.receive(first.clone()), vec![first.clone(), second]);
+        assert!(n3.receive(first).is_empty());
+        assert_eq!(n3.pending(), 0);
+    }
+}
@@ -0,0 +1,169 @@
+//! The binary's command-line options.
+
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::level_filters::LevelFilter;
+
+use crate::config::Config;
+#[cfg(feature = "kv")]
+use crate::kv::KvMode;
+use crate::topology::OverlayStrategy;
+
+/// A Maelstrom node. Options override the matching environment variables and config file
+/// settings; unset options leave the node as configured from those.
+#[derive(Clone, Debug, Default, PartialEq, Parser)]
+#[command(name = "tranquility", version)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// The config file (TRANQUILITY_CONFIG, or tranquility.toml).
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<String>,
+    /// How often counters replicate to the other nodes, in milliseconds.
+    #[arg(long, value_name = "MS", value_parser = millis)]
+    pub gossip_interval: Option<Duration>,
+    /// The delay before the first resend, in milliseconds (TRANQUILITY_RETRY_INITIAL).
+    #[arg(long, value_name = "MS", value_parser = millis)]
+    pub retry_delay: Option<Duration>,
+    /// Collect gossip for this many milliseconds (TRANQUILITY_GOSSIP_BATCH).
+    #[arg(long, value_name = "MS", value_parser = millis)]
+    pub batch_window: Option<Duration>,
+    /// given, star, tree, tree:<fanout>, or kary:<k> (TRANQUILITY_TOPOLOGY).
+    #[arg(long, value_name = "NAME", value_parser = topology_strategy)]
+    pub topology_strategy: Option<OverlayStrategy>,
+    /// Log a metrics delta to stderr every this many seconds.
+    #[arg(long, value_name = "SECS", value_parser = seconds)]
+    pub metrics_interval: Option<Duration>,
+    /// off, error, warn, info, debug, or trace (TRANQUILITY_LOG_LEVEL).
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<LevelFilter>,
+    /// Serve over TCP on this address instead of stdin/stdout.
+    #[arg(long, value_name = "ADDR")]
+    pub listen: Option<String>,
+    /// Append every message in and out to this file.
+    #[arg(long, value_name = "PATH")]
+    pub record: Option<PathBuf>,
+    /// Feed a recorded trace through the node instead of stdin.
+    #[arg(long, value_name = "PATH")]
+    pub replay: Option<PathBuf>,
+    /// Replay this many times faster than recorded; 0 for no waits. 1 when unset.
+    #[arg(long, value_name = "FACTOR", value_parser = speed)]
+    pub replay_speed: Option<f64>,
+    /// The kv workload's: eventual, session, or linearizable (TRANQUILITY_KV_MODE).
+    #[cfg(feature = "kv")]
+    #[arg(long, value_name = "LEVEL", value_parser = consistency)]
+    pub consistency: Option<KvMode>,
+    /// Save the node's state here, and recover it on restart (TRANQUILITY_STATE_DIR).
+    #[arg(long, value_name = "PATH")]
+    pub state_dir: Option<PathBuf>,
+    /// Fsync the saved state's appends together once the oldest has waited this many
+    /// milliseconds (TRANQUILITY_WAL_COMMIT_WINDOW).
+    #[arg(long, value_name = "MS", value_parser = millis)]
+    pub wal_commit_window: Option<Duration>,
+    /// Deliver broadcast values in causal order.
+    #[arg(long)]
+    pub causal: bool,
+    /// Accept admin messages, e.g. crash, for fault injection.
+    #[arg(long)]
+    pub enable_admin: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Subcommand)]
+pub enum Command {
+    /// Run a scripted session against an in-process node.
+    SelfTest,
+    /// Print the wire format's JSON Schema.
+    #[cfg(feature = "schema")]
+    Schema,
+}
+
+fn millis(value: &str) -> Result<Duration, String> {
+    value
+        .parse()
+        .map(Duration::from_millis)
+        .map_err(|_| format!("{value:?} isn't a number of milliseconds."))
+}
+
+fn seconds(value: &str) -> Result<Duration, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|secs: &f64| secs.is_finite() && *secs > 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| format!("{value:?} isn't a number of seconds."))
+}
+
+fn speed(value: &str) -> Result<f64, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|speed: &f64| *speed >= 0.0)
+        .ok_or_else(|| format!("{value:?} isn't a factor."))
+}
+
+fn topology_strategy(value: &str) -> Result<OverlayStrategy, String> {
+    OverlayStrategy::parse(value).ok_or_else(|| format!("Unknown topology strategy {value:?}."))
+}
+
+#[cfg(feature = "kv")]
+fn consistency(value: &str) -> Result<KvMode, String> {
+    KvMode::from_consistency(value).ok_or_else(|| format!("Unknown consistency level {value:?}."))
+}
+
+impl Args {
+    /// Override the config with the options that were given.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(interval) = self.gossip_interval {
+            config.gossip_interval = interval;
+        }
+
+        if let Some(delay) = self.retry_delay {
+            config.retry.initial = delay;
+        }
+
+        if let Some(window) = self.batch_window {
+            config.gossip_batch = Some(window);
+        }
+
+        if let Some(strategy) = self.topology_strategy {
+            config.topology = strategy;
+        }
+
+        #[cfg(feature = "kv")]
+        if let Some(mode) = self.consistency {
+            config.kv_mode = mode;
+        }
+
+        if let Some(level) = self.log_level {
+            config.log_level = level;
+        }
+
+        if let Some(dir) = &self.state_dir {
+            config.state_dir = Some(dir.clone());
+        }
+
+        if let Some(window) = self.wal_commit_window {
+            config.wal_commit_window = window;
+        }
+
+        if self.causal {
+            config.causal = true;
+        }
+
+        if self.enable_admin {
+            config.admin = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn declares_consistent_options() {
+        Args::command().debug_assert();
+    }
+}
@@ -0,0 +1,199 @@
+//! Logical clocks, for ordering events across nodes without relying on wall-clock time.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A Lamport clock: it ticks on every send and jumps past every time it receives, so a message is
+/// always stamped later than anything that happened before it was sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LamportClock {
+    time: u64,
+}
+
+impl LamportClock {
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    /// Advance for a send, returning the time to stamp it with.
+    pub fn tick(&mut self) -> u64 {
+        self.time += 1;
+        self.time
+    }
+
+    /// Advance past a time received from another node.
+    pub fn merge(&mut self, received: u64) -> u64 {
+        self.time = self.time.max(received) + 1;
+        self.time
+    }
+}
+
+/// A vector clock: a count of events per node. Unlike a Lamport time, it tells causally related
+/// events from concurrent ones; `partial_cmp` is `None` for concurrent clocks.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct VectorClock(pub BTreeMap<String, u64>);
+
+impl VectorClock {
+    pub fn get(&self, node_id: &str) -> u64 {
+        self.0.get(node_id).copied().unwrap_or(0)
+    }
+
+    pub fn set(&mut self, node_id: &str, time: u64) {
+        self.0.insert(node_id.to_string(), time);
+    }
+
+    /// Count an event on `node_id`.
+    pub fn increment(&mut self, node_id: &str) -> u64 {
+        let time = self.0.entry(node_id.to_string()).or_insert(0);
+        *time += 1;
+        *time
+    }
+
+    /// Take the later time for every node.
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (node_id, time) in &other.0 {
+            let entry = self.0.entry(node_id.clone()).or_insert(0);
+            *entry = (*entry).max(*time);
+        }
+    }
+}
+
+/// Clocks are equal when every node's time is, whether it's missing or zero.
+impl PartialEq for VectorClock {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for VectorClock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let node_ids = self.0.keys().chain(other.0.keys());
+        let (mut less, mut greater) = (false, false);
+
+        for node_id in node_ids {
+            match self.get(node_id).cmp(&other.get(node_id)) {
+                Ordering::Less => less = true,
+                Ordering::Greater => greater = true,
+                Ordering::Equal => {}
+            }
+        }
+
+        match (less, greater) {
+            (false, false) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (true, true) => None,
+        }
+    }
+}
+
+/// A hybrid logical clock timestamp: wall-clock milliseconds, a counter for events within the
+/// same millisecond, and the node's id to break ties. Timestamps order totally, and stay close to
+/// wall-clock time while never going backwards past one a node has seen.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct HybridTimestamp {
+    pub wall: u64,
+    pub logical: u32,
+    pub node_id: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HybridClock {
+    wall: u64,
+    logical: u32,
+}
+
+impl HybridClock {
+    /// A timestamp for an event on `node_id`, later than any this clock has issued or observed.
+    pub fn now(&mut self, node_id: &str) -> HybridTimestamp {
+        self.advance(physical_millis(), 0, 0);
+
+        HybridTimestamp {
+            wall: self.wall,
+            logical: self.logical,
+            node_id: node_id.to_string(),
+        }
+    }
+
+    /// Move past a timestamp received from another node.
+    pub fn observe(&mut self, timestamp: &HybridTimestamp) {
+        self.advance(physical_millis(), timestamp.wall, timestamp.logical);
+    }
+
+    fn advance(&mut self, physical: u64, wall: u64, logical: u32) {
+        let latest = physical.max(self.wall).max(wall);
+
+        self.logical = match (latest == self.wall, latest == wall) {
+            (true, true) => self.logical.max(logical) + 1,
+            (true, false) => self.logical + 1,
+            (false, true) => logical + 1,
+            (false, false) => 0,
+        };
+        self.wall = latest;
+    }
+}
+
+fn physical_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn moves_past_received_times() {
+        let mut clock = LamportClock::default();
+
+        assert_eq!(clock.tick(), 1);
+        assert_eq!(clock.merge(10), 11);
+        assert_eq!(clock.merge(3), 12);
+        assert_eq!(clock.tick(), 13);
+    }
+
+    #[test]
+    fn orders_causally_related_clocks_only() {
+        let mut n1 = VectorClock::default();
+        n1.increment("n1");
+
+        let mut n2 = n1.clone();
+        n2.increment("n2");
+
+        let mut n3 = n1.clone();
+        n3.increment("n3");
+
+        assert!(n1 < n2);
+        assert_eq!(n2.partial_cmp(&n3), None);
+
+        n2.merge(&n3);
+
+        assert!(n3 < n2);
+    }
+
+    #[test]
+    fn stamps_after_observed_timestamps() {
+        let mut clock = HybridClock::default();
+        let first = clock.now("n1");
+
+        // A timestamp from a node whose clock runs an hour ahead.
+        let ahead = HybridTimestamp {
+            wall: first.wall + 3_600_000,
+            logical: 5,
+            node_id: "n2".to_string(),
+        };
+        clock.observe(&ahead);
+
+        let next = clock.now("n1");
+
+        assert!(first < ahead);
+        assert!(ahead < next);
+        assert_eq!(next.wall, ahead.wall);
+    }
+}
@@ -0,0 +1,1276 @@
+//! Node configuration from a `tranquility.toml` file, overridden by environment variables and then
+//! by command-line options.
+//!
+//! ```toml
+//! workloads = ["echo", "broadcast"]
+//! topology = "tree:3"
+//! causal = false
+//! admin = false
+//! kv_mode = "raft"
+//! raft_reads = "lease"
+//! raft_log_limit = 1000
+//! rpc_timeout = 1000 # milliseconds
+//! id_format = "uuid"
+//! state_dir = "/var/lib/tranquility"
+//! wal_commit_window = 5 # milliseconds
+//! log_level = "info"
+//! peer_bootstrap = false
+//! strict_replies = ["kafka"]
+//! max_memory_bytes = 67108864
+//! reply_cache = 1024 # client requests remembered
+//! kafka_sink = "records.ndjson"
+//!
+//! [retry]
+//! initial = 500 # milliseconds
+//! multiplier = 2.0
+//! max_delay = 8000
+//! max_attempts = 10
+//! jitter = 0.1
+//!
+//! [gossip]
+//! interval = 200
+//! batch = 100
+//! anti_entropy = 1000
+//! digest_above = 1000
+//!
+//! [channels]
+//! stdin = 32
+//! responses = 10
+//!
+//! [callbacks]
+//! ttl = 300000
+//! cap = 100000
+//!
+//! [heartbeat]
+//! interval = 500
+//! threshold = 8.0
+//! detector = "phi"
+//!
+//! [readiness] # milliseconds, by workload
+//! broadcast = 500
+//!
+//! [shutdown]
+//! drain = 1000
+//! report = "shutdown.json"
+//!
+//! [jitter]
+//! max = 50
+//! seed = 7
+//!
+//! [dedupe]
+//! policy = "ttl:60000" # or "lru", or "2q"
+//! capacity = 1024
+//!
+//! [lanes]
+//! count = 4
+//! capacity = 32
+//! affinity = "body:key" # or "src", "dest", or "shard:<count>"
+//!
+//! [rpc]
+//! max_outstanding = 1024
+//! max_outstanding_per_peer = 256
+//!
+//! [dial]
+//! buffer = 1024 # lines held for each peer
+//! backoff = 100
+//! max_delay = 5000
+//!
+//! [tls] # PEM files
+//! cert = "node.pem"
+//! key = "node.key"
+//! ca = "ca.pem"
+//! mutual = true
+//!
+//! [spill]
+//! after = 100000
+//! dir = "/tmp"
+//!
+//! [discovery]
+//! node_id = "n1"
+//! seeds = "n1,n2,n3" # or "dns:cluster.local:7000"
+//! ```
+//!
+//! Every setting also has an environment variable, e.g. `TRANQUILITY_RETRY_INITIAL` for
+//! `retry.initial`; see `Config::with_env`.
+
+use serde::de::{Deserializer, Error as _};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::level_filters::LevelFilter;
+use tracing::warn;
+
+use crate::core::message::IdFormat;
+use crate::dedupe::{DedupeConfig, EvictionPolicy};
+use crate::discovery::Discovery;
+use crate::failure::DetectorKind;
+use crate::gossip::AntiEntropy;
+use crate::jitter::StartupJitter;
+#[cfg(feature = "kv")]
+use crate::kv::KvMode;
+use crate::lanes::{Affinity, LaneConfig};
+#[cfg(feature = "raft")]
+use crate::raft::RaftReads;
+use crate::retry::RetryPolicy;
+use crate::rpc::RpcLimits;
+use crate::shutdown::Drain;
+use crate::tcp::Dialing;
+use crate::tls::TlsConfig;
+use crate::topology::OverlayStrategy;
+use crate::workload::Workload;
+
+/// Read when neither `--config` nor `TRANQUILITY_CONFIG` names a file; it's fine for it not to
+/// exist.
+pub const DEFAULT_PATH: &str = "tranquility.toml";
+
+/// The node's resolved tunables.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub retry: RetryPolicy,
+    /// How often counters replicate to the other nodes.
+    pub gossip_interval: Duration,
+    /// How long new broadcast values are collected before they're gossiped; see `GossipBatch`.
+    pub gossip_batch: Option<Duration>,
+    pub topology: OverlayStrategy,
+    pub workloads: Vec<Workload>,
+    /// Deliver broadcast values in causal order.
+    pub causal: bool,
+    /// Accept admin messages, e.g. `crash`.
+    pub admin: bool,
+    #[cfg(feature = "kv")]
+    pub kv_mode: KvMode,
+    #[cfg(feature = "raft")]
+    pub raft_reads: RaftReads,
+    /// How many entries the Raft log holds before they're compacted into a snapshot.
+    #[cfg(feature = "raft")]
+    pub raft_log_limit: usize,
+    /// How long a request to another node or a service waits for its reply.
+    pub rpc_timeout: Duration,
+    /// How many lines read from stdin are buffered before reading waits.
+    pub stdin_capacity: usize,
+    /// How many outbound messages are buffered before writing waits.
+    pub response_capacity: usize,
+    /// How long a request waits for a reply before it's evicted.
+    pub callback_ttl: Duration,
+    /// How many requests wait for replies before the oldest are evicted.
+    pub callback_cap: usize,
+    /// Where the node's state is saved to survive a restart; not saved without one.
+    pub state_dir: Option<PathBuf>,
+    /// How long the saved state's appends wait to be fsynced together; see `Wal::commit`.
+    pub wal_commit_window: Duration,
+    /// How the generate workload writes its IDs.
+    pub id_format: IdFormat,
+    /// How often heartbeats are sent to every other node; none are without one.
+    pub heartbeat_interval: Option<Duration>,
+    /// The failure detector's suspicion at which a peer is considered dead.
+    pub suspicion_threshold: f64,
+    pub failure_detector: DetectorKind,
+    /// How much is logged to stderr, unless `RUST_LOG` says otherwise for some targets.
+    pub log_level: LevelFilter,
+    /// Read the neighbors' values before answering reads when starting without any; see
+    /// `Bootstrap`.
+    pub peer_bootstrap: bool,
+    /// The workloads that only reply once a write is replicated; see `ReplyModes`.
+    pub strict_replies: Vec<Workload>,
+    /// How long each workload's requests are held until it's ready; see `Readiness`.
+    pub readiness: Vec<(Workload, Duration)>,
+    /// The cap on the memory used by the state stores; unbounded without one.
+    pub max_memory_bytes: Option<usize>,
+    pub drain: Drain,
+    /// Where the shutdown report is written; it's logged without one.
+    pub shutdown_report: Option<PathBuf>,
+    pub startup_jitter: StartupJitter,
+    pub dedupe: DedupeConfig,
+    pub lanes: LaneConfig,
+    pub rpc_limits: RpcLimits,
+    /// How many client requests are remembered, with their replies, to answer retries.
+    pub reply_cache: usize,
+    pub anti_entropy: AntiEntropy,
+    /// How peers are dialed when serving over TCP.
+    pub dialing: Dialing,
+    /// The certificates for TLS when serving over TCP; plaintext without them.
+    pub tls: TlsConfig,
+    /// Where committed kafka records are mirrored to; they aren't without one.
+    #[cfg(feature = "kafka")]
+    pub kafka_sink: Option<PathBuf>,
+    /// How many broadcast values are kept in memory before older ones are spilled to disk.
+    pub spill_after: Option<usize>,
+    /// Where spilled values are written; the temp directory without one.
+    pub spill_dir: Option<PathBuf>,
+    /// The node's id when it isn't started by Maelstrom; required along with `discovery`.
+    pub node_id: Option<String>,
+    /// Where the node finds its peers when it isn't started by Maelstrom.
+    pub discovery: Option<Discovery>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            retry: RetryPolicy::default(),
+            gossip_interval: Config::GOSSIP_INTERVAL,
+            gossip_batch: None,
+            topology: OverlayStrategy::Given,
+            workloads: Workload::ALL.to_vec(),
+            causal: false,
+            admin: false,
+            #[cfg(feature = "kv")]
+            kv_mode: KvMode::Linearizable,
+            #[cfg(feature = "raft")]
+            raft_reads: RaftReads::ReadIndex,
+            #[cfg(feature = "raft")]
+            raft_log_limit: 1000,
+            rpc_timeout: Duration::from_millis(1000),
+            stdin_capacity: 32,
+            response_capacity: 10,
+            callback_ttl: Duration::from_secs(300),
+            callback_cap: 100_000,
+            state_dir: None,
+            wal_commit_window: Duration::ZERO,
+            id_format: IdFormat::default(),
+            heartbeat_interval: None,
+            suspicion_threshold: 8.0,
+            failure_detector: DetectorKind::default(),
+            log_level: LevelFilter::DEBUG,
+            peer_bootstrap: false,
+            strict_replies: vec![],
+            readiness: vec![],
+            max_memory_bytes: None,
+            drain: Drain::default(),
+            shutdown_report: None,
+            startup_jitter: StartupJitter::default(),
+            dedupe: DedupeConfig::default(),
+            lanes: LaneConfig::default(),
+            rpc_limits: RpcLimits::default(),
+            reply_cache: 1024,
+            anti_entropy: AntiEntropy::default(),
+            dialing: Dialing::default(),
+            tls: TlsConfig::default(),
+            #[cfg(feature = "kafka")]
+            kafka_sink: None,
+            spill_after: None,
+            spill_dir: None,
+            node_id: None,
+            discovery: None,
+        }
+    }
+}
+
+impl Config {
+    /// How often, by default, a node sends its counts to every other node.
+    pub const GOSSIP_INTERVAL: Duration = Duration::from_millis(200);
+
+    /// Read the file at `path`, or `TRANQUILITY_CONFIG`, or `tranquility.toml`, then apply the
+    /// environment variables on top.
+    pub fn load(path: Option<&str>) -> Result<Config, String> {
+        let path = path
+            .map(str::to_string)
+            .or_else(|| std::env::var("TRANQUILITY_CONFIG").ok());
+
+        let contents = match &path {
+            Some(path) => Some(
+                std::fs::read_to_string(path)
+                    .map_err(|err| format!("Unable to read {}: {}", path, err))?,
+            ),
+            None => std::fs::read_to_string(DEFAULT_PATH).ok(),
+        };
+
+        let config = match contents {
+            Some(contents) => Config::from_toml(&contents)?,
+            None => Config::default(),
+        };
+
+        Ok(config.with_env())
+    }
+
+    /// Parse a config file. Unknown settings, and settings of the wrong type, are errors.
+    pub fn from_toml(contents: &str) -> Result<Config, String> {
+        let file: file::Root = toml::from_str(contents).map_err(|err| err.to_string())?;
+        let mut config = Config::default();
+
+        file.apply(&mut config);
+
+        Ok(config)
+    }
+
+    /// Override the settings that have environment variables with the ones that are set. A
+    /// variable that can't be parsed is ignored, with a warning.
+    pub fn with_env(mut self) -> Config {
+        set(
+            &mut self.retry.initial,
+            var("TRANQUILITY_RETRY_INITIAL", millis),
+        );
+        set(
+            &mut self.retry.multiplier,
+            var("TRANQUILITY_RETRY_MULTIPLIER", number),
+        );
+        set(
+            &mut self.retry.max_delay,
+            var("TRANQUILITY_RETRY_MAX_DELAY", millis),
+        );
+        set(
+            &mut self.retry.max_attempts,
+            var("TRANQUILITY_RETRY_MAX_ATTEMPTS", number).map(Some),
+        );
+        set(
+            &mut self.retry.jitter,
+            var("TRANQUILITY_RETRY_JITTER", number::<f64>).map(|jitter| jitter.clamp(0.0, 1.0)),
+        );
+        set(
+            &mut self.gossip_batch,
+            var("TRANQUILITY_GOSSIP_BATCH", millis).map(Some),
+        );
+        set(
+            &mut self.topology,
+            var("TRANQUILITY_TOPOLOGY", Setting::parse),
+        );
+        set(&mut self.workloads, var("TRANQUILITY_WORKLOADS", list));
+        #[cfg(feature = "kv")]
+        set(
+            &mut self.kv_mode,
+            var("TRANQUILITY_KV_MODE", Setting::parse),
+        );
+        #[cfg(feature = "raft")]
+        set(
+            &mut self.raft_reads,
+            var("TRANQUILITY_RAFT_READS", Setting::parse),
+        );
+        #[cfg(feature = "raft")]
+        set(
+            &mut self.raft_log_limit,
+            var("TRANQUILITY_RAFT_LOG_LIMIT", number),
+        );
+        set(
+            &mut self.rpc_timeout,
+            var("TRANQUILITY_RPC_TIMEOUT", millis),
+        );
+        set(
+            &mut self.stdin_capacity,
+            var("TRANQUILITY_STDIN_CAPACITY", number),
+        );
+        set(
+            &mut self.response_capacity,
+            var("TRANQUILITY_RESPONSE_CAPACITY", number),
+        );
+        set(
+            &mut self.callback_ttl,
+            var("TRANQUILITY_CALLBACK_TTL", millis),
+        );
+        set(
+            &mut self.callback_cap,
+            var("TRANQUILITY_CALLBACK_CAP", number),
+        );
+        set(
+            &mut self.heartbeat_interval,
+            var("TRANQUILITY_HEARTBEAT_INTERVAL", millis).map(Some),
+        );
+        set(
+            &mut self.suspicion_threshold,
+            var("TRANQUILITY_SUSPICION_THRESHOLD", number),
+        );
+        set(
+            &mut self.failure_detector,
+            var("TRANQUILITY_FAILURE_DETECTOR", Setting::parse),
+        );
+        set(
+            &mut self.id_format,
+            var("TRANQUILITY_ID_FORMAT", Setting::parse),
+        );
+        set(&mut self.state_dir, var("TRANQUILITY_STATE_DIR", path));
+        set(
+            &mut self.wal_commit_window,
+            var("TRANQUILITY_WAL_COMMIT_WINDOW", millis),
+        );
+        set(
+            &mut self.log_level,
+            var("TRANQUILITY_LOG_LEVEL", Setting::parse),
+        );
+        set(
+            &mut self.peer_bootstrap,
+            var("TRANQUILITY_PEER_BOOTSTRAP", |value| Ok(value == "1")),
+        );
+        set(
+            &mut self.strict_replies,
+            var("TRANQUILITY_STRICT_REPLIES", list),
+        );
+        set(&mut self.readiness, var("TRANQUILITY_READINESS", gates));
+        set(
+            &mut self.max_memory_bytes,
+            var("TRANQUILITY_MAX_MEMORY_BYTES", number).map(Some),
+        );
+        set(
+            &mut self.drain.timeout,
+            var("TRANQUILITY_DRAIN_TIMEOUT", millis),
+        );
+        set(
+            &mut self.shutdown_report,
+            var("TRANQUILITY_SHUTDOWN_REPORT", path),
+        );
+        set(
+            &mut self.startup_jitter.max,
+            var("TRANQUILITY_STARTUP_JITTER", millis),
+        );
+        set(
+            &mut self.startup_jitter.seed,
+            var("TRANQUILITY_JITTER_SEED", number),
+        );
+        set(
+            &mut self.dedupe.policy,
+            var("TRANQUILITY_DEDUPE_POLICY", Setting::parse),
+        );
+        set(
+            &mut self.dedupe.capacity,
+            var("TRANQUILITY_DEDUPE_CAPACITY", number),
+        );
+        set(&mut self.lanes.lanes, var("TRANQUILITY_LANES", number));
+        set(
+            &mut self.lanes.capacity,
+            var("TRANQUILITY_LANE_CAPACITY", number),
+        );
+        set(
+            &mut self.lanes.affinity,
+            var("TRANQUILITY_AFFINITY", Setting::parse),
+        );
+        set(
+            &mut self.rpc_limits.global,
+            var("TRANQUILITY_MAX_OUTSTANDING", number),
+        );
+        set(
+            &mut self.rpc_limits.per_peer,
+            var("TRANQUILITY_MAX_OUTSTANDING_PER_PEER", number),
+        );
+        set(
+            &mut self.reply_cache,
+            var("TRANQUILITY_REPLY_CACHE", number),
+        );
+        set(
+            &mut self.anti_entropy.interval,
+            var("TRANQUILITY_ANTI_ENTROPY", millis).map(Some),
+        );
+        set(
+            &mut self.anti_entropy.digest_above,
+            var("TRANQUILITY_DIGEST_ABOVE", number),
+        );
+        set(
+            &mut self.dialing.buffer,
+            var("TRANQUILITY_DIAL_BUFFER", number),
+        );
+        set(
+            &mut self.dialing.backoff.initial,
+            var("TRANQUILITY_DIAL_BACKOFF", millis),
+        );
+        set(
+            &mut self.dialing.backoff.max_delay,
+            var("TRANQUILITY_DIAL_MAX_DELAY", millis),
+        );
+        set(&mut self.tls.cert, var("TRANQUILITY_TLS_CERT", path));
+        set(&mut self.tls.key, var("TRANQUILITY_TLS_KEY", path));
+        set(&mut self.tls.ca, var("TRANQUILITY_TLS_CA", path));
+        set(
+            &mut self.tls.mutual,
+            var("TRANQUILITY_TLS_MUTUAL", |value| Ok(value == "1")),
+        );
+        #[cfg(feature = "kafka")]
+        set(&mut self.kafka_sink, var("TRANQUILITY_KAFKA_SINK", path));
+        set(
+            &mut self.spill_after,
+            var("TRANQUILITY_SPILL_AFTER", number).map(Some),
+        );
+        set(&mut self.spill_dir, var("TRANQUILITY_SPILL_DIR", path));
+        set(
+            &mut self.node_id,
+            var("TRANQUILITY_NODE_ID", |id| Ok(id.to_string())).map(Some),
+        );
+        set(
+            &mut self.discovery,
+            var("TRANQUILITY_SEEDS", Setting::parse).map(Some),
+        );
+
+        self
+    }
+
+    /// The resolved settings, keyed as in the config file, with durations in milliseconds. The
+    /// enums are written as their `Debug` forms.
+    pub fn settings(&self) -> BTreeMap<String, Value> {
+        let millis = |duration: Duration| Value::from(duration.as_millis() as u64);
+        let debug = |value: &dyn fmt::Debug| Value::from(format!("{:?}", value));
+        let path = |path: &Option<PathBuf>| {
+            Value::from(path.as_ref().map(|path| path.display().to_string()))
+        };
+
+        #[cfg_attr(
+            not(any(feature = "kv", feature = "kafka", feature = "raft")),
+            allow(unused_mut)
+        )]
+        let mut settings = BTreeMap::from([
+            (
+                "workloads".to_string(),
+                Value::from_iter(self.workloads.iter().map(|workload| debug(workload))),
+            ),
+            ("topology".to_string(), debug(&self.topology)),
+            ("causal".to_string(), Value::from(self.causal)),
+            ("admin".to_string(), Value::from(self.admin)),
+            ("rpc_timeout".to_string(), millis(self.rpc_timeout)),
+            ("id_format".to_string(), debug(&self.id_format)),
+            ("state_dir".to_string(), path(&self.state_dir)),
+            (
+                "wal_commit_window".to_string(),
+                millis(self.wal_commit_window),
+            ),
+            ("retry.initial".to_string(), millis(self.retry.initial)),
+            (
+                "retry.multiplier".to_string(),
+                Value::from(self.retry.multiplier),
+            ),
+            ("retry.max_delay".to_string(), millis(self.retry.max_delay)),
+            (
+                "retry.max_attempts".to_string(),
+                Value::from(self.retry.max_attempts),
+            ),
+            ("retry.jitter".to_string(), Value::from(self.retry.jitter)),
+            ("gossip.interval".to_string(), millis(self.gossip_interval)),
+            (
+                "gossip.batch".to_string(),
+                Value::from(self.gossip_batch.map(|batch| batch.as_millis() as u64)),
+            ),
+            (
+                "channels.stdin".to_string(),
+                Value::from(self.stdin_capacity),
+            ),
+            (
+                "channels.responses".to_string(),
+                Value::from(self.response_capacity),
+            ),
+            ("callbacks.ttl".to_string(), millis(self.callback_ttl)),
+            ("callbacks.cap".to_string(), Value::from(self.callback_cap)),
+            (
+                "heartbeat.interval".to_string(),
+                Value::from(
+                    self.heartbeat_interval
+                        .map(|interval| interval.as_millis() as u64),
+                ),
+            ),
+            (
+                "heartbeat.threshold".to_string(),
+                Value::from(self.suspicion_threshold),
+            ),
+            (
+                "heartbeat.detector".to_string(),
+                debug(&self.failure_detector),
+            ),
+            (
+                "log_level".to_string(),
+                Value::from(self.log_level.to_string()),
+            ),
+            (
+                "peer_bootstrap".to_string(),
+                Value::from(self.peer_bootstrap),
+            ),
+            (
+                "strict_replies".to_string(),
+                Value::from_iter(self.strict_replies.iter().map(|workload| debug(workload))),
+            ),
+            (
+                "readiness".to_string(),
+                Value::from_iter(
+                    self.readiness
+                        .iter()
+                        .map(|(workload, timeout)| (format!("{:?}", workload), millis(*timeout))),
+                ),
+            ),
+            (
+                "max_memory_bytes".to_string(),
+                Value::from(self.max_memory_bytes),
+            ),
+            ("shutdown.drain".to_string(), millis(self.drain.timeout)),
+            ("shutdown.report".to_string(), path(&self.shutdown_report)),
+            ("jitter.max".to_string(), millis(self.startup_jitter.max)),
+            (
+                "jitter.seed".to_string(),
+                Value::from(self.startup_jitter.seed),
+            ),
+            ("dedupe.policy".to_string(), debug(&self.dedupe.policy)),
+            (
+                "dedupe.capacity".to_string(),
+                Value::from(self.dedupe.capacity),
+            ),
+            ("lanes.count".to_string(), Value::from(self.lanes.lanes)),
+            (
+                "lanes.capacity".to_string(),
+                Value::from(self.lanes.capacity),
+            ),
+            ("lanes.affinity".to_string(), debug(&self.lanes.affinity)),
+            (
+                "rpc.max_outstanding".to_string(),
+                Value::from(self.rpc_limits.global),
+            ),
+            (
+                "rpc.max_outstanding_per_peer".to_string(),
+                Value::from(self.rpc_limits.per_peer),
+            ),
+            ("reply_cache".to_string(), Value::from(self.reply_cache)),
+            (
+                "gossip.anti_entropy".to_string(),
+                Value::from(
+                    self.anti_entropy
+                        .interval
+                        .map(|interval| interval.as_millis() as u64),
+                ),
+            ),
+            (
+                "gossip.digest_above".to_string(),
+                Value::from(self.anti_entropy.digest_above),
+            ),
+            ("dial.buffer".to_string(), Value::from(self.dialing.buffer)),
+            (
+                "dial.backoff".to_string(),
+                millis(self.dialing.backoff.initial),
+            ),
+            (
+                "dial.max_delay".to_string(),
+                millis(self.dialing.backoff.max_delay),
+            ),
+            ("tls.cert".to_string(), path(&self.tls.cert)),
+            ("tls.key".to_string(), path(&self.tls.key)),
+            ("tls.ca".to_string(), path(&self.tls.ca)),
+            ("tls.mutual".to_string(), Value::from(self.tls.mutual)),
+            ("spill.after".to_string(), Value::from(self.spill_after)),
+            ("spill.dir".to_string(), path(&self.spill_dir)),
+            (
+                "discovery.node_id".to_string(),
+                Value::from(self.node_id.clone()),
+            ),
+            (
+                "discovery.seeds".to_string(),
+                Value::from(self.discovery.as_ref().map(|seeds| format!("{:?}", seeds))),
+            ),
+        ]);
+
+        #[cfg(feature = "kv")]
+        settings.insert("kv_mode".to_string(), debug(&self.kv_mode));
+
+        #[cfg(feature = "kafka")]
+        settings.insert("kafka_sink".to_string(), path(&self.kafka_sink));
+
+        #[cfg(feature = "raft")]
+        settings.extend([
+            ("raft_reads".to_string(), debug(&self.raft_reads)),
+            (
+                "raft_log_limit".to_string(),
+                Value::from(self.raft_log_limit),
+            ),
+        ]);
+
+        settings
+    }
+}
+
+/// A setting written as a string, the same way in the config file as in its environment
+/// variable.
+trait Setting: Sized {
+    fn parse(value: &str) -> Result<Self, String>;
+}
+
+impl Setting for Workload {
+    fn parse(value: &str) -> Result<Self, String> {
+        Workload::parse(value).ok_or_else(|| format!("Unknown workload {:?}.", value))
+    }
+}
+
+impl Setting for OverlayStrategy {
+    fn parse(value: &str) -> Result<Self, String> {
+        OverlayStrategy::parse(value)
+            .ok_or_else(|| format!("Unknown topology strategy {:?}.", value))
+    }
+}
+
+#[cfg(feature = "kv")]
+impl Setting for KvMode {
+    fn parse(value: &str) -> Result<Self, String> {
+        KvMode::parse(value)
+    }
+}
+
+#[cfg(feature = "raft")]
+impl Setting for RaftReads {
+    fn parse(value: &str) -> Result<Self, String> {
+        RaftReads::parse(value)
+            .ok_or_else(|| format!("Unknown way of serving Raft reads {:?}.", value))
+    }
+}
+
+impl Setting for IdFormat {
+    fn parse(value: &str) -> Result<Self, String> {
+        IdFormat::parse(value).ok_or_else(|| format!("Unknown ID format {:?}.", value))
+    }
+}
+
+impl Setting for DetectorKind {
+    fn parse(value: &str) -> Result<Self, String> {
+        DetectorKind::parse(value).ok_or_else(|| format!("Unknown failure detector {:?}.", value))
+    }
+}
+
+impl Setting for LevelFilter {
+    fn parse(value: &str) -> Result<Self, String> {
+        value
+            .parse()
+            .map_err(|_| format!("Unknown log level {:?}.", value))
+    }
+}
+
+impl Setting for EvictionPolicy {
+    fn parse(value: &str) -> Result<Self, String> {
+        EvictionPolicy::parse(value).ok_or_else(|| format!("Unknown eviction policy {:?}.", value))
+    }
+}
+
+impl Setting for Affinity {
+    fn parse(value: &str) -> Result<Self, String> {
+        Affinity::parse(value).ok_or_else(|| format!("Unknown lane affinity {:?}.", value))
+    }
+}
+
+impl Setting for Discovery {
+    fn parse(value: &str) -> Result<Self, String> {
+        Ok(Discovery::parse(value))
+    }
+}
+
+/// Replace `field` when `value` is set.
+fn set<T>(field: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *field = value;
+    }
+}
+
+/// The environment variable `name`, when it's set and `parse` accepts it.
+fn var<T>(name: &str, parse: impl FnOnce(&str) -> Result<T, String>) -> Option<T> {
+    let value = std::env::var(name).ok()?;
+
+    parse(&value)
+        .map_err(|err| warn!("Ignoring {}: {}", name, err))
+        .ok()
+}
+
+fn number<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("{:?} isn't a number.", value))
+}
+
+fn millis(value: &str) -> Result<Duration, String> {
+    number(value)
+        .map(Duration::from_millis)
+        .map_err(|_| format!("{:?} isn't a number of milliseconds.", value))
+}
+
+fn path(value: &str) -> Result<Option<PathBuf>, String> {
+    Ok(Some(value.into()))
+}
+
+/// A comma-separated list, e.g. `echo,broadcast`.
+fn list<T: Setting>(value: &str) -> Result<Vec<T>, String> {
+    value.split(',').map(|item| T::parse(item.trim())).collect()
+}
+
+/// A comma-separated list of `<workload>:<millis>`, e.g. `broadcast:500`.
+fn gates(value: &str) -> Result<Vec<(Workload, Duration)>, String> {
+    value
+        .split(',')
+        .map(|gate| {
+            let (workload, timeout) = gate
+                .trim()
+                .split_once(':')
+                .ok_or_else(|| format!("{:?} isn't <workload>:<millis>.", gate))?;
+
+            Ok((Setting::parse(workload)?, millis(timeout)?))
+        })
+        .collect()
+}
+
+/// The config file's layout. Every setting is optional; unset ones keep the value they had.
+mod file {
+    use super::*;
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    pub struct Root {
+        #[serde(deserialize_with = "settings")]
+        workloads: Option<Vec<Workload>>,
+        #[serde(deserialize_with = "setting")]
+        topology: Option<OverlayStrategy>,
+        causal: Option<bool>,
+        admin: Option<bool>,
+        #[cfg(feature = "kv")]
+        #[serde(deserialize_with = "setting")]
+        kv_mode: Option<KvMode>,
+        #[cfg(not(feature = "kv"))]
+        #[serde(deserialize_with = "needs_kv")]
+        kv_mode: Option<()>,
+        #[cfg(feature = "raft")]
+        #[serde(deserialize_with = "setting")]
+        raft_reads: Option<RaftReads>,
+        #[cfg(not(feature = "raft"))]
+        #[serde(deserialize_with = "needs_raft")]
+        raft_reads: Option<()>,
+        #[cfg(feature = "raft")]
+        raft_log_limit: Option<usize>,
+        #[cfg(not(feature = "raft"))]
+        #[serde(deserialize_with = "needs_raft")]
+        raft_log_limit: Option<()>,
+        #[serde(deserialize_with = "millis")]
+        rpc_timeout: Option<Duration>,
+        #[serde(deserialize_with = "setting")]
+        id_format: Option<IdFormat>,
+        state_dir: Option<PathBuf>,
+        #[serde(deserialize_with = "millis")]
+        wal_commit_window: Option<Duration>,
+        #[serde(deserialize_with = "setting")]
+        log_level: Option<LevelFilter>,
+        peer_bootstrap: Option<bool>,
+        #[serde(deserialize_with = "settings")]
+        strict_replies: Option<Vec<Workload>>,
+        #[serde(deserialize_with = "gates")]
+        readiness: Option<Vec<(Workload, Duration)>>,
+        max_memory_bytes: Option<usize>,
+        reply_cache: Option<usize>,
+        #[cfg(feature = "kafka")]
+        kafka_sink: Option<PathBuf>,
+        #[cfg(not(feature = "kafka"))]
+        #[serde(deserialize_with = "needs_kafka")]
+        kafka_sink: Option<()>,
+        retry: Retry,
+        gossip: Gossip,
+        channels: Channels,
+        callbacks: Callbacks,
+        heartbeat: Heartbeat,
+        shutdown: Shutdown,
+        jitter: Jitter,
+        dedupe: Dedupe,
+        lanes: Lanes,
+        rpc: Rpc,
+        dial: Dial,
+        tls: Tls,
+        spill: Spill,
+        discovery: Peers,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Retry {
+        #[serde(deserialize_with = "millis")]
+        initial: Option<Duration>,
+        multiplier: Option<f64>,
+        #[serde(deserialize_with = "millis")]
+        max_delay: Option<Duration>,
+        max_attempts: Option<u32>,
+        jitter: Option<f64>,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Gossip {
+        #[serde(deserialize_with = "millis")]
+        interval: Option<Duration>,
+        #[serde(deserialize_with = "millis")]
+        batch: Option<Duration>,
+        #[serde(deserialize_with = "millis")]
+        anti_entropy: Option<Duration>,
+        digest_above: Option<usize>,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Channels {
+        stdin: Option<usize>,
+        responses: Option<usize>,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Callbacks {
+        #[serde(deserialize_with = "millis")]
+        ttl: Option<Duration>,
+        cap: Option<usize>,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Heartbeat {
+        #[serde(deserialize_with = "millis")]
+        interval: Option<Duration>,
+        threshold: Option<f64>,
+        #[serde(deserialize_with = "setting")]
+        detector: Option<DetectorKind>,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Shutdown {
+        #[serde(deserialize_with = "millis")]
+        drain: Option<Duration>,
+        report: Option<PathBuf>,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Jitter {
+        #[serde(deserialize_with = "millis")]
+        max: Option<Duration>,
+        seed: Option<u64>,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Dedupe {
+        #[serde(deserialize_with = "setting")]
+        policy: Option<EvictionPolicy>,
+        capacity: Option<usize>,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Lanes {
+        count: Option<usize>,
+        capacity: Option<usize>,
+        #[serde(deserialize_with = "setting")]
+        affinity: Option<Affinity>,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Rpc {
+        max_outstanding: Option<usize>,
+        max_outstanding_per_peer: Option<usize>,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Dial {
+        buffer: Option<usize>,
+        #[serde(deserialize_with = "millis")]
+        backoff: Option<Duration>,
+        #[serde(deserialize_with = "millis")]
+        max_delay: Option<Duration>,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Tls {
+        cert: Option<PathBuf>,
+        key: Option<PathBuf>,
+        ca: Option<PathBuf>,
+        mutual: Option<bool>,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Spill {
+        after: Option<usize>,
+        dir: Option<PathBuf>,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Peers {
+        node_id: Option<String>,
+        #[serde(deserialize_with = "setting")]
+        seeds: Option<Discovery>,
+    }
+
+    impl Root {
+        /// Override `config` with the settings the file sets.
+        pub fn apply(self, config: &mut Config) {
+            set(&mut config.workloads, self.workloads);
+            set(&mut config.topology, self.topology);
+            set(&mut config.causal, self.causal);
+            set(&mut config.admin, self.admin);
+            #[cfg(feature = "kv")]
+            set(&mut config.kv_mode, self.kv_mode);
+            #[cfg(feature = "raft")]
+            set(&mut config.raft_reads, self.raft_reads);
+            #[cfg(feature = "raft")]
+            set(&mut config.raft_log_limit, self.raft_log_limit);
+            set(&mut config.rpc_timeout, self.rpc_timeout);
+            set(&mut config.id_format, self.id_format);
+            set(&mut config.state_dir, self.state_dir.map(Some));
+            set(&mut config.wal_commit_window, self.wal_commit_window);
+            set(&mut config.log_level, self.log_level);
+            set(&mut config.peer_bootstrap, self.peer_bootstrap);
+            set(&mut config.strict_replies, self.strict_replies);
+            set(&mut config.readiness, self.readiness);
+            set(
+                &mut config.max_memory_bytes,
+                self.max_memory_bytes.map(Some),
+            );
+
+            set(&mut config.retry.initial, self.retry.initial);
+            set(&mut config.retry.multiplier, self.retry.multiplier);
+            set(&mut config.retry.max_delay, self.retry.max_delay);
+            set(
+                &mut config.retry.max_attempts,
+                self.retry.max_attempts.map(Some),
+            );
+            set(
+                &mut config.retry.jitter,
+                self.retry.jitter.map(|jitter| jitter.clamp(0.0, 1.0)),
+            );
+            set(&mut config.gossip_interval, self.gossip.interval);
+            set(&mut config.gossip_batch, self.gossip.batch.map(Some));
+            set(&mut config.stdin_capacity, self.channels.stdin);
+            set(&mut config.response_capacity, self.channels.responses);
+            set(&mut config.callback_ttl, self.callbacks.ttl);
+            set(&mut config.callback_cap, self.callbacks.cap);
+            set(
+                &mut config.heartbeat_interval,
+                self.heartbeat.interval.map(Some),
+            );
+            set(&mut config.suspicion_threshold, self.heartbeat.threshold);
+            set(&mut config.failure_detector, self.heartbeat.detector);
+            set(&mut config.drain.timeout, self.shutdown.drain);
+            set(&mut config.shutdown_report, self.shutdown.report.map(Some));
+            set(&mut config.startup_jitter.max, self.jitter.max);
+            set(&mut config.startup_jitter.seed, self.jitter.seed);
+            set(&mut config.dedupe.policy, self.dedupe.policy);
+            set(&mut config.dedupe.capacity, self.dedupe.capacity);
+            set(&mut config.lanes.lanes, self.lanes.count);
+            set(&mut config.lanes.capacity, self.lanes.capacity);
+            set(&mut config.lanes.affinity, self.lanes.affinity);
+            set(&mut config.rpc_limits.global, self.rpc.max_outstanding);
+            set(
+                &mut config.rpc_limits.per_peer,
+                self.rpc.max_outstanding_per_peer,
+            );
+            set(&mut config.reply_cache, self.reply_cache);
+            #[cfg(feature = "kafka")]
+            set(&mut config.kafka_sink, self.kafka_sink.map(Some));
+            set(
+                &mut config.anti_entropy.interval,
+                self.gossip.anti_entropy.map(Some),
+            );
+            set(
+                &mut config.anti_entropy.digest_above,
+                self.gossip.digest_above,
+            );
+            set(&mut config.dialing.buffer, self.dial.buffer);
+            set(&mut config.dialing.backoff.initial, self.dial.backoff);
+            set(&mut config.dialing.backoff.max_delay, self.dial.max_delay);
+            set(&mut config.tls.cert, self.tls.cert.map(Some));
+            set(&mut config.tls.key, self.tls.key.map(Some));
+            set(&mut config.tls.ca, self.tls.ca.map(Some));
+            set(&mut config.tls.mutual, self.tls.mutual);
+            set(&mut config.spill_after, self.spill.after.map(Some));
+            set(&mut config.spill_dir, self.spill.dir.map(Some));
+            set(&mut config.node_id, self.discovery.node_id.map(Some));
+            set(&mut config.discovery, self.discovery.seeds.map(Some));
+        }
+    }
+
+    fn setting<'de, D: Deserializer<'de>, T: Setting>(
+        deserializer: D,
+    ) -> Result<Option<T>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+
+        T::parse(&value).map(Some).map_err(D::Error::custom)
+    }
+
+    fn settings<'de, D: Deserializer<'de>, T: Setting>(
+        deserializer: D,
+    ) -> Result<Option<Vec<T>>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|value| T::parse(value))
+            .collect::<Result<_, _>>()
+            .map(Some)
+            .map_err(D::Error::custom)
+    }
+
+    fn millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+        u64::deserialize(deserializer).map(|millis| Some(Duration::from_millis(millis)))
+    }
+
+    /// A table of timeouts in milliseconds, by workload.
+    fn gates<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<(Workload, Duration)>>, D::Error> {
+        BTreeMap::<String, u64>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(workload, millis)| {
+                Ok((Setting::parse(&workload)?, Duration::from_millis(millis)))
+            })
+            .collect::<Result<_, String>>()
+            .map(Some)
+            .map_err(D::Error::custom)
+    }
+
+    #[cfg(not(feature = "kv"))]
+    fn needs_kv<'de, D: Deserializer<'de>>(_: D) -> Result<Option<()>, D::Error> {
+        Err(D::Error::custom("kv_mode needs the kv feature."))
+    }
+
+    #[cfg(not(feature = "kafka"))]
+    fn needs_kafka<'de, D: Deserializer<'de>>(_: D) -> Result<Option<()>, D::Error> {
+        Err(D::Error::custom("kafka_sink needs the kafka feature."))
+    }
+
+    #[cfg(not(feature = "raft"))]
+    fn needs_raft<'de, D: Deserializer<'de>>(_: D) -> Result<Option<()>, D::Error> {
+        Err(D::Error::custom("This setting needs the raft feature."))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_a_config_file() {
+        let config = Config::from_toml(
+            r#"
+            workloads = ["echo", "broadcast"] # no counters
+            topology = "tree:3"
+            wal_commit_window = 5
+
+            [retry]
+            initial = 500
+            jitter = 0.5
+
+            [gossip]
+            batch = 100
+
+            [heartbeat]
+            interval = 250
+            detector = "timeout"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.workloads, vec![Workload::Echo, Workload::Broadcast]);
+        assert_eq!(config.topology, OverlayStrategy::Tree { fanout: 3 });
+        assert_eq!(config.wal_commit_window, Duration::from_millis(5));
+        assert_eq!(config.retry.initial, Duration::from_millis(500));
+        assert_eq!(config.retry.jitter, 0.5);
+        assert_eq!(config.retry.max_delay, RetryPolicy::default().max_delay);
+        assert_eq!(config.gossip_batch, Some(Duration::from_millis(100)));
+        assert_eq!(config.gossip_interval, Config::GOSSIP_INTERVAL);
+        assert_eq!(config.heartbeat_interval, Some(Duration::from_millis(250)));
+        assert_eq!(config.failure_detector, DetectorKind::Timeout);
+
+        assert!(Config::from_toml("[retry]\ninitial = \"soon\"").is_err());
+        assert!(Config::from_toml("verbose = true").is_err());
+    }
+
+    #[test]
+    fn reads_the_settings_that_used_to_be_environment_only() {
+        let config = Config::from_toml(
+            r#"
+            peer_bootstrap = true
+            strict_replies = ["broadcast"]
+
+            [readiness]
+            broadcast = 500
+
+            [shutdown]
+            drain = 250
+            report = "shutdown.json"
+
+            [dedupe]
+            policy = "ttl:60000"
+
+            [lanes]
+            count = 2
+            affinity = "body:key"
+
+            [rpc]
+            max_outstanding_per_peer = 8
+
+            [gossip]
+            anti_entropy = 1000
+            digest_above = 50
+
+            [dial]
+            buffer = 16
+
+            [tls]
+            cert = "node.pem"
+            mutual = true
+
+            [discovery]
+            node_id = "n1"
+            seeds = "dns:cluster.local:7000"
+            "#,
+        )
+        .unwrap();
+
+        assert!(config.peer_bootstrap);
+        assert_eq!(config.strict_replies, vec![Workload::Broadcast]);
+        assert_eq!(
+            config.readiness,
+            vec![(Workload::Broadcast, Duration::from_millis(500))]
+        );
+        assert_eq!(config.drain.timeout, Duration::from_millis(250));
+        assert_eq!(config.shutdown_report, Some("shutdown.json".into()));
+        assert_eq!(
+            config.dedupe.policy,
+            EvictionPolicy::Ttl(Duration::from_secs(60))
+        );
+        assert_eq!(config.lanes.lanes, 2);
+        assert_eq!(config.lanes.affinity, Affinity::BodyKey("key".to_string()));
+        assert_eq!(config.rpc_limits.per_peer, 8);
+        assert_eq!(config.rpc_limits.global, RpcLimits::default().global);
+        assert_eq!(
+            config.anti_entropy.interval,
+            Some(Duration::from_millis(1000))
+        );
+        assert_eq!(config.anti_entropy.digest_above, 50);
+        assert_eq!(config.dialing.buffer, 16);
+        assert_eq!(config.dialing.backoff, Dialing::default().backoff);
+        assert_eq!(config.tls.cert, Some("node.pem".into()));
+        assert_eq!(config.tls.key, None);
+        assert!(config.tls.mutual);
+        assert_eq!(config.node_id.as_deref(), Some("n1"));
+        assert_eq!(
+            config.discovery,
+            Some(Discovery::Dns("cluster.local:7000".to_string()))
+        );
+
+        let err = Config::from_toml("[lanes]\naffinity = \"random\"").unwrap_err();
+        assert!(err.contains("Unknown lane affinity"), "{}", err);
+        assert!(Config::from_toml("[lanes]\nshards = 2").is_err());
+    }
+
+    #[test]
+    fn parses_environment_lists() {
+        assert_eq!(
+            list::<Workload>("echo, kafka"),
+            Ok(vec![Workload::Echo, Workload::Kafka])
+        );
+        assert!(list::<Workload>("echo,paxos").is_err());
+        assert_eq!(
+            gates("broadcast:500,kafka:20"),
+            Ok(vec![
+                (Workload::Broadcast, Duration::from_millis(500)),
+                (Workload::Kafka, Duration::from_millis(20)),
+            ])
+        );
+        assert!(gates("broadcast").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "kv")]
+    fn accepts_the_raft_kv_mode_only_with_its_feature() {
+        let config = Config::from_toml(r#"kv_mode = "raft""#);
+
+        #[cfg(feature = "raft")]
+        assert_eq!(config.unwrap().kv_mode, KvMode::Raft);
+        #[cfg(not(feature = "raft"))]
+        assert!(config.unwrap_err().contains("raft feature"));
+
+        assert!(Config::from_toml(r#"kv_mode = "paxos""#).is_err());
+    }
+}
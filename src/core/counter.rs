@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use crate::machine::StateMachine;
+
+/// A grow-only counter: each node only increments its own count, and merging takes the maximum
+/// of each node's count, so replicas converge regardless of the order states are exchanged in.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GCounter {
+    counts: HashMap<String, u64>,
+}
+
+impl GCounter {
+    pub fn add(&mut self, node_id: &str, delta: u64) {
+        *self.counts.entry(node_id.to_string()).or_default() += delta;
+    }
+
+    /// Take the maximum of each node's count, returning whether any grew.
+    pub fn merge(&mut self, counts: &HashMap<String, u64>) -> bool {
+        let mut grew = false;
+
+        for (node_id, count) in counts {
+            let current = self.counts.entry(node_id.clone()).or_default();
+
+            grew |= *count > *current;
+            *current = (*current).max(*count);
+        }
+
+        grew
+    }
+
+    pub fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn counts(&self) -> &HashMap<String, u64> {
+        &self.counts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+}
+
+/// A counter that can also be decremented: increments and decrements are kept in separate
+/// grow-only counters, and the value is their difference. Merging merges each side, so it is
+/// commutative just like `GCounter`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PnCounter {
+    increments: GCounter,
+    decrements: GCounter,
+}
+
+impl PnCounter {
+    pub fn add(&mut self, node_id: &str, delta: i64) {
+        if delta >= 0 {
+            self.increments.add(node_id, delta.unsigned_abs());
+        } else {
+            self.decrements.add(node_id, delta.unsigned_abs());
+        }
+    }
+
+    /// Merge each side, returning whether either grew.
+    pub fn merge(
+        &mut self,
+        increments: &HashMap<String, u64>,
+        decrements: &HashMap<String, u64>,
+    ) -> bool {
+        let incremented = self.increments.merge(increments);
+        let decremented = self.decrements.merge(decrements);
+
+        incremented || decremented
+    }
+
+    pub fn value(&self) -> i64 {
+        self.increments.value() as i64 - self.decrements.value() as i64
+    }
+
+    pub fn increments(&self) -> &HashMap<String, u64> {
+        self.increments.counts()
+    }
+
+    pub fn decrements(&self) -> &HashMap<String, u64> {
+        self.decrements.counts()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.increments.is_empty() && self.decrements.is_empty()
+    }
+}
+
+/// A change to the counter: a node's own increment, or the counts replicated from a peer.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CounterOp {
+    Add {
+        node_id: String,
+        delta: i64,
+    },
+    Merge {
+        increments: HashMap<String, u64>,
+        decrements: HashMap<String, u64>,
+    },
+}
+
+impl StateMachine for PnCounter {
+    type Op = CounterOp;
+    /// Whether the counter changed.
+    type Effect = bool;
+    type Query = ();
+    /// The counter's value.
+    type Answer = i64;
+
+    fn apply(&mut self, op: CounterOp) -> bool {
+        match op {
+            CounterOp::Add { node_id, delta } => {
+                self.add(&node_id, delta);
+                delta != 0
+            }
+            CounterOp::Merge {
+                increments,
+                decrements,
+            } => self.merge(&increments, &decrements),
+        }
+    }
+
+    fn query(&self, _: ()) -> i64 {
+        self.value()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merges_to_the_same_value_in_any_order() {
+        let mut n1 = GCounter::default();
+        let mut n2 = GCounter::default();
+
+        n1.add("n1", 2);
+        n2.add("n2", 3);
+        n2.merge(n1.counts());
+        n1.add("n1", 1);
+        n1.merge(n2.counts());
+        n2.merge(n1.counts());
+        n2.merge(n1.counts());
+
+        assert_eq!(n1.value(), 6);
+        assert_eq!(n1, n2);
+    }
+
+    #[test]
+    fn nets_increments_and_decrements() {
+        let mut n1 = PnCounter::default();
+        let mut n2 = PnCounter::default();
+
+        n1.add("n1", 5);
+        n2.add("n2", -7);
+        n1.merge(n2.increments(), n2.decrements());
+        n2.merge(n1.increments(), n1.decrements());
+
+        assert_eq!(n1.value(), -2);
+        assert_eq!(n1, n2);
+    }
+}
@@ -0,0 +1,138 @@
+//! Last-writer-wins CRDTs: every write carries a hybrid logical clock timestamp, and merging
+//! keeps the write with the latest one, so replicas converge whatever order writes arrive in.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::clock::HybridTimestamp;
+use crate::core::message::ErrorCode;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LwwRegister<T> {
+    pub value: T,
+    pub timestamp: HybridTimestamp,
+}
+
+impl<T> LwwRegister<T> {
+    pub fn new(value: T, timestamp: HybridTimestamp) -> Self {
+        LwwRegister { value, timestamp }
+    }
+
+    /// Take `value` if it was written after the current one. Returns whether it was taken.
+    pub fn set(&mut self, value: T, timestamp: HybridTimestamp) -> bool {
+        if timestamp <= self.timestamp {
+            return false;
+        }
+
+        self.value = value;
+        self.timestamp = timestamp;
+
+        true
+    }
+
+    pub fn merge(&mut self, other: LwwRegister<T>) -> bool {
+        self.set(other.value, other.timestamp)
+    }
+}
+
+/// A map of last-writer-wins registers. Keys may be any JSON value; they're stored by their
+/// serialization, like `KvStore`'s.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LwwMap {
+    entries: BTreeMap<String, LwwRegister<Value>>,
+}
+
+impl LwwMap {
+    pub fn read(&self, key: &Value) -> Result<&Value, ErrorCode> {
+        self.entries
+            .get(&key.to_string())
+            .map(|register| &register.value)
+            .ok_or(ErrorCode::KeyDoesNotExist)
+    }
+
+    pub fn write(&mut self, key: &Value, value: Value, timestamp: HybridTimestamp) -> bool {
+        self.merge_entry(key.to_string(), LwwRegister::new(value, timestamp))
+    }
+
+    /// Set `key` to `to` if this replica currently has it as `from`. Other replicas may not agree
+    /// yet, so unlike `KvStore::cas` this is not atomic across the cluster.
+    pub fn cas(
+        &mut self,
+        key: &Value,
+        from: &Value,
+        to: Value,
+        create_if_not_exists: bool,
+        timestamp: HybridTimestamp,
+    ) -> Result<(), ErrorCode> {
+        match self.read(key) {
+            Ok(current) if current == from => {}
+            Ok(_) => return Err(ErrorCode::PreconditionFailed),
+            Err(_) if create_if_not_exists => {}
+            Err(err) => return Err(err),
+        }
+
+        self.write(key, to, timestamp);
+
+        Ok(())
+    }
+
+    /// Merge another replica's entries. Returns the number of entries that changed.
+    pub fn merge(&mut self, entries: BTreeMap<String, LwwRegister<Value>>) -> usize {
+        entries
+            .into_iter()
+            .filter(|(key, register)| self.merge_entry(key.clone(), register.clone()))
+            .count()
+    }
+
+    pub fn entries(&self) -> &BTreeMap<String, LwwRegister<Value>> {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn merge_entry(&mut self, key: String, register: LwwRegister<Value>) -> bool {
+        match self.entries.get_mut(&key) {
+            Some(current) => current.merge(register),
+            None => {
+                self.entries.insert(key, register);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(wall: u64, node_id: &str) -> HybridTimestamp {
+        HybridTimestamp {
+            wall,
+            logical: 0,
+            node_id: node_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn converges_on_the_latest_write() {
+        let key = Value::from(1);
+
+        let mut n1 = LwwMap::default();
+        n1.write(&key, 10.into(), at(5, "n1"));
+
+        let mut n2 = LwwMap::default();
+        n2.write(&key, 20.into(), at(5, "n2"));
+        n2.write(&key, 30.into(), at(4, "n2"));
+
+        n1.merge(n2.entries().clone());
+        n2.merge(n1.entries().clone());
+
+        // Same millisecond: the higher node id wins.
+        assert_eq!(n1.read(&key), Ok(&Value::from(20)));
+        assert_eq!(n1, n2);
+    }
+}
@@ -0,0 +1,2462 @@
+use serde::de::value::{BorrowedStrDeserializer, MapAccessDeserializer, MapDeserializer};
+use serde::de::{self, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+#[cfg(any(feature = "counter", feature = "kafka"))]
+use std::collections::HashMap;
+#[cfg(any(all(test, feature = "broadcast"), feature = "schema"))]
+use std::collections::HashSet;
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::admin::CrashMode;
+#[cfg(feature = "kv")]
+use crate::clock::HybridTimestamp;
+#[cfg(feature = "broadcast")]
+use crate::clock::VectorClock;
+#[cfg(feature = "kv")]
+use crate::core::lww::LwwRegister;
+use crate::core::state::BroadcastValues;
+use crate::health::PeerReport;
+use crate::id128;
+use crate::metrics::MetricsReport;
+#[cfg(feature = "raft")]
+use crate::raft::LogEntry;
+#[cfg(feature = "kv")]
+use crate::session::SessionVersion;
+use crate::snowflake::Layout;
+use crate::timer::TimerEvent;
+#[cfg(feature = "broadcast")]
+use crate::topology::{Topology, TopologyReport};
+
+/// A message, with its body's strings borrowed from the line it was parsed from where they can
+/// be; `into_owned` copies them out to keep the message past the line. Messages the node builds
+/// are `Message<'static>`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Message<'a> {
+    pub src: Option<String>,
+    pub dest: String,
+    pub body: MessageBody<'a>,
+    /// The sender's Lamport time, carried as the body's `lamport` field.
+    #[cfg_attr(feature = "schema", schemars(skip))]
+    pub lamport: Option<u64>,
+}
+
+/// A message as it's written: the Lamport time sits in the body alongside the body's own fields.
+#[derive(Serialize)]
+struct WireMessage<'m, 'a> {
+    src: &'m Option<String>,
+    dest: &'m str,
+    body: WireBody<'m, 'a>,
+}
+
+#[derive(Serialize)]
+struct WireBody<'m, 'a> {
+    #[serde(flatten)]
+    body: &'m MessageBody<'a>,
+    #[serde(skip_serializing_if = "Option::is_none", with = "wide::option")]
+    lamport: Option<u64>,
+}
+
+/// A message as it's read. The body's fields are handed to its own `Deserialize` as they're read
+/// rather than buffered, so its strings can still be borrowed from the input.
+#[derive(Deserialize)]
+struct IncomingMessage<'a> {
+    src: Option<String>,
+    dest: String,
+    #[serde(borrow)]
+    body: IncomingBody<'a>,
+}
+
+struct IncomingBody<'a> {
+    body: MessageBody<'a>,
+    lamport: Option<u64>,
+}
+
+impl Serialize for Message<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        WireMessage {
+            src: &self.src,
+            dest: &self.dest,
+            body: WireBody {
+                body: &self.body,
+                lamport: self.lamport,
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Message<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let message = IncomingMessage::deserialize(deserializer)?;
+
+        Ok(Message {
+            src: message.src,
+            dest: message.dest,
+            body: message.body.body,
+            lamport: message.body.lamport,
+        })
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for MessageBody<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        IncomingBody::deserialize(deserializer).map(|read| read.body)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for IncomingBody<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(BodyVisitor(PhantomData))
+    }
+}
+
+struct BodyVisitor<'a>(PhantomData<MessageBody<'a>>);
+
+impl<'de: 'a, 'a> Visitor<'de> for BodyVisitor<'a> {
+    type Value = IncomingBody<'a>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a message body")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<IncomingBody<'a>, A::Error> {
+        let mut lamport = None;
+        // Fields before `type` can't be handed on until the body's type is known, so they're
+        // kept as values. There are none when `type` comes first, as the node writes it.
+        let mut before = vec![];
+
+        let kind = loop {
+            let Some(Borrowed(key)) = map.next_key()? else {
+                return Err(de::Error::missing_field("type"));
+            };
+
+            match &*key {
+                "type" => break map.next_value::<Borrowed>()?.0,
+                "lamport" => lamport = map.next_value::<Lamport>()?.0,
+                _ => before.push((key, map.next_value::<serde_json::Value>()?)),
+            }
+        };
+
+        let fields = Fields {
+            before: before.into_iter(),
+            value: None,
+            rest: map,
+            lamport: &mut lamport,
+        };
+        let body = MessageBody::from_fields(&kind, MapAccessDeserializer::new(fields))?;
+
+        Ok(IncomingBody { body, lamport })
+    }
+}
+
+#[derive(Deserialize)]
+struct Borrowed<'a>(#[serde(borrow)] Cow<'a, str>);
+
+#[derive(Deserialize)]
+struct Lamport(#[serde(with = "wide::option")] Option<u64>);
+
+/// A body's fields other than `type` and `lamport`: those read before `type`, then the rest as
+/// they're read.
+struct Fields<'l, 'de, A> {
+    before: std::vec::IntoIter<(Cow<'de, str>, serde_json::Value)>,
+    /// The value of the buffered field whose key was just handed on.
+    value: Option<serde_json::Value>,
+    rest: A,
+    lamport: &'l mut Option<u64>,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for Fields<'_, 'de, A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        if let Some((key, value)) = self.before.next() {
+            self.value = Some(value);
+            return field_name(seed, key).map(Some);
+        }
+
+        loop {
+            let Some(Borrowed(key)) = self.rest.next_key()? else {
+                return Ok(None);
+            };
+
+            match &*key {
+                "lamport" => *self.lamport = self.rest.next_value::<Lamport>()?.0,
+                _ => return field_name(seed, key).map(Some),
+            }
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, A::Error> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value).map_err(de::Error::custom),
+            None => self.rest.next_value_seed(seed),
+        }
+    }
+}
+
+fn field_name<'de, K: DeserializeSeed<'de>, E: de::Error>(
+    seed: K,
+    key: Cow<'de, str>,
+) -> Result<K::Value, E> {
+    match key {
+        Cow::Borrowed(key) => seed.deserialize(BorrowedStrDeserializer::new(key)),
+        Cow::Owned(key) => seed.deserialize(key.into_deserializer()),
+    }
+}
+
+/// Deserialized by `from_fields` once the `type` is read, so the body's fields aren't buffered as
+/// they would be for an internally tagged enum.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MessageBody<'a> {
+    Init(InitBody<'a>),
+    InitOk(InitOkBody),
+    Echo(EchoBody),
+    EchoOk(EchoOkBody),
+    #[cfg(feature = "broadcast")]
+    Broadcast(BroadcastBody),
+    #[cfg(feature = "broadcast")]
+    BroadcastOk(BroadcastOkBody),
+    #[cfg(feature = "broadcast")]
+    Topology(TopologyBody),
+    #[cfg(feature = "broadcast")]
+    TopologyOk(TopologyOkBody),
+    #[cfg(feature = "broadcast")]
+    TopologyReport(TopologyReportBody),
+    #[cfg(feature = "broadcast")]
+    TopologyReportOk(TopologyReportOkBody),
+    Metrics(MetricsBody),
+    MetricsOk(MetricsOkBody),
+    PeerStatus(PeerStatusBody),
+    PeerStatusOk(PeerStatusOkBody),
+    Crash(CrashBody),
+    CrashOk(CrashOkBody),
+    DebugState(DebugStateBody),
+    DebugStateOk(DebugStateOkBody),
+    Read(ReadBody),
+    ReadOk(ReadOkBody),
+    Generate(GenerateBody),
+    GenerateOk(GenerateOkBody),
+    #[cfg(feature = "counter")]
+    Add(AddBody),
+    #[cfg(feature = "counter")]
+    AddOk(AddOkBody),
+    #[cfg(feature = "kafka")]
+    Send(SendBody<'a>),
+    #[cfg(feature = "kafka")]
+    SendOk(SendOkBody),
+    #[cfg(feature = "kafka")]
+    Poll(PollBody),
+    #[cfg(feature = "kafka")]
+    PollOk(PollOkBody),
+    #[cfg(feature = "kafka")]
+    CommitOffsets(CommitOffsetsBody),
+    #[cfg(feature = "kafka")]
+    CommitOffsetsOk(CommitOffsetsOkBody),
+    #[cfg(feature = "kafka")]
+    ListCommittedOffsets(ListCommittedOffsetsBody),
+    #[cfg(feature = "kafka")]
+    ListCommittedOffsetsOk(ListCommittedOffsetsOkBody),
+    #[cfg(feature = "kv")]
+    Write(WriteBody),
+    #[cfg(feature = "kv")]
+    WriteOk(WriteOkBody),
+    #[cfg(feature = "kv")]
+    Cas(CasBody),
+    #[cfg(feature = "kv")]
+    CasOk(CasOkBody),
+    #[cfg(feature = "txn")]
+    Txn(TxnBody),
+    #[cfg(feature = "txn")]
+    TxnOk(TxnOkBody),
+    Error(ErrorBody<'a>),
+    /// Node-to-node messages, whose `type`s are namespaced; see `InternalBody`.
+    #[serde(untagged)]
+    Internal(InternalBody),
+}
+
+impl<'a> MessageBody<'a> {
+    /// The body of type `kind`, from the rest of its fields.
+    fn from_fields<'de: 'a, D: Deserializer<'de>>(kind: &str, fields: D) -> Result<Self, D::Error> {
+        Ok(match kind {
+            "init" => MessageBody::Init(Deserialize::deserialize(fields)?),
+            "init_ok" => MessageBody::InitOk(Deserialize::deserialize(fields)?),
+            "echo" => MessageBody::Echo(Deserialize::deserialize(fields)?),
+            "echo_ok" => MessageBody::EchoOk(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "broadcast")]
+            "broadcast" => MessageBody::Broadcast(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "broadcast")]
+            "broadcast_ok" => MessageBody::BroadcastOk(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "broadcast")]
+            "topology" => MessageBody::Topology(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "broadcast")]
+            "topology_ok" => MessageBody::TopologyOk(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "broadcast")]
+            "topology_report" => MessageBody::TopologyReport(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "broadcast")]
+            "topology_report_ok" => {
+                MessageBody::TopologyReportOk(Deserialize::deserialize(fields)?)
+            }
+            "metrics" => MessageBody::Metrics(Deserialize::deserialize(fields)?),
+            "metrics_ok" => MessageBody::MetricsOk(Deserialize::deserialize(fields)?),
+            "peer_status" => MessageBody::PeerStatus(Deserialize::deserialize(fields)?),
+            "peer_status_ok" => MessageBody::PeerStatusOk(Deserialize::deserialize(fields)?),
+            "crash" => MessageBody::Crash(Deserialize::deserialize(fields)?),
+            "crash_ok" => MessageBody::CrashOk(Deserialize::deserialize(fields)?),
+            "debug_state" => MessageBody::DebugState(Deserialize::deserialize(fields)?),
+            "debug_state_ok" => MessageBody::DebugStateOk(Deserialize::deserialize(fields)?),
+            "read" => MessageBody::Read(Deserialize::deserialize(fields)?),
+            "read_ok" => MessageBody::ReadOk(Deserialize::deserialize(fields)?),
+            "generate" => MessageBody::Generate(Deserialize::deserialize(fields)?),
+            "generate_ok" => MessageBody::GenerateOk(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "counter")]
+            "add" => MessageBody::Add(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "counter")]
+            "add_ok" => MessageBody::AddOk(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "kafka")]
+            "send" => MessageBody::Send(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "kafka")]
+            "send_ok" => MessageBody::SendOk(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "kafka")]
+            "poll" => MessageBody::Poll(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "kafka")]
+            "poll_ok" => MessageBody::PollOk(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "kafka")]
+            "commit_offsets" => MessageBody::CommitOffsets(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "kafka")]
+            "commit_offsets_ok" => MessageBody::CommitOffsetsOk(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "kafka")]
+            "list_committed_offsets" => {
+                MessageBody::ListCommittedOffsets(Deserialize::deserialize(fields)?)
+            }
+            #[cfg(feature = "kafka")]
+            "list_committed_offsets_ok" => {
+                MessageBody::ListCommittedOffsetsOk(Deserialize::deserialize(fields)?)
+            }
+            #[cfg(feature = "kv")]
+            "write" => MessageBody::Write(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "kv")]
+            "write_ok" => MessageBody::WriteOk(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "kv")]
+            "cas" => MessageBody::Cas(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "kv")]
+            "cas_ok" => MessageBody::CasOk(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "txn")]
+            "txn" => MessageBody::Txn(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "txn")]
+            "txn_ok" => MessageBody::TxnOk(Deserialize::deserialize(fields)?),
+            "error" => MessageBody::Error(Deserialize::deserialize(fields)?),
+            _ => MessageBody::Internal(InternalBody::from_fields(kind, fields)?),
+        })
+    }
+
+    /// Whether this build has a body of type `kind`, whether or not a given body of that type
+    /// has the fields it needs.
+    pub fn is_known(kind: &str) -> bool {
+        let fields = MapDeserializer::<_, UnknownType>::new(std::iter::empty::<(&str, &str)>());
+
+        !matches!(
+            MessageBody::from_fields(kind, fields),
+            Err(UnknownType(true))
+        )
+    }
+
+    /// Copy the body's strings out of the line they were borrowed from.
+    pub fn into_owned(self) -> MessageBody<'static> {
+        match self {
+            MessageBody::Init(body) => MessageBody::Init(body.into_owned()),
+            #[cfg(feature = "kafka")]
+            MessageBody::Send(body) => MessageBody::Send(body.into_owned()),
+            MessageBody::Error(body) => MessageBody::Error(body.into_owned()),
+            MessageBody::InitOk(body) => MessageBody::InitOk(body),
+            MessageBody::Echo(body) => MessageBody::Echo(body),
+            MessageBody::EchoOk(body) => MessageBody::EchoOk(body),
+            #[cfg(feature = "broadcast")]
+            MessageBody::Broadcast(body) => MessageBody::Broadcast(body),
+            #[cfg(feature = "broadcast")]
+            MessageBody::BroadcastOk(body) => MessageBody::BroadcastOk(body),
+            #[cfg(feature = "broadcast")]
+            MessageBody::Topology(body) => MessageBody::Topology(body),
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyOk(body) => MessageBody::TopologyOk(body),
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyReport(body) => MessageBody::TopologyReport(body),
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyReportOk(body) => MessageBody::TopologyReportOk(body),
+            MessageBody::Metrics(body) => MessageBody::Metrics(body),
+            MessageBody::MetricsOk(body) => MessageBody::MetricsOk(body),
+            MessageBody::PeerStatus(body) => MessageBody::PeerStatus(body),
+            MessageBody::PeerStatusOk(body) => MessageBody::PeerStatusOk(body),
+            MessageBody::Crash(body) => MessageBody::Crash(body),
+            MessageBody::CrashOk(body) => MessageBody::CrashOk(body),
+            MessageBody::DebugState(body) => MessageBody::DebugState(body),
+            MessageBody::DebugStateOk(body) => MessageBody::DebugStateOk(body),
+            MessageBody::Read(body) => MessageBody::Read(body),
+            MessageBody::ReadOk(body) => MessageBody::ReadOk(body),
+            MessageBody::Generate(body) => MessageBody::Generate(body),
+            MessageBody::GenerateOk(body) => MessageBody::GenerateOk(body),
+            #[cfg(feature = "counter")]
+            MessageBody::Add(body) => MessageBody::Add(body),
+            #[cfg(feature = "counter")]
+            MessageBody::AddOk(body) => MessageBody::AddOk(body),
+            #[cfg(feature = "kafka")]
+            MessageBody::SendOk(body) => MessageBody::SendOk(body),
+            #[cfg(feature = "kafka")]
+            MessageBody::Poll(body) => MessageBody::Poll(body),
+            #[cfg(feature = "kafka")]
+            MessageBody::PollOk(body) => MessageBody::PollOk(body),
+            #[cfg(feature = "kafka")]
+            MessageBody::CommitOffsets(body) => MessageBody::CommitOffsets(body),
+            #[cfg(feature = "kafka")]
+            MessageBody::CommitOffsetsOk(body) => MessageBody::CommitOffsetsOk(body),
+            #[cfg(feature = "kafka")]
+            MessageBody::ListCommittedOffsets(body) => MessageBody::ListCommittedOffsets(body),
+            #[cfg(feature = "kafka")]
+            MessageBody::ListCommittedOffsetsOk(body) => MessageBody::ListCommittedOffsetsOk(body),
+            #[cfg(feature = "kv")]
+            MessageBody::Write(body) => MessageBody::Write(body),
+            #[cfg(feature = "kv")]
+            MessageBody::WriteOk(body) => MessageBody::WriteOk(body),
+            #[cfg(feature = "kv")]
+            MessageBody::Cas(body) => MessageBody::Cas(body),
+            #[cfg(feature = "kv")]
+            MessageBody::CasOk(body) => MessageBody::CasOk(body),
+            #[cfg(feature = "txn")]
+            MessageBody::Txn(body) => MessageBody::Txn(body),
+            #[cfg(feature = "txn")]
+            MessageBody::TxnOk(body) => MessageBody::TxnOk(body),
+            MessageBody::Internal(body) => MessageBody::Internal(body),
+        }
+    }
+
+    /// The `type` field on the wire, which handlers are registered under.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MessageBody::Init(_) => "init",
+            MessageBody::InitOk(_) => "init_ok",
+            MessageBody::Echo(_) => "echo",
+            MessageBody::EchoOk(_) => "echo_ok",
+            #[cfg(feature = "broadcast")]
+            MessageBody::Broadcast(_) => "broadcast",
+            #[cfg(feature = "broadcast")]
+            MessageBody::BroadcastOk(_) => "broadcast_ok",
+            #[cfg(feature = "broadcast")]
+            MessageBody::Topology(_) => "topology",
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyOk(_) => "topology_ok",
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyReport(_) => "topology_report",
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyReportOk(_) => "topology_report_ok",
+            MessageBody::Metrics(_) => "metrics",
+            MessageBody::MetricsOk(_) => "metrics_ok",
+            MessageBody::PeerStatus(_) => "peer_status",
+            MessageBody::PeerStatusOk(_) => "peer_status_ok",
+            MessageBody::Crash(_) => "crash",
+            MessageBody::CrashOk(_) => "crash_ok",
+            MessageBody::DebugState(_) => "debug_state",
+            MessageBody::DebugStateOk(_) => "debug_state_ok",
+            MessageBody::Read(_) => "read",
+            MessageBody::ReadOk(_) => "read_ok",
+            MessageBody::Generate(_) => "generate",
+            MessageBody::GenerateOk(_) => "generate_ok",
+            #[cfg(feature = "counter")]
+            MessageBody::Add(_) => "add",
+            #[cfg(feature = "counter")]
+            MessageBody::AddOk(_) => "add_ok",
+            #[cfg(feature = "kafka")]
+            MessageBody::Send(_) => "send",
+            #[cfg(feature = "kafka")]
+            MessageBody::SendOk(_) => "send_ok",
+            #[cfg(feature = "kafka")]
+            MessageBody::Poll(_) => "poll",
+            #[cfg(feature = "kafka")]
+            MessageBody::PollOk(_) => "poll_ok",
+            #[cfg(feature = "kafka")]
+            MessageBody::CommitOffsets(_) => "commit_offsets",
+            #[cfg(feature = "kafka")]
+            MessageBody::CommitOffsetsOk(_) => "commit_offsets_ok",
+            #[cfg(feature = "kafka")]
+            MessageBody::ListCommittedOffsets(_) => "list_committed_offsets",
+            #[cfg(feature = "kafka")]
+            MessageBody::ListCommittedOffsetsOk(_) => "list_committed_offsets_ok",
+            #[cfg(feature = "kv")]
+            MessageBody::Write(_) => "write",
+            #[cfg(feature = "kv")]
+            MessageBody::WriteOk(_) => "write_ok",
+            #[cfg(feature = "kv")]
+            MessageBody::Cas(_) => "cas",
+            #[cfg(feature = "kv")]
+            MessageBody::CasOk(_) => "cas_ok",
+            #[cfg(feature = "txn")]
+            MessageBody::Txn(_) => "txn",
+            #[cfg(feature = "txn")]
+            MessageBody::TxnOk(_) => "txn_ok",
+            MessageBody::Error(_) => "error",
+            MessageBody::Internal(body) => body.kind(),
+        }
+    }
+
+    pub fn msg_id(&self) -> Option<u32> {
+        match self {
+            MessageBody::Init(body) => body.msg_id,
+            MessageBody::InitOk(body) => body.msg_id,
+            MessageBody::Echo(body) => body.msg_id,
+            MessageBody::EchoOk(body) => body.msg_id,
+            #[cfg(feature = "broadcast")]
+            MessageBody::Broadcast(body) => body.msg_id,
+            #[cfg(feature = "broadcast")]
+            MessageBody::BroadcastOk(body) => body.msg_id,
+            #[cfg(feature = "broadcast")]
+            MessageBody::Topology(body) => body.msg_id,
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyOk(body) => body.msg_id,
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyReport(body) => body.msg_id,
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyReportOk(body) => body.msg_id,
+            MessageBody::Metrics(body) => body.msg_id,
+            MessageBody::MetricsOk(body) => body.msg_id,
+            MessageBody::PeerStatus(body) => body.msg_id,
+            MessageBody::PeerStatusOk(body) => body.msg_id,
+            MessageBody::Crash(body) => body.msg_id,
+            MessageBody::CrashOk(body) => body.msg_id,
+            MessageBody::DebugState(body) => body.msg_id,
+            MessageBody::DebugStateOk(body) => body.msg_id,
+            MessageBody::Read(body) => body.msg_id,
+            MessageBody::ReadOk(body) => body.msg_id,
+            MessageBody::Generate(body) => Some(body.msg_id),
+            MessageBody::GenerateOk(body) => body.msg_id,
+            #[cfg(feature = "counter")]
+            MessageBody::Add(body) => body.msg_id,
+            #[cfg(feature = "counter")]
+            MessageBody::AddOk(body) => body.msg_id,
+            #[cfg(feature = "kafka")]
+            MessageBody::Send(body) => body.msg_id,
+            #[cfg(feature = "kafka")]
+            MessageBody::SendOk(body) => body.msg_id,
+            #[cfg(feature = "kafka")]
+            MessageBody::Poll(body) => body.msg_id,
+            #[cfg(feature = "kafka")]
+            MessageBody::PollOk(body) => body.msg_id,
+            #[cfg(feature = "kafka")]
+            MessageBody::CommitOffsets(body) => body.msg_id,
+            #[cfg(feature = "kafka")]
+            MessageBody::CommitOffsetsOk(body) => body.msg_id,
+            #[cfg(feature = "kafka")]
+            MessageBody::ListCommittedOffsets(body) => body.msg_id,
+            #[cfg(feature = "kafka")]
+            MessageBody::ListCommittedOffsetsOk(body) => body.msg_id,
+            #[cfg(feature = "kv")]
+            MessageBody::Write(body) => body.msg_id,
+            #[cfg(feature = "kv")]
+            MessageBody::WriteOk(body) => body.msg_id,
+            #[cfg(feature = "kv")]
+            MessageBody::Cas(body) => body.msg_id,
+            #[cfg(feature = "kv")]
+            MessageBody::CasOk(body) => body.msg_id,
+            #[cfg(feature = "txn")]
+            MessageBody::Txn(body) => body.msg_id,
+            #[cfg(feature = "txn")]
+            MessageBody::TxnOk(body) => body.msg_id,
+            MessageBody::Error(body) => body.msg_id,
+            MessageBody::Internal(body) => body.msg_id(),
+        }
+    }
+
+    /// Give the body new ids, e.g. to send a request on, or to relay a reply to the client a
+    /// request was forwarded for. `in_reply_to` is left as is when `None`, and bodies that aren't
+    /// replies only take the `msg_id`.
+    pub fn set_ids(&mut self, msg_id: Option<u32>, in_reply_to: Option<u32>) {
+        match self {
+            MessageBody::Init(body) => body.msg_id = msg_id,
+            MessageBody::InitOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.or(body.in_reply_to))
+            }
+            MessageBody::Echo(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.or(body.in_reply_to))
+            }
+            MessageBody::EchoOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.or(body.in_reply_to))
+            }
+            #[cfg(feature = "broadcast")]
+            MessageBody::Broadcast(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.or(body.in_reply_to))
+            }
+            #[cfg(feature = "broadcast")]
+            MessageBody::BroadcastOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            #[cfg(feature = "broadcast")]
+            MessageBody::Topology(body) => body.msg_id = msg_id,
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyReport(body) => body.msg_id = msg_id,
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyReportOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            MessageBody::Metrics(body) => body.msg_id = msg_id,
+            MessageBody::MetricsOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            MessageBody::PeerStatus(body) => body.msg_id = msg_id,
+            MessageBody::PeerStatusOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            MessageBody::Crash(body) => body.msg_id = msg_id,
+            MessageBody::CrashOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            MessageBody::DebugState(body) => body.msg_id = msg_id,
+            MessageBody::DebugStateOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            MessageBody::Read(body) => body.msg_id = msg_id,
+            MessageBody::ReadOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            // A `generate` always has a `msg_id`, so it keeps its own rather than none.
+            MessageBody::Generate(body) => body.msg_id = msg_id.unwrap_or(body.msg_id),
+            MessageBody::GenerateOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.or(body.in_reply_to))
+            }
+            #[cfg(feature = "counter")]
+            MessageBody::Add(body) => body.msg_id = msg_id,
+            #[cfg(feature = "counter")]
+            MessageBody::AddOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.or(body.in_reply_to))
+            }
+            #[cfg(feature = "kafka")]
+            MessageBody::Send(body) => body.msg_id = msg_id,
+            #[cfg(feature = "kafka")]
+            MessageBody::SendOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            #[cfg(feature = "kafka")]
+            MessageBody::Poll(body) => body.msg_id = msg_id,
+            #[cfg(feature = "kafka")]
+            MessageBody::PollOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            #[cfg(feature = "kafka")]
+            MessageBody::CommitOffsets(body) => body.msg_id = msg_id,
+            #[cfg(feature = "kafka")]
+            MessageBody::CommitOffsetsOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            #[cfg(feature = "kafka")]
+            MessageBody::ListCommittedOffsets(body) => body.msg_id = msg_id,
+            #[cfg(feature = "kafka")]
+            MessageBody::ListCommittedOffsetsOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            #[cfg(feature = "kv")]
+            MessageBody::Write(body) => body.msg_id = msg_id,
+            #[cfg(feature = "kv")]
+            MessageBody::WriteOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            #[cfg(feature = "kv")]
+            MessageBody::Cas(body) => body.msg_id = msg_id,
+            #[cfg(feature = "kv")]
+            MessageBody::CasOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            #[cfg(feature = "txn")]
+            MessageBody::Txn(body) => body.msg_id = msg_id,
+            #[cfg(feature = "txn")]
+            MessageBody::TxnOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            MessageBody::Error(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.or(body.in_reply_to))
+            }
+            MessageBody::Internal(body) => body.set_ids(msg_id, in_reply_to),
+        }
+    }
+
+    /// Set a reply body's ids. Bodies that aren't replies are left as they are.
+    pub fn set_reply_ids(&mut self, msg_id: Option<u32>, in_reply_to: Option<u32>) {
+        match self {
+            MessageBody::InitOk(body) => (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to),
+            MessageBody::EchoOk(body) => (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to),
+            MessageBody::GenerateOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to)
+            }
+            #[cfg(feature = "counter")]
+            MessageBody::AddOk(body) => (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to),
+            MessageBody::Error(body) => (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to),
+            #[cfg(feature = "broadcast")]
+            MessageBody::BroadcastOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyReportOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            MessageBody::MetricsOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            MessageBody::PeerStatusOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            MessageBody::CrashOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            MessageBody::DebugStateOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            MessageBody::ReadOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            #[cfg(feature = "kafka")]
+            MessageBody::SendOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            #[cfg(feature = "kafka")]
+            MessageBody::PollOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            #[cfg(feature = "kafka")]
+            MessageBody::CommitOffsetsOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            #[cfg(feature = "kafka")]
+            MessageBody::ListCommittedOffsetsOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            #[cfg(feature = "kv")]
+            MessageBody::WriteOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            #[cfg(feature = "kv")]
+            MessageBody::CasOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            #[cfg(feature = "txn")]
+            MessageBody::TxnOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            MessageBody::Internal(body) => body.set_reply_ids(msg_id, in_reply_to),
+            _ => {}
+        }
+    }
+
+    pub fn in_reply_to(&self) -> Option<u32> {
+        match self {
+            MessageBody::InitOk(body) => body.in_reply_to,
+            MessageBody::Echo(body) => body.in_reply_to,
+            MessageBody::EchoOk(body) => body.in_reply_to,
+            #[cfg(feature = "broadcast")]
+            MessageBody::Broadcast(body) => body.in_reply_to,
+            #[cfg(feature = "broadcast")]
+            MessageBody::BroadcastOk(body) => Some(body.in_reply_to),
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyOk(body) => Some(body.in_reply_to),
+            #[cfg(feature = "broadcast")]
+            MessageBody::TopologyReportOk(body) => Some(body.in_reply_to),
+            MessageBody::MetricsOk(body) => Some(body.in_reply_to),
+            MessageBody::PeerStatusOk(body) => Some(body.in_reply_to),
+            MessageBody::CrashOk(body) => Some(body.in_reply_to),
+            MessageBody::DebugStateOk(body) => Some(body.in_reply_to),
+            MessageBody::ReadOk(body) => Some(body.in_reply_to),
+            MessageBody::GenerateOk(body) => body.in_reply_to,
+            #[cfg(feature = "counter")]
+            MessageBody::AddOk(body) => body.in_reply_to,
+            #[cfg(feature = "kafka")]
+            MessageBody::SendOk(body) => Some(body.in_reply_to),
+            #[cfg(feature = "kafka")]
+            MessageBody::PollOk(body) => Some(body.in_reply_to),
+            #[cfg(feature = "kafka")]
+            MessageBody::CommitOffsetsOk(body) => Some(body.in_reply_to),
+            #[cfg(feature = "kafka")]
+            MessageBody::ListCommittedOffsetsOk(body) => Some(body.in_reply_to),
+            #[cfg(feature = "kv")]
+            MessageBody::WriteOk(body) => Some(body.in_reply_to),
+            #[cfg(feature = "kv")]
+            MessageBody::CasOk(body) => Some(body.in_reply_to),
+            #[cfg(feature = "txn")]
+            MessageBody::TxnOk(body) => Some(body.in_reply_to),
+            MessageBody::Error(body) => body.in_reply_to,
+            MessageBody::Internal(body) => body.in_reply_to(),
+            #[cfg(feature = "broadcast")]
+            MessageBody::Topology(_) | MessageBody::TopologyReport(_) => None,
+            MessageBody::Init(_)
+            | MessageBody::Metrics(_)
+            | MessageBody::PeerStatus(_)
+            | MessageBody::Crash(_)
+            | MessageBody::DebugState(_)
+            | MessageBody::Read(_)
+            | MessageBody::Generate(_) => None,
+            #[cfg(feature = "kv")]
+            MessageBody::Write(_) | MessageBody::Cas(_) => None,
+            #[cfg(feature = "counter")]
+            MessageBody::Add(_) => None,
+            #[cfg(feature = "kafka")]
+            MessageBody::Send(_) => None,
+            #[cfg(feature = "kafka")]
+            MessageBody::Poll(_) => None,
+            #[cfg(feature = "kafka")]
+            MessageBody::CommitOffsets(_) => None,
+            #[cfg(feature = "kafka")]
+            MessageBody::ListCommittedOffsets(_) => None,
+            #[cfg(feature = "txn")]
+            MessageBody::Txn(_) => None,
+        }
+    }
+}
+
+/// Messages only nodes send each other, e.g. gossip, heartbeats, Raft, and two-phase commit. Their
+/// types are all prefixed `internal_`, so they never collide with a Maelstrom workload's types,
+/// and a checker reading the messages can tell them apart from client-facing ones.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type")]
+pub enum InternalBody {
+    #[cfg(feature = "broadcast")]
+    #[serde(rename = "internal_sync")]
+    Sync(SyncBody),
+    #[cfg(feature = "broadcast")]
+    #[serde(rename = "internal_sync_ok")]
+    SyncOk(SyncOkBody),
+    #[cfg(feature = "broadcast")]
+    #[serde(rename = "internal_gossip")]
+    Gossip(GossipBody),
+    #[cfg(feature = "broadcast")]
+    #[serde(rename = "internal_gossip_digest")]
+    GossipDigest(GossipDigestBody),
+    #[cfg(feature = "broadcast")]
+    #[serde(rename = "internal_gossip_pull")]
+    GossipPull(GossipPullBody),
+    #[serde(rename = "internal_heartbeat")]
+    Heartbeat(HeartbeatBody),
+    #[serde(rename = "internal_timer")]
+    Timer(TimerBody),
+    #[cfg(any(feature = "counter", feature = "kv"))]
+    #[serde(rename = "internal_replicate")]
+    Replicate(ReplicateBody),
+    #[cfg(feature = "raft")]
+    #[serde(rename = "internal_request_vote")]
+    RequestVote(RequestVoteBody),
+    #[cfg(feature = "raft")]
+    #[serde(rename = "internal_request_vote_res")]
+    RequestVoteRes(RequestVoteResBody),
+    #[cfg(feature = "raft")]
+    #[serde(rename = "internal_append_entries")]
+    AppendEntries(AppendEntriesBody),
+    #[cfg(feature = "raft")]
+    #[serde(rename = "internal_append_entries_res")]
+    AppendEntriesRes(AppendEntriesResBody),
+    #[cfg(feature = "raft")]
+    #[serde(rename = "internal_install_snapshot")]
+    InstallSnapshot(InstallSnapshotBody),
+    #[cfg(feature = "txn")]
+    #[serde(rename = "internal_txn_prepare")]
+    TxnPrepare(TxnPrepareBody),
+    #[cfg(feature = "txn")]
+    #[serde(rename = "internal_txn_prepare_ok")]
+    TxnPrepareOk(TxnPrepareOkBody),
+    #[cfg(feature = "txn")]
+    #[serde(rename = "internal_txn_commit")]
+    TxnCommit(TxnCommitBody),
+    #[cfg(feature = "txn")]
+    #[serde(rename = "internal_txn_commit_ok")]
+    TxnCommitOk(TxnCommitOkBody),
+    #[cfg(feature = "txn")]
+    #[serde(rename = "internal_txn_abort")]
+    TxnAbort(TxnAbortBody),
+    #[cfg(feature = "txn")]
+    #[serde(rename = "internal_txn_status")]
+    TxnStatus(TxnStatusBody),
+    #[cfg(feature = "txn")]
+    #[serde(rename = "internal_txn_status_ok")]
+    TxnStatusOk(TxnStatusOkBody),
+}
+
+/// Whether deserializing a body failed on its `type`, rather than on its fields.
+#[derive(Debug)]
+struct UnknownType(bool);
+
+impl fmt::Display for UnknownType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown message type: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownType {}
+
+impl de::Error for UnknownType {
+    fn custom<T: fmt::Display>(_: T) -> Self {
+        UnknownType(false)
+    }
+
+    fn unknown_variant(_: &str, _: &'static [&'static str]) -> Self {
+        UnknownType(true)
+    }
+}
+
+impl InternalBody {
+    fn from_fields<'de, D: Deserializer<'de>>(kind: &str, fields: D) -> Result<Self, D::Error> {
+        Ok(match kind {
+            #[cfg(feature = "broadcast")]
+            "internal_sync" => InternalBody::Sync(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "broadcast")]
+            "internal_sync_ok" => InternalBody::SyncOk(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "broadcast")]
+            "internal_gossip" => InternalBody::Gossip(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "broadcast")]
+            "internal_gossip_digest" =>
+            {
+                #[cfg(feature = "broadcast")]
+                InternalBody::GossipDigest(Deserialize::deserialize(fields)?)
+            }
+            #[cfg(feature = "broadcast")]
+            "internal_gossip_pull" => InternalBody::GossipPull(Deserialize::deserialize(fields)?),
+            "internal_heartbeat" => InternalBody::Heartbeat(Deserialize::deserialize(fields)?),
+            "internal_timer" => InternalBody::Timer(Deserialize::deserialize(fields)?),
+            #[cfg(any(feature = "counter", feature = "kv"))]
+            "internal_replicate" => InternalBody::Replicate(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "raft")]
+            "internal_request_vote" => InternalBody::RequestVote(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "raft")]
+            "internal_request_vote_res" => {
+                InternalBody::RequestVoteRes(Deserialize::deserialize(fields)?)
+            }
+            #[cfg(feature = "raft")]
+            "internal_append_entries" => {
+                InternalBody::AppendEntries(Deserialize::deserialize(fields)?)
+            }
+            #[cfg(feature = "raft")]
+            "internal_append_entries_res" => {
+                InternalBody::AppendEntriesRes(Deserialize::deserialize(fields)?)
+            }
+            #[cfg(feature = "raft")]
+            "internal_install_snapshot" => {
+                InternalBody::InstallSnapshot(Deserialize::deserialize(fields)?)
+            }
+            #[cfg(feature = "txn")]
+            "internal_txn_prepare" => InternalBody::TxnPrepare(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "txn")]
+            "internal_txn_prepare_ok" => {
+                InternalBody::TxnPrepareOk(Deserialize::deserialize(fields)?)
+            }
+            #[cfg(feature = "txn")]
+            "internal_txn_commit" => InternalBody::TxnCommit(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "txn")]
+            "internal_txn_commit_ok" => {
+                InternalBody::TxnCommitOk(Deserialize::deserialize(fields)?)
+            }
+            #[cfg(feature = "txn")]
+            "internal_txn_abort" => InternalBody::TxnAbort(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "txn")]
+            "internal_txn_status" => InternalBody::TxnStatus(Deserialize::deserialize(fields)?),
+            #[cfg(feature = "txn")]
+            "internal_txn_status_ok" => {
+                InternalBody::TxnStatusOk(Deserialize::deserialize(fields)?)
+            }
+            _ => return Err(de::Error::unknown_variant(kind, &[])),
+        })
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "broadcast")]
+            InternalBody::Sync(_) => "internal_sync",
+            #[cfg(feature = "broadcast")]
+            InternalBody::SyncOk(_) => "internal_sync_ok",
+            #[cfg(feature = "broadcast")]
+            InternalBody::Gossip(_) => "internal_gossip",
+            #[cfg(feature = "broadcast")]
+            InternalBody::GossipDigest(_) => "internal_gossip_digest",
+            #[cfg(feature = "broadcast")]
+            InternalBody::GossipPull(_) => "internal_gossip_pull",
+            InternalBody::Heartbeat(_) => "internal_heartbeat",
+            InternalBody::Timer(_) => "internal_timer",
+            #[cfg(any(feature = "counter", feature = "kv"))]
+            InternalBody::Replicate(_) => "internal_replicate",
+            #[cfg(feature = "raft")]
+            InternalBody::RequestVote(_) => "internal_request_vote",
+            #[cfg(feature = "raft")]
+            InternalBody::RequestVoteRes(_) => "internal_request_vote_res",
+            #[cfg(feature = "raft")]
+            InternalBody::AppendEntries(_) => "internal_append_entries",
+            #[cfg(feature = "raft")]
+            InternalBody::AppendEntriesRes(_) => "internal_append_entries_res",
+            #[cfg(feature = "raft")]
+            InternalBody::InstallSnapshot(_) => "internal_install_snapshot",
+            #[cfg(feature = "txn")]
+            InternalBody::TxnPrepare(_) => "internal_txn_prepare",
+            #[cfg(feature = "txn")]
+            InternalBody::TxnPrepareOk(_) => "internal_txn_prepare_ok",
+            #[cfg(feature = "txn")]
+            InternalBody::TxnCommit(_) => "internal_txn_commit",
+            #[cfg(feature = "txn")]
+            InternalBody::TxnCommitOk(_) => "internal_txn_commit_ok",
+            #[cfg(feature = "txn")]
+            InternalBody::TxnAbort(_) => "internal_txn_abort",
+            #[cfg(feature = "txn")]
+            InternalBody::TxnStatus(_) => "internal_txn_status",
+            #[cfg(feature = "txn")]
+            InternalBody::TxnStatusOk(_) => "internal_txn_status_ok",
+        }
+    }
+
+    pub fn msg_id(&self) -> Option<u32> {
+        match self {
+            #[cfg(feature = "broadcast")]
+            InternalBody::Sync(body) => body.msg_id,
+            #[cfg(feature = "broadcast")]
+            InternalBody::SyncOk(body) => body.msg_id,
+            #[cfg(feature = "broadcast")]
+            InternalBody::Gossip(body) => body.msg_id,
+            #[cfg(feature = "broadcast")]
+            InternalBody::GossipDigest(body) => body.msg_id,
+            #[cfg(feature = "broadcast")]
+            InternalBody::GossipPull(body) => body.msg_id,
+            InternalBody::Heartbeat(body) => body.msg_id,
+            InternalBody::Timer(body) => body.msg_id,
+            #[cfg(any(feature = "counter", feature = "kv"))]
+            InternalBody::Replicate(body) => body.msg_id,
+            #[cfg(feature = "raft")]
+            InternalBody::RequestVote(body) => body.msg_id,
+            #[cfg(feature = "raft")]
+            InternalBody::RequestVoteRes(body) => body.msg_id,
+            #[cfg(feature = "raft")]
+            InternalBody::AppendEntries(body) => body.msg_id,
+            #[cfg(feature = "raft")]
+            InternalBody::AppendEntriesRes(body) => body.msg_id,
+            #[cfg(feature = "raft")]
+            InternalBody::InstallSnapshot(body) => body.msg_id,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnPrepare(body) => body.msg_id,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnPrepareOk(body) => body.msg_id,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnCommit(body) => body.msg_id,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnCommitOk(body) => body.msg_id,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnAbort(body) => body.msg_id,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnStatus(body) => body.msg_id,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnStatusOk(body) => body.msg_id,
+        }
+    }
+
+    // Only broadcast and txn have internal replies.
+    #[cfg_attr(
+        not(any(feature = "broadcast", feature = "txn")),
+        allow(unused_variables)
+    )]
+    fn set_ids(&mut self, msg_id: Option<u32>, in_reply_to: Option<u32>) {
+        match self {
+            #[cfg(feature = "broadcast")]
+            InternalBody::Sync(body) => body.msg_id = msg_id,
+            #[cfg(feature = "broadcast")]
+            InternalBody::SyncOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            #[cfg(feature = "broadcast")]
+            InternalBody::Gossip(body) => body.msg_id = msg_id,
+            #[cfg(feature = "broadcast")]
+            InternalBody::GossipDigest(body) => body.msg_id = msg_id,
+            #[cfg(feature = "broadcast")]
+            InternalBody::GossipPull(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            InternalBody::Heartbeat(body) => body.msg_id = msg_id,
+            InternalBody::Timer(body) => body.msg_id = msg_id,
+            #[cfg(any(feature = "counter", feature = "kv"))]
+            InternalBody::Replicate(body) => body.msg_id = msg_id,
+            #[cfg(feature = "raft")]
+            InternalBody::RequestVote(body) => body.msg_id = msg_id,
+            #[cfg(feature = "raft")]
+            InternalBody::RequestVoteRes(body) => body.msg_id = msg_id,
+            #[cfg(feature = "raft")]
+            InternalBody::AppendEntries(body) => body.msg_id = msg_id,
+            #[cfg(feature = "raft")]
+            InternalBody::AppendEntriesRes(body) => body.msg_id = msg_id,
+            #[cfg(feature = "raft")]
+            InternalBody::InstallSnapshot(body) => body.msg_id = msg_id,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnPrepare(body) => body.msg_id = msg_id,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnPrepareOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            #[cfg(feature = "txn")]
+            InternalBody::TxnCommit(body) => body.msg_id = msg_id,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnCommitOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+            #[cfg(feature = "txn")]
+            InternalBody::TxnAbort(body) => body.msg_id = msg_id,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnStatus(body) => body.msg_id = msg_id,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnStatusOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or(body.in_reply_to))
+            }
+        }
+    }
+
+    // Only broadcast and txn have internal replies.
+    #[cfg_attr(
+        not(any(feature = "broadcast", feature = "txn")),
+        allow(unused_variables)
+    )]
+    fn set_reply_ids(&mut self, msg_id: Option<u32>, in_reply_to: Option<u32>) {
+        match self {
+            #[cfg(feature = "broadcast")]
+            InternalBody::SyncOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            #[cfg(feature = "broadcast")]
+            InternalBody::GossipPull(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            #[cfg(feature = "txn")]
+            InternalBody::TxnPrepareOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            #[cfg(feature = "txn")]
+            InternalBody::TxnCommitOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            #[cfg(feature = "txn")]
+            InternalBody::TxnStatusOk(body) => {
+                (body.msg_id, body.in_reply_to) = (msg_id, in_reply_to.unwrap_or_default())
+            }
+            _ => {}
+        }
+    }
+
+    pub fn in_reply_to(&self) -> Option<u32> {
+        match self {
+            #[cfg(feature = "broadcast")]
+            InternalBody::SyncOk(body) => Some(body.in_reply_to),
+            #[cfg(feature = "broadcast")]
+            InternalBody::GossipPull(body) => Some(body.in_reply_to),
+            #[cfg(feature = "txn")]
+            InternalBody::TxnPrepareOk(body) => Some(body.in_reply_to),
+            #[cfg(feature = "txn")]
+            InternalBody::TxnCommitOk(body) => Some(body.in_reply_to),
+            #[cfg(feature = "txn")]
+            InternalBody::TxnStatusOk(body) => Some(body.in_reply_to),
+            #[cfg(feature = "broadcast")]
+            InternalBody::Sync(_) | InternalBody::Gossip(_) | InternalBody::GossipDigest(_) => None,
+            InternalBody::Heartbeat(_) | InternalBody::Timer(_) => None,
+            #[cfg(any(feature = "counter", feature = "kv"))]
+            InternalBody::Replicate(_) => None,
+            #[cfg(feature = "raft")]
+            InternalBody::RequestVote(_) => None,
+            #[cfg(feature = "raft")]
+            InternalBody::RequestVoteRes(_) => None,
+            #[cfg(feature = "raft")]
+            InternalBody::AppendEntries(_) => None,
+            #[cfg(feature = "raft")]
+            InternalBody::AppendEntriesRes(_) => None,
+            #[cfg(feature = "raft")]
+            InternalBody::InstallSnapshot(_) => None,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnPrepare(_) => None,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnCommit(_) => None,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnAbort(_) => None,
+            #[cfg(feature = "txn")]
+            InternalBody::TxnStatus(_) => None,
+        }
+    }
+}
+
+/// The standard Maelstrom error codes. Codes outside the standard set are kept as `Other`.
+///
+/// See https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "u32", into = "u32")]
+pub enum ErrorCode {
+    Timeout,
+    NodeNotFound,
+    NotSupported,
+    TemporarilyUnavailable,
+    MalformedRequest,
+    Crash,
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    PreconditionFailed,
+    TxnConflict,
+    Other(u32),
+}
+
+impl From<u32> for ErrorCode {
+    fn from(code: u32) -> Self {
+        match code {
+            0 => ErrorCode::Timeout,
+            1 => ErrorCode::NodeNotFound,
+            10 => ErrorCode::NotSupported,
+            11 => ErrorCode::TemporarilyUnavailable,
+            12 => ErrorCode::MalformedRequest,
+            13 => ErrorCode::Crash,
+            14 => ErrorCode::Abort,
+            20 => ErrorCode::KeyDoesNotExist,
+            21 => ErrorCode::KeyAlreadyExists,
+            22 => ErrorCode::PreconditionFailed,
+            30 => ErrorCode::TxnConflict,
+            code => ErrorCode::Other(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Timeout => 0,
+            ErrorCode::NodeNotFound => 1,
+            ErrorCode::NotSupported => 10,
+            ErrorCode::TemporarilyUnavailable => 11,
+            ErrorCode::MalformedRequest => 12,
+            ErrorCode::Crash => 13,
+            ErrorCode::Abort => 14,
+            ErrorCode::KeyDoesNotExist => 20,
+            ErrorCode::KeyAlreadyExists => 21,
+            ErrorCode::PreconditionFailed => 22,
+            ErrorCode::TxnConflict => 30,
+            ErrorCode::Other(code) => code,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ErrorBody<'a> {
+    #[cfg_attr(feature = "schema", schemars(with = "u32"))]
+    pub code: ErrorCode,
+    #[serde(borrow)]
+    pub text: Cow<'a, str>,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: Option<u32>,
+}
+
+/// An `error` reply from a peer or a Maelstrom service, handed to whoever awaits the request it
+/// replies to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemoteError {
+    pub code: ErrorCode,
+    pub text: String,
+}
+
+impl RemoteError {
+    /// Whether the request definitely didn't take effect. Timeouts and crashes are indefinite:
+    /// the request may or may not have been applied.
+    pub fn is_definite(&self) -> bool {
+        !matches!(self.code, ErrorCode::Timeout | ErrorCode::Crash)
+    }
+}
+
+impl ErrorBody<'_> {
+    pub fn into_owned(self) -> ErrorBody<'static> {
+        ErrorBody {
+            text: Cow::Owned(self.text.into_owned()),
+            ..self
+        }
+    }
+}
+
+impl From<&ErrorBody<'_>> for RemoteError {
+    fn from(body: &ErrorBody<'_>) -> Self {
+        RemoteError {
+            code: body.code,
+            text: body.text.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error {}: {}", u32::from(self.code), self.text)
+    }
+}
+
+/// The fields a message is routed by, borrowed from the line it was read from, for the checks
+/// made before it's handed to a handler. Only strings with escapes are copied, and the rest of the
+/// body is skipped rather than parsed.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Envelope<'a> {
+    #[serde(default, borrow, deserialize_with = "borrow_optional")]
+    pub src: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    pub dest: Cow<'a, str>,
+    #[serde(borrow)]
+    pub body: EnvelopeBody<'a>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EnvelopeBody<'a> {
+    #[serde(rename = "type", borrow)]
+    pub kind: Cow<'a, str>,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: Option<u32>,
+}
+
+impl Envelope<'_> {
+    /// Copy the fields out of the line they were borrowed from, e.g. to queue them with it.
+    pub fn into_owned(self) -> Envelope<'static> {
+        Envelope {
+            src: self.src.map(|src| Cow::Owned(src.into_owned())),
+            dest: Cow::Owned(self.dest.into_owned()),
+            body: EnvelopeBody {
+                kind: Cow::Owned(self.body.kind.into_owned()),
+                msg_id: self.body.msg_id,
+                in_reply_to: self.body.in_reply_to,
+            },
+        }
+    }
+
+    /// An error from `src` in reply to this message, without parsing the rest of it. A message
+    /// without a `src` can't be replied to.
+    pub fn error_reply(
+        &self,
+        src: Option<String>,
+        code: ErrorCode,
+        text: &str,
+    ) -> Option<Message<'static>> {
+        Some(Message {
+            src,
+            dest: self.src.as_deref()?.to_string(),
+            body: MessageBody::Error(ErrorBody {
+                code,
+                text: Cow::Owned(text.to_string()),
+                msg_id: None,
+                in_reply_to: self.body.msg_id,
+            }),
+            lamport: None,
+        })
+    }
+}
+
+/// Serde only borrows a `Cow` that isn't wrapped, e.g. in an `Option`.
+fn borrow_optional<'de: 'a, 'a, D>(deserializer: D) -> Result<Option<Cow<'a, str>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<Borrowed>::deserialize(deserializer)?.map(|borrowed| borrowed.0))
+}
+
+impl Message<'static> {
+    /// Deserialize a message to keep, e.g. in a struct that's deserialized from a reader.
+    pub fn deserialize_owned<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Message::deserialize(deserializer).map(Message::into_owned)
+    }
+}
+
+impl Message<'_> {
+    /// Copy the message's strings out of the line they were borrowed from, e.g. to keep it
+    /// until it's answered.
+    pub fn into_owned(self) -> Message<'static> {
+        Message {
+            src: self.src,
+            dest: self.dest,
+            body: self.body.into_owned(),
+            lamport: self.lamport,
+        }
+    }
+
+    /// A reply from `src` to this message, with `msg_id` as its own id and this message's
+    /// `msg_id` as its `in_reply_to`.
+    pub fn reply_with<'b>(
+        &self,
+        src: Option<String>,
+        msg_id: Option<u32>,
+        mut body: MessageBody<'b>,
+    ) -> Message<'b> {
+        body.set_reply_ids(msg_id, self.body.msg_id());
+
+        Message {
+            src,
+            dest: self.src.clone().unwrap_or_default(),
+            body,
+            lamport: None,
+        }
+    }
+
+    /// An `error` from `src` in reply to this message.
+    pub fn error_reply(
+        &self,
+        src: Option<String>,
+        code: ErrorCode,
+        text: &str,
+    ) -> Message<'static> {
+        let body = MessageBody::Error(ErrorBody {
+            code,
+            text: Cow::Owned(text.to_string()),
+            msg_id: None,
+            in_reply_to: None,
+        });
+
+        self.reply_with(src, None, body)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct InitBody<'a> {
+    pub msg_id: Option<u32>,
+    #[serde(borrow)]
+    pub node_id: Cow<'a, str>,
+    pub node_ids: Option<Vec<String>>,
+}
+
+impl InitBody<'_> {
+    pub fn into_owned(self) -> InitBody<'static> {
+        InitBody {
+            node_id: Cow::Owned(self.node_id.into_owned()),
+            ..self
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct EchoBody {
+    pub msg_id: Option<u32>,
+    pub in_reply_to: Option<u32>,
+    /// Any JSON value, echoed back verbatim.
+    pub echo: serde_json::Value,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GenerateBody {
+    pub msg_id: u32,
+}
+
+#[cfg(feature = "broadcast")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BroadcastBody {
+    /// A single value, as clients send it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<BroadcastValue>,
+    /// Several values at once, as batched gossip sends them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<BroadcastValue>,
+    /// Values gossiped with their causal dependencies, with `--causal`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub causal: Vec<CausalValue>,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: Option<u32>,
+}
+
+/// A broadcast value tagged with the node a client broadcast it to and that node's vector clock
+/// at the time, which covers every value delivered there before it.
+#[cfg(feature = "broadcast")]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CausalValue {
+    pub value: BroadcastValue,
+    pub origin: String,
+    pub clock: VectorClock,
+}
+
+#[cfg(feature = "broadcast")]
+impl BroadcastBody {
+    pub fn values(&self) -> impl Iterator<Item = &BroadcastValue> {
+        self.message.iter().chain(&self.messages)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ReadBody {
+    /// The key to read, for the key-value workload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<serde_json::Value>,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "broadcast")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TopologyBody {
+    pub topology: Topology,
+    pub msg_id: Option<u32>,
+}
+
+/// A broadcast payload, which can be any JSON value. Values compare and hash structurally,
+/// without serializing them; objects keep their keys sorted, so equal objects hash their
+/// entries in the same order.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct BroadcastValue(pub serde_json::Value);
+
+impl From<u32> for BroadcastValue {
+    fn from(value: u32) -> Self {
+        BroadcastValue(value.into())
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct InitOkBody {
+    pub msg_id: Option<u32>,
+    pub in_reply_to: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct EchoOkBody {
+    pub msg_id: Option<u32>,
+    pub in_reply_to: Option<u32>,
+    pub echo: serde_json::Value,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GenerateOkBody {
+    pub msg_id: Option<u32>,
+    pub in_reply_to: Option<u32>,
+    pub id: GeneratedId,
+}
+
+/// How generated IDs are written to the wire. JSON consumers that parse numbers as doubles lose
+/// precision above 2^53, so IDs can be emitted as strings or generated in the safe range.
+///
+/// Any format but `Number` also writes the other integers clients see, e.g. Kafka offsets and
+/// Lamport times, as strings once they're above 2^53; see `IdFormat::to_json`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum IdFormat {
+    #[default]
+    Number,
+    String,
+    /// A number generated with `Layout::SAFE`, so it never exceeds 2^53.
+    Safe,
+    /// A 128-bit UUIDv7 string.
+    Uuid,
+    /// A 128-bit ULID string.
+    Ulid,
+}
+
+thread_local! {
+    /// The format of whatever `IdFormat::to_json` is serializing on this thread.
+    static WIRE_FORMAT: Cell<IdFormat> = const { Cell::new(IdFormat::Number) };
+}
+
+impl IdFormat {
+    /// The largest integer a double can represent exactly, i.e. 2^53 - 1.
+    pub const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+    /// Parse `number`, `string`, `safe`, `uuid`, or `ulid`.
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "number" => Some(IdFormat::Number),
+            "string" => Some(IdFormat::String),
+            "safe" => Some(IdFormat::Safe),
+            "uuid" => Some(IdFormat::Uuid),
+            "ulid" => Some(IdFormat::Ulid),
+            _ => None,
+        }
+    }
+
+    /// The layout of the Snowflake IDs this format is built from.
+    pub fn layout(&self) -> Layout {
+        match self {
+            IdFormat::Safe => Layout::SAFE,
+            _ => Layout::WIDE,
+        }
+    }
+
+    /// `value` as JSON, with its wide integer fields written in this format. Serde hands fields
+    /// no context, so the format is set on the thread while `value` is serialized.
+    pub fn to_json<T: Serialize + ?Sized>(self, value: &T) -> serde_json::Result<String> {
+        let previous = WIRE_FORMAT.replace(self);
+        let json = serde_json::to_string(value);
+        WIRE_FORMAT.set(previous);
+
+        json
+    }
+
+    /// Whether the wide integer fields being serialized are written as strings once they're
+    /// above 2^53.
+    fn quotes_wide_integers() -> bool {
+        WIRE_FORMAT.get() != IdFormat::Number
+    }
+
+    /// `id`, generated with this format's `layout`, as it's written to the wire.
+    pub fn format(&self, id: u64) -> GeneratedId {
+        match self {
+            IdFormat::Number | IdFormat::Safe => GeneratedId::Number(id),
+            IdFormat::String => GeneratedId::String(id.to_string()),
+            IdFormat::Uuid => GeneratedId::String(id128::uuid_v7(id)),
+            IdFormat::Ulid => GeneratedId::String(id128::ulid(id)),
+        }
+    }
+}
+
+/// `serde(with)` helpers for the `u64` fields clients read. Serialized with any `IdFormat` but
+/// `Number`, a value above 2^53 is written as a string; either form is read back.
+mod wide {
+    #[cfg(feature = "kafka")]
+    use std::collections::HashMap;
+    use std::fmt;
+
+    use serde::de::{self, Deserializer, Visitor};
+    use serde::ser::Serializer;
+    use serde::{Deserialize, Serialize};
+
+    use super::IdFormat;
+
+    struct Wide(u64);
+
+    impl Serialize for Wide {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self.0 > IdFormat::MAX_SAFE_INTEGER && IdFormat::quotes_wide_integers() {
+                true => serializer.collect_str(&self.0),
+                false => serializer.serialize_u64(self.0),
+            }
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Wide {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct WideVisitor;
+
+            impl Visitor<'_> for WideVisitor {
+                type Value = Wide;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "an unsigned integer, or one as a string")
+                }
+
+                fn visit_u64<E: de::Error>(self, value: u64) -> Result<Wide, E> {
+                    Ok(Wide(value))
+                }
+
+                fn visit_i64<E: de::Error>(self, value: i64) -> Result<Wide, E> {
+                    u64::try_from(value).map(Wide).map_err(E::custom)
+                }
+
+                fn visit_str<E: de::Error>(self, value: &str) -> Result<Wide, E> {
+                    value.parse().map(Wide).map_err(E::custom)
+                }
+            }
+
+            deserializer.deserialize_any(WideVisitor)
+        }
+    }
+
+    #[cfg(feature = "kafka")]
+    pub fn serialize<S: Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        Wide(*value).serialize(serializer)
+    }
+
+    #[cfg(feature = "kafka")]
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        Wide::deserialize(deserializer).map(|wide| wide.0)
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            value: &Option<u64>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            value.map(Wide).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<u64>, D::Error> {
+            Option::<Wide>::deserialize(deserializer).map(|wide| wide.map(|wide| wide.0))
+        }
+    }
+
+    #[cfg(feature = "kafka")]
+    pub mod map {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            values: &HashMap<String, u64>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(values.iter().map(|(key, value)| (key, Wide(*value))))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<HashMap<String, u64>, D::Error> {
+            let values = HashMap::<String, Wide>::deserialize(deserializer)?;
+
+            Ok(values
+                .into_iter()
+                .map(|(key, wide)| (key, wide.0))
+                .collect())
+        }
+    }
+
+    /// Each key's `[offset, msg]` pairs.
+    #[cfg(feature = "kafka")]
+    pub mod pairs {
+        use super::*;
+
+        type Pairs = HashMap<String, Vec<(u64, serde_json::Value)>>;
+
+        pub fn serialize<S: Serializer>(values: &Pairs, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(values.iter().map(|(key, pairs)| {
+                let pairs: Vec<_> = pairs
+                    .iter()
+                    .map(|(offset, msg)| (Wide(*offset), msg))
+                    .collect();
+                (key, pairs)
+            }))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pairs, D::Error> {
+            let values =
+                HashMap::<String, Vec<(Wide, serde_json::Value)>>::deserialize(deserializer)?;
+
+            Ok(values
+                .into_iter()
+                .map(|(key, pairs)| {
+                    let pairs = pairs.into_iter().map(|(wide, msg)| (wide.0, msg)).collect();
+                    (key, pairs)
+                })
+                .collect())
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum GeneratedId {
+    Number(u64),
+    String(String),
+}
+
+#[cfg(feature = "broadcast")]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BroadcastOkBody {
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+/// Anti-entropy: every broadcast value the sender knows.
+#[cfg(feature = "broadcast")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SyncBody {
+    #[cfg_attr(feature = "schema", schemars(with = "HashSet<BroadcastValue>"))]
+    pub messages: BroadcastValues,
+    pub msg_id: Option<u32>,
+}
+
+/// The values the receiver of a `sync` knows that its sender didn't.
+#[cfg(feature = "broadcast")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SyncOkBody {
+    pub messages: Vec<BroadcastValue>,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+/// Broadcast values pushed to a peer during a digest exchange; no reply is expected.
+#[cfg(feature = "broadcast")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GossipBody {
+    pub messages: Vec<BroadcastValue>,
+    pub msg_id: Option<u32>,
+}
+
+/// Anti-entropy for large sets: a fingerprint of each bucket of the sender's values, in place
+/// of the values themselves.
+#[cfg(feature = "broadcast")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GossipDigestBody {
+    pub digest: Vec<u64>,
+    pub msg_id: Option<u32>,
+}
+
+/// The buckets whose fingerprints differ, for the sender of the digest to push its values in,
+/// along with the receiver's values in them.
+#[cfg(feature = "broadcast")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GossipPullBody {
+    pub buckets: Vec<usize>,
+    pub messages: Vec<BroadcastValue>,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ReadOkBody {
+    /// The broadcast values, for the broadcast workload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<HashSet<BroadcastValue>>"))]
+    pub messages: Option<BroadcastValues>,
+    /// The counter's value, or the key's value for the key-value workload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+#[cfg(feature = "broadcast")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TopologyOkBody {
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+/// A debug request for a summary of the overlay the node was given.
+#[cfg(feature = "broadcast")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TopologyReportBody {
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "broadcast")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TopologyReportOkBody {
+    #[serde(flatten)]
+    pub report: TopologyReport,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+/// A debug request for the node's counters, gauges, and handler latencies.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MetricsBody {
+    pub msg_id: Option<u32>,
+}
+
+/// Sent to every other node on the heartbeat interval, for failure detection. Not replied to.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct HeartbeatBody {
+    pub msg_id: Option<u32>,
+}
+
+/// A timer firing, sent by a node to itself; see `Timers`. Not replied to.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TimerBody {
+    pub event: TimerEvent,
+    pub msg_id: Option<u32>,
+}
+
+/// A debug request for the health of each peer that has timed out.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PeerStatusBody {
+    pub msg_id: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PeerStatusOkBody {
+    pub peers: BTreeMap<String, PeerReport>,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+/// An admin request to exit, or to drop the node's state; see `admin`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CrashBody {
+    #[serde(default)]
+    pub mode: CrashMode,
+    pub msg_id: Option<u32>,
+}
+
+/// Sent once the node's state has been dropped. An exit isn't replied to.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CrashOkBody {
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+/// A debug request for what the node thinks is happening, e.g. when a run wedges.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DebugStateBody {
+    pub msg_id: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DebugStateOkBody {
+    pub node_id: Option<String>,
+    pub node_ids: Vec<String>,
+    /// The neighbors from the last `topology` message.
+    #[cfg(feature = "broadcast")]
+    pub topology: Vec<String>,
+    /// How many broadcast values the node has.
+    #[cfg(feature = "broadcast")]
+    pub messages: usize,
+    /// Messages to peers waiting to be acknowledged, and retried until they are.
+    pub pending_retries: usize,
+    /// Requests waiting for replies.
+    pub callbacks: usize,
+    /// The node's settings, keyed as in the config file; see `Config::settings`.
+    pub config: BTreeMap<String, serde_json::Value>,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MetricsOkBody {
+    #[serde(flatten)]
+    pub report: MetricsReport,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+#[cfg(feature = "counter")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AddBody {
+    pub delta: i64,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "counter")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AddOkBody {
+    pub msg_id: Option<u32>,
+    pub in_reply_to: Option<u32>,
+}
+
+/// A node's counter and LWW map state, sent periodically to every other node. It isn't
+/// acknowledged; a lost state is superseded by the next one.
+#[cfg(any(feature = "counter", feature = "kv"))]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ReplicateBody {
+    #[cfg(feature = "counter")]
+    #[serde(default)]
+    pub increments: HashMap<String, u64>,
+    #[cfg(feature = "counter")]
+    #[serde(default)]
+    pub decrements: HashMap<String, u64>,
+    /// The kv workload's entries, in its `lww` mode.
+    #[cfg(feature = "kv")]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub entries: BTreeMap<String, LwwRegister<serde_json::Value>>,
+    /// The sender's clock when it took the entries, in the `session` mode.
+    #[cfg(feature = "kv")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<HybridTimestamp>,
+    /// The session versions the sender knows of, in the `session` mode.
+    #[cfg(feature = "kv")]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sessions: BTreeMap<String, SessionVersion>,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "kafka")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SendBody<'a> {
+    #[serde(borrow)]
+    pub key: Cow<'a, str>,
+    pub msg: serde_json::Value,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "kafka")]
+impl SendBody<'_> {
+    pub fn into_owned(self) -> SendBody<'static> {
+        SendBody {
+            key: Cow::Owned(self.key.into_owned()),
+            ..self
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SendOkBody {
+    #[serde(with = "wide")]
+    #[cfg_attr(feature = "schema", schemars(with = "u64"))]
+    pub offset: u64,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+#[cfg(feature = "kafka")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PollBody {
+    #[serde(with = "wide::map")]
+    #[cfg_attr(feature = "schema", schemars(with = "HashMap<String, u64>"))]
+    pub offsets: HashMap<String, u64>,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "kafka")]
+/// Each key's messages as `[offset, msg]` pairs.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PollOkBody {
+    #[serde(with = "wide::pairs")]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "HashMap<String, Vec<(u64, serde_json::Value)>>")
+    )]
+    pub msgs: HashMap<String, Vec<(u64, serde_json::Value)>>,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+#[cfg(feature = "kafka")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CommitOffsetsBody {
+    #[serde(with = "wide::map")]
+    #[cfg_attr(feature = "schema", schemars(with = "HashMap<String, u64>"))]
+    pub offsets: HashMap<String, u64>,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "kafka")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CommitOffsetsOkBody {
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+#[cfg(feature = "kafka")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ListCommittedOffsetsBody {
+    pub keys: Vec<String>,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "kafka")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ListCommittedOffsetsOkBody {
+    #[serde(with = "wide::map")]
+    #[cfg_attr(feature = "schema", schemars(with = "HashMap<String, u64>"))]
+    pub offsets: HashMap<String, u64>,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+#[cfg(feature = "kv")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WriteBody {
+    pub key: serde_json::Value,
+    pub value: serde_json::Value,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "kv")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WriteOkBody {
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+#[cfg(feature = "kv")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CasBody {
+    pub key: serde_json::Value,
+    pub from: serde_json::Value,
+    pub to: serde_json::Value,
+    #[serde(default)]
+    pub create_if_not_exists: bool,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "kv")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CasOkBody {
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+#[cfg(feature = "txn")]
+/// A micro-operation of a transaction: `["r", key, null]` reads the key, and `["w", key, value]`
+/// writes it. In `txn_ok`, reads carry the value read.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TxnOp(pub String, pub serde_json::Value, pub serde_json::Value);
+
+#[cfg(feature = "txn")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TxnBody {
+    pub txn: Vec<TxnOp>,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "txn")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TxnOkBody {
+    pub txn: Vec<TxnOp>,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+#[cfg(feature = "txn")]
+/// Asks a participant to lock the keys of `txn` it owns and vote on committing. The reply has
+/// the reads filled in.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TxnPrepareBody {
+    pub txn_id: String,
+    pub txn: Vec<TxnOp>,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "txn")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TxnPrepareOkBody {
+    pub txn_id: String,
+    pub txn: Vec<TxnOp>,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+#[cfg(feature = "txn")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TxnCommitBody {
+    pub txn_id: String,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "txn")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TxnCommitOkBody {
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+#[cfg(feature = "txn")]
+/// Aborts aren't acknowledged: a participant that misses one finds out by asking.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TxnAbortBody {
+    pub txn_id: String,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "txn")]
+/// Asks the coordinator how a transaction the participant prepared ended.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TxnStatusBody {
+    pub txn_id: String,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "txn")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TxnStatusOkBody {
+    pub committed: bool,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: u32,
+}
+
+#[cfg(feature = "raft")]
+/// A candidate asking for a node's vote in `term`. Raft's messages are answered with messages of
+/// their own type rather than replies, so a late answer is still counted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RequestVoteBody {
+    pub term: u64,
+    pub last_log_index: u64,
+    pub last_log_term: u64,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "raft")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RequestVoteResBody {
+    pub term: u64,
+    pub vote_granted: bool,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "raft")]
+/// The leader's log from `prev_log_index + 1`, or a heartbeat when `entries` is empty.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AppendEntriesBody {
+    pub term: u64,
+    pub prev_log_index: u64,
+    pub prev_log_term: u64,
+    pub entries: Vec<LogEntry>,
+    pub leader_commit: u64,
+    /// Numbers the leader's rounds of `append_entries`, so it can tell which its followers have
+    /// acknowledged.
+    pub round: u64,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "raft")]
+/// The leader's snapshot, for a follower that's behind the start of its log. The follower
+/// answers with `append_entries_res`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct InstallSnapshotBody {
+    pub term: u64,
+    pub last_included_index: u64,
+    pub last_included_term: u64,
+    pub data: serde_json::Value,
+    pub round: u64,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(feature = "raft")]
+/// On success, `match_index` is the last index the follower's log now agrees on; otherwise it's
+/// where the leader should try again from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AppendEntriesResBody {
+    pub term: u64,
+    pub success: bool,
+    pub match_index: u64,
+    pub round: u64,
+    pub msg_id: Option<u32>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats_generated_ids() {
+        let id = u64::MAX;
+
+        assert_eq!(IdFormat::Number.format(id), GeneratedId::Number(id));
+        assert_eq!(
+            serde_json::to_string(&IdFormat::String.format(id)).unwrap(),
+            format!("\"{}\"", id)
+        );
+        assert_eq!(
+            IdFormat::Safe.format(IdFormat::MAX_SAFE_INTEGER),
+            GeneratedId::Number(IdFormat::MAX_SAFE_INTEGER)
+        );
+        assert_eq!(IdFormat::Safe.layout(), Layout::SAFE);
+        assert!(matches!(
+            IdFormat::parse("ulid").unwrap().format(1 << 22),
+            GeneratedId::String(ulid) if ulid.len() == 26
+        ));
+    }
+
+    #[test]
+    fn dispatches_on_the_type_field() {
+        let read: Message = serde_json::from_str(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "read", "msg_id": 1}}"#,
+        )
+        .unwrap();
+        let generate: Message = serde_json::from_str(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "generate", "msg_id": 1}}"#,
+        )
+        .unwrap();
+
+        assert!(matches!(read.body, MessageBody::Read(_)));
+        assert!(matches!(generate.body, MessageBody::Generate(_)));
+        assert!(serde_json::from_str::<Message>(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "unknown", "msg_id": 1}}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "broadcast")]
+    fn namespaces_node_to_node_types() {
+        let sync: Message = serde_json::from_str(
+            r#"{"src": "n2", "dest": "n1", "body": {"type": "internal_sync", "messages": [1]}}"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            sync.body,
+            MessageBody::Internal(InternalBody::Sync(_))
+        ));
+        assert_eq!(sync.body.kind(), "internal_sync");
+        assert!(serde_json::to_string(&sync)
+            .unwrap()
+            .contains(r#""type":"internal_sync""#));
+        assert!(serde_json::from_str::<Message>(
+            r#"{"src": "n2", "dest": "n1", "body": {"type": "sync", "messages": [1]}}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "broadcast")]
+    fn writes_the_type_field() {
+        let message = Message {
+            src: Some("n1".to_string()),
+            dest: "n2".to_string(),
+            body: MessageBody::BroadcastOk(BroadcastOkBody {
+                msg_id: Some(2),
+                in_reply_to: 1,
+            }),
+            lamport: None,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"src":"n1","dest":"n2","body":{"type":"broadcast_ok","msg_id":2,"in_reply_to":1}}"#
+        );
+    }
+
+    #[test]
+    fn replies_to_the_sender() {
+        let request: Message = serde_json::from_str(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": "hi", "msg_id": 3}}"#,
+        )
+        .unwrap();
+
+        let body = MessageBody::EchoOk(EchoOkBody {
+            echo: "hi".into(),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            serde_json::to_string(&request.reply_with(Some("n1".to_string()), Some(9), body))
+                .unwrap(),
+            r#"{"src":"n1","dest":"c1","body":{"type":"echo_ok","msg_id":9,"in_reply_to":3,"echo":"hi"}}"#
+        );
+    }
+
+    #[cfg(feature = "kafka")]
+    #[test]
+    fn reads_wide_offsets_as_numbers_or_strings() {
+        let json = r#"{"type":"poll_ok","msgs":{"k":[[9007199254740993,"x"]]},"msg_id":1,"in_reply_to":2}"#;
+        let quoted = r#"{"type":"poll_ok","msgs":{"k":[["9007199254740993","x"]]},"msg_id":1,"in_reply_to":2}"#;
+
+        for json in [json, quoted] {
+            let MessageBody::PollOk(body) = serde_json::from_str(json).unwrap() else {
+                panic!("Expected a poll_ok body.");
+            };
+            assert_eq!(body.msgs["k"][0].0, (1 << 53) + 1);
+        }
+    }
+
+    #[cfg(feature = "kafka")]
+    #[test]
+    fn quotes_wide_offsets_in_any_format_but_number() {
+        let body = |offset| SendOkBody {
+            offset,
+            msg_id: None,
+            in_reply_to: 1,
+        };
+
+        let wide = IdFormat::String.to_json(&body((1 << 53) + 1)).unwrap();
+        let narrow = IdFormat::String.to_json(&body(5)).unwrap();
+
+        assert!(wide.contains(r#""offset":"9007199254740993""#));
+        assert!(narrow.contains(r#""offset":5"#));
+        assert!(IdFormat::Number
+            .to_json(&body((1 << 53) + 1))
+            .unwrap()
+            .contains(r#""offset":9007199254740993"#));
+        assert!(serde_json::to_string(&body((1 << 53) + 1))
+            .unwrap()
+            .contains(r#""offset":9007199254740993"#));
+    }
+
+    #[test]
+    #[cfg(feature = "broadcast")]
+    fn carries_the_lamport_time_in_the_body() {
+        let json = r#"{"src":"n1","dest":"n2","body":{"type":"broadcast_ok","msg_id":2,"in_reply_to":1,"lamport":7}}"#;
+        let message = serde_json::from_str::<Message>(json).unwrap();
+
+        assert_eq!(message.lamport, Some(7));
+        assert_eq!(serde_json::to_string(&message).unwrap(), json);
+    }
+
+    #[test]
+    fn reads_fields_on_either_side_of_the_type() {
+        let json = r#"{"src":"c1","dest":"n1","body":{"lamport":3,"code":20,"type":"error","text":"gone","in_reply_to":1}}"#;
+        let message = serde_json::from_str::<Message>(json).unwrap();
+
+        let MessageBody::Error(body) = &message.body else {
+            panic!("{message:?}");
+        };
+        assert!(matches!(body.text, Cow::Borrowed("gone")));
+        assert_eq!(body.code, ErrorCode::KeyDoesNotExist);
+        assert_eq!(message.lamport, Some(3));
+
+        // Escaped strings can't be borrowed.
+        let json = r#"{"src":"c1","dest":"n1","body":{"type":"error","code":20,"text":"\"gone\"","lamport":4}}"#;
+        let message = serde_json::from_str::<Message>(json).unwrap().into_owned();
+
+        assert!(matches!(&message.body, MessageBody::Error(body) if body.text == "\"gone\""));
+        assert_eq!(message.lamport, Some(4));
+    }
+
+    #[test]
+    #[cfg(feature = "broadcast")]
+    fn broadcasts_any_json_value() {
+        let message: Message = serde_json::from_str(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "broadcast", "message": {"b": 1.5, "a": "x"}, "msg_id": 1}}"#,
+        )
+        .unwrap();
+
+        let MessageBody::Broadcast(body) = message.body else {
+            panic!("Expected a broadcast body.");
+        };
+
+        let value = body.message.unwrap();
+        let same = BroadcastValue(serde_json::json!({"a": "x", "b": 1.5}));
+
+        assert_eq!(value, same);
+        assert_eq!(HashSet::from([value, same]).len(), 1);
+    }
+}
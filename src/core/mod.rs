@@ -0,0 +1,13 @@
+//! The protocol and the state transitions it drives: parsing and writing messages, the broadcast
+//! store, and the CRDT merges behind the counter and the kv workload's `lww` mode.
+//!
+//! Nothing here depends on tokio, TLS, or the network, and it builds without the `runtime`
+//! feature, e.g. for `wasm32-unknown-unknown`, so a recorded run can be stepped through with the
+//! same code the node runs.
+
+#[cfg(feature = "counter")]
+pub mod counter;
+#[cfg(feature = "kv")]
+pub mod lww;
+pub mod message;
+pub mod state;
@@ -0,0 +1,353 @@
+//! Reading input into messages, and the broadcast store.
+//!
+//! Nothing in this module waits on the clock or the node's stdin/stdout, so it can be driven step
+//! by step, e.g. to replay a recorded run using the same transitions as the binary. Storage for
+//! overflowing values is injected through the `Overflow` trait.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::error;
+
+use crate::core::message::{BroadcastValue, Envelope, Message, MessageBody};
+use crate::error::NodeError;
+
+/// Split input into its JSON documents, whether they're on separate lines or concatenated. From
+/// the first document that doesn't parse, the rest of the input is returned as one document, so
+/// the error can be reported.
+pub fn documents(input: &str) -> Vec<&str> {
+    let mut stream = serde_json::Deserializer::from_str(input).into_iter::<serde::de::IgnoredAny>();
+    let mut documents = vec![];
+
+    loop {
+        let start = stream.byte_offset();
+
+        match stream.next() {
+            Some(Ok(_)) => documents.push(input[start..stream.byte_offset()].trim()),
+            Some(Err(_)) => {
+                documents.push(input[start..].trim());
+                break;
+            }
+            None => break,
+        }
+    }
+
+    documents
+}
+
+/// Parse a single line of input into a message. A message whose `type` the binary has no body
+/// for, e.g. one of a workload it wasn't built with, is unsupported rather than malformed.
+pub fn parse(line: &str) -> Result<Message<'_>, NodeError> {
+    serde_json::from_str::<Message>(line)
+        .map_err(|err| parse_error(err, envelope(line).ok().as_ref()))
+}
+
+/// The error for a message that didn't parse, given its envelope if that could be read.
+pub fn parse_error(err: serde_json::Error, envelope: Option<&Envelope>) -> NodeError {
+    match envelope {
+        Some(envelope) if !MessageBody::is_known(&envelope.body.kind) => {
+            NodeError::UnsupportedType(envelope.body.kind.to_string())
+        }
+        _ => NodeError::Parse(err.to_string()),
+    }
+}
+
+/// Read only a line's routing fields, without copying them out of it.
+pub fn envelope(line: &str) -> Result<Envelope<'_>, NodeError> {
+    serde_json::from_str::<Envelope>(line).map_err(|err| NodeError::Parse(err.to_string()))
+}
+
+/// Values read back from an overflow, one at a time.
+pub type Spilled = Box<dyn Iterator<Item = io::Result<BroadcastValue>> + Send>;
+
+/// Reads back the values an overflow held when the reader was made, even after more are
+/// appended.
+pub type SpillReader = Arc<dyn Fn() -> io::Result<Spilled> + Send + Sync>;
+
+/// Storage for broadcast values moved out of memory once the store grows past its limit, e.g. a
+/// segment on disk.
+pub trait Overflow: fmt::Debug + Send + Sync {
+    fn append(&mut self, messages: &HashSet<BroadcastValue>) -> io::Result<()>;
+    fn contains(&self, message: &BroadcastValue) -> io::Result<bool>;
+    fn reader(&self) -> SpillReader;
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The set of broadcast values the node has seen.
+///
+/// The set is copy-on-write: taking its `values` is a reference count increment, and the next
+/// insert only copies the set if they're still alive.
+///
+/// With an overflow, the in-memory set is moved to the overflow whenever it holds more than
+/// `limit` values, so memory stays bounded while reads and dedupe still see every value.
+#[derive(Debug, Default)]
+pub struct BroadcastStore {
+    messages: Arc<HashSet<BroadcastValue>>,
+    limit: usize,
+    overflow: Option<Box<dyn Overflow>>,
+}
+
+impl BroadcastStore {
+    pub fn with_overflow(limit: usize, overflow: Box<dyn Overflow>) -> Self {
+        BroadcastStore {
+            messages: Arc::default(),
+            limit,
+            overflow: Some(overflow),
+        }
+    }
+
+    /// Record a value, returning `true` if it hadn't been seen before.
+    pub fn insert(&mut self, message: BroadcastValue) -> bool {
+        if self.contains(&message) {
+            return false;
+        }
+
+        Arc::make_mut(&mut self.messages).insert(message);
+
+        if let Some(overflow) = &mut self.overflow {
+            if self.messages.len() > self.limit {
+                match overflow.append(&self.messages) {
+                    Ok(()) => self.messages = Arc::default(),
+                    // Keep the values in memory; the next insert tries to move them again.
+                    Err(err) => error!("Unable to move values to the overflow: {:?}", err),
+                }
+            }
+        }
+
+        true
+    }
+
+    pub fn contains(&self, message: &BroadcastValue) -> bool {
+        if self.messages.contains(message) {
+            return true;
+        }
+
+        let Some(overflow) = &self.overflow else {
+            return false;
+        };
+
+        overflow.contains(message).unwrap_or_else(|err| {
+            error!("Unable to read the overflow: {:?}", err);
+            false
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len() + self.overflow.as_ref().map_or(0, |overflow| overflow.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop the values held in memory. The overflow's are kept, as they would be through a
+    /// crash.
+    pub fn forget_in_memory(&mut self) {
+        self.messages = Arc::default();
+    }
+
+    /// The number of values held in memory, i.e. excluding the overflow.
+    pub fn len_in_memory(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Every value as of now. The overflow isn't loaded: its values are read back one at a time
+    /// as they're iterated, e.g. as a reply carrying them is serialized.
+    pub fn values(&self) -> BroadcastValues {
+        BroadcastValues {
+            messages: self.messages.clone(),
+            spilled: self
+                .overflow
+                .as_ref()
+                .filter(|overflow| !overflow.is_empty())
+                .map(|overflow| overflow.reader()),
+        }
+    }
+}
+
+/// The broadcast values in a `read_ok` or `sync`: the in-memory set at the time they were taken,
+/// and a reader for the overflow's. Deserialized values are all in memory.
+#[derive(Clone, Default)]
+pub struct BroadcastValues {
+    messages: Arc<HashSet<BroadcastValue>>,
+    spilled: Option<SpillReader>,
+}
+
+impl BroadcastValues {
+    fn spilled(reader: &SpillReader) -> impl Iterator<Item = BroadcastValue> {
+        reader()
+            .unwrap_or_else(|err| {
+                error!("Unable to read the overflow: {:?}", err);
+                Box::new(std::iter::empty())
+            })
+            .map_while(|value| {
+                value
+                    .map_err(|err| error!("Unable to read the overflow: {:?}", err))
+                    .ok()
+            })
+    }
+
+    /// Whether `value` is among the values, reading the overflow's back to check if it isn't
+    /// in memory.
+    pub fn contains(&self, value: &BroadcastValue) -> bool {
+        self.messages.contains(value)
+            || self
+                .spilled
+                .iter()
+                .flat_map(BroadcastValues::spilled)
+                .any(|spilled| spilled == *value)
+    }
+
+    /// Every value, reading the overflow's back as they're reached.
+    pub fn iter(&self) -> impl Iterator<Item = BroadcastValue> + '_ {
+        self.messages.iter().cloned().chain(
+            self.spilled
+                .iter()
+                .flat_map(BroadcastValues::spilled)
+                .filter(|value| !self.messages.contains(value)),
+        )
+    }
+}
+
+impl<V: Into<BroadcastValue>, I: IntoIterator<Item = V>> From<I> for BroadcastValues {
+    fn from(messages: I) -> Self {
+        BroadcastValues {
+            messages: Arc::new(messages.into_iter().map(Into::into).collect()),
+            spilled: None,
+        }
+    }
+}
+
+impl fmt::Debug for BroadcastValues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BroadcastValues")
+            .field("messages", &self.messages)
+            .field("spilled", &self.spilled.is_some())
+            .finish()
+    }
+}
+
+impl Serialize for BroadcastValues {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for BroadcastValues {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(BroadcastValues {
+            messages: Arc::new(HashSet::deserialize(deserializer)?),
+            spilled: None,
+        })
+    }
+}
+
+impl<V: Into<BroadcastValue>, I: IntoIterator<Item = V>> From<I> for BroadcastStore {
+    fn from(messages: I) -> Self {
+        BroadcastStore {
+            messages: Arc::new(messages.into_iter().map(Into::into).collect()),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn splits_concatenated_documents() {
+        let input = concat!(
+            r#"{"src": "c1", "body": {"type": "read", "msg_id": 1}}"#,
+            r#"{"src": "c1", "body": {"type": "read", "msg_id": 2}}"#,
+            "\n",
+            r#" {"src": "c1", "body": {"type": "read", "msg_id": 3}}"#,
+            "\n{\"src\": oops}\n",
+        );
+
+        let documents = documents(input);
+
+        assert_eq!(documents.len(), 4);
+        assert!(documents[..3]
+            .iter()
+            .all(|document| document.starts_with('{')));
+        assert_eq!(documents[3], r#"{"src": oops}"#);
+        assert!(super::documents(" \n").is_empty());
+    }
+
+    #[test]
+    fn reads_the_envelope_in_place() {
+        use std::borrow::Cow;
+
+        let line = r#"{"src": "c1", "dest": "n\u0031", "body": {"type": "txn", "msg_id": 4, "txn": [["r", 1, null]]}}"#;
+        let envelope = envelope(line).unwrap();
+
+        assert!(matches!(envelope.src, Some(Cow::Borrowed("c1"))));
+        // Escapes have to be decoded, so only they are copied.
+        assert!(matches!(envelope.dest, Cow::Owned(ref dest) if dest == "n1"));
+        assert_eq!(envelope.body.kind, "txn");
+        assert_eq!(envelope.body.msg_id, Some(4));
+        assert!(super::envelope(r#"{"src": "c1", "body": {}}"#).is_err());
+    }
+
+    #[test]
+    fn values_are_unaffected_by_later_inserts() {
+        let mut store = BroadcastStore::default();
+
+        assert!(store.insert(1.into()));
+        assert!(!store.insert(1.into()));
+
+        let values = store.values();
+        store.insert(BroadcastValue(serde_json::json!({"nested": [2]})));
+
+        assert_eq!(values.iter().collect::<Vec<_>>(), [1.into()]);
+        assert!(values.contains(&1.into()));
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn replies_to_unparseable_messages_with_a_malformed_request_error() {
+        let line = r#"{"src": "c1", "dest": "n1", "body": {"type": "init", "msg_id": 7}}"#;
+        let err = parse(line).unwrap_err();
+
+        let reply = envelope(line)
+            .unwrap()
+            .error_reply(Some("n1".to_string()), err.code(), &err.to_string())
+            .unwrap();
+
+        assert_eq!(reply.dest, "c1");
+        assert!(serde_json::to_string(&reply)
+            .unwrap()
+            .contains(r#""type":"error","code":12,"#));
+        assert!(serde_json::to_string(&reply)
+            .unwrap()
+            .contains(r#""in_reply_to":7"#));
+    }
+
+    #[test]
+    fn replies_to_unknown_types_as_unsupported() {
+        let line = r#"{"src": "c1", "dest": "n1", "body": {"type": "bogus", "msg_id": 7}}"#;
+        let err = parse(line).unwrap_err();
+
+        assert_eq!(err, NodeError::UnsupportedType("bogus".to_string()));
+        assert_eq!(err.code(), crate::core::message::ErrorCode::NotSupported);
+    }
+
+    #[test]
+    #[cfg(not(feature = "txn"))]
+    fn replies_to_requests_for_unbuilt_workloads_as_unsupported() {
+        let line =
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "txn", "msg_id": 7, "txn": []}}"#;
+        let err = parse(line).unwrap_err();
+
+        assert_eq!(err, NodeError::UnsupportedType("txn".to_string()));
+        assert_eq!(err.code(), crate::core::message::ErrorCode::NotSupported);
+    }
+}
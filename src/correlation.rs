@@ -0,0 +1,144 @@
+//! The requests waiting for replies, by the `msg_id` they were sent with, each with the channel
+//! its reply is handed to.
+//!
+//! A request can be awaited past its deadline: gossip awaits its acknowledgement again after
+//! every timeout until the neighbor acknowledges it. So that a reply that never comes doesn't
+//! hold an entry forever, entries are evicted once they've waited `ttl` since they were first
+//! awaited, and the oldest are evicted once there are more than `cap`.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+use crate::core::message::{Message, RemoteError};
+
+/// Where the reply to a request, or the error a peer or service replied with instead, goes.
+pub type ReplySender = oneshot::Sender<Result<Message<'static>, RemoteError>>;
+
+#[derive(Debug)]
+pub struct Correlations {
+    waiting: HashMap<u32, ReplySender>,
+    /// When each `msg_id` was first awaited. Kept when it's answered with an error, so a request
+    /// awaited again keeps its age; dropped with the TTL if it isn't.
+    since: HashMap<u32, Instant>,
+    pub ttl: Duration,
+    pub cap: usize,
+}
+
+impl Correlations {
+    pub fn new(ttl: Duration, cap: usize) -> Self {
+        Correlations {
+            waiting: HashMap::new(),
+            since: HashMap::new(),
+            ttl,
+            cap,
+        }
+    }
+
+    pub fn insert(&mut self, msg_id: u32, reply_tx: ReplySender) {
+        self.since.entry(msg_id).or_insert_with(Instant::now);
+        self.waiting.insert(msg_id, reply_tx);
+    }
+
+    /// Take the sender for `msg_id` to answer it with an error, keeping its age.
+    pub fn take(&mut self, msg_id: u32) -> Option<ReplySender> {
+        self.waiting.remove(&msg_id)
+    }
+
+    pub fn remove(&mut self, msg_id: u32) -> Option<ReplySender> {
+        self.since.remove(&msg_id);
+        self.waiting.remove(&msg_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.waiting.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiting.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &u32> {
+        self.waiting.keys()
+    }
+
+    /// Remove the entries older than the TTL, then the oldest while there are more than the cap,
+    /// returning their `msg_id`s.
+    pub fn evict(&mut self, now: Instant) -> Vec<u32> {
+        let ttl = self.ttl;
+        let stale = |since: &Instant| now.saturating_duration_since(*since) >= ttl;
+
+        let mut evicted: Vec<u32> = self
+            .waiting
+            .keys()
+            .filter(|msg_id| self.since.get(msg_id).is_some_and(stale))
+            .copied()
+            .collect();
+
+        let excess = (self.waiting.len() - evicted.len()).saturating_sub(self.cap);
+
+        if excess > 0 {
+            let mut oldest: Vec<(Instant, u32)> = self
+                .waiting
+                .keys()
+                .filter(|msg_id| !evicted.contains(msg_id))
+                .map(|msg_id| (self.since.get(msg_id).copied().unwrap_or(now), *msg_id))
+                .collect();
+            oldest.sort();
+
+            evicted.extend(oldest.into_iter().take(excess).map(|(_, msg_id)| msg_id));
+        }
+
+        evicted.sort();
+
+        for msg_id in &evicted {
+            self.remove(*msg_id);
+        }
+
+        // The ages of requests answered with an error and not awaited again.
+        let waiting = &self.waiting;
+        self.since
+            .retain(|msg_id, since| waiting.contains_key(msg_id) || !stale(since));
+
+        evicted
+    }
+}
+
+impl Default for Correlations {
+    fn default() -> Self {
+        Correlations::new(Duration::from_secs(300), 100_000)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn reply_tx() -> ReplySender {
+        oneshot::channel().0
+    }
+
+    #[test]
+    fn evicts_stale_entries_then_the_oldest_over_the_cap() {
+        let mut correlations = Correlations::new(Duration::from_secs(60), 2);
+
+        for msg_id in 1..=4 {
+            correlations.insert(msg_id, reply_tx());
+        }
+
+        // Awaiting the same request again keeps its age.
+        correlations.take(1).unwrap();
+        correlations.insert(1, reply_tx());
+
+        // One answered with an error and never awaited again keeps its age only until the TTL.
+        correlations.take(4).unwrap();
+
+        let now = Instant::now();
+        assert_eq!(correlations.evict(now), [1]);
+        assert_eq!(correlations.len(), 2);
+
+        assert_eq!(correlations.evict(now + Duration::from_secs(60)), [2, 3]);
+        assert!(correlations.is_empty());
+        assert!(correlations.since.is_empty());
+    }
+}
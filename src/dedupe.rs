@@ -0,0 +1,267 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// How a `DedupeCache` decides which keys to forget once it is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EvictionPolicy {
+    /// Forget the least recently seen key.
+    #[default]
+    Lru,
+    /// Forget keys once they are older than the given age, or the oldest key when full.
+    Ttl(Duration),
+    /// 2Q: keys seen once are kept in a small FIFO, and only promoted to the LRU once seen
+    /// again, so a burst of one-off keys can't flush the frequently seen ones.
+    TwoQ,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DedupeConfig {
+    pub policy: EvictionPolicy,
+    pub capacity: usize,
+}
+
+impl Default for DedupeConfig {
+    fn default() -> Self {
+        DedupeConfig {
+            policy: EvictionPolicy::default(),
+            capacity: 1024,
+        }
+    }
+}
+
+impl EvictionPolicy {
+    /// `lru`, `ttl:<millis>`, or `2q`.
+    pub fn parse(policy: &str) -> Option<Self> {
+        match policy {
+            "lru" => Some(EvictionPolicy::Lru),
+            "2q" => Some(EvictionPolicy::TwoQ),
+            _ => policy
+                .strip_prefix("ttl:")
+                .and_then(|millis| millis.parse().ok())
+                .map(|millis| EvictionPolicy::Ttl(Duration::from_millis(millis))),
+        }
+    }
+}
+
+/// Keys in the order they were pushed or last touched.
+#[derive(Debug)]
+struct Queue<K> {
+    order: BTreeMap<u64, (K, Instant)>,
+    index: HashMap<K, u64>,
+    tick: u64,
+}
+
+impl<K> Default for Queue<K> {
+    fn default() -> Self {
+        Queue {
+            order: BTreeMap::new(),
+            index: HashMap::new(),
+            tick: 0,
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash> Queue<K> {
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.index.contains_key(key)
+    }
+
+    fn push(&mut self, key: K, now: Instant) {
+        self.remove(&key);
+        self.tick += 1;
+        self.index.insert(key.clone(), self.tick);
+        self.order.insert(self.tick, (key, now));
+    }
+
+    /// Move a key to the back of the queue, keeping the time it was pushed.
+    fn touch(&mut self, key: &K) {
+        if let Some(tick) = self.index.get(key).copied() {
+            let entry = self.order.remove(&tick).unwrap();
+
+            self.tick += 1;
+            self.index.insert(key.clone(), self.tick);
+            self.order.insert(self.tick, entry);
+        }
+    }
+
+    fn remove(&mut self, key: &K) -> bool {
+        match self.index.remove(key) {
+            Some(tick) => self.order.remove(&tick).is_some(),
+            None => false,
+        }
+    }
+
+    fn oldest(&self) -> Option<&(K, Instant)> {
+        self.order.values().next()
+    }
+
+    fn pop_oldest(&mut self) -> Option<K> {
+        let (_, (key, _)) = self.order.pop_first()?;
+        self.index.remove(&key);
+
+        Some(key)
+    }
+}
+
+/// A bounded set of recently seen keys.
+#[derive(Debug)]
+pub struct DedupeCache<K> {
+    config: DedupeConfig,
+    /// Every key under LRU and TTL; keys seen more than once under 2Q.
+    main: Queue<K>,
+    /// 2Q only: keys seen once.
+    recent: Queue<K>,
+    /// 2Q only: keys recently evicted from `recent`, without their values.
+    ghosts: Queue<K>,
+}
+
+impl<K> Default for DedupeCache<K> {
+    fn default() -> Self {
+        DedupeCache::new(DedupeConfig::default())
+    }
+}
+
+impl<K> DedupeCache<K> {
+    pub fn new(config: DedupeConfig) -> Self {
+        DedupeCache {
+            config,
+            main: Queue::default(),
+            recent: Queue::default(),
+            ghosts: Queue::default(),
+        }
+    }
+
+    /// Forget every key.
+    pub fn clear(&mut self) {
+        self.main = Queue::default();
+        self.recent = Queue::default();
+        self.ghosts = Queue::default();
+    }
+}
+
+impl<K: Clone + Eq + Hash> DedupeCache<K> {
+    /// How many keys the cache holds, ghosts included.
+    pub fn len(&self) -> usize {
+        self.main.len() + self.recent.len() + self.ghosts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the key was seen recently. A hit counts as use of the key.
+    pub fn check(&mut self, key: &K) -> bool {
+        let now = Instant::now();
+
+        match self.config.policy {
+            EvictionPolicy::Lru => {
+                self.main.touch(key);
+                self.main.contains(key)
+            }
+            EvictionPolicy::Ttl(ttl) => {
+                self.expire(ttl, now);
+                self.main.contains(key)
+            }
+            EvictionPolicy::TwoQ => {
+                self.main.touch(key);
+                self.main.contains(key) || self.recent.contains(key)
+            }
+        }
+    }
+
+    pub fn insert(&mut self, key: K) {
+        let now = Instant::now();
+        let capacity = self.config.capacity.max(1);
+
+        match self.config.policy {
+            EvictionPolicy::Lru | EvictionPolicy::Ttl(_) => {
+                self.main.push(key, now);
+
+                while self.main.len() > capacity {
+                    self.main.pop_oldest();
+                }
+            }
+            EvictionPolicy::TwoQ => {
+                if self.main.contains(&key) || self.recent.contains(&key) {
+                    return;
+                }
+
+                // Sizes from the 2Q paper: a quarter of the capacity for keys seen once, and
+                // ghosts for half the capacity.
+                let recent_capacity = (capacity / 4).max(1);
+                let ghost_capacity = (capacity / 2).max(1);
+
+                if self.ghosts.remove(&key) {
+                    self.main.push(key, now);
+
+                    while self.main.len() > capacity.saturating_sub(recent_capacity).max(1) {
+                        self.main.pop_oldest();
+                    }
+                } else {
+                    self.recent.push(key, now);
+
+                    while self.recent.len() > recent_capacity {
+                        if let Some(evicted) = self.recent.pop_oldest() {
+                            self.ghosts.push(evicted, now);
+                        }
+                    }
+
+                    while self.ghosts.len() > ghost_capacity {
+                        self.ghosts.pop_oldest();
+                    }
+                }
+            }
+        }
+    }
+
+    fn expire(&mut self, ttl: Duration, now: Instant) {
+        while let Some((_, inserted)) = self.main.oldest() {
+            if now.duration_since(*inserted) < ttl {
+                break;
+            }
+
+            self.main.pop_oldest();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cache(policy: EvictionPolicy, capacity: usize) -> DedupeCache<u32> {
+        DedupeCache::new(DedupeConfig { policy, capacity })
+    }
+
+    #[test]
+    fn evicts_according_to_the_policy() {
+        // LRU keeps the key that was used most recently.
+        let mut lru = cache(EvictionPolicy::Lru, 2);
+        lru.insert(1);
+        lru.insert(2);
+        assert!(lru.check(&1));
+        lru.insert(3);
+        assert!(lru.check(&1));
+        assert!(!lru.check(&2));
+
+        // TTL forgets keys once they expire.
+        let mut ttl = cache(EvictionPolicy::Ttl(Duration::ZERO), 2);
+        ttl.insert(1);
+        assert!(!ttl.check(&1));
+
+        // 2Q keeps a key seen twice through a burst of one-off keys.
+        let mut two_q = cache(EvictionPolicy::TwoQ, 8);
+        two_q.insert(1);
+        two_q.insert(2);
+        two_q.insert(3);
+        two_q.insert(1);
+        (10..20).for_each(|key| two_q.insert(key));
+        assert!(two_q.check(&1));
+        assert!(!two_q.check(&10));
+    }
+}
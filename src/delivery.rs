@@ -0,0 +1,150 @@
+//! Messages sent to peers that haven't been acknowledged yet, each a delivery of a payload to a
+//! destination. Every resend is a new attempt with a fresh `msg_id`, as Maelstrom expects each
+//! message to be unique, and an acknowledgement of any attempt completes the delivery.
+//!
+//! A delivery is identified by the `msg_id` it was first sent with, which the retry schedule and
+//! the wait for its acknowledgement are keyed by. Sending the same payload to the same
+//! destination while it's pending adds an attempt to the existing delivery instead of starting
+//! another.
+
+use std::collections::HashMap;
+
+use crate::core::message::Message;
+
+/// What a delivery is: its destination, and its body without ids.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DeliveryKey {
+    pub dest: String,
+    pub payload: String,
+}
+
+impl DeliveryKey {
+    pub fn of(message: &Message) -> Self {
+        let mut body = message.body.clone();
+        body.set_ids(None, None);
+
+        DeliveryKey {
+            dest: message.dest.clone(),
+            payload: serde_json::to_string(&body).expect("Couldn't serialize body."),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Delivery {
+    /// The `msg_id` of the first attempt.
+    pub id: u32,
+    /// The message as it was first sent.
+    pub message: Message<'static>,
+    /// The `msg_id` of every attempt, oldest first.
+    pub attempts: Vec<u32>,
+}
+
+#[derive(Debug, Default)]
+pub struct Deliveries {
+    pending: HashMap<DeliveryKey, Delivery>,
+    /// The delivery each attempt's `msg_id` belongs to.
+    attempts: HashMap<u32, DeliveryKey>,
+}
+
+impl Deliveries {
+    /// Track a message that's being sent, returning the id of the delivery it's an attempt of.
+    pub fn insert(&mut self, message: Message<'static>) -> u32 {
+        let msg_id = message.body.msg_id().unwrap_or_default();
+        let key = DeliveryKey::of(&message);
+
+        self.attempts.insert(msg_id, key.clone());
+
+        let delivery = self.pending.entry(key).or_insert_with(|| Delivery {
+            id: msg_id,
+            message,
+            attempts: vec![],
+        });
+        delivery.attempts.push(msg_id);
+
+        delivery.id
+    }
+
+    /// A new attempt at delivery `id`: its message with `msg_id` instead.
+    pub fn attempt(&mut self, id: u32, msg_id: u32) -> Option<Message<'static>> {
+        let key = self.attempts.get(&id)?.clone();
+        let delivery = self.pending.get_mut(&key)?;
+
+        delivery.attempts.push(msg_id);
+        self.attempts.insert(msg_id, key);
+
+        let mut message = delivery.message.clone();
+        message.body.set_ids(Some(msg_id), None);
+
+        Some(message)
+    }
+
+    /// The delivery an attempt's `msg_id` belongs to.
+    pub fn get(&self, msg_id: u32) -> Option<&Delivery> {
+        self.pending.get(self.attempts.get(&msg_id)?)
+    }
+
+    /// Complete or give up on the delivery an attempt's `msg_id` belongs to.
+    pub fn remove(&mut self, msg_id: u32) -> Option<Delivery> {
+        let key = self.attempts.get(&msg_id)?.clone();
+        let delivery = self.pending.remove(&key)?;
+
+        for attempt in &delivery.attempts {
+            self.attempts.remove(attempt);
+        }
+
+        Some(delivery)
+    }
+
+    /// The ids of the pending deliveries.
+    pub fn ids(&self) -> Vec<u32> {
+        self.pending.values().map(|delivery| delivery.id).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Delivery> {
+        self.pending.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(all(test, feature = "broadcast"))]
+mod test {
+    use super::*;
+
+    fn gossip(dest: &str, value: u32, msg_id: u32) -> Message<'static> {
+        serde_json::from_str(&format!(
+            r#"{{"src": "n1", "dest": "{}", "body": {{"type": "broadcast", "message": {}, "msg_id": {}}}}}"#,
+            dest, value, msg_id
+        ))
+        .map(Message::into_owned)
+        .unwrap()
+    }
+
+    #[test]
+    fn completes_a_delivery_on_any_attempts_acknowledgement() {
+        let mut deliveries = Deliveries::default();
+
+        assert_eq!(deliveries.insert(gossip("n2", 7, 1)), 1);
+        assert_eq!(deliveries.insert(gossip("n3", 7, 2)), 2);
+        // The same value to the same peer is another attempt at the same delivery.
+        assert_eq!(deliveries.insert(gossip("n2", 7, 3)), 1);
+        assert_eq!(deliveries.len(), 2);
+
+        let resend = deliveries.attempt(1, 4).unwrap();
+        assert_eq!(resend.body.msg_id(), Some(4));
+        assert_eq!(resend.dest, "n2");
+
+        assert_eq!(deliveries.get(3).unwrap().id, 1);
+        assert_eq!(deliveries.remove(4).unwrap().attempts, [1, 3, 4]);
+        assert!(deliveries.get(1).is_none());
+        assert!(deliveries.remove(3).is_none());
+        assert_eq!(deliveries.ids(), [2]);
+    }
+}
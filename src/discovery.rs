@@ -0,0 +1,113 @@
+use std::io;
+use tracing::info;
+
+use crate::actor::NodeHandle;
+use crate::node::Node;
+use crate::tiebreak;
+
+/// Where a node learns about its peers when it isn't started by Maelstrom, i.e. when no `init`
+/// message will arrive.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Discovery {
+    /// A fixed list of node ids.
+    Seeds(Vec<String>),
+    /// A `host:port` to resolve; every address it resolves to is a node, identified by `ip:port`.
+    Dns(String),
+}
+
+impl Discovery {
+    /// Either a comma-separated list of node ids (`n1,n2,n3`) or a host to resolve
+    /// (`dns:cluster.local:7000`).
+    pub fn parse(seeds: &str) -> Self {
+        if let Some(host) = seeds.strip_prefix("dns:") {
+            return Discovery::Dns(host.to_string());
+        }
+
+        Discovery::Seeds(
+            seeds
+                .split(',')
+                .map(str::trim)
+                .filter(|seed| !seed.is_empty())
+                .map(str::to_string)
+                .collect(),
+        )
+    }
+
+    pub async fn node_ids(&self) -> io::Result<Vec<String>> {
+        match self {
+            Discovery::Seeds(seeds) => Ok(seeds.clone()),
+            Discovery::Dns(host) => {
+                let mut node_ids = tokio::net::lookup_host(host)
+                    .await?
+                    .map(|address| address.to_string())
+                    .collect::<Vec<String>>();
+
+                // Every node resolves the same records; sort them so they agree on the order.
+                node_ids.sort_by(|a, b| tiebreak::compare(a, b));
+                node_ids.dedup();
+
+                Ok(node_ids)
+            }
+        }
+    }
+
+    /// Initialize the node as if it had received `init`, with every other node as a neighbor. A
+    /// `topology` message received later still replaces the neighbors.
+    pub async fn bootstrap(&self, node: &NodeHandle, node_id: String) -> io::Result<()> {
+        let node_ids = self.node_ids().await?;
+
+        node.call(move |node| Discovery::init(node, node_ids, node_id))
+            .await
+            .map_err(io::Error::other)?;
+
+        Ok(())
+    }
+
+    fn init(node: &mut Node, node_ids: Vec<String>, node_id: String) {
+        #[cfg(feature = "broadcast")]
+        {
+            node.broadcast.topology = node_ids
+                .iter()
+                .filter(|peer| **peer != node_id)
+                .cloned()
+                .collect();
+        }
+        node.node_ids = node_ids;
+        node.id = Some(node_id);
+
+        info!("Discovered nodes: {:?}", node.node_ids);
+        #[cfg(feature = "broadcast")]
+        info!("My neighbors are: {:?}", node.broadcast.topology);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn bootstraps_from_a_seed_list() {
+        let node = NodeHandle::spawn(Node::default());
+        let discovery = Discovery::Seeds(vec!["n1".into(), "n2".into(), "n3".into()]);
+
+        discovery.bootstrap(&node, "n2".into()).await.unwrap();
+
+        let (id, node_ids) = node
+            .call(|node| (node.id.clone(), node.node_ids.clone()))
+            .await
+            .unwrap();
+
+        assert_eq!(id, Some("n2".to_string()));
+        assert_eq!(node_ids, vec!["n1", "n2", "n3"]);
+
+        #[cfg(feature = "broadcast")]
+        {
+            let topology = node
+                .call(|node| node.broadcast.topology.clone())
+                .await
+                .unwrap();
+
+            assert_eq!(topology, vec!["n1", "n3"]);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Why a message couldn't be handled, and the Maelstrom error each reason is answered with.
+
+use thiserror::Error;
+
+use crate::core::message::{ErrorCode, Message, RemoteError};
+
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum NodeError {
+    /// The input isn't a message.
+    #[error("Uh-oh, unable to parse that message: {0}")]
+    Parse(String),
+    /// The message lacks a field it needs to be handled, e.g. `src` or `msg_id`.
+    #[error("The message has no {0}.")]
+    MissingField(&'static str),
+    /// No handler is registered for the message's type.
+    #[error("Unsupported message type {0}.")]
+    UnsupportedType(String),
+    /// A command panicked. The node's task caught the panic, logged it, and went on to the next
+    /// command; the caller decides what to do instead, e.g. answer the message with an error.
+    #[error("The node panicked running a command.")]
+    Panicked,
+    /// The node's task stopped, so a command couldn't be sent to it or its result received.
+    #[error("The node's task stopped.")]
+    ChannelClosed,
+}
+
+impl NodeError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            NodeError::Parse(_) | NodeError::MissingField(_) => ErrorCode::MalformedRequest,
+            NodeError::UnsupportedType(_) => ErrorCode::NotSupported,
+            NodeError::Panicked | NodeError::ChannelClosed => ErrorCode::Crash,
+        }
+    }
+
+    /// The `error` from `src` answering `message` with this error.
+    pub fn reply_to(&self, message: &Message, src: Option<String>) -> Message<'static> {
+        message.error_reply(src, self.code(), &self.to_string())
+    }
+}
+
+impl From<NodeError> for RemoteError {
+    fn from(err: NodeError) -> Self {
+        RemoteError {
+            code: err.code(),
+            text: err.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::message::MessageBody;
+    use crate::core::state;
+
+    #[test]
+    fn answers_with_the_matching_error_code() {
+        let err = state::parse("{").unwrap_err();
+        assert_eq!(err.code(), ErrorCode::MalformedRequest);
+
+        let request = state::parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": 1, "msg_id": 4}}"#,
+        )
+        .unwrap();
+        let reply = NodeError::UnsupportedType("echo".to_string())
+            .reply_to(&request, Some("n1".to_string()));
+
+        assert_eq!(reply.dest, "c1");
+        assert!(matches!(
+            reply.body,
+            MessageBody::Error(body) if body.code == ErrorCode::NotSupported && body.in_reply_to == Some(4)
+        ));
+    }
+}
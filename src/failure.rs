@@ -0,0 +1,239 @@
+//! Failure detection from heartbeats. With a heartbeat interval set, every node sends every other
+//! node a `heartbeat` on that interval, and a failure detector turns the arrival times into a
+//! suspicion level per peer. A peer whose suspicion reaches the threshold is considered dead:
+//! anti-entropy and replication skip it until its heartbeats resume.
+//!
+//! Two detectors are built in. `PhiAccrual` learns each peer's inter-arrival times and reports φ,
+//! how unlikely it is that the next heartbeat is merely late, as in "The φ Accrual Failure
+//! Detector" (Hayashibara et al.). `Timeout` reports the number of heartbeat intervals missed.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Turns heartbeat arrivals into a suspicion level per peer.
+pub trait FailureDetector: fmt::Debug + Send {
+    fn heartbeat(&mut self, peer: &str, at: Instant);
+
+    /// How suspect `peer` is at `now`; 0 for a peer that has never sent a heartbeat.
+    fn suspicion(&self, peer: &str, now: Instant) -> f64;
+}
+
+/// Suspicion is the number of heartbeat intervals since the last heartbeat.
+#[derive(Debug)]
+pub struct Timeout {
+    interval: Duration,
+    last: HashMap<String, Instant>,
+}
+
+impl Timeout {
+    pub fn new(interval: Duration) -> Self {
+        Timeout {
+            interval,
+            last: HashMap::new(),
+        }
+    }
+}
+
+impl FailureDetector for Timeout {
+    fn heartbeat(&mut self, peer: &str, at: Instant) {
+        self.last.insert(peer.to_string(), at);
+    }
+
+    fn suspicion(&self, peer: &str, now: Instant) -> f64 {
+        self.last.get(peer).map_or(0.0, |last| {
+            now.saturating_duration_since(*last).as_secs_f64() / self.interval.as_secs_f64()
+        })
+    }
+}
+
+/// Suspicion is φ = -log10 of the probability that a heartbeat arrives later than now, with
+/// inter-arrival times modelled as normally distributed over the last `WINDOW` of them.
+#[derive(Debug)]
+pub struct PhiAccrual {
+    interval: Duration,
+    arrivals: HashMap<String, Arrivals>,
+}
+
+#[derive(Debug)]
+struct Arrivals {
+    last: Instant,
+    intervals: VecDeque<f64>,
+}
+
+impl PhiAccrual {
+    const WINDOW: usize = 100;
+
+    pub fn new(interval: Duration) -> Self {
+        PhiAccrual {
+            interval,
+            arrivals: HashMap::new(),
+        }
+    }
+
+    /// The mean and standard deviation of the intervals, in seconds. The deviation has a floor of
+    /// a quarter of the heartbeat interval, so steady heartbeats don't make a little jitter look
+    /// like a failure.
+    fn distribution(&self, intervals: &VecDeque<f64>) -> (f64, f64) {
+        let expected = self.interval.as_secs_f64();
+
+        if intervals.is_empty() {
+            return (expected, expected / 4.0);
+        }
+
+        let count = intervals.len() as f64;
+        let mean = intervals.iter().sum::<f64>() / count;
+        let variance = intervals.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / count;
+
+        (mean, variance.sqrt().max(expected / 4.0))
+    }
+}
+
+impl FailureDetector for PhiAccrual {
+    fn heartbeat(&mut self, peer: &str, at: Instant) {
+        match self.arrivals.get_mut(peer) {
+            Some(arrivals) => {
+                let interval = at.saturating_duration_since(arrivals.last).as_secs_f64();
+
+                if arrivals.intervals.len() == Self::WINDOW {
+                    arrivals.intervals.pop_front();
+                }
+
+                arrivals.intervals.push_back(interval);
+                arrivals.last = at;
+            }
+            None => {
+                let arrivals = Arrivals {
+                    last: at,
+                    intervals: VecDeque::new(),
+                };
+
+                self.arrivals.insert(peer.to_string(), arrivals);
+            }
+        }
+    }
+
+    fn suspicion(&self, peer: &str, now: Instant) -> f64 {
+        let Some(arrivals) = self.arrivals.get(peer) else {
+            return 0.0;
+        };
+
+        let elapsed = now.saturating_duration_since(arrivals.last).as_secs_f64();
+        let (mean, deviation) = self.distribution(&arrivals.intervals);
+
+        // A logistic approximation of the normal distribution's tail.
+        let y = (elapsed - mean) / deviation;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+
+        match elapsed > mean {
+            true => -(e / (1.0 + e)).log10(),
+            false => -(1.0 - 1.0 / (1.0 + e)).log10(),
+        }
+    }
+}
+
+/// Which failure detector to use.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DetectorKind {
+    #[default]
+    PhiAccrual,
+    Timeout,
+}
+
+impl DetectorKind {
+    /// `phi` or `timeout`.
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "phi" => Some(DetectorKind::PhiAccrual),
+            "timeout" => Some(DetectorKind::Timeout),
+            _ => None,
+        }
+    }
+}
+
+/// The heartbeat schedule and the detector its heartbeats feed.
+#[derive(Debug)]
+pub struct Liveness {
+    /// How often heartbeats are sent; none are without one, and every peer is considered alive.
+    pub interval: Option<Duration>,
+    /// The suspicion at which a peer is considered dead.
+    pub threshold: f64,
+    detector: Box<dyn FailureDetector>,
+}
+
+impl Liveness {
+    pub fn new(interval: Option<Duration>, threshold: f64, kind: DetectorKind) -> Self {
+        let expected = interval.unwrap_or(Duration::from_secs(1));
+
+        let detector: Box<dyn FailureDetector> = match kind {
+            DetectorKind::PhiAccrual => Box::new(PhiAccrual::new(expected)),
+            DetectorKind::Timeout => Box::new(Timeout::new(expected)),
+        };
+
+        Liveness {
+            interval,
+            threshold,
+            detector,
+        }
+    }
+
+    pub fn heartbeat(&mut self, peer: &str) {
+        self.detector.heartbeat(peer, Instant::now());
+    }
+
+    pub fn is_alive(&self, peer: &str) -> bool {
+        self.detector.suspicion(peer, Instant::now()) < self.threshold
+    }
+
+    /// The peers that are alive, or all of them if none are, so a node that's cut off from
+    /// everyone keeps trying.
+    pub fn alive(&self, peers: Vec<String>) -> Vec<String> {
+        let alive: Vec<String> = peers
+            .iter()
+            .filter(|peer| self.is_alive(peer))
+            .cloned()
+            .collect();
+
+        match alive.is_empty() {
+            true => peers,
+            false => alive,
+        }
+    }
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Liveness::new(None, 8.0, DetectorKind::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn suspects_a_peer_once_its_heartbeats_stop() {
+        let interval = Duration::from_millis(100);
+        let start = Instant::now();
+
+        let mut phi = PhiAccrual::new(interval);
+        let mut timeout = Timeout::new(interval);
+
+        for beat in 0..10 {
+            phi.heartbeat("n2", start + interval * beat);
+            timeout.heartbeat("n2", start + interval * beat);
+        }
+
+        let last = start + interval * 9;
+
+        assert!(phi.suspicion("n2", last + interval) < 1.0);
+        assert!(phi.suspicion("n2", last + interval * 3) > 8.0);
+        assert!(
+            phi.suspicion("n2", last + interval * 2) < phi.suspicion("n2", last + interval * 3)
+        );
+        assert_eq!(phi.suspicion("n3", last), 0.0);
+
+        assert_eq!(timeout.suspicion("n2", last + interval * 4), 4.0);
+        assert_eq!(timeout.suspicion("n3", last), 0.0);
+    }
+}
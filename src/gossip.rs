@@ -0,0 +1,161 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, DefaultHasher, Hash, Hasher};
+use std::time::Duration;
+
+use crate::core::message::BroadcastValue;
+
+/// New broadcast values waiting to be gossiped, by neighbor. With a window set, values are
+/// collected for that long and sent to each neighbor in a single `broadcast`, trading latency for
+/// fewer messages per operation.
+#[derive(Debug, Default)]
+pub struct GossipBatch {
+    pub window: Option<Duration>,
+    pending: HashMap<String, Vec<BroadcastValue>>,
+}
+
+impl GossipBatch {
+    pub fn new(window: Duration) -> Self {
+        GossipBatch {
+            window: Some(window),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.window.is_some()
+    }
+
+    pub fn push(&mut self, neighbor: String, values: &[BroadcastValue]) {
+        self.pending
+            .entry(neighbor)
+            .or_default()
+            .extend_from_slice(values);
+    }
+
+    /// The number of values waiting, counted once per neighbor.
+    pub fn len(&self) -> usize {
+        self.pending.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// The values collected for each neighbor since the last call.
+    pub fn take(&mut self) -> HashMap<String, Vec<BroadcastValue>> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Periodic anti-entropy: every `interval`, a node sends its whole set to a random neighbor,
+/// which merges it and replies with the values the node is missing. Values lost despite
+/// retries, e.g. across a long partition, still converge.
+///
+/// Once the set holds more than `digest_above` values, a `Digest` of it is sent instead, and the
+/// two nodes only exchange the values in the buckets where their digests differ.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AntiEntropy {
+    pub interval: Option<Duration>,
+    pub digest_above: usize,
+}
+
+impl Default for AntiEntropy {
+    fn default() -> Self {
+        AntiEntropy {
+            interval: None,
+            digest_above: 1000,
+        }
+    }
+}
+
+impl AntiEntropy {
+    /// A random peer to sync with.
+    pub fn pick(peers: &[String]) -> Option<&String> {
+        if peers.is_empty() {
+            return None;
+        }
+
+        let random = RandomState::new().build_hasher().finish();
+
+        peers.get((random % peers.len() as u64) as usize)
+    }
+}
+/// A fingerprint of a set of broadcast values: the values are hashed into `BUCKETS` buckets, and
+/// each bucket's fingerprint is the sum of its values' hashes, so it doesn't depend on the order
+/// they were inserted in. Two sets differ in a bucket exactly when (barring collisions) their
+/// fingerprints for it do.
+pub struct Digest;
+
+impl Digest {
+    pub const BUCKETS: usize = 256;
+
+    fn hash(value: &BroadcastValue) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn bucket(value: &BroadcastValue) -> usize {
+        (Digest::hash(value) % Digest::BUCKETS as u64) as usize
+    }
+
+    pub fn of(values: impl IntoIterator<Item = BroadcastValue>) -> Vec<u64> {
+        let mut digest = vec![0u64; Digest::BUCKETS];
+
+        for value in values {
+            let fingerprint = &mut digest[Digest::bucket(&value)];
+            *fingerprint = fingerprint.wrapping_add(Digest::hash(&value));
+        }
+
+        digest
+    }
+
+    /// The buckets where two digests differ. A digest of the wrong length differs everywhere.
+    pub fn differing(mine: &[u64], theirs: &[u64]) -> Vec<usize> {
+        (0..Digest::BUCKETS)
+            .filter(|bucket| mine.get(*bucket) != theirs.get(*bucket))
+            .collect()
+    }
+
+    /// The values that fall in `buckets`.
+    pub fn values_in(
+        values: impl IntoIterator<Item = BroadcastValue>,
+        buckets: &[usize],
+    ) -> Vec<BroadcastValue> {
+        let buckets: HashSet<&usize> = buckets.iter().collect();
+
+        values
+            .into_iter()
+            .filter(|value| buckets.contains(&Digest::bucket(value)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_the_buckets_two_sets_differ_in() {
+        let mine: HashSet<BroadcastValue> = (0..500).map(BroadcastValue::from).collect();
+        let mut theirs = mine.clone();
+        theirs.insert(BroadcastValue::from(1000));
+
+        let differing = Digest::differing(
+            &Digest::of(mine.iter().cloned()),
+            &Digest::of(theirs.iter().cloned()),
+        );
+
+        assert_eq!(differing.len(), 1);
+        assert_eq!(
+            Digest::values_in(theirs.iter().cloned(), &differing)
+                .into_iter()
+                .filter(|value| !mine.contains(value))
+                .collect::<Vec<_>>(),
+            [BroadcastValue::from(1000)]
+        );
+        let digest = Digest::of(mine);
+        assert!(Digest::differing(&digest, &digest).is_empty());
+    }
+}
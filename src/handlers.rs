@@ -0,0 +1,1953 @@
+#[cfg(feature = "kafka")]
+use futures_util::future;
+#[cfg(feature = "kv")]
+use serde_json::Value;
+#[cfg(feature = "kafka")]
+use std::collections::HashMap;
+#[cfg(feature = "broadcast")]
+use std::collections::HashSet;
+#[cfg(any(feature = "kafka", feature = "raft"))]
+use tracing::error;
+use tracing::warn;
+#[cfg(feature = "broadcast")]
+use tracing::{debug, info};
+
+use crate::admin::{self, CrashMode};
+#[cfg(feature = "counter")]
+use crate::core::counter::CounterOp;
+#[cfg(feature = "counter")]
+use crate::core::message::AddOkBody;
+#[cfg(any(feature = "broadcast", feature = "counter", feature = "kv"))]
+use crate::core::message::ReadOkBody;
+#[cfg(feature = "broadcast")]
+use crate::core::message::{
+    BroadcastBody, BroadcastOkBody, BroadcastValue, CausalValue, ErrorBody, GossipPullBody,
+    ReadBody, RemoteError, SyncOkBody, TopologyOkBody, TopologyReportOkBody,
+};
+#[cfg(feature = "kv")]
+use crate::core::message::{CasOkBody, WriteOkBody};
+#[cfg(feature = "kafka")]
+use crate::core::message::{
+    CommitOffsetsBody, CommitOffsetsOkBody, ListCommittedOffsetsBody, ListCommittedOffsetsOkBody,
+    PollBody, PollOkBody, SendOkBody,
+};
+use crate::core::message::{
+    CrashOkBody, DebugStateOkBody, EchoOkBody, ErrorCode, GenerateOkBody, InitOkBody, InternalBody,
+    Message, MessageBody, MetricsOkBody, PeerStatusOkBody,
+};
+#[cfg(feature = "txn")]
+use crate::core::message::{
+    TxnAbortBody, TxnCommitBody, TxnCommitOkBody, TxnOkBody, TxnOp, TxnPrepareBody,
+    TxnPrepareOkBody, TxnStatusBody, TxnStatusOkBody,
+};
+#[cfg(feature = "broadcast")]
+use crate::core::state::BroadcastValues;
+#[cfg(feature = "broadcast")]
+use crate::gossip::Digest;
+#[cfg(feature = "kv")]
+use crate::kv::{KvMode, KvOp};
+#[cfg(feature = "kafka")]
+use crate::log::{LogAnswer, LogEffect, LogOp, LogQuery};
+#[cfg(any(feature = "counter", feature = "kafka", feature = "kv"))]
+use crate::machine::StateMachine;
+#[cfg(any(feature = "broadcast", feature = "kafka", feature = "kv"))]
+use crate::memory::MemoryUsage;
+#[cfg(feature = "broadcast")]
+use crate::metrics::Metrics;
+use crate::metrics::MetricsReport;
+use crate::node::{Handler, Node};
+use crate::persist::Persistence;
+#[cfg(any(feature = "counter", feature = "kv"))]
+use crate::persist::Record;
+#[cfg(feature = "raft")]
+use crate::raft::{Outgoing, RaftReads};
+#[cfg(any(feature = "broadcast", feature = "txn"))]
+use crate::rpc;
+#[cfg(any(feature = "kafka", feature = "kv"))]
+use crate::rpc::rpc;
+#[cfg(feature = "kv")]
+use crate::services::{KvClient, KvService};
+#[cfg(feature = "kv")]
+use crate::tiebreak;
+#[cfg(feature = "txn")]
+use crate::txn::{Commit, Transactions};
+#[cfg(feature = "broadcast")]
+use crate::workload::Workload;
+
+pub struct InitHandler;
+
+impl Handler for InitHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Init(body) = &message.body else {
+            return vec![];
+        };
+
+        node.id = Some(body.node_id.to_string());
+        node.node_ids = body.node_ids.to_owned().unwrap_or_default();
+
+        // A restarted node picks up where its previous run left off.
+        Persistence::recover(node, &body.node_id);
+
+        let body = MessageBody::InitOk(InitOkBody::default());
+        let mut messages = vec![node.reply(&message, body)];
+
+        for workload in node.registry.workloads() {
+            messages.extend(workload.on_init(node));
+        }
+
+        messages
+    }
+}
+
+pub struct EchoHandler;
+
+impl Handler for EchoHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Echo(body) = &message.body else {
+            return vec![];
+        };
+
+        let body = MessageBody::EchoOk(EchoOkBody {
+            echo: body.echo.to_owned(),
+            ..Default::default()
+        });
+
+        vec![node.reply(&message, body)]
+    }
+}
+
+pub struct GenerateHandler;
+
+impl Handler for GenerateHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Generate(_) = &message.body else {
+            return vec![];
+        };
+
+        let Some(id) = node.generate_id() else {
+            return vec![message.error_reply(
+                node.id.clone(),
+                ErrorCode::TemporarilyUnavailable,
+                "This node has no index to generate IDs with yet.",
+            )];
+        };
+
+        let body = MessageBody::GenerateOk(GenerateOkBody {
+            msg_id: None,
+            in_reply_to: None,
+            id: node.id_format.format(id),
+        });
+
+        vec![node.reply(&message, body)]
+    }
+}
+
+/// A reply held back until every neighbor in `outstanding` acknowledges its gossip.
+#[cfg(feature = "broadcast")]
+#[derive(Debug)]
+pub struct HeldReply {
+    pub reply: Message<'static>,
+    pub outstanding: HashSet<String>,
+}
+
+/// Acknowledge the gossip to one of the neighbors a held reply waits on, releasing the reply if
+/// it was the last.
+#[cfg(feature = "broadcast")]
+fn release(node: &mut Node, reply_id: u32, neighbor: &str) -> Vec<Message<'static>> {
+    let Some(held) = node.broadcast.held_replies.get_mut(&reply_id) else {
+        return vec![];
+    };
+
+    held.outstanding.remove(neighbor);
+
+    if !held.outstanding.is_empty() {
+        return vec![];
+    }
+
+    node.broadcast
+        .held_replies
+        .remove(&reply_id)
+        .map(|held| vec![held.reply])
+        .unwrap_or_default()
+}
+
+/// Give up holding a reply, and tell the client its request timed out instead.
+#[cfg(feature = "broadcast")]
+fn time_out(node: &mut Node, reply_id: u32) -> Vec<Message<'static>> {
+    let Some(held) = node.broadcast.held_replies.remove(&reply_id) else {
+        return vec![];
+    };
+
+    vec![Message {
+        src: held.reply.src,
+        dest: held.reply.dest,
+        body: MessageBody::Error(ErrorBody {
+            code: ErrorCode::Timeout,
+            text: "The broadcast wasn't acknowledged in time.".into(),
+            msg_id: None,
+            in_reply_to: held.reply.body.in_reply_to(),
+        }),
+        lamport: None,
+    }]
+}
+
+/// Gossip values to a neighbor, resent by the node's retry task until the neighbor acknowledges
+/// them. `reply_id` is the held reply waiting on the neighbor, if any; a timeout releases it as
+/// an error.
+#[cfg(feature = "broadcast")]
+pub fn gossip(
+    node: &mut Node,
+    neighbor: String,
+    mut values: Vec<BroadcastValue>,
+    reply_id: Option<u32>,
+) {
+    // A single value is sent the way clients send it.
+    let (message, messages) = match values.len() {
+        1 => (values.pop(), vec![]),
+        _ => (None, values),
+    };
+
+    let body = BroadcastBody {
+        message,
+        messages,
+        causal: vec![],
+        msg_id: None,
+        in_reply_to: None,
+    };
+
+    send_gossip(node, neighbor, body, reply_id)
+}
+
+/// Gossip values with their causal dependencies to a neighbor, like `gossip`.
+#[cfg(feature = "broadcast")]
+pub fn gossip_causally(node: &mut Node, neighbor: String, values: Vec<CausalValue>) {
+    let body = BroadcastBody {
+        message: None,
+        messages: vec![],
+        causal: values,
+        msg_id: None,
+        in_reply_to: None,
+    };
+
+    send_gossip(node, neighbor, body, None)
+}
+
+#[cfg(feature = "broadcast")]
+fn send_gossip(node: &mut Node, neighbor: String, body: BroadcastBody, reply_id: Option<u32>) {
+    let handle = node.handle();
+
+    node.spawn(async move {
+        let to = neighbor.clone();
+
+        // An error leaves the gossip pending, so it is retried.
+        let rejected = move |node: &mut Node, err: RemoteError| {
+            warn!("Gossip to {} was rejected: {}", to, err);
+
+            if let (ErrorCode::Timeout, Some(reply_id)) = (err.code, reply_id) {
+                let messages = time_out(node, reply_id);
+                node.send(messages);
+            }
+        };
+
+        let acknowledged =
+            rpc::deliver(&handle, &neighbor, MessageBody::Broadcast(body), rejected).await;
+
+        if acknowledged.is_none() {
+            return;
+        }
+
+        debug!("Broadcast Ok received from {}.", neighbor);
+
+        if let Some(reply_id) = reply_id {
+            let _ = handle
+                .call(move |node| {
+                    let messages = release(node, reply_id, &neighbor);
+                    node.send(messages);
+                })
+                .await;
+        }
+    });
+}
+
+/// The error a request that would store more is answered with once the memory bounds are
+/// reached.
+#[cfg(any(feature = "broadcast", feature = "kafka", feature = "kv"))]
+fn memory_full(node: &Node, message: &Message) -> Message<'static> {
+    warn!(
+        "Rejecting {} because the memory bounds were reached: {:?}",
+        message.body.kind(),
+        message
+    );
+
+    message.error_reply(
+        node.id.clone(),
+        ErrorCode::TemporarilyUnavailable,
+        "Memory limit reached.",
+    )
+}
+
+#[cfg(feature = "broadcast")]
+pub struct BroadcastHandler;
+
+#[cfg(feature = "broadcast")]
+impl Handler for BroadcastHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Broadcast(body) = &message.body else {
+            return vec![];
+        };
+
+        if node.broadcast.causal.is_some() {
+            return broadcast_causally(node, &message, body);
+        }
+
+        let mut messages = vec![];
+        let mut gossiped_to = HashSet::new();
+        let mut unseen = vec![];
+
+        // In strict mode a client's broadcast is only acknowledged once every neighbor has it.
+        // Other nodes are acknowledged right away; they hold their own clients' replies.
+        let is_client = message
+            .src
+            .as_ref()
+            .is_some_and(|src| !node.node_ids.contains(src));
+        let strict = is_client && node.reply_modes.is_strict(Workload::Broadcast);
+        let reply_id = node.next_message_id();
+
+        // Once the state stores reach the memory bounds, refuse a message with new values before
+        // storing any of it, so a retry carries the whole batch again. Messages with only values
+        // the node already has are still acknowledged, so senders stop retrying them.
+        if !node.memory_bounds.allows(&MemoryUsage::measure(node))
+            && body
+                .values()
+                .any(|value| !node.broadcast.messages.contains(value))
+        {
+            return vec![memory_full(node, &message)];
+        }
+
+        for value in body.values() {
+            let recently_seen = node.broadcast.recently_seen.check(value);
+
+            if recently_seen {
+                Metrics::increment(&node.metrics.dedupe_hits);
+                continue;
+            }
+
+            Metrics::increment(&node.metrics.dedupe_misses);
+
+            if node.insert_message(value.clone()) {
+                unseen.push(value.clone());
+            }
+
+            node.broadcast.recently_seen.insert(value.clone());
+        }
+
+        if !unseen.is_empty() {
+            node.quiescence.record_activity();
+
+            // Don't send the message back to the message's original src, even if the src is a
+            // neighbor.
+            let neighbors = node
+                .peers()
+                .into_iter()
+                .filter(|node_id| Some(node_id) != message.src.as_ref());
+
+            for neighbor in neighbors {
+                // A strict reply waits on this message's own gossip, so it isn't batched.
+                if node.broadcast.gossip_batch.is_enabled() && !strict {
+                    node.broadcast.gossip_batch.push(neighbor, &unseen);
+                    continue;
+                }
+
+                gossip(node, neighbor.clone(), unseen.clone(), Some(reply_id));
+                gossiped_to.insert(neighbor);
+            }
+        } else {
+            debug!(
+                "Message seen {:?} - acknowledging it, but do nothing.",
+                message
+            );
+        }
+
+        // Gossip from other nodes is acknowledged too, so they stop retrying.
+        if body.msg_id.is_some() {
+            let body = MessageBody::BroadcastOk(BroadcastOkBody::default());
+            let reply = message.reply_with(node.id.clone(), Some(reply_id), body);
+
+            if strict && !gossiped_to.is_empty() {
+                node.broadcast.held_replies.insert(
+                    reply_id,
+                    HeldReply {
+                        reply,
+                        outstanding: gossiped_to,
+                    },
+                );
+            } else {
+                messages.push(reply);
+            }
+        }
+
+        messages
+    }
+}
+
+/// Deliver a broadcast in causal order: clients' values right away, and values gossiped from
+/// other nodes once their dependencies have been delivered. Strict replies and gossip batching
+/// don't apply.
+#[cfg(feature = "broadcast")]
+fn broadcast_causally(
+    node: &mut Node,
+    message: &Message,
+    body: &BroadcastBody,
+) -> Vec<Message<'static>> {
+    let node_id = node.id.clone().unwrap_or_default();
+    let Some(causal) = node.broadcast.causal.as_mut() else {
+        return vec![];
+    };
+
+    let mut unseen = vec![];
+    let mut delivered = vec![];
+
+    for value in body.values() {
+        let value = causal.broadcast(&node_id, value.clone());
+
+        delivered.push(value.value.clone());
+        unseen.push(value);
+    }
+
+    for value in &body.causal {
+        if causal.has_seen(value) {
+            continue;
+        }
+
+        unseen.push(value.clone());
+        delivered.extend(
+            causal
+                .receive(value.clone())
+                .into_iter()
+                .map(|value| value.value),
+        );
+    }
+
+    for value in delivered {
+        node.insert_message(value.clone());
+        node.broadcast.recently_seen.insert(value);
+    }
+
+    let mut messages = vec![];
+
+    if !unseen.is_empty() {
+        node.quiescence.record_activity();
+
+        let neighbors = node
+            .peers()
+            .into_iter()
+            .filter(|node_id| Some(node_id) != message.src.as_ref());
+
+        for neighbor in neighbors {
+            gossip_causally(node, neighbor, unseen.clone());
+        }
+    }
+
+    if body.msg_id.is_some() {
+        let body = MessageBody::BroadcastOk(BroadcastOkBody::default());
+
+        messages.push(node.reply(message, body));
+    }
+
+    messages
+}
+
+/// Store broadcast values learned through anti-entropy, unless the memory bounds are reached.
+#[cfg(feature = "broadcast")]
+pub fn merge(node: &mut Node, values: impl IntoIterator<Item = BroadcastValue>) {
+    // Synced values carry no clocks, so they'd be delivered out of causal order.
+    if node.broadcast.causal.is_some() {
+        return;
+    }
+
+    let mut merged = 0;
+
+    for value in values {
+        if node.broadcast.messages.contains(&value) {
+            continue;
+        }
+
+        if !node.memory_bounds.allows(&MemoryUsage::measure(node)) {
+            warn!("Not merging synced values because the memory bounds were reached.");
+            break;
+        }
+
+        node.insert_message(value.clone());
+        node.broadcast.recently_seen.insert(value);
+        merged += 1;
+    }
+
+    if merged > 0 {
+        info!("Merged {} values through anti-entropy.", merged);
+    }
+}
+
+#[cfg(feature = "broadcast")]
+pub struct SyncHandler;
+
+#[cfg(feature = "broadcast")]
+impl Handler for SyncHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Internal(InternalBody::Sync(body)) = &message.body else {
+            return vec![];
+        };
+
+        // Their values are all in memory; ours are read back from the overflow as they're
+        // compared.
+        let missing = node
+            .broadcast
+            .messages
+            .values()
+            .iter()
+            .filter(|value| !body.messages.contains(value))
+            .collect();
+
+        merge(node, body.messages.iter());
+
+        if body.msg_id.is_none() {
+            return vec![];
+        }
+
+        let body = MessageBody::Internal(InternalBody::SyncOk(SyncOkBody {
+            messages: missing,
+            ..Default::default()
+        }));
+
+        vec![node.reply(&message, body)]
+    }
+}
+
+#[cfg(feature = "broadcast")]
+pub struct GossipDigestHandler;
+
+#[cfg(feature = "broadcast")]
+impl Handler for GossipDigestHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Internal(InternalBody::GossipDigest(body)) = &message.body else {
+            return vec![];
+        };
+
+        let values = node.broadcast.messages.values();
+        let buckets = Digest::differing(&Digest::of(values.iter()), &body.digest);
+
+        if buckets.is_empty() {
+            return vec![];
+        }
+
+        let body = MessageBody::Internal(InternalBody::GossipPull(GossipPullBody {
+            messages: Digest::values_in(values.iter(), &buckets),
+            buckets,
+            ..Default::default()
+        }));
+
+        vec![node.reply(&message, body)]
+    }
+}
+
+#[cfg(feature = "broadcast")]
+pub struct GossipHandler;
+
+#[cfg(feature = "broadcast")]
+impl Handler for GossipHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Internal(InternalBody::Gossip(body)) = &message.body else {
+            return vec![];
+        };
+
+        merge(node, body.messages.iter().cloned());
+
+        vec![]
+    }
+}
+
+#[cfg(feature = "broadcast")]
+pub struct ReadHandler;
+
+#[cfg(feature = "broadcast")]
+impl Handler for ReadHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Read(_) = &message.body else {
+            return vec![];
+        };
+
+        // The message set is copy-on-write, so this is a reference count increment; the reply
+        // is serialized outside the node's task, reading back any spilled values as it goes.
+        let body = MessageBody::ReadOk(ReadOkBody {
+            messages: Some(node.broadcast.messages.values()),
+            value: None,
+            ..Default::default()
+        });
+
+        vec![node.reply(&message, body)]
+    }
+}
+
+#[cfg(feature = "broadcast")]
+pub struct ReadOkHandler;
+
+#[cfg(feature = "broadcast")]
+impl Handler for ReadOkHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::ReadOk(body) = &message.body else {
+            return vec![];
+        };
+
+        if node.broadcast.bootstrap.complete(body.in_reply_to) {
+            for value in body.messages.iter().flat_map(BroadcastValues::iter) {
+                node.insert_message(value);
+            }
+
+            info!("Recovered values from {:?}", message.src);
+        }
+
+        vec![]
+    }
+}
+
+#[cfg(feature = "broadcast")]
+pub struct TopologyHandler;
+
+#[cfg(feature = "broadcast")]
+impl Handler for TopologyHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Topology(body) = &message.body else {
+            return vec![];
+        };
+
+        let mut messages = vec![];
+
+        for warning in body.topology.validate(&node.node_ids) {
+            warn!("Topology warning: {}", warning);
+        }
+
+        let overlay = node
+            .broadcast
+            .overlay_strategy
+            .build(&body.topology, &node.node_ids);
+
+        if let Some(topology) = node.id.as_ref().and_then(|id| overlay.neighbors(id)) {
+            node.broadcast.topology = topology.to_vec();
+
+            info!("My neighbors are: {:?}", node.broadcast.topology);
+        }
+
+        node.broadcast.overlay = overlay;
+
+        // A node without any values may have restarted; recover them from the neighbors before
+        // answering client reads.
+        if node.broadcast.bootstrap.enabled && node.broadcast.messages.is_empty() {
+            for neighbor in node.broadcast.topology.clone() {
+                let msg_id = node.next_message_id();
+
+                messages.push(Message {
+                    src: node.id.clone(),
+                    dest: neighbor,
+                    body: MessageBody::Read(ReadBody {
+                        key: None,
+                        msg_id: Some(msg_id),
+                    }),
+                    lamport: None,
+                });
+            }
+
+            node.broadcast
+                .bootstrap
+                .start(messages.iter().filter_map(|read| read.body.msg_id()));
+        }
+
+        let body = MessageBody::TopologyOk(TopologyOkBody::default());
+
+        messages.push(node.reply(&message, body));
+
+        messages
+    }
+}
+
+#[cfg(feature = "broadcast")]
+pub struct TopologyReportHandler;
+
+#[cfg(feature = "broadcast")]
+impl Handler for TopologyReportHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::TopologyReport(_) = &message.body else {
+            return vec![];
+        };
+
+        let body = MessageBody::TopologyReportOk(TopologyReportOkBody {
+            report: node.broadcast.overlay.report(&node.node_ids),
+            ..Default::default()
+        });
+
+        vec![node.reply(&message, body)]
+    }
+}
+
+pub struct MetricsHandler;
+
+impl Handler for MetricsHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Metrics(_) = &message.body else {
+            return vec![];
+        };
+
+        let body = MessageBody::MetricsOk(MetricsOkBody {
+            report: MetricsReport::take(node),
+            ..Default::default()
+        });
+
+        vec![node.reply(&message, body)]
+    }
+}
+
+pub struct HeartbeatHandler;
+
+impl Handler for HeartbeatHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        if let Some(src) = &message.src {
+            node.liveness.heartbeat(src);
+        }
+
+        vec![]
+    }
+}
+
+/// Fires a timer the node sent itself. Timers from anyone else are ignored.
+pub struct TimerHandler;
+
+impl Handler for TimerHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Internal(InternalBody::Timer(body)) = &message.body else {
+            return vec![];
+        };
+
+        if message.src.is_none() || message.src != node.id {
+            warn!("Ignoring a timer from {:?}.", message.src);
+            return vec![];
+        }
+
+        node.fire(&body.event)
+    }
+}
+
+/// Crashes the node on request, with `--enable-admin`.
+pub struct CrashHandler;
+
+impl Handler for CrashHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Crash(body) = &message.body else {
+            return vec![];
+        };
+
+        if !node.config.admin {
+            return vec![message.error_reply(
+                node.id.clone(),
+                ErrorCode::NotSupported,
+                "Admin messages need --enable-admin.",
+            )];
+        }
+
+        match body.mode {
+            CrashMode::Exit => admin::exit(),
+            CrashMode::Reset => admin::reset(node),
+        }
+
+        vec![node.reply(&message, MessageBody::CrashOk(CrashOkBody::default()))]
+    }
+}
+
+pub struct DebugStateHandler;
+
+impl Handler for DebugStateHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::DebugState(_) = &message.body else {
+            return vec![];
+        };
+
+        let body = MessageBody::DebugStateOk(DebugStateOkBody {
+            node_id: node.id.clone(),
+            node_ids: node.node_ids.clone(),
+            #[cfg(feature = "broadcast")]
+            topology: node.broadcast.topology.clone(),
+            #[cfg(feature = "broadcast")]
+            messages: node.broadcast.messages.len(),
+            pending_retries: node.unacknowledged.len(),
+            callbacks: node.correlations.len(),
+            config: node.config.settings(),
+            ..Default::default()
+        });
+
+        vec![node.reply(&message, body)]
+    }
+}
+
+pub struct PeerStatusHandler;
+
+impl Handler for PeerStatusHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::PeerStatus(_) = &message.body else {
+            return vec![];
+        };
+
+        let body = MessageBody::PeerStatusOk(PeerStatusOkBody {
+            peers: node.peer_health.table(),
+            ..Default::default()
+        });
+
+        vec![node.reply(&message, body)]
+    }
+}
+
+#[cfg(feature = "counter")]
+pub struct AddHandler;
+
+#[cfg(feature = "counter")]
+impl Handler for AddHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Add(body) = &message.body else {
+            return vec![];
+        };
+
+        // Every node only increments its own count; other nodes learn it through `replicate`.
+        if let Some(node_id) = node.id.clone() {
+            let op = CounterOp::Add {
+                node_id,
+                delta: body.delta,
+            };
+
+            if node.counter.apply(op) {
+                record_counter(node);
+            }
+        }
+
+        let body = MessageBody::AddOk(AddOkBody::default());
+
+        vec![node.reply(&message, body)]
+    }
+}
+
+/// Log the counter's counts to be saved, after a change.
+#[cfg(feature = "counter")]
+fn record_counter(node: &mut Node) {
+    node.persistence.record(|| Record::Counter {
+        increments: node.counter.increments().clone(),
+        decrements: node.counter.decrements().clone(),
+    });
+}
+
+#[cfg(feature = "counter")]
+pub struct CounterReadHandler;
+
+#[cfg(feature = "counter")]
+impl Handler for CounterReadHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Read(_) = &message.body else {
+            return vec![];
+        };
+
+        let body = MessageBody::ReadOk(ReadOkBody {
+            messages: None,
+            value: Some(node.counter.query(()).into()),
+            ..Default::default()
+        });
+
+        vec![node.reply(&message, body)]
+    }
+}
+
+#[cfg(any(feature = "counter", feature = "kv"))]
+pub struct ReplicateHandler;
+
+#[cfg(any(feature = "counter", feature = "kv"))]
+impl Handler for ReplicateHandler {
+    fn handle(&self, node: &mut Node, mut message: Message) -> Vec<Message<'static>> {
+        if let MessageBody::Internal(InternalBody::Replicate(body)) = &mut message.body {
+            #[cfg(feature = "counter")]
+            {
+                let op = CounterOp::Merge {
+                    increments: std::mem::take(&mut body.increments),
+                    decrements: std::mem::take(&mut body.decrements),
+                };
+
+                if node.counter.apply(op) {
+                    record_counter(node);
+                }
+            }
+
+            #[cfg(feature = "kv")]
+            {
+                for register in body.entries.values() {
+                    node.kv.hlc.observe(&register.timestamp);
+                }
+
+                node.kv.lww.merge(std::mem::take(&mut body.entries));
+
+                if let (Some(src), Some(sent_at)) = (&message.src, body.sent_at.take()) {
+                    node.kv.sessions.replicated(src, sent_at);
+                }
+
+                node.kv.sessions.merge(&body.sessions);
+
+                return serve_session_reads(node);
+            }
+        }
+
+        vec![]
+    }
+}
+
+#[cfg(feature = "kafka")]
+pub struct SendHandler;
+
+#[cfg(feature = "kafka")]
+impl Handler for SendHandler {
+    fn handle(&self, node: &mut Node, mut message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Send(body) = &message.body else {
+            return vec![];
+        };
+
+        // Each key's log lives on the node that owns the key.
+        let (_, remote) = by_owner(node, &message, [(body.key.to_string(), ())]);
+
+        if let Some(owner) = remote.into_keys().next() {
+            return forward(node, owner, message);
+        }
+
+        if !node.memory_bounds.allows(&MemoryUsage::measure(node)) {
+            return vec![memory_full(node, &message)];
+        }
+
+        let MessageBody::Send(body) = &mut message.body else {
+            return vec![];
+        };
+
+        let LogEffect::Appended(offset) = node.kafka.logs.apply(LogOp::Send {
+            key: std::mem::take(&mut body.key).into_owned(),
+            msg: body.msg.take(),
+        }) else {
+            return vec![];
+        };
+
+        let body = MessageBody::SendOk(SendOkBody {
+            offset,
+            ..Default::default()
+        });
+
+        vec![node.reply(&message, body)]
+    }
+}
+
+#[cfg(feature = "kafka")]
+pub struct PollHandler;
+
+#[cfg(feature = "kafka")]
+impl Handler for PollHandler {
+    fn handle(&self, node: &mut Node, mut message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Poll(body) = &mut message.body else {
+            return vec![];
+        };
+
+        let offsets = std::mem::take(&mut body.offsets);
+        let (local, remote) = by_owner(node, &message, offsets);
+
+        let LogAnswer::Messages(msgs) = node
+            .kafka
+            .logs
+            .query(LogQuery::Poll(local.into_iter().collect()))
+        else {
+            return vec![];
+        };
+
+        let requests = remote
+            .into_iter()
+            .map(|(owner, offsets)| {
+                let offsets = offsets.into_iter().collect();
+                let body = MessageBody::Poll(PollBody {
+                    offsets,
+                    msg_id: None,
+                });
+
+                (owner, body)
+            })
+            .collect();
+
+        gather(
+            node,
+            message,
+            requests,
+            msgs,
+            |msgs, reply| {
+                if let MessageBody::PollOk(body) = &reply.body {
+                    msgs.extend(body.msgs.clone());
+                }
+            },
+            |msgs| {
+                MessageBody::PollOk(PollOkBody {
+                    msgs,
+                    ..Default::default()
+                })
+            },
+        )
+    }
+}
+
+#[cfg(feature = "kafka")]
+pub struct CommitOffsetsHandler;
+
+#[cfg(feature = "kafka")]
+impl Handler for CommitOffsetsHandler {
+    fn handle(&self, node: &mut Node, mut message: Message) -> Vec<Message<'static>> {
+        let MessageBody::CommitOffsets(body) = &mut message.body else {
+            return vec![];
+        };
+
+        let offsets = std::mem::take(&mut body.offsets);
+        let (local, remote) = by_owner(node, &message, offsets);
+
+        let LogEffect::Committed(records) = node
+            .kafka
+            .logs
+            .apply(LogOp::Commit(local.into_iter().collect()))
+        else {
+            return vec![];
+        };
+
+        if let Some(sink) = &mut node.kafka.sink {
+            if let Err(err) = sink.mirror(&records) {
+                error!("Unable to mirror committed records: {:?}", err);
+            }
+        }
+
+        let requests = remote
+            .into_iter()
+            .map(|(owner, offsets)| {
+                let offsets = offsets.into_iter().collect();
+                let body = MessageBody::CommitOffsets(CommitOffsetsBody {
+                    offsets,
+                    msg_id: None,
+                });
+
+                (owner, body)
+            })
+            .collect();
+
+        gather(
+            node,
+            message,
+            requests,
+            (),
+            |_, _| (),
+            |()| MessageBody::CommitOffsetsOk(CommitOffsetsOkBody::default()),
+        )
+    }
+}
+
+#[cfg(feature = "kafka")]
+pub struct ListCommittedOffsetsHandler;
+
+#[cfg(feature = "kafka")]
+impl Handler for ListCommittedOffsetsHandler {
+    fn handle(&self, node: &mut Node, mut message: Message) -> Vec<Message<'static>> {
+        let MessageBody::ListCommittedOffsets(body) = &mut message.body else {
+            return vec![];
+        };
+
+        let keys = std::mem::take(&mut body.keys);
+        let (local, remote) = by_owner(node, &message, keys.into_iter().map(|key| (key, ())));
+        let local = local.into_iter().map(|(key, ())| key).collect();
+
+        let LogAnswer::Offsets(offsets) = node.kafka.logs.query(LogQuery::Committed(local)) else {
+            return vec![];
+        };
+
+        let requests = remote
+            .into_iter()
+            .map(|(owner, keys)| {
+                let keys = keys.into_iter().map(|(key, ())| key).collect();
+                let body = MessageBody::ListCommittedOffsets(ListCommittedOffsetsBody {
+                    keys,
+                    msg_id: None,
+                });
+
+                (owner, body)
+            })
+            .collect();
+
+        gather(
+            node,
+            message,
+            requests,
+            offsets,
+            |offsets, reply| {
+                if let MessageBody::ListCommittedOffsetsOk(body) = &reply.body {
+                    offsets.extend(body.offsets.clone());
+                }
+            },
+            |offsets| {
+                MessageBody::ListCommittedOffsetsOk(ListCommittedOffsetsOkBody {
+                    offsets,
+                    ..Default::default()
+                })
+            },
+        )
+    }
+}
+
+/// Some of a kafka request's keys, each with what the request asks of it.
+#[cfg(feature = "kafka")]
+type Keys<T> = Vec<(String, T)>;
+
+/// Split a kafka request's keys into this node's and those of each other node that owns some,
+/// by the ring. A request from another node was split already, so its keys are all this node's.
+#[cfg(feature = "kafka")]
+fn by_owner<T>(
+    node: &mut Node,
+    message: &Message,
+    keys: impl IntoIterator<Item = (String, T)>,
+) -> (Keys<T>, HashMap<String, Keys<T>>) {
+    let from_peer = message
+        .src
+        .as_ref()
+        .is_some_and(|src| node.node_ids.contains(src));
+    let mut local = vec![];
+    let mut remote: HashMap<String, Keys<T>> = HashMap::new();
+
+    node.ring.update(&node.node_ids);
+
+    for (key, value) in keys {
+        match node.ring.owner(&key) {
+            Some(owner) if !from_peer && Some(owner) != node.id.as_ref() => {
+                remote.entry(owner.clone()).or_default().push((key, value))
+            }
+            _ => local.push((key, value)),
+        }
+    }
+
+    (local, remote)
+}
+
+/// Send each owner its part of a client's request, merge their replies into what this node
+/// answered for its own keys, and reply to the client once every owner has. If any owner fails,
+/// the client gets its error instead.
+#[cfg(feature = "kafka")]
+fn gather<T: Send + 'static>(
+    node: &mut Node,
+    message: Message,
+    requests: Vec<(String, MessageBody<'static>)>,
+    local: T,
+    merge: fn(&mut T, &Message),
+    reply: fn(T) -> MessageBody<'static>,
+) -> Vec<Message<'static>> {
+    if requests.is_empty() {
+        return vec![node.reply(&message, reply(local))];
+    }
+
+    let message = message.into_owned();
+    let handle = node.handle();
+
+    node.spawn(async move {
+        let answers = future::try_join_all(requests.into_iter().map(|(owner, body)| {
+            let handle = &handle;
+
+            async move { rpc(handle, &owner, body).await }
+        }))
+        .await;
+
+        let _ = handle
+            .call(move |node| {
+                let answer = match answers {
+                    Ok(answers) => {
+                        let mut merged = local;
+                        answers.iter().for_each(|answer| merge(&mut merged, answer));
+
+                        node.reply(&message, reply(merged))
+                    }
+                    Err(err) => message.error_reply(node.id.clone(), err.code, &err.text),
+                };
+
+                node.send(vec![answer]);
+            })
+            .await;
+    });
+
+    vec![]
+}
+
+/// Forward a client's request to `dest`, and relay its reply back to the client.
+#[cfg(any(feature = "kafka", feature = "kv"))]
+fn forward(node: &mut Node, dest: String, message: Message) -> Vec<Message<'static>> {
+    let message = message.into_owned();
+    let handle = node.handle();
+
+    node.spawn(async move {
+        let reply = rpc(&handle, &dest, message.body.clone()).await;
+
+        let _ = handle
+            .call(move |node| {
+                let relayed = match reply {
+                    Ok(reply) => node.reply(&message, reply.body),
+                    Err(err) => message.error_reply(node.id.clone(), err.code, &err.text),
+                };
+
+                node.send(vec![relayed]);
+            })
+            .await;
+    });
+
+    vec![]
+}
+
+/// Serve a request in the `session` mode, holding a read until the node has every entry its
+/// client's session depends on.
+#[cfg(feature = "kv")]
+fn serve_in_session(node: &mut Node, message: Message) -> Vec<Message<'static>> {
+    let me = node.id.clone().unwrap_or_default();
+    let client = message.src.clone().unwrap_or_default();
+
+    if let MessageBody::Read(_) = message.body {
+        if node.kv.sessions.missing(&me, &client).is_some() {
+            node.kv.sessions.wait(message.into_owned());
+            return vec![];
+        }
+    }
+
+    let replies = kv_reply(node, &message);
+
+    // Whatever the outcome, the client has now seen the key's latest entry on this node.
+    if let Some(register) =
+        kv_key(&message.body).and_then(|key| node.kv.lww.entries().get(&key.to_string()))
+    {
+        let timestamp = register.timestamp.clone();
+        node.kv.sessions.observe(&client, &timestamp);
+    }
+
+    replies
+}
+
+/// Serve the held reads this node has caught up for, in the `session` mode.
+#[cfg(feature = "kv")]
+fn serve_session_reads(node: &mut Node) -> Vec<Message<'static>> {
+    let me = node.id.clone().unwrap_or_default();
+
+    node.kv
+        .sessions
+        .take_ready(&me)
+        .into_iter()
+        .flat_map(|read| serve_in_session(node, read))
+        .collect()
+}
+
+/// Forward the reads that have waited too long for replication to a node that has what they
+/// need, in the `session` mode.
+#[cfg(feature = "kv")]
+pub fn expire_session_reads(node: &mut Node) -> Vec<Message<'static>> {
+    let me = node.id.clone().unwrap_or_default();
+
+    node.kv
+        .sessions
+        .take_expired(&me)
+        .into_iter()
+        .flat_map(|(read, node_id)| forward(node, node_id, read))
+        .collect()
+}
+
+#[cfg(feature = "kv")]
+fn kv_key<'m>(body: &'m MessageBody) -> Option<&'m Value> {
+    match body {
+        MessageBody::Read(body) => body.key.as_ref(),
+        MessageBody::Write(body) => Some(&body.key),
+        MessageBody::Cas(body) => Some(&body.key),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "kv")]
+fn kv_read(node: &Node, key: &Value) -> Result<Value, ErrorCode> {
+    match node.config.kv_mode {
+        KvMode::Linearizable | KvMode::Sharded | KvMode::Service => {
+            node.kv.store.query(key.clone())
+        }
+        #[cfg(feature = "raft")]
+        KvMode::Raft => node.kv.store.query(key.clone()),
+        KvMode::Lww | KvMode::Session => node.kv.lww.read(key).cloned(),
+    }
+}
+
+#[cfg(feature = "kv")]
+fn kv_apply(node: &mut Node, op: KvOp) -> Result<(), ErrorCode> {
+    if !node.config.kv_mode.is_lww() {
+        // In the `raft` mode the store is rebuilt from the Raft log instead.
+        if matches!(node.config.kv_mode, KvMode::Linearizable | KvMode::Sharded) {
+            node.persistence.record(|| Record::Kv(op.clone()));
+        }
+
+        return node.kv.store.apply(op);
+    }
+
+    let timestamp = node.kv.hlc.now(node.id.as_deref().unwrap_or_default());
+
+    match op {
+        KvOp::Write { key, value } => {
+            node.kv.lww.write(&key, value, timestamp);
+            Ok(())
+        }
+        KvOp::Cas {
+            key,
+            from,
+            to,
+            create_if_not_exists,
+        } => node
+            .kv
+            .lww
+            .cas(&key, &from, to, create_if_not_exists, timestamp),
+    }
+}
+
+/// Serves `read`, `write`, and `cas` from the node's key-value store. By default every request
+/// is applied on one node, the cluster's `tiebreak::leader`, so operations are linearizable; the
+/// other nodes forward requests to it. In the `lww` mode every node serves requests from its own
+/// `LwwMap`, which the replicate task spreads to the others. In the `raft` mode requests are
+/// applied once the Raft log commits them. In the `sharded` mode each key's requests are applied
+/// on the node that owns it on the `HashRing`. In the `service` mode Maelstrom's `lin-kv` service
+/// applies them.
+#[cfg(feature = "kv")]
+pub struct KvHandler;
+
+#[cfg(feature = "kv")]
+impl Handler for KvHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        if matches!(message.body, MessageBody::Write(_) | MessageBody::Cas(_))
+            && !node.memory_bounds.allows(&MemoryUsage::measure(node))
+        {
+            return vec![memory_full(node, &message)];
+        }
+
+        let leader = match node.config.kv_mode {
+            KvMode::Linearizable => tiebreak::leader(&node.node_ids).cloned(),
+            KvMode::Sharded => {
+                node.ring.update(&node.node_ids);
+
+                kv_key(&message.body)
+                    .and_then(|key| node.ring.owner(&key.to_string()))
+                    .cloned()
+            }
+            KvMode::Lww => None,
+            KvMode::Session => return serve_in_session(node, message),
+            KvMode::Service => return serve_from_service(node, message),
+            #[cfg(feature = "raft")]
+            KvMode::Raft => match message.body {
+                MessageBody::Read(_)
+                    if node.config.raft_reads != RaftReads::Log
+                        && node.kv.raft.knows_commit_index() =>
+                {
+                    return read_index(node, message)
+                }
+                _ => return propose(node, message),
+            },
+        };
+
+        if let Some(leader) = leader.filter(|leader| Some(leader) != node.id.as_ref()) {
+            return forward(node, leader, message);
+        }
+
+        kv_reply(node, &message)
+    }
+}
+
+/// Serve a request from Maelstrom's `lin-kv` service, replying once the service has.
+#[cfg(feature = "kv")]
+fn serve_from_service(node: &mut Node, message: Message) -> Vec<Message<'static>> {
+    let message = message.into_owned();
+    let kv = KvClient::new(node.handle(), KvService::Lin);
+    let handle = node.handle();
+
+    node.spawn(async move {
+        let result = match &message.body {
+            MessageBody::Read(body) => {
+                kv.kv_read(body.key.clone().unwrap_or_default())
+                    .await
+                    .map(|value| {
+                        MessageBody::ReadOk(ReadOkBody {
+                            value: Some(value),
+                            ..Default::default()
+                        })
+                    })
+            }
+            MessageBody::Write(body) => kv
+                .kv_write(body.key.clone(), body.value.clone())
+                .await
+                .map(|()| MessageBody::WriteOk(WriteOkBody::default())),
+            MessageBody::Cas(body) => kv
+                .kv_cas(
+                    body.key.clone(),
+                    body.from.clone(),
+                    body.to.clone(),
+                    body.create_if_not_exists,
+                )
+                .await
+                .map(|()| MessageBody::CasOk(CasOkBody::default())),
+            _ => return,
+        };
+
+        let _ = handle
+            .call(move |node| {
+                let reply = match result {
+                    Ok(body) => node.reply(&message, body),
+                    Err(err) => message.error_reply(node.id.clone(), err.code, &err.text),
+                };
+
+                node.send(vec![reply]);
+            })
+            .await;
+    });
+
+    vec![]
+}
+
+/// Serve a read on the Raft leader without adding it to the log, once the leader has confirmed
+/// it's still the leader.
+#[cfg(feature = "raft")]
+fn read_index(node: &mut Node, message: Message) -> Vec<Message<'static>> {
+    let me = node.id.clone().unwrap_or_default();
+    let lease = node.config.raft_reads == RaftReads::Lease;
+
+    let mut messages = match node.kv.raft.read(message.into_owned(), lease) {
+        true => {
+            let outgoing = node.kv.raft.replicate(&me, &node.node_ids);
+            raft_messages(node, outgoing)
+        }
+        false => vec![],
+    };
+
+    messages.extend(apply_committed(node));
+
+    messages
+}
+
+/// Propose a kv request to Raft as the leader, forward it to the leader, or turn it away while
+/// there's none. The leader replies once the request is committed.
+#[cfg(feature = "raft")]
+fn propose(node: &mut Node, message: Message) -> Vec<Message<'static>> {
+    let me = node.id.clone().unwrap_or_default();
+
+    if node
+        .kv
+        .raft
+        .propose(&me, &node.node_ids, message.clone().into_owned())
+        .is_some()
+    {
+        let outgoing = node.kv.raft.replicate(&me, &node.node_ids);
+        let mut messages = raft_messages(node, outgoing);
+
+        // Alone, the leader commits right away.
+        messages.extend(apply_committed(node));
+
+        return messages;
+    }
+
+    match node.kv.raft.leader.clone() {
+        Some(leader) if leader != me => forward(node, leader, message),
+        _ => vec![message.error_reply(
+            node.id.clone(),
+            ErrorCode::TemporarilyUnavailable,
+            "There's no leader yet.",
+        )],
+    }
+}
+
+/// Apply newly committed entries to the store, replying to the requests this node proposed, then
+/// serve the reads that are now safe to.
+#[cfg(feature = "raft")]
+pub fn apply_committed(node: &mut Node) -> Vec<Message<'static>> {
+    let mut messages = vec![];
+
+    if let Some(snapshot) = node.kv.raft.take_restore() {
+        match serde_json::from_value(snapshot) {
+            Ok(kv) => node.kv.store = kv,
+            Err(err) => error!("Unable to restore the leader's snapshot: {}", err),
+        }
+    }
+
+    for (index, entry) in node.kv.raft.take_committed() {
+        let replies = kv_reply(node, &entry.request);
+
+        match node.kv.raft.waiting.remove(&index) {
+            Some(request)
+                if request.src == entry.request.src
+                    && request.body.msg_id() == entry.request.body.msg_id() =>
+            {
+                messages.extend(replies)
+            }
+            // A new leader replaced the entry before it was committed.
+            Some(request) => messages.push(request.error_reply(
+                node.id.clone(),
+                ErrorCode::TemporarilyUnavailable,
+                "The leader changed before the request was committed.",
+            )),
+            None => {}
+        }
+    }
+
+    if node.kv.raft.log_len() > node.config.raft_log_limit {
+        match serde_json::to_value(&node.kv.store) {
+            Ok(state) => node.kv.raft.compact(state),
+            Err(err) => error!("Unable to snapshot the store: {}", err),
+        }
+    }
+
+    let (ready, abandoned) = node.kv.raft.take_reads();
+
+    for request in ready {
+        messages.extend(kv_reply(node, &request));
+    }
+
+    for request in abandoned {
+        messages.push(request.error_reply(
+            node.id.clone(),
+            ErrorCode::TemporarilyUnavailable,
+            "The leader changed before the read was served.",
+        ));
+    }
+
+    messages
+}
+
+#[cfg(feature = "raft")]
+pub fn raft_messages(node: &Node, outgoing: Outgoing) -> Vec<Message<'static>> {
+    outgoing
+        .into_iter()
+        .map(|(dest, body)| Message {
+            src: node.id.clone(),
+            dest,
+            body,
+            lamport: None,
+        })
+        .collect()
+}
+
+/// Raft's own messages, in the kv workload's `raft` mode.
+#[cfg(feature = "raft")]
+pub struct RaftHandler;
+
+#[cfg(feature = "raft")]
+impl Handler for RaftHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let me = node.id.clone().unwrap_or_default();
+        let from = message.src.unwrap_or_default();
+
+        let outgoing = node
+            .kv
+            .raft
+            .handle(&me, &node.node_ids, &from, &message.body);
+        let mut messages = raft_messages(node, outgoing);
+
+        messages.extend(apply_committed(node));
+
+        messages
+    }
+}
+
+/// Apply a kv request to the node's store, returning the reply to its sender.
+#[cfg(feature = "kv")]
+fn kv_reply(node: &mut Node, message: &Message) -> Vec<Message<'static>> {
+    let result = match &message.body {
+        MessageBody::Read(body) => body
+            .key
+            .as_ref()
+            .ok_or(ErrorCode::MalformedRequest)
+            .and_then(|key| kv_read(node, key))
+            .map(|value| {
+                MessageBody::ReadOk(ReadOkBody {
+                    value: Some(value),
+                    ..Default::default()
+                })
+            }),
+        MessageBody::Write(body) => kv_apply(
+            node,
+            KvOp::Write {
+                key: body.key.clone(),
+                value: body.value.clone(),
+            },
+        )
+        .map(|()| MessageBody::WriteOk(WriteOkBody::default())),
+        MessageBody::Cas(body) => kv_apply(
+            node,
+            KvOp::Cas {
+                key: body.key.clone(),
+                from: body.from.clone(),
+                to: body.to.clone(),
+                create_if_not_exists: body.create_if_not_exists,
+            },
+        )
+        .map(|()| MessageBody::CasOk(CasOkBody::default())),
+        _ => return vec![],
+    };
+
+    match result {
+        Ok(body) => vec![node.reply(message, body)],
+        Err(code) => {
+            let text = match code {
+                ErrorCode::KeyDoesNotExist => "Key does not exist.",
+                ErrorCode::PreconditionFailed => "The value didn't match.",
+                _ => "A key is required.",
+            };
+
+            vec![message.error_reply(node.id.clone(), code, text)]
+        }
+    }
+}
+
+/// Coordinates a client's `txn`: the nodes owning its keys prepare their parts, and it commits
+/// once every one has voted to.
+#[cfg(feature = "txn")]
+pub struct TxnHandler;
+
+#[cfg(feature = "txn")]
+impl Handler for TxnHandler {
+    fn handle(&self, node: &mut Node, mut message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Txn(body) = &mut message.body else {
+            return vec![];
+        };
+
+        if body.txn.iter().any(|TxnOp(op, _, _)| op == "w")
+            && !node.memory_bounds.allows(&MemoryUsage::measure(node))
+        {
+            return vec![memory_full(node, &message)];
+        }
+
+        let me = node.id.clone().unwrap_or_default();
+        let txn_id = format!("{}-{}", me, node.next_message_id());
+        // Replies only need the request's `src` and `msg_id`, so its operations are moved out.
+        let ops = std::mem::take(&mut body.txn);
+        node.ring.update(&node.node_ids);
+        let plan = Transactions::plan(&ops, &node.ring);
+
+        // The local part goes first, so a conflict aborts before any other node is asked.
+        let mut parts: Vec<(String, Vec<TxnOp>)> = plan
+            .iter()
+            .map(|(participant, indexes)| {
+                let part = indexes.iter().map(|index| ops[*index].clone()).collect();
+                (participant.clone(), part)
+            })
+            .collect();
+        parts.sort_by_key(|(participant, _)| *participant != me);
+
+        if parts.is_empty() {
+            let body = MessageBody::TxnOk(TxnOkBody {
+                txn: ops,
+                ..Default::default()
+            });
+
+            return vec![node.reply(&message, body)];
+        }
+
+        node.txns.begin(&txn_id, message.into_owned(), ops, plan);
+
+        let mut messages = vec![];
+
+        for (participant, part) in parts {
+            if !node.txns.is_coordinating(&txn_id) {
+                break;
+            }
+
+            if participant == me {
+                let vote = node.txns.prepare(&txn_id, &me, &part);
+                messages.extend(count_vote(node, &txn_id, &participant, vote));
+            } else {
+                send_prepare(node, &txn_id, participant, part);
+            }
+        }
+
+        messages
+    }
+}
+
+#[cfg(feature = "txn")]
+fn txn_error_text(code: ErrorCode) -> &'static str {
+    match code {
+        ErrorCode::TxnConflict => "A key is locked by another transaction.",
+        ErrorCode::MalformedRequest => "Unknown micro-operation.",
+        _ => "The transaction was aborted.",
+    }
+}
+
+/// Ask a participant to prepare its part of a transaction, counting its vote when it replies.
+#[cfg(feature = "txn")]
+fn send_prepare(node: &mut Node, txn_id: &str, participant: String, part: Vec<TxnOp>) {
+    let handle = node.handle();
+    let txn_id = txn_id.to_string();
+
+    node.spawn(async move {
+        let body = MessageBody::Internal(InternalBody::TxnPrepare(TxnPrepareBody {
+            txn_id: txn_id.clone(),
+            txn: part,
+            msg_id: None,
+        }));
+
+        let vote = match rpc(&handle, &participant, body).await {
+            Ok(Message {
+                body: MessageBody::Internal(InternalBody::TxnPrepareOk(body)),
+                ..
+            }) => Ok(body.txn),
+            Ok(_) => Err(ErrorCode::MalformedRequest),
+            // Aborting settles the outcome, so the client can be told it definitely failed.
+            Err(err) if err.code == ErrorCode::Timeout => Err(ErrorCode::Abort),
+            Err(err) => Err(err.code),
+        };
+
+        let _ = handle
+            .call(move |node| {
+                let messages = count_vote(node, &txn_id, &participant, vote);
+                node.send(messages);
+            })
+            .await;
+    });
+}
+
+#[cfg(feature = "txn")]
+fn count_vote(
+    node: &mut Node,
+    txn_id: &str,
+    participant: &str,
+    vote: Result<Vec<TxnOp>, ErrorCode>,
+) -> Vec<Message<'static>> {
+    match vote {
+        Ok(part) => match node.txns.vote(txn_id, participant, part) {
+            Some(commit) => commit_txn(node, txn_id, commit),
+            None if node.txns.is_coordinating(txn_id) || node.txns.is_committed(txn_id) => vec![],
+            // The transaction was aborted while the vote was on its way.
+            None => send_aborts(node, txn_id, &[participant.to_string()]),
+        },
+        Err(code) => abort_txn(node, txn_id, code),
+    }
+}
+
+/// Tell every participant to commit, resending until each acknowledges, and reply to the
+/// client.
+#[cfg(feature = "txn")]
+fn commit_txn(node: &mut Node, txn_id: &str, commit: Commit) -> Vec<Message<'static>> {
+    let me = node.id.clone().unwrap_or_default();
+    let mut messages = vec![];
+
+    for participant in commit.participants {
+        if participant == me {
+            node.txns.commit(txn_id);
+            node.txns.acknowledge(txn_id, &me);
+            continue;
+        }
+
+        let handle = node.handle();
+        let txn_id = txn_id.to_string();
+
+        node.spawn(async move {
+            let body = MessageBody::Internal(InternalBody::TxnCommit(TxnCommitBody {
+                txn_id: txn_id.clone(),
+                msg_id: None,
+            }));
+
+            // Until the participant acknowledges, the retry task resends it.
+            if rpc::deliver(&handle, &participant, body, |_, _| {})
+                .await
+                .is_some()
+            {
+                let _ = handle
+                    .call(move |node| node.txns.acknowledge(&txn_id, &participant))
+                    .await;
+            }
+        });
+    }
+
+    let body = MessageBody::TxnOk(TxnOkBody {
+        txn: commit.ops,
+        ..Default::default()
+    });
+
+    messages.push(node.reply(&commit.request, body));
+
+    messages
+}
+
+/// Abort a transaction this node coordinates, if it hasn't committed, and tell the client.
+#[cfg(feature = "txn")]
+fn abort_txn(node: &mut Node, txn_id: &str, code: ErrorCode) -> Vec<Message<'static>> {
+    let Some((request, participants)) = node.txns.abort_coordinating(txn_id) else {
+        return vec![];
+    };
+
+    let mut messages = send_aborts(node, txn_id, &participants);
+    messages.push(request.error_reply(node.id.clone(), code, txn_error_text(code)));
+
+    messages
+}
+
+#[cfg(feature = "txn")]
+fn send_aborts(node: &mut Node, txn_id: &str, participants: &[String]) -> Vec<Message<'static>> {
+    let mut messages = vec![];
+
+    for participant in participants {
+        if Some(participant) == node.id.as_ref() {
+            node.txns.abort(txn_id);
+            continue;
+        }
+
+        messages.push(Message {
+            src: node.id.clone(),
+            dest: participant.clone(),
+            body: MessageBody::Internal(InternalBody::TxnAbort(TxnAbortBody {
+                txn_id: txn_id.to_string(),
+                msg_id: None,
+            })),
+            lamport: None,
+        });
+    }
+
+    messages
+}
+
+/// Abort the transactions this node coordinates that have waited too long for votes, and ask
+/// the coordinators of transactions prepared here for too long how they ended.
+#[cfg(feature = "txn")]
+pub fn expire_transactions(node: &mut Node) -> Vec<Message<'static>> {
+    let mut messages = vec![];
+
+    for txn_id in node.txns.expired() {
+        messages.extend(abort_txn(node, &txn_id, ErrorCode::Abort));
+    }
+
+    for (txn_id, coordinator) in node.txns.in_doubt() {
+        // This node's own transactions are settled by `expired`.
+        if Some(&coordinator) == node.id.as_ref() {
+            continue;
+        }
+
+        let handle = node.handle();
+
+        node.spawn(async move {
+            let body = MessageBody::Internal(InternalBody::TxnStatus(TxnStatusBody {
+                txn_id: txn_id.clone(),
+                msg_id: None,
+            }));
+
+            let status = rpc(&handle, &coordinator, body).await;
+
+            let _ = handle
+                .call(move |node| match status {
+                    Ok(Message {
+                        body: MessageBody::Internal(InternalBody::TxnStatusOk(body)),
+                        ..
+                    }) if body.committed => node.txns.commit(&txn_id),
+                    Ok(_) => node.txns.abort(&txn_id),
+                    // Asked again after the next timeout.
+                    Err(_) => {}
+                })
+                .await;
+        });
+    }
+
+    messages
+}
+
+/// A participant locks its keys of a transaction and votes.
+#[cfg(feature = "txn")]
+pub struct TxnPrepareHandler;
+
+#[cfg(feature = "txn")]
+impl Handler for TxnPrepareHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Internal(InternalBody::TxnPrepare(body)) = &message.body else {
+            return vec![];
+        };
+
+        let coordinator = message.src.clone().unwrap_or_default();
+
+        match node.txns.prepare(&body.txn_id, &coordinator, &body.txn) {
+            Ok(txn) => {
+                let body = TxnPrepareOkBody {
+                    txn_id: body.txn_id.clone(),
+                    txn,
+                    ..Default::default()
+                };
+
+                vec![node.reply(
+                    &message,
+                    MessageBody::Internal(InternalBody::TxnPrepareOk(body)),
+                )]
+            }
+            Err(code) => vec![message.error_reply(node.id.clone(), code, txn_error_text(code))],
+        }
+    }
+}
+
+#[cfg(feature = "txn")]
+pub struct TxnCommitHandler;
+
+#[cfg(feature = "txn")]
+impl Handler for TxnCommitHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Internal(InternalBody::TxnCommit(body)) = &message.body else {
+            return vec![];
+        };
+
+        node.txns.commit(&body.txn_id);
+
+        vec![node.reply(
+            &message,
+            MessageBody::Internal(InternalBody::TxnCommitOk(TxnCommitOkBody::default())),
+        )]
+    }
+}
+
+#[cfg(feature = "txn")]
+pub struct TxnAbortHandler;
+
+#[cfg(feature = "txn")]
+impl Handler for TxnAbortHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        if let MessageBody::Internal(InternalBody::TxnAbort(body)) = &message.body {
+            node.txns.abort(&body.txn_id);
+        }
+
+        vec![]
+    }
+}
+
+/// Answers a participant in doubt. A transaction still waiting for votes is aborted, and any
+/// without a commit on record was aborted.
+#[cfg(feature = "txn")]
+pub struct TxnStatusHandler;
+
+#[cfg(feature = "txn")]
+impl Handler for TxnStatusHandler {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Internal(InternalBody::TxnStatus(body)) = &message.body else {
+            return vec![];
+        };
+
+        let mut messages = abort_txn(node, &body.txn_id, ErrorCode::Abort);
+
+        let status = TxnStatusOkBody {
+            committed: node.txns.is_committed(&body.txn_id),
+            ..Default::default()
+        };
+        messages.push(node.reply(
+            &message,
+            MessageBody::Internal(InternalBody::TxnStatusOk(status)),
+        ));
+
+        messages
+    }
+}
+
+pub struct ErrorHandler;
+
+impl Handler for ErrorHandler {
+    fn handle(&self, _node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        warn!("Received an error: {:?}", message);
+
+        vec![]
+    }
+}
@@ -0,0 +1,122 @@
+//! How responsive each peer has been. Every resend to a peer counts as a timeout, and any reply
+//! from it clears them; a peer with enough consecutive timeouts is suspect, then down, and
+//! messages to it are retried less often.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use tracing::info;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    #[default]
+    Healthy,
+    Suspect,
+    Down,
+}
+
+/// A peer's row in the health table.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PeerReport {
+    pub status: Health,
+    pub consecutive_timeouts: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct PeerHealth {
+    timeouts: HashMap<String, u32>,
+}
+
+impl PeerHealth {
+    pub const SUSPECT_AFTER: u32 = 3;
+    pub const DOWN_AFTER: u32 = 6;
+
+    pub fn record_timeout(&mut self, peer: &str) {
+        let timeouts = self.timeouts.entry(peer.to_string()).or_default();
+        let before = PeerHealth::health(*timeouts);
+
+        *timeouts += 1;
+
+        let after = PeerHealth::health(*timeouts);
+        if after != before {
+            info!("Peer {} is now {:?}.", peer, after);
+        }
+    }
+
+    pub fn record_reply(&mut self, peer: &str) {
+        if let Some(timeouts) = self.timeouts.get_mut(peer) {
+            if *timeouts >= PeerHealth::SUSPECT_AFTER {
+                info!("Peer {} is healthy again.", peer);
+            }
+
+            *timeouts = 0;
+        }
+    }
+
+    fn health(timeouts: u32) -> Health {
+        match timeouts {
+            timeouts if timeouts >= PeerHealth::DOWN_AFTER => Health::Down,
+            timeouts if timeouts >= PeerHealth::SUSPECT_AFTER => Health::Suspect,
+            _ => Health::Healthy,
+        }
+    }
+
+    pub fn status(&self, peer: &str) -> Health {
+        PeerHealth::health(self.timeouts.get(peer).copied().unwrap_or_default())
+    }
+
+    /// How many times longer than usual to wait before resending to `peer`.
+    pub fn backoff(&self, peer: &str) -> u32 {
+        match self.status(peer) {
+            Health::Healthy => 1,
+            Health::Suspect => 2,
+            Health::Down => 4,
+        }
+    }
+
+    /// Every peer that has timed out, for the metrics and `peer_status`.
+    pub fn table(&self) -> BTreeMap<String, PeerReport> {
+        self.timeouts
+            .iter()
+            .map(|(peer, timeouts)| {
+                let report = PeerReport {
+                    status: PeerHealth::health(*timeouts),
+                    consecutive_timeouts: *timeouts,
+                };
+
+                (peer.clone(), report)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn suspects_then_downs_a_peer_until_it_replies() {
+        let mut health = PeerHealth::default();
+
+        for _ in 0..PeerHealth::SUSPECT_AFTER {
+            health.record_timeout("n2");
+        }
+        assert_eq!(health.status("n2"), Health::Suspect);
+
+        for _ in PeerHealth::SUSPECT_AFTER..PeerHealth::DOWN_AFTER {
+            health.record_timeout("n2");
+        }
+        assert_eq!(health.status("n2"), Health::Down);
+        assert_eq!(health.backoff("n2"), 4);
+        assert_eq!(
+            health.table()["n2"].consecutive_timeouts,
+            PeerHealth::DOWN_AFTER
+        );
+
+        health.record_reply("n2");
+        assert_eq!(health.status("n2"), Health::Healthy);
+        assert_eq!(health.status("n3"), Health::Healthy);
+    }
+}
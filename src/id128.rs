@@ -0,0 +1,92 @@
+//! 128-bit IDs, as UUIDv7s or ULIDs, built from a Snowflake ID. The Snowflake's timestamp becomes
+//! the 48-bit Unix millisecond timestamp both formats start with, and its sequence and node index
+//! follow, so the IDs keep the Snowflake's uniqueness and order; the remaining bits are random.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::snowflake::{Layout, Snowflake};
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// The Unix millisecond timestamp, sequence, and node index of a wide Snowflake ID.
+fn parts(id: u64) -> (u128, u128, u128) {
+    let layout = Layout::WIDE;
+
+    (
+        (layout.millis(id) + Snowflake::EPOCH) as u128,
+        layout.sequence(id) as u128,
+        layout.node_index(id) as u128,
+    )
+}
+
+fn random() -> u128 {
+    RandomState::new().build_hasher().finish() as u128
+}
+
+/// A UUIDv7: the timestamp, the version, the sequence as the 12-bit counter, the variant, the
+/// node index, and 52 random bits.
+pub fn uuid_v7(id: u64) -> String {
+    let (millis, sequence, node_index) = parts(id);
+
+    let value = millis << 80
+        | 0x7 << 76
+        | sequence << 64
+        | 0b10 << 62
+        | node_index << 52
+        | random() & ((1 << 52) - 1);
+
+    let hex = format!("{:032x}", value);
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// A ULID: the timestamp, the sequence, the node index, and 58 random bits, in Crockford's
+/// base32.
+pub fn ulid(id: u64) -> String {
+    let (millis, sequence, node_index) = parts(id);
+
+    let value = millis << 80 | sequence << 68 | node_index << 58 | random() & ((1 << 58) - 1);
+
+    // 26 characters of 5 bits hold 130; the first only uses 3.
+    (0..26)
+        .rev()
+        .map(|digit| CROCKFORD[(value >> (digit * 5)) as usize & 0x1F] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_the_snowflake_order_and_node() {
+        let mut snowflake = Snowflake::default();
+        let ids: Vec<u64> = (0..100).map(|_| snowflake.next(5).unwrap()).collect();
+
+        let uuids: Vec<String> = ids.iter().map(|id| uuid_v7(*id)).collect();
+        let ulids: Vec<String> = ids.iter().map(|id| ulid(*id)).collect();
+
+        assert!(uuids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ulids.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let uuid = &uuids[0];
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "7");
+        assert!("89ab".contains(&uuid[19..20]));
+        assert_eq!(
+            u128::from_str_radix(&uuid.replace('-', ""), 16).unwrap() >> 52 & 0x3FF,
+            5
+        );
+
+        assert_eq!(ulids[0].len(), 26);
+        assert!(ulids[0].as_str() <= "7ZZZZZZZZZZZZZZZZZZZZZZZZZ");
+    }
+}
@@ -0,0 +1,125 @@
+//! Replies to recent client requests, keyed by the request's `(src, msg_id)`. Maelstrom clients
+//! retransmit requests; a retransmitted `add` or `send` is answered with the replies the first
+//! one got instead of being applied again. Transient errors, like `temporarily-unavailable`,
+//! aren't remembered, so a retransmission after one is handled afresh.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::core::message::{ErrorCode, Message, MessageBody};
+
+type RequestKey = (String, u32);
+
+/// A bounded map of client requests to the replies they were sent, forgetting the least
+/// recently used request once full.
+#[derive(Debug)]
+pub struct ReplyCache {
+    capacity: usize,
+    replies: HashMap<RequestKey, (u64, Vec<Message<'static>>)>,
+    order: BTreeMap<u64, RequestKey>,
+    tick: u64,
+}
+
+impl Default for ReplyCache {
+    fn default() -> Self {
+        ReplyCache::new(1024)
+    }
+}
+
+impl ReplyCache {
+    pub fn new(capacity: usize) -> Self {
+        ReplyCache {
+            capacity: capacity.max(1),
+            replies: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// How many requests are remembered.
+    pub fn len(&self) -> usize {
+        self.replies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replies.is_empty()
+    }
+
+    /// Forget every request.
+    pub fn clear(&mut self) {
+        self.replies.clear();
+        self.order.clear();
+    }
+
+    /// The key of a request from a client, i.e. a sender that isn't one of `node_ids`. Replies
+    /// and requests without a `msg_id` have none.
+    pub fn key(message: &Message, node_ids: &[String]) -> Option<RequestKey> {
+        let src = message.src.as_ref()?;
+
+        if node_ids.contains(src) || message.body.in_reply_to().is_some() {
+            return None;
+        }
+
+        Some((src.clone(), message.body.msg_id()?))
+    }
+
+    /// The replies to a request seen before. A request still waiting on its replies has none
+    /// yet; they'll answer the retransmission too.
+    pub fn replay(&mut self, key: &RequestKey) -> Option<Vec<Message<'static>>> {
+        let (tick, replies) = self.replies.get_mut(key)?;
+
+        self.order.remove(tick);
+        self.tick += 1;
+        *tick = self.tick;
+        self.order.insert(self.tick, key.clone());
+
+        Some(replies.clone())
+    }
+
+    /// Start remembering the replies to a request.
+    pub fn start(&mut self, key: RequestKey) {
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.replies.insert(key, (self.tick, vec![]));
+
+        while self.replies.len() > self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+
+            self.replies.remove(&oldest);
+        }
+    }
+
+    /// Remember the messages that reply to a request being remembered.
+    pub fn record(&mut self, messages: &[Message<'static>]) {
+        for message in messages {
+            let Some(in_reply_to) = message.body.in_reply_to() else {
+                continue;
+            };
+
+            let key = (message.dest.clone(), in_reply_to);
+
+            match &message.body {
+                MessageBody::Error(body)
+                    if matches!(
+                        body.code,
+                        ErrorCode::Timeout | ErrorCode::TemporarilyUnavailable
+                    ) =>
+                {
+                    self.forget(&key)
+                }
+                _ => {
+                    if let Some((_, replies)) = self.replies.get_mut(&key) {
+                        replies.push(message.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    fn forget(&mut self, key: &RequestKey) {
+        if let Some((tick, _)) = self.replies.remove(key) {
+            self.order.remove(&tick);
+        }
+    }
+}
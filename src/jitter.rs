@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use crate::tiebreak;
+
+/// Offsets the first tick of each timer by up to `max`, so nodes launched together don't
+/// gossip and retry in lockstep. Offsets are derived from the seed and the node, so a run can be
+/// reproduced.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StartupJitter {
+    pub max: Duration,
+    pub seed: u64,
+}
+
+impl StartupJitter {
+    /// The offset for one of a node's tasks. Before `init` the node has no id, so the process id
+    /// tells nodes apart instead.
+    pub fn delay(&self, node_id: Option<&str>, task: &str) -> Duration {
+        if self.max.is_zero() {
+            return Duration::ZERO;
+        }
+
+        let node = match node_id {
+            Some(node_id) => node_id.to_string(),
+            None => std::process::id().to_string(),
+        };
+
+        let hash = tiebreak::stable_hash(&[&self.seed.to_string(), &node, task]);
+
+        self.max.mul_f64(hash as f64 / u64::MAX as f64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn staggers_nodes_reproducibly() {
+        let jitter = StartupJitter {
+            max: Duration::from_millis(100),
+            seed: 7,
+        };
+
+        let delays: Vec<_> = ["n1", "n2", "n3"]
+            .iter()
+            .map(|node_id| jitter.delay(Some(node_id), "gossip"))
+            .collect();
+
+        assert!(delays.iter().all(|delay| *delay <= jitter.max));
+        assert_ne!(delays[0], delays[1]);
+        assert_eq!(delays[2], jitter.delay(Some("n3"), "gossip"));
+        assert_eq!(
+            StartupJitter::default().delay(None, "gossip"),
+            Duration::ZERO
+        );
+    }
+}
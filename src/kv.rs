@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::clock::HybridClock;
+use crate::core::lww::LwwMap;
+use crate::core::message::ErrorCode;
+use crate::machine::StateMachine;
+#[cfg(feature = "raft")]
+use crate::raft::Raft;
+use crate::session::Sessions;
+
+/// The kv workload's state on the node. Which parts are in use depends on its mode.
+#[derive(Debug, Default)]
+pub struct KvState {
+    /// The store, in the `linearizable` and `raft` modes.
+    pub store: KvStore,
+    /// The entries in the `lww` and `session` modes.
+    pub lww: LwwMap,
+    /// Timestamps the `lww` entries.
+    pub hlc: HybridClock,
+    /// Clients' session versions, in the `session` mode.
+    pub sessions: Sessions,
+    /// The consensus state in the `raft` mode.
+    #[cfg(feature = "raft")]
+    pub raft: Raft,
+}
+
+/// An in-memory key-value store with the semantics of Maelstrom's `lin-kv` service. Keys may be
+/// any JSON value; they're stored by their serialization.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct KvStore {
+    values: HashMap<String, Value>,
+}
+
+impl KvStore {
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn read(&self, key: &Value) -> Result<&Value, ErrorCode> {
+        self.values
+            .get(&key.to_string())
+            .ok_or(ErrorCode::KeyDoesNotExist)
+    }
+
+    pub fn write(&mut self, key: &Value, value: Value) {
+        self.values.insert(key.to_string(), value);
+    }
+
+    /// Set `key` to `to` if it is currently `from`. A missing key is only created when
+    /// `create_if_not_exists` is set.
+    pub fn cas(
+        &mut self,
+        key: &Value,
+        from: &Value,
+        to: Value,
+        create_if_not_exists: bool,
+    ) -> Result<(), ErrorCode> {
+        match self.values.get_mut(&key.to_string()) {
+            Some(current) if current == from => {
+                *current = to;
+                Ok(())
+            }
+            Some(_) => Err(ErrorCode::PreconditionFailed),
+            None if create_if_not_exists => {
+                self.write(key, to);
+                Ok(())
+            }
+            None => Err(ErrorCode::KeyDoesNotExist),
+        }
+    }
+}
+
+/// How the kv workload serves requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum KvMode {
+    /// Every request is applied on one node, so operations are linearizable.
+    #[default]
+    Linearizable,
+    /// Every node serves requests from its own `LwwMap` and replicates it to the others, so reads
+    /// may be stale, but no request waits on another node.
+    Lww,
+    /// Like `Lww`, but a client always reads its own writes and never reads older entries than
+    /// it has already read, waiting for replication if the node is behind.
+    Session,
+    /// Each key's requests are applied on the node that owns it, so operations on a key are
+    /// linearizable and the load is spread across the nodes.
+    Sharded,
+    /// Requests are applied in the order a Raft log commits them, so operations are linearizable
+    /// and the cluster survives losing a minority of its nodes. Only built with the `raft`
+    /// feature.
+    #[cfg(feature = "raft")]
+    Raft,
+    /// Every request is applied by Maelstrom's `lin-kv` service, through a `KvClient`, so
+    /// operations are linearizable and the nodes keep no kv state of their own.
+    Service,
+}
+
+impl KvMode {
+    /// The mode with this name, or why there's none; `raft` is only known with its feature.
+    pub fn parse(mode: &str) -> Result<Self, String> {
+        match mode {
+            "linearizable" => Ok(KvMode::Linearizable),
+            "lww" => Ok(KvMode::Lww),
+            "session" => Ok(KvMode::Session),
+            "sharded" => Ok(KvMode::Sharded),
+            "service" => Ok(KvMode::Service),
+            #[cfg(feature = "raft")]
+            "raft" => Ok(KvMode::Raft),
+            #[cfg(not(feature = "raft"))]
+            "raft" => Err("The raft kv mode isn't built; it needs the raft feature.".to_string()),
+            _ => Err(format!("Unknown kv mode {:?}.", mode)),
+        }
+    }
+
+    /// The mode for a `--consistency` level: `eventual`, `session`, or `linearizable`.
+    pub fn from_consistency(level: &str) -> Option<Self> {
+        match level {
+            "eventual" => Some(KvMode::Lww),
+            "session" => Some(KvMode::Session),
+            "linearizable" => Some(KvMode::Linearizable),
+            _ => None,
+        }
+    }
+
+    /// Whether the mode serves requests from the node's own `LwwMap`.
+    pub fn is_lww(&self) -> bool {
+        matches!(self, KvMode::Lww | KvMode::Session)
+    }
+}
+
+/// A change to the store.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum KvOp {
+    Write {
+        key: Value,
+        value: Value,
+    },
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        create_if_not_exists: bool,
+    },
+}
+
+impl StateMachine for KvStore {
+    type Op = KvOp;
+    type Effect = Result<(), ErrorCode>;
+    /// A key to read.
+    type Query = Value;
+    type Answer = Result<Value, ErrorCode>;
+
+    fn apply(&mut self, op: KvOp) -> Result<(), ErrorCode> {
+        match op {
+            KvOp::Write { key, value } => {
+                self.write(&key, value);
+                Ok(())
+            }
+            KvOp::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => self.cas(&key, &from, to, create_if_not_exists),
+        }
+    }
+
+    fn query(&self, key: Value) -> Result<Value, ErrorCode> {
+        self.read(&key).cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compares_and_sets() {
+        let mut kv = KvStore::default();
+        let key = Value::from(1);
+
+        assert_eq!(kv.read(&key), Err(ErrorCode::KeyDoesNotExist));
+        assert_eq!(
+            kv.cas(&key, &0.into(), 1.into(), false),
+            Err(ErrorCode::KeyDoesNotExist)
+        );
+        assert_eq!(kv.cas(&key, &0.into(), 1.into(), true), Ok(()));
+        assert_eq!(
+            kv.cas(&key, &0.into(), 2.into(), false),
+            Err(ErrorCode::PreconditionFailed)
+        );
+        assert_eq!(kv.cas(&key, &1.into(), 2.into(), false), Ok(()));
+        assert_eq!(kv.read(&key), Ok(&Value::from(2)));
+    }
+}
@@ -0,0 +1,446 @@
+//! Worker lanes between stdin and the node. Each message is routed to a lane by its affinity
+//! key, and each lane handles its messages one after another, so messages sharing a key keep the
+//! order they arrived in while a slow one only holds up its own lane.
+//!
+//! Lanes order and bound the work; they don't split the node. Every lane hands its messages to
+//! the same node through its [`NodeHandle`], which runs them one at a time, so handlers never see
+//! each other's state half-updated. What runs in parallel is the waiting: a message held for its
+//! workload to be ready, or parsed and dispatched, doesn't stop the other lanes.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Sender};
+use tokio::task::JoinHandle;
+use tokio_util::task::TaskTracker;
+use tracing::warn;
+
+use crate::actor::NodeHandle;
+use crate::core::message::{Envelope, ErrorCode};
+use crate::core::state;
+use crate::metrics::Metrics;
+use crate::node::Node;
+use crate::tiebreak::stable_hash;
+
+/// Determines which lane a message is routed to. Messages that share a key are processed in the
+/// order they were received; messages with different keys may be processed in parallel.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Affinity {
+    /// Route by the message's `src`, preserving per-sender ordering.
+    #[default]
+    Src,
+    /// Route by the message's `dest`.
+    Dest,
+    /// Route by a field of the message body, e.g. `key` for keyed workloads.
+    BodyKey(String),
+    /// Route by the shard of the body's `key`, out of this many shards. Keys in the same shard
+    /// keep their order with each other, and a key's shard is the same on every node.
+    Shard(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LaneConfig {
+    pub lanes: usize,
+    pub capacity: usize,
+    pub affinity: Affinity,
+}
+
+impl Default for LaneConfig {
+    fn default() -> Self {
+        LaneConfig {
+            lanes: 4,
+            capacity: 32,
+            affinity: Affinity::default(),
+        }
+    }
+}
+
+impl Affinity {
+    /// `src`, `dest`, `body:<field>`, or `shard:<count>`.
+    pub fn parse(affinity: &str) -> Option<Self> {
+        match affinity {
+            "src" => Some(Affinity::Src),
+            "dest" => Some(Affinity::Dest),
+            _ => {
+                if let Some(field) = affinity.strip_prefix("body:") {
+                    return Some(Affinity::BodyKey(field.to_string()));
+                }
+
+                match affinity.strip_prefix("shard:")?.parse() {
+                    Ok(0) | Err(_) => None,
+                    Ok(shards) => Some(Affinity::Shard(shards)),
+                }
+            }
+        }
+    }
+}
+
+/// A document queued on a lane, with the envelope it was routed by.
+type Queued = (String, Option<Envelope<'static>>);
+
+pub struct Lanes {
+    senders: Vec<Sender<Queued>>,
+    tasks: Vec<JoinHandle<()>>,
+    affinity: Affinity,
+    node: NodeHandle,
+}
+
+impl Lanes {
+    /// Spawn one task per lane on the tracker. Each lane drains its own channel sequentially, so
+    /// the number of messages handled concurrently is bounded by the number of lanes.
+    pub fn spawn(
+        config: LaneConfig,
+        node: NodeHandle,
+        response_tx: Sender<String>,
+        task_tracker: &TaskTracker,
+    ) -> Lanes {
+        let (senders, tasks) = (0..config.lanes.max(1))
+            .map(|_| {
+                let (tx, mut rx) = mpsc::channel::<Queued>(config.capacity.max(1));
+                let node = node.clone();
+                let response_tx = response_tx.clone();
+
+                // The lane exits once every sender is dropped, i.e. when `Lanes` is dropped.
+                let task = task_tracker.spawn(async move {
+                    while let Some((document, envelope)) = rx.recv().await {
+                        Node::process_document(node.clone(), &document, envelope, &response_tx)
+                            .await;
+                    }
+                });
+
+                (tx, task)
+            })
+            .unzip();
+
+        Lanes {
+            senders,
+            tasks,
+            affinity: config.affinity,
+            node,
+        }
+    }
+
+    /// Stop accepting messages, and wait for the lanes to handle the ones already queued.
+    pub async fn close(self) {
+        drop(self.senders);
+
+        for task in self.tasks {
+            let _ = task.await;
+        }
+    }
+
+    /// Queue each message in `from_stdin` on its lane, reading its envelope once to route, gate
+    /// and reject it by. When the lane is full, a client's request is answered with a
+    /// `temporarily-unavailable` error so the client can retry, rather than holding up every
+    /// other lane; messages from other nodes wait for room.
+    pub async fn dispatch(&self, from_stdin: String) {
+        let documents = state::documents(&from_stdin);
+
+        // A line holding a single message is queued as is, rather than copied.
+        let documents: Vec<Queued> = match documents[..] {
+            [document] if document.len() == from_stdin.len() => {
+                let envelope = state::envelope(document).ok().map(Envelope::into_owned);
+                vec![(from_stdin, envelope)]
+            }
+            _ => documents
+                .into_iter()
+                .map(|document| {
+                    let envelope = state::envelope(document).ok().map(Envelope::into_owned);
+                    (document.to_string(), envelope)
+                })
+                .collect(),
+        };
+
+        for queued in documents {
+            self.queue(queued).await;
+        }
+    }
+
+    async fn queue(&self, queued: Queued) {
+        let lane = self.lane_for(&queued.0, queued.1.as_ref());
+
+        let queued = match self.senders[lane].try_send(queued) {
+            Ok(()) => return,
+            Err(TrySendError::Closed(_)) => {
+                warn!("Lane {} is closed; dropping message.", lane);
+                return;
+            }
+            Err(TrySendError::Full(queued)) => queued,
+        };
+
+        if let Some(envelope) = queued.1.clone() {
+            let rejected = self
+                .node
+                .call(move |node| Self::reject(node, &envelope))
+                .await;
+
+            if rejected == Ok(true) {
+                warn!("Lane {} is full; rejecting client request.", lane);
+                return;
+            }
+        }
+
+        if self.senders[lane].send(queued).await.is_err() {
+            warn!("Lane {} is closed; dropping message.", lane);
+        }
+    }
+
+    /// Answer a client's request with an overload error. Replies, and messages from other nodes,
+    /// aren't rejected.
+    fn reject(node: &mut Node, envelope: &Envelope) -> bool {
+        let from_client = envelope
+            .src
+            .as_ref()
+            .is_some_and(|src| !node.node_ids.iter().any(|id| id == src));
+
+        if !from_client || envelope.body.msg_id.is_none() || envelope.body.in_reply_to.is_some() {
+            return false;
+        }
+
+        let Some(mut error) = envelope.error_reply(
+            node.id.clone(),
+            ErrorCode::TemporarilyUnavailable,
+            "The node is overloaded; try again.",
+        ) else {
+            return false;
+        };
+        node.stamp(&mut error);
+        node.send_all(vec![error]);
+        Metrics::increment(&node.metrics.overloaded);
+
+        true
+    }
+
+    /// Messages without the affinity key (or whose envelope couldn't be read) all land on the
+    /// first lane; the handler reports the parse error.
+    pub fn lane_for(&self, document: &str, envelope: Option<&Envelope>) -> usize {
+        let mut hasher = DefaultHasher::new();
+
+        match &self.affinity {
+            // Only a body field needs the whole document parsed.
+            Affinity::Src => match envelope {
+                Some(Envelope { src: Some(src), .. }) => src.hash(&mut hasher),
+                _ => return 0,
+            },
+            Affinity::Dest => match envelope {
+                Some(envelope) => envelope.dest.hash(&mut hasher),
+                None => return 0,
+            },
+            Affinity::BodyKey(field) => match body_field(document, field) {
+                Some(key) => key.hash(&mut hasher),
+                None => return 0,
+            },
+            Affinity::Shard(shards) => {
+                let Some(key) = body_field(document, "key") else {
+                    return 0;
+                };
+                let shard = stable_hash(&[&key]) % *shards as u64;
+
+                return (shard % self.senders.len() as u64) as usize;
+            }
+        }
+
+        (hasher.finish() % self.senders.len() as u64) as usize
+    }
+}
+
+/// A field of the document's body, as JSON.
+fn body_field(document: &str, field: &str) -> Option<String> {
+    let value = serde_json::from_str::<serde_json::Value>(document).ok()?;
+
+    value
+        .get("body")
+        .and_then(|body| body.get(field))
+        .map(|key| key.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::message::{Message, MessageBody};
+    use crate::node::{Handler, Registry};
+    use crate::workload::Workload;
+
+    #[tokio::test]
+    async fn routes_messages_with_the_same_key_to_the_same_lane() {
+        let (response_tx, _response_rx) = mpsc::channel(10);
+        let tracker = TaskTracker::new();
+        let node = NodeHandle::spawn(Node::default());
+
+        let lanes = Lanes::spawn(LaneConfig::default(), node, response_tx, &tracker);
+
+        let first = r#"{"src": "c1", "dest": "n1", "body": {"type": "read", "msg_id": 1}}"#;
+        let second = r#"{"src": "c1", "dest": "n1", "body": {"type": "read", "msg_id": 2}}"#;
+
+        let lane_for = |document| lanes.lane_for(document, state::envelope(document).ok().as_ref());
+
+        assert_eq!(lane_for(first), lane_for(second));
+        assert_eq!(lane_for("not json"), 0);
+    }
+
+    #[tokio::test]
+    async fn routes_keys_in_the_same_shard_to_the_same_lane() {
+        let (response_tx, _response_rx) = mpsc::channel(10);
+        let tracker = TaskTracker::new();
+        let node = NodeHandle::spawn(Node::default());
+
+        let config = LaneConfig {
+            affinity: Affinity::parse("shard:1").unwrap(),
+            ..Default::default()
+        };
+        let lanes = Lanes::spawn(config, node, response_tx, &tracker);
+
+        let read = |key: u32| {
+            format!(
+                r#"{{"src": "c{key}", "dest": "n1", "body": {{"type": "read", "key": {key}}}}}"#
+            )
+        };
+        let lane_for =
+            |document: &str| lanes.lane_for(document, state::envelope(document).ok().as_ref());
+
+        assert!((0..16).all(|key| lane_for(&read(key)) == lane_for(&read(0))));
+        assert_eq!(Affinity::parse("shard:0"), None);
+        assert_eq!(Affinity::parse("shard:8"), Some(Affinity::Shard(8)));
+    }
+
+    #[tokio::test]
+    async fn queues_each_message_of_a_line_on_its_own_lane() {
+        let (response_tx, mut response_rx) = mpsc::channel(10);
+        let tracker = TaskTracker::new();
+        let node = NodeHandle::spawn(Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string()],
+            registry: Registry::for_workloads(&[Workload::Echo]),
+            ..Default::default()
+        });
+
+        let lanes = Lanes::spawn(LaneConfig::default(), node, response_tx, &tracker);
+
+        lanes
+            .dispatch(concat!(
+                r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": 1, "msg_id": 1}}"#,
+                r#"{"src": "c2", "dest": "n1", "body": {"type": "echo", "echo": 2, "msg_id": 1}}"#,
+            ).to_string())
+            .await;
+        lanes.close().await;
+
+        let mut dests = vec![];
+        while let Ok(reply) = response_rx.try_recv() {
+            dests.push(serde_json::from_str::<Message>(&reply).unwrap().dest);
+        }
+        dests.sort();
+
+        assert_eq!(dests, ["c1", "c2"]);
+    }
+
+    struct Explode;
+
+    impl Handler for Explode {
+        fn handle(&self, _node: &mut Node, _message: Message) -> Vec<Message<'static>> {
+            panic!("boom");
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_serving_a_lane_after_a_handler_panics() {
+        let mut registry = Registry::for_workloads(&[Workload::Echo]);
+        registry.register("generate", Explode);
+
+        let (response_tx, mut response_rx) = mpsc::channel(10);
+        let tracker = TaskTracker::new();
+        let node = NodeHandle::spawn(Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string()],
+            registry,
+            ..Default::default()
+        });
+
+        let config = LaneConfig {
+            lanes: 1,
+            ..Default::default()
+        };
+        let lanes = Lanes::spawn(config, node, response_tx, &tracker);
+
+        let request = |kind: &str, msg_id: u32| {
+            format!(
+                r#"{{"src": "c1", "dest": "n1", "body": {{"type": "{kind}", "echo": 1, "msg_id": {msg_id}}}}}"#
+            )
+        };
+        lanes.dispatch(request("generate", 1)).await;
+        lanes.dispatch(request("echo", 2)).await;
+        lanes.dispatch(request("generate", 3)).await;
+        lanes.dispatch(request("echo", 4)).await;
+        lanes.close().await;
+
+        let mut replies = vec![];
+        while let Ok(reply) = response_rx.try_recv() {
+            replies.push(
+                serde_json::from_str::<Message>(&reply)
+                    .unwrap()
+                    .into_owned(),
+            );
+        }
+
+        let kinds: Vec<_> = replies
+            .iter()
+            .map(|reply| (reply.body.kind(), reply.body.in_reply_to()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("error", Some(1)),
+                ("echo_ok", Some(2)),
+                ("error", Some(3)),
+                ("echo_ok", Some(4))
+            ]
+        );
+        assert!(matches!(
+            &replies[0].body,
+            MessageBody::Error(body) if body.code == ErrorCode::Crash
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_client_requests_when_a_lane_is_full() {
+        let node = NodeHandle::spawn(Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string(), "n2".to_string()],
+            ..Default::default()
+        });
+        node.call(|node| node.outbox.open()).await.unwrap();
+
+        // Dropping the lane's receiver would close it; keep it open, but never drain it.
+        let (tx, _rx) = mpsc::channel(1);
+        let lanes = Lanes {
+            senders: vec![tx],
+            tasks: vec![],
+            affinity: Affinity::Src,
+            node: node.clone(),
+        };
+
+        let request = |msg_id| {
+            format!(
+                r#"{{"src": "c1", "dest": "n1", "body": {{"type": "read", "msg_id": {msg_id}}}}}"#
+            )
+        };
+        lanes.dispatch(request(1)).await;
+        lanes.dispatch(request(2)).await;
+
+        let sent = node.call(|node| node.outbox.take()).await.unwrap();
+
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dest, "c1");
+        assert_eq!(sent[0].body.in_reply_to(), Some(2));
+        assert!(matches!(
+            &sent[0].body,
+            MessageBody::Error(body) if body.code == ErrorCode::TemporarilyUnavailable
+        ));
+        assert_eq!(
+            node.call(|node| node
+                .metrics
+                .overloaded
+                .load(std::sync::atomic::Ordering::Relaxed))
+                .await,
+            Ok(1)
+        );
+    }
+}
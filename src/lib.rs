@@ -0,0 +1,110 @@
+//! A node for the Maelstrom distributed systems challenges.
+//!
+//! The binary reads messages from stdin and writes replies to stdout; other binaries and
+//! integration tests can build a `Node` and drive `Node::run` over channels instead.
+//!
+//! The node needs the default `runtime` feature; without it only `core` and the other modules
+//! free of tokio and the network build.
+
+#[cfg(feature = "runtime")]
+pub mod actor;
+pub mod admin;
+#[cfg(feature = "runtime")]
+pub mod app;
+#[cfg(feature = "runtime")]
+pub mod bootstrap;
+#[cfg(all(feature = "runtime", feature = "broadcast"))]
+pub mod broadcast;
+#[cfg(feature = "broadcast")]
+pub mod causal;
+#[cfg(feature = "runtime")]
+pub mod cli;
+pub mod clock;
+#[cfg(feature = "runtime")]
+pub mod config;
+pub mod core;
+#[cfg(feature = "runtime")]
+pub mod correlation;
+pub mod dedupe;
+pub mod delivery;
+#[cfg(feature = "runtime")]
+pub mod discovery;
+pub mod error;
+pub mod failure;
+pub mod gossip;
+#[cfg(feature = "runtime")]
+pub mod handlers;
+pub mod health;
+pub mod id128;
+pub mod idempotency;
+pub mod jitter;
+#[cfg(feature = "kv")]
+pub mod kv;
+#[cfg(feature = "runtime")]
+pub mod lanes;
+#[cfg(feature = "runtime")]
+pub mod lifecycle;
+#[cfg(feature = "kafka")]
+pub mod log;
+pub mod machine;
+#[cfg(feature = "runtime")]
+pub mod memory;
+pub mod metrics;
+#[cfg(feature = "runtime")]
+pub mod node;
+#[cfg(feature = "runtime")]
+pub mod outbox;
+#[cfg(feature = "runtime")]
+pub mod persist;
+#[cfg(feature = "pyo3")]
+pub mod python;
+pub mod quiescence;
+#[cfg(feature = "raft")]
+pub mod raft;
+#[cfg(feature = "runtime")]
+pub mod readiness;
+#[cfg(feature = "runtime")]
+pub mod record;
+#[cfg(feature = "runtime")]
+pub mod replay;
+pub mod retry;
+#[cfg(feature = "runtime")]
+pub mod rpc;
+#[cfg(feature = "schema")]
+pub mod schema;
+#[cfg(feature = "runtime")]
+pub mod selftest;
+#[cfg(all(feature = "runtime", feature = "kv"))]
+pub mod services;
+pub mod session;
+pub mod sharding;
+#[cfg(feature = "runtime")]
+pub mod shutdown;
+#[cfg(feature = "runtime")]
+pub mod simulation;
+#[cfg(feature = "kafka")]
+pub mod sink;
+pub mod snowflake;
+pub mod spill;
+#[cfg(feature = "runtime")]
+pub mod tasks;
+#[cfg(feature = "runtime")]
+pub mod tcp;
+#[cfg(feature = "runtime")]
+pub mod testing;
+pub mod tiebreak;
+pub mod timer;
+#[cfg(feature = "runtime")]
+pub mod tls;
+pub mod topology;
+#[cfg(feature = "runtime")]
+pub mod transport;
+#[cfg(feature = "txn")]
+pub mod txn;
+pub mod wal;
+#[cfg(feature = "runtime")]
+pub mod workload;
+
+pub use crate::core::message::Message;
+#[cfg(feature = "runtime")]
+pub use node::{Handler, Node, Registry};
@@ -0,0 +1,107 @@
+//! Workloads with lifecycle hooks. A workload implementing `Workload` is hosted by the node's
+//! registry: its message types are routed to `on_message`, `on_init` runs once the node is
+//! initialized, `on_tick` runs on the workload's interval while the node is running, and
+//! `on_shutdown` runs once stdin closes, before the node drains.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::core::message::Message;
+use crate::node::{Handler, Node};
+
+pub trait Workload: Send + Sync {
+    /// For logs, and to stagger the first tick.
+    fn name(&self) -> &'static str;
+
+    /// The message types routed to `on_message`.
+    fn message_types(&self) -> &'static [&'static str];
+
+    fn on_init(&self, _node: &mut Node) -> Vec<Message<'static>> {
+        vec![]
+    }
+
+    fn on_message(&self, node: &mut Node, message: Message) -> Vec<Message<'static>>;
+
+    /// How often `on_tick` runs; never without one.
+    fn interval(&self, _node: &Node) -> Option<Duration> {
+        None
+    }
+
+    fn on_tick(&self, _node: &mut Node) -> Vec<Message<'static>> {
+        vec![]
+    }
+
+    fn on_shutdown(&self, _node: &mut Node) -> Vec<Message<'static>> {
+        vec![]
+    }
+}
+
+/// Routes a message type to its workload's `on_message`.
+pub struct OnMessage(pub Arc<dyn Workload>);
+
+impl Handler for OnMessage {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        self.0.on_message(node, message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::state::parse;
+    use crate::node::Registry;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Default)]
+    struct Tally {
+        inits: AtomicU32,
+        messages: AtomicU32,
+    }
+
+    impl Workload for Arc<Tally> {
+        fn name(&self) -> &'static str {
+            "tally"
+        }
+
+        fn message_types(&self) -> &'static [&'static str] {
+            &["echo"]
+        }
+
+        fn on_init(&self, _node: &mut Node) -> Vec<Message<'static>> {
+            self.inits.fetch_add(1, Ordering::Relaxed);
+            vec![]
+        }
+
+        fn on_message(&self, _node: &mut Node, _message: Message) -> Vec<Message<'static>> {
+            self.messages.fetch_add(1, Ordering::Relaxed);
+            vec![]
+        }
+    }
+
+    #[test]
+    fn routes_init_and_messages_to_hosted_workloads() {
+        let tally = Arc::new(Tally::default());
+
+        let mut registry = Registry::for_workloads(&[]);
+        registry.host(tally.clone());
+
+        let mut node = Node {
+            registry,
+            ..Default::default()
+        };
+
+        node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "init", "node_id": "n1", "node_ids": ["n1"], "msg_id": 1}}"#,
+        ).unwrap());
+        node.dispatch(
+            parse(
+                r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": 1, "msg_id": 2}}"#,
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(tally.inits.load(Ordering::Relaxed), 1);
+        assert_eq!(tally.messages.load(Ordering::Relaxed), 1);
+        assert_eq!(node.registry.workloads().len(), 1);
+    }
+}
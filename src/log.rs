@@ -0,0 +1,205 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+use crate::machine::StateMachine;
+
+/// The kafka workload's state on the node.
+#[derive(Debug, Default)]
+pub struct KafkaState {
+    pub logs: Logs,
+    /// Where committed records are mirrored, if anywhere.
+    pub sink: Option<Box<dyn Sink>>,
+}
+
+/// Append-only logs, one per key, and the offset each key's consumers have committed up to.
+///
+/// A node only holds the logs of the keys it owns on the ring, so each key's offsets are
+/// assigned in one place; requests for other keys are sent on to their owners.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Logs {
+    logs: HashMap<String, Vec<Value>>,
+    committed: HashMap<String, u64>,
+}
+
+impl Logs {
+    /// How many messages the logs hold, across every key.
+    pub fn len(&self) -> usize {
+        self.logs.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Append a message to the key's log, returning its offset.
+    pub fn append(&mut self, key: &str, message: Value) -> u64 {
+        let log = self.logs.entry(key.to_string()).or_default();
+
+        log.push(message);
+
+        (log.len() - 1) as u64
+    }
+
+    /// The messages at or after each requested offset, with their offsets.
+    pub fn poll(&self, offsets: &HashMap<String, u64>) -> HashMap<String, Vec<(u64, Value)>> {
+        offsets
+            .iter()
+            .filter_map(|(key, offset)| {
+                let log = self.logs.get(key)?;
+
+                let messages = log
+                    .iter()
+                    .enumerate()
+                    .skip(*offset as usize)
+                    .map(|(offset, message)| (offset as u64, message.clone()))
+                    .collect();
+
+                Some((key.clone(), messages))
+            })
+            .collect()
+    }
+
+    /// Commit offsets, returning the records the commit newly covers, by key and offset. A
+    /// commit never moves a key's committed offset backwards.
+    pub fn commit(&mut self, offsets: &HashMap<String, u64>) -> Vec<Record> {
+        let mut keys: Vec<_> = offsets.keys().collect();
+        let mut records = vec![];
+
+        keys.sort();
+
+        for key in keys {
+            let offset = offsets[key];
+            let from = self.committed.get(key).map_or(0, |committed| committed + 1);
+            let committed = self.committed.entry(key.clone()).or_default();
+
+            *committed = (*committed).max(offset);
+
+            let Some(log) = self.logs.get(key).filter(|_| offset >= from) else {
+                continue;
+            };
+
+            records.extend(
+                log.iter()
+                    .enumerate()
+                    .take(offset as usize + 1)
+                    .skip(from as usize)
+                    .map(|(offset, msg)| Record {
+                        key: key.clone(),
+                        offset: offset as u64,
+                        msg: msg.clone(),
+                    }),
+            );
+        }
+
+        records
+    }
+
+    /// The committed offsets of the requested keys. Keys without a commit are left out.
+    pub fn committed(&self, keys: &[String]) -> HashMap<String, u64> {
+        keys.iter()
+            .filter_map(|key| Some((key.clone(), *self.committed.get(key)?)))
+            .collect()
+    }
+}
+
+/// A message in a key's log, at its offset.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Record {
+    pub key: String,
+    pub offset: u64,
+    pub msg: Value,
+}
+
+/// Where committed records are mirrored, e.g. an NDJSON file, so the node doubles as a small
+/// ingest buffer.
+pub trait Sink: fmt::Debug + Send {
+    fn mirror(&mut self, records: &[Record]) -> io::Result<()>;
+}
+
+/// A change to the logs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LogOp {
+    Send { key: String, msg: Value },
+    Commit(HashMap<String, u64>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LogEffect {
+    /// The offset a sent message was appended at.
+    Appended(u64),
+    /// The records the commit newly covers.
+    Committed(Vec<Record>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LogQuery {
+    Poll(HashMap<String, u64>),
+    Committed(Vec<String>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LogAnswer {
+    Messages(HashMap<String, Vec<(u64, Value)>>),
+    Offsets(HashMap<String, u64>),
+}
+
+impl StateMachine for Logs {
+    type Op = LogOp;
+    type Effect = LogEffect;
+    type Query = LogQuery;
+    type Answer = LogAnswer;
+
+    fn apply(&mut self, op: LogOp) -> LogEffect {
+        match op {
+            LogOp::Send { key, msg } => LogEffect::Appended(self.append(&key, msg)),
+            LogOp::Commit(offsets) => LogEffect::Committed(self.commit(&offsets)),
+        }
+    }
+
+    fn query(&self, query: LogQuery) -> LogAnswer {
+        match query {
+            LogQuery::Poll(offsets) => LogAnswer::Messages(self.poll(&offsets)),
+            LogQuery::Committed(keys) => LogAnswer::Offsets(self.committed(&keys)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn assigns_offsets_and_tracks_commits() {
+        let mut logs = Logs::default();
+
+        assert_eq!(logs.append("k1", 10.into()), 0);
+        assert_eq!(logs.append("k1", 11.into()), 1);
+        assert_eq!(logs.append("k2", 20.into()), 0);
+
+        let polled = logs.poll(&HashMap::from([("k1".into(), 1), ("k3".into(), 0)]));
+        assert_eq!(polled, HashMap::from([("k1".into(), vec![(1, 11.into())])]));
+
+        let record = |offset: u64, msg: u32| Record {
+            key: "k1".into(),
+            offset,
+            msg: msg.into(),
+        };
+
+        assert_eq!(
+            logs.commit(&HashMap::from([("k1".into(), 0)])),
+            vec![record(0, 10)]
+        );
+        assert_eq!(
+            logs.commit(&HashMap::from([("k1".into(), 1)])),
+            vec![record(1, 11)]
+        );
+        assert_eq!(logs.commit(&HashMap::from([("k1".into(), 0)])), vec![]);
+        assert_eq!(
+            logs.committed(&["k1".into(), "k2".into()]),
+            HashMap::from([("k1".into(), 1)])
+        );
+    }
+}
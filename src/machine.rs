@@ -0,0 +1,68 @@
+//! Each workload's logic as a state machine, with no I/O or locking: the live node applies
+//! client operations to it, and tooling can replay the same operations to check or rebuild
+//! the state.
+
+/// Operations change the state and report what happened; queries only read it. Applying the
+/// same operations in the same order always gives the same state and effects.
+pub trait StateMachine {
+    type Op;
+    type Effect;
+    type Query;
+    type Answer;
+
+    fn apply(&mut self, op: Self::Op) -> Self::Effect;
+
+    fn query(&self, query: Self::Query) -> Self::Answer;
+}
+
+/// Apply the operations to a fresh state machine, returning it with each operation's effect.
+pub fn replay<M: StateMachine + Default>(
+    ops: impl IntoIterator<Item = M::Op>,
+) -> (M, Vec<M::Effect>) {
+    let mut machine = M::default();
+    let effects = ops.into_iter().map(|op| machine.apply(op)).collect();
+
+    (machine, effects)
+}
+
+#[cfg(all(test, feature = "kv"))]
+mod test {
+    use super::*;
+    use crate::core::message::ErrorCode;
+    use crate::kv::{KvOp, KvStore};
+    use serde_json::json;
+
+    #[test]
+    fn replaying_the_same_operations_gives_the_same_state() {
+        let ops = || {
+            vec![
+                KvOp::Write {
+                    key: json!(1),
+                    value: json!("a"),
+                },
+                KvOp::Cas {
+                    key: json!(1),
+                    from: json!("b"),
+                    to: json!("c"),
+                    create_if_not_exists: false,
+                },
+                KvOp::Cas {
+                    key: json!(1),
+                    from: json!("a"),
+                    to: json!("c"),
+                    create_if_not_exists: false,
+                },
+            ]
+        };
+
+        let (first, effects) = replay::<KvStore>(ops());
+        let (second, _) = replay::<KvStore>(ops());
+
+        assert_eq!(first, second);
+        assert_eq!(
+            effects,
+            vec![Ok(()), Err(ErrorCode::PreconditionFailed), Ok(())]
+        );
+        assert_eq!(first.query(json!(1)), Ok(json!("c")));
+    }
+}
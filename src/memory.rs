@@ -0,0 +1,157 @@
+use serde_json::Value;
+use std::mem::size_of;
+
+use crate::core::message::{BroadcastValue, Message};
+use crate::correlation::ReplySender;
+use crate::node::Node;
+
+/// Hash tables keep one control byte per bucket and are at most 7/8 full.
+fn hash_table_bytes<T>(len: usize) -> usize {
+    len * (size_of::<T>() + 1) * 8 / 7
+}
+
+/// Approximate bytes held by the node's state stores, estimated from their lengths rather than
+/// measured from the allocator.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryUsage {
+    pub broadcast_store: usize,
+    /// Recently seen broadcast values.
+    pub dedupe: usize,
+    /// Replies remembered for retransmitted requests.
+    pub replies: usize,
+    pub callbacks: usize,
+    pub unacknowledged: usize,
+    /// The kafka workload's logs.
+    pub logs: usize,
+    /// The kv workload's store, LWW map, and Raft log.
+    pub kv: usize,
+}
+
+impl MemoryUsage {
+    /// Unacknowledged messages own their body; assume a typical broadcast.
+    const MESSAGE_BYTES: usize = 128;
+    /// Logged messages and stored values are JSON; assume a small one, with its key.
+    const VALUE_BYTES: usize = 64;
+
+    pub fn measure(node: &Node) -> Self {
+        #[cfg(feature = "broadcast")]
+        let (stored, seen) = (
+            node.broadcast.messages.len_in_memory(),
+            node.broadcast.recently_seen.len(),
+        );
+        #[cfg(not(feature = "broadcast"))]
+        let (stored, seen) = (0, 0);
+        #[cfg(feature = "kv")]
+        let kv_entries = node.kv.lww.entries().len() + node.kv.store.len();
+        #[cfg(not(feature = "kv"))]
+        let kv_entries = 0;
+        #[cfg(feature = "raft")]
+        let kv_entries = kv_entries + node.kv.raft.log_len();
+        #[cfg(feature = "kafka")]
+        let logged = node.kafka.logs.len();
+        #[cfg(not(feature = "kafka"))]
+        let logged = 0;
+
+        MemoryUsage {
+            broadcast_store: hash_table_bytes::<BroadcastValue>(stored),
+            dedupe: hash_table_bytes::<BroadcastValue>(seen) * 2,
+            replies: hash_table_bytes::<((String, u32), Vec<Message<'static>>)>(node.replies.len())
+                + node.replies.len() * MemoryUsage::MESSAGE_BYTES,
+            callbacks: hash_table_bytes::<(u32, ReplySender)>(node.correlations.len()),
+            unacknowledged: hash_table_bytes::<(u32, Message)>(node.unacknowledged.len())
+                + node.unacknowledged.len() * MemoryUsage::MESSAGE_BYTES,
+            logs: logged * (size_of::<Value>() + MemoryUsage::VALUE_BYTES),
+            kv: hash_table_bytes::<(String, Value)>(kv_entries)
+                + kv_entries * MemoryUsage::VALUE_BYTES,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.broadcast_store
+            + self.dedupe
+            + self.replies
+            + self.callbacks
+            + self.unacknowledged
+            + self.logs
+            + self.kv
+    }
+}
+
+/// An optional cap on the memory used by the state stores. Once it is reached, requests that
+/// would store more, i.e. new broadcast values, `send`s, and kv writes, are rejected with an
+/// error instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryBounds {
+    pub max_bytes: Option<usize>,
+}
+
+impl MemoryBounds {
+    pub fn allows(&self, usage: &MemoryUsage) -> bool {
+        self.max_bytes.is_none_or(|max| usage.total() < max)
+    }
+}
+
+#[cfg(all(test, feature = "broadcast"))]
+mod test {
+    use super::*;
+    use crate::broadcast::BroadcastState;
+    use crate::core::state::BroadcastStore;
+    use crate::testing::NodeTestFixture;
+    use serde_json::json;
+
+    #[test]
+    fn rejects_once_the_bound_is_reached() {
+        let node = Node {
+            broadcast: BroadcastState {
+                messages: BroadcastStore::from(0..100),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let usage = MemoryUsage::measure(&node);
+
+        assert!(usage.broadcast_store > 0);
+        assert!(MemoryBounds::default().allows(&usage));
+        assert!(MemoryBounds {
+            max_bytes: Some(usage.total() + 1)
+        }
+        .allows(&usage));
+        assert!(!MemoryBounds {
+            max_bytes: Some(usage.total())
+        }
+        .allows(&usage));
+    }
+
+    #[test]
+    fn stores_and_gossips_a_batch_whole_or_not_at_all() {
+        let mut fixture = NodeTestFixture::with_node(Node {
+            broadcast: BroadcastState {
+                messages: BroadcastStore::from(0..10),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .init("n1", ["n1", "n2", "n3"]);
+
+        let gossip = json!({
+            "src": "n2", "dest": "n1",
+            "body": {"type": "broadcast", "messages": [100, 101, 102], "msg_id": 1},
+        });
+
+        // At the bound, nothing in the batch is stored, so a retry carries all of it again.
+        let usage = MemoryUsage::measure(&fixture.node).total();
+        fixture.node.memory_bounds.max_bytes = Some(usage);
+        let mut fixture = fixture.receive(gossip.clone());
+
+        assert!(!fixture.node.broadcast.messages.contains(&100.into()));
+        assert_eq!(fixture.sent[0].body.kind(), "error");
+
+        // Just under it, the whole batch is stored and gossiped, even though it passes the bound.
+        fixture.node.memory_bounds.max_bytes = Some(usage + 1);
+        let fixture = fixture.receive(gossip).expect_sent("n3", "broadcast");
+
+        for value in 100..103 {
+            assert!(fixture.node.broadcast.messages.contains(&value.into()));
+        }
+    }
+}
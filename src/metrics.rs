@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[cfg(feature = "runtime")]
+use crate::actor::NodeHandle;
+use crate::health::PeerReport;
+#[cfg(feature = "runtime")]
+use crate::memory::MemoryUsage;
+#[cfg(feature = "runtime")]
+use crate::node::Node;
+
+/// Counters updated as messages flow through the node. They only ever increase; reporters
+/// compute deltas between snapshots.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub messages_in: AtomicU64,
+    pub messages_out: AtomicU64,
+    pub retries: AtomicU64,
+    pub dedupe_hits: AtomicU64,
+    pub dedupe_misses: AtomicU64,
+    /// Outbound messages that couldn't be written because the node was shutting down.
+    pub dropped: AtomicU64,
+    /// Client requests rejected because the node was saturated.
+    pub overloaded: AtomicU64,
+    /// Requests no longer awaited because they waited too long or there were too many.
+    pub evicted_callbacks: AtomicU64,
+    /// Handled messages by type.
+    pub handled: Mutex<BTreeMap<String, TypeStats>>,
+}
+
+impl Metrics {
+    pub fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_handled(&self, kind: &str, elapsed: Duration) {
+        self.handled
+            .lock()
+            .unwrap()
+            .entry(kind.to_string())
+            .or_default()
+            .record(elapsed);
+    }
+}
+
+/// Upper bounds of the handler latency buckets, in microseconds. A last bucket holds everything
+/// slower.
+pub const LATENCY_BUCKETS_MICROS: [u64; 7] = [10, 50, 100, 500, 1_000, 5_000, 10_000];
+
+/// How many messages of a type were handled, and how long handling them took.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TypeStats {
+    pub count: u64,
+    pub total_micros: u64,
+    /// A histogram over `LATENCY_BUCKETS_MICROS`, plus the bucket for everything slower.
+    pub latency: Vec<u64>,
+}
+
+impl TypeStats {
+    fn record(&mut self, elapsed: Duration) {
+        let micros = elapsed.as_micros() as u64;
+        let bucket = LATENCY_BUCKETS_MICROS
+            .iter()
+            .position(|bound| micros <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MICROS.len());
+
+        self.latency.resize(LATENCY_BUCKETS_MICROS.len() + 1, 0);
+        self.latency[bucket] += 1;
+        self.count += 1;
+        self.total_micros += micros;
+    }
+}
+
+/// The counters, plus gauges read from the node's state at the time of the snapshot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MetricsSnapshot {
+    pub messages_in: u64,
+    pub messages_out: u64,
+    pub retries: u64,
+    pub dedupe_hits: u64,
+    pub dedupe_misses: u64,
+    pub pending: usize,
+    pub stored: usize,
+    pub callbacks: usize,
+    pub memory: usize,
+}
+
+impl MetricsSnapshot {
+    #[cfg(feature = "runtime")]
+    pub fn take(node: &Node) -> Self {
+        let memory = MemoryUsage::measure(node).total();
+
+        MetricsSnapshot {
+            messages_in: node.metrics.messages_in.load(Ordering::Relaxed),
+            messages_out: node.metrics.messages_out.load(Ordering::Relaxed),
+            retries: node.metrics.retries.load(Ordering::Relaxed),
+            dedupe_hits: node.metrics.dedupe_hits.load(Ordering::Relaxed),
+            dedupe_misses: node.metrics.dedupe_misses.load(Ordering::Relaxed),
+            pending: node.unacknowledged.len(),
+            #[cfg(feature = "broadcast")]
+            stored: node.broadcast.messages.len(),
+            #[cfg(not(feature = "broadcast"))]
+            stored: 0,
+            callbacks: node.correlations.len(),
+            memory,
+        }
+    }
+
+    /// A single line with the change in each counter since `previous`, and the current gauges.
+    pub fn delta(&self, previous: &MetricsSnapshot) -> String {
+        format!(
+            "metrics in=+{} out=+{} retries=+{} hits=+{} misses=+{} pending={} stored={} callbacks={} memory={}",
+            self.messages_in - previous.messages_in,
+            self.messages_out - previous.messages_out,
+            self.retries - previous.retries,
+            self.dedupe_hits - previous.dedupe_hits,
+            self.dedupe_misses - previous.dedupe_misses,
+            self.pending,
+            self.stored,
+            self.callbacks,
+            self.memory,
+        )
+    }
+}
+
+/// Everything the node counts, as answered to a `metrics` message and logged on shutdown.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MetricsReport {
+    #[serde(flatten)]
+    pub totals: MetricsSnapshot,
+    pub dropped: u64,
+    pub overloaded: u64,
+    pub evicted_callbacks: u64,
+    /// The peers that have timed out, and how they're faring.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub peers: BTreeMap<String, PeerReport>,
+    pub by_type: BTreeMap<String, TypeStats>,
+}
+
+#[cfg(feature = "runtime")]
+impl MetricsReport {
+    pub fn take(node: &Node) -> Self {
+        MetricsReport {
+            totals: MetricsSnapshot::take(node),
+            dropped: node.metrics.dropped.load(Ordering::Relaxed),
+            overloaded: node.metrics.overloaded.load(Ordering::Relaxed),
+            evicted_callbacks: node.metrics.evicted_callbacks.load(Ordering::Relaxed),
+            peers: node.peer_health.table(),
+            by_type: node.metrics.handled.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Write a metrics delta to stderr every `interval`, until the task is aborted. The lines go
+/// straight to stderr rather than through the log, so `--log-level` and `RUST_LOG` don't drop
+/// them.
+#[cfg(feature = "runtime")]
+pub async fn report(node: NodeHandle, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    let mut previous = MetricsSnapshot::default();
+
+    loop {
+        ticker.tick().await;
+
+        let Ok(snapshot) = node.call(|node| MetricsSnapshot::take(node)).await else {
+            continue;
+        };
+
+        eprintln!("{}", snapshot.delta(&previous));
+
+        previous = snapshot;
+    }
+}
+
+#[cfg(all(test, feature = "runtime"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reports_counter_deltas_and_gauges() {
+        let node = Node::default();
+
+        Metrics::increment(&node.metrics.messages_in);
+        let previous = MetricsSnapshot::take(&node);
+
+        Metrics::increment(&node.metrics.messages_in);
+        Metrics::increment(&node.metrics.messages_out);
+
+        assert_eq!(
+            MetricsSnapshot::take(&node).delta(&previous),
+            "metrics in=+1 out=+1 retries=+0 hits=+0 misses=+0 pending=0 stored=0 callbacks=0 memory=0"
+        );
+    }
+
+    #[test]
+    fn buckets_handler_latency_by_type() {
+        let node = Node::default();
+
+        node.metrics
+            .record_handled("echo", Duration::from_micros(30));
+        node.metrics
+            .record_handled("echo", Duration::from_millis(50));
+
+        let report = MetricsReport::take(&node);
+        let echo = &report.by_type["echo"];
+
+        assert_eq!(echo.count, 2);
+        assert_eq!(echo.latency, vec![0, 1, 0, 0, 0, 0, 0, 1]);
+
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(
+            serde_json::from_str::<MetricsReport>(&json).unwrap(),
+            report
+        );
+    }
+}
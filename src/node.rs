@@ -1,126 +1,854 @@
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+#[cfg(feature = "kv")]
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+#[cfg(feature = "broadcast")]
+use std::collections::HashSet;
 use std::fmt;
-use std::io::{self, Write};
-use std::sync::{Arc, Mutex, MutexGuard};
-use std::time::SystemTime;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{oneshot, Notify};
+use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
 
-use crate::message::{BroadcastBody, Message, MessageBody, MessageKind};
-use serde::Serialize;
-use std::hash::{DefaultHasher, Hash, Hasher};
+use crate::actor::NodeHandle;
+#[cfg(feature = "broadcast")]
+use crate::bootstrap::Bootstrap;
+#[cfg(feature = "broadcast")]
+use crate::broadcast::BroadcastState;
+use crate::clock::LamportClock;
+use crate::config::Config;
+#[cfg(feature = "counter")]
+use crate::core::counter::PnCounter;
+#[cfg(any(feature = "counter", feature = "kv"))]
+use crate::core::message::ReplicateBody;
+#[cfg(feature = "broadcast")]
+use crate::core::message::{BroadcastValue, GossipBody, GossipDigestBody, SyncBody};
+use crate::core::message::{
+    Envelope, ErrorBody, ErrorCode, HeartbeatBody, IdFormat, InternalBody, Message, MessageBody,
+    RemoteError, TimerBody,
+};
+use crate::core::state;
+use crate::correlation::Correlations;
+use crate::delivery::Deliveries;
+use crate::error::NodeError;
+use crate::failure::Liveness;
+#[cfg(feature = "broadcast")]
+use crate::gossip::{AntiEntropy, Digest};
+#[cfg(any(feature = "broadcast", feature = "kv"))]
+use crate::handlers;
+use crate::health::PeerHealth;
+use crate::idempotency::ReplyCache;
+use crate::jitter::StartupJitter;
+#[cfg(feature = "kv")]
+use crate::kv::{KvMode, KvState};
+use crate::lanes::{LaneConfig, Lanes};
+use crate::lifecycle::{self, OnMessage};
+#[cfg(feature = "kafka")]
+use crate::log::KafkaState;
+use crate::memory::MemoryBounds;
+use crate::metrics::Metrics;
+use crate::outbox::{self, Outbox};
+use crate::persist::Persistence;
+#[cfg(feature = "broadcast")]
+use crate::persist::Record;
+use crate::quiescence::Quiescence;
+#[cfg(feature = "raft")]
+use crate::raft::Raft;
+use crate::readiness::Readiness;
+use crate::retry::Retries;
+#[cfg(feature = "broadcast")]
+use crate::rpc::rpc;
+use crate::rpc::RpcPermits;
+#[cfg(any(feature = "kafka", feature = "kv"))]
+use crate::sharding::HashRing;
+use crate::shutdown::Drain;
+use crate::snowflake::Snowflake;
+use crate::tasks::Tasks;
+use crate::tiebreak;
+use crate::timer::{TimerEvent, Timers};
+#[cfg(feature = "txn")]
+use crate::txn::Transactions;
+use crate::workload::{ReplyModes, Workload};
 
-#[derive(Clone, Hash, Serialize)]
-struct UniqueId(String);
+/// Handles one type of message, returning the messages to send in response: replies to the
+/// sender, and any messages for other nodes.
+pub trait Handler: Send + Sync {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>>;
+}
+
+/// The handlers for each message `type`. Messages without a handler are answered with a
+/// not-supported error, unless they are themselves replies.
+#[derive(Clone)]
+pub struct Registry {
+    handlers: HashMap<String, Arc<dyn Handler>>,
+    /// The workloads with lifecycle hooks; their message types are routed to `on_message`.
+    workloads: Vec<Arc<dyn lifecycle::Workload>>,
+}
+
+impl Registry {
+    pub fn empty() -> Self {
+        Registry {
+            handlers: HashMap::new(),
+            workloads: vec![],
+        }
+    }
+
+    pub fn register(&mut self, kind: &str, handler: impl Handler + 'static) {
+        self.handlers.insert(kind.to_string(), Arc::new(handler));
+    }
+
+    /// Host a workload, registering it for each of its message types.
+    pub fn host(&mut self, workload: impl lifecycle::Workload + 'static) {
+        let workload: Arc<dyn lifecycle::Workload> = Arc::new(workload);
+
+        for kind in workload.message_types() {
+            self.register(kind, OnMessage(workload.clone()));
+        }
+
+        self.workloads.push(workload);
+    }
+
+    pub fn workloads(&self) -> Vec<Arc<dyn lifecycle::Workload>> {
+        self.workloads.clone()
+    }
+
+    pub fn get(&self, kind: &str) -> Option<Arc<dyn Handler>> {
+        self.handlers.get(kind).cloned()
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::for_workloads(&Workload::ALL)
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.handlers.keys()).finish()
+    }
+}
+
+/// What a message read from stdin has to wait for before it's handled, read from the node in one
+/// call.
+struct Admission {
+    metrics: Arc<Metrics>,
+    changed: Arc<Notify>,
+    /// Notified once the bootstrap completes, for a client's read while it's pending.
+    #[cfg(feature = "broadcast")]
+    bootstrap: Option<Arc<Notify>>,
+    /// The workload a client's request waits on, and for how long, while it isn't ready.
+    gate: Option<(Workload, Duration)>,
+}
+
+impl Admission {
+    fn new(node: &Node, kind: Option<&str>, src: Option<&str>) -> Self {
+        // Messages from other nodes are handled immediately; they may be bootstrapping too.
+        let from_client = !src.is_some_and(|src| node.node_ids.iter().any(|id| id == src));
 
-impl UniqueId {
-    fn generate_hash(&self) -> u64 {
-        let mut hasher = DefaultHasher::new();
+        #[cfg(feature = "broadcast")]
+        let bootstrap =
+            (from_client && kind == Some("read") && node.broadcast.bootstrap.is_pending())
+                .then(|| node.broadcast.bootstrap.done());
 
-        self.0.hash(&mut hasher);
+        let gate = kind
+            .filter(|_| from_client)
+            .and_then(|kind| node.readiness.gate(kind))
+            .filter(|(workload, _)| !workload.is_ready(node));
 
-        hasher.finish()
+        Admission {
+            metrics: node.metrics.clone(),
+            changed: node.readiness.changed(),
+            #[cfg(feature = "broadcast")]
+            bootstrap,
+            gate,
+        }
+    }
+}
+
+/// Messages `Node::outgoing` handed back for the caller to send, and the format they're written
+/// in.
+#[derive(Debug, Default)]
+struct Unsent {
+    format: IdFormat,
+    messages: Vec<Message<'static>>,
+}
+
+impl Unsent {
+    /// Each message as it's written to the wire.
+    fn lines(&self) -> Vec<String> {
+        self.messages
+            .iter()
+            .map(|message| {
+                self.format
+                    .to_json(message)
+                    .expect("Couldn't parse response.")
+            })
+            .collect()
     }
 }
 
 #[derive(Debug, Default)]
 pub struct Node {
     pub id: Option<String>,
-    pub messages: HashSet<u32>,
-    pub topology: Vec<String>,
+    pub node_ids: Vec<String>,
+    /// The broadcast workload's values, gossip, and overlay.
+    #[cfg(feature = "broadcast")]
+    pub broadcast: BroadcastState,
     pub current_message_id: u32,
-    pub response_callbacks: HashMap<u32, ResponseCallback>,
-    pub unacknowledged_messages: Arc<Mutex<HashSet<u32>>>,
+    pub lamport: LamportClock,
+    /// The requests awaiting a reply, by `msg_id`.
+    pub correlations: Correlations,
+    /// When each request awaited with `expect_reply` times out.
+    pub rpc_deadlines: HashMap<u32, Instant>,
+    /// Work waiting on other nodes' replies; see `Tasks`.
+    pub tasks: Tasks,
+    /// Messages to peers waiting to be acknowledged; see `Deliveries`.
+    pub unacknowledged: Deliveries,
+    /// Consecutive timeouts, and so health, of each peer.
+    pub peer_health: PeerHealth,
+    /// Which peers are alive, from their heartbeats.
+    pub liveness: Liveness,
+    /// The node's periodic work, fired through `dispatch` while the node is running.
+    pub timers: Timers,
+    pub id_format: IdFormat,
+    pub snowflake: Snowflake,
+    pub metrics: Arc<Metrics>,
+    pub memory_bounds: MemoryBounds,
+    pub readiness: Readiness,
+    pub quiescence: Quiescence,
+    pub retries: Retries,
+    pub startup_jitter: StartupJitter,
+    pub drain: Drain,
+    /// The counter workloads' counts.
+    #[cfg(feature = "counter")]
+    pub counter: PnCounter,
+    /// The kafka workload's logs.
+    #[cfg(feature = "kafka")]
+    pub kafka: KafkaState,
+    /// The kv workload's store, entries, and sessions, whichever its mode uses.
+    #[cfg(feature = "kv")]
+    pub kv: KvState,
+    /// Which node owns each key, in the kafka workload, the kv workload's `sharded` mode, and the
+    /// txn workload.
+    #[cfg(any(feature = "kafka", feature = "kv"))]
+    pub ring: HashRing,
+    /// The txn workload's transactions, as coordinator and as participant.
+    #[cfg(feature = "txn")]
+    pub txns: Transactions,
+    pub rpc_permits: Arc<RpcPermits>,
+    /// Queues every message the node sends while it is running.
+    pub outbox: Outbox,
+    pub reply_modes: ReplyModes,
+    /// Replies to recent client requests, replayed when a client retransmits one.
+    pub replies: ReplyCache,
+    /// Where the node's state is saved, with `--state-dir`.
+    pub persistence: Persistence,
+    pub registry: Registry,
+    /// The tunables the node was started with, for handlers to read.
+    pub config: Config,
 }
 
-// Define the callback type and allow it to be displayed.
-pub struct ResponseCallback(pub Box<dyn Fn(MutexGuard<Node>) + Send + Sync + 'static>);
+impl Node {
+    pub async fn run(
+        node: NodeHandle,
+        rx: Receiver<String>,
+        response_tx: Sender<String>,
+        task_tracker: &TaskTracker,
+    ) -> () {
+        let lanes = node
+            .call(|node| node.config.lanes.clone())
+            .await
+            .unwrap_or_default();
 
-impl fmt::Debug for ResponseCallback {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Callback Function")
+        Node::run_with_lanes(node, rx, response_tx, task_tracker, lanes).await;
     }
-}
 
-impl Node {
-    pub async fn run(
-        node: Arc<Mutex<Node>>,
+    pub async fn run_with_lanes(
+        node: NodeHandle,
         mut rx: Receiver<String>,
         response_tx: Sender<String>,
         task_tracker: &TaskTracker,
-    ) -> () {
+        config: LaneConfig,
+    ) {
+        let started = node
+            .call(move |node| {
+                node.outbox.open();
+
+                let retry = node.startup_jitter.delay(node.id.as_deref(), "retry")
+                    + node.retries.next_wake().max(Duration::from_millis(1));
+                node.schedule(retry, TimerEvent::Retry);
+
+                #[cfg(any(feature = "counter", feature = "kv"))]
+                node.schedule_repeating(node.config.gossip_interval, TimerEvent::Replicate);
+                node.schedule_repeating(
+                    (node.config.rpc_timeout / 4).max(Duration::from_millis(1)),
+                    TimerEvent::ExpireRpcs,
+                );
+
+                for workload in node.registry.workloads() {
+                    if let Some(interval) = workload.interval(node) {
+                        let event = TimerEvent::WorkloadTick(workload.name().to_string());
+                        node.schedule_repeating(interval, event);
+                    }
+                }
+
+                #[cfg(feature = "broadcast")]
+                if let Some(interval) = node.broadcast.anti_entropy.interval {
+                    node.schedule_repeating(interval, TimerEvent::AntiEntropy);
+                }
+
+                if let Some(interval) = node.liveness.interval {
+                    node.schedule_repeating(interval, TimerEvent::Heartbeat);
+                }
+
+                if let Some(interval) = node.persistence.commit_interval() {
+                    node.schedule_repeating(interval, TimerEvent::CommitWal);
+                }
+
+                #[cfg(feature = "raft")]
+                if node.config.kv_mode == KvMode::Raft {
+                    node.schedule_repeating(Raft::TICK, TimerEvent::RaftTick);
+                }
+
+                #[cfg(feature = "txn")]
+                if node.config.workloads.contains(&Workload::Txn) {
+                    node.schedule_repeating(
+                        Transactions::TIMEOUT / 4,
+                        TimerEvent::ExpireTransactions,
+                    );
+                }
+
+                node.drain.clone()
+            })
+            .await;
+
+        let Ok(drain) = started else {
+            error!("Unable to start the node.");
+            return;
+        };
+
+        // Every message the node sends while running goes through the outbox, written by this
+        // one task.
+        let outbox = task_tracker.spawn(outbox::drain(node.clone(), response_tx.clone()));
+
+        // Cancelled once stdin closes and the lanes are done; the timers then wind down instead
+        // of being cut off mid-send.
+        let shutdown = CancellationToken::new();
+
+        let timers = task_tracker.spawn(Node::run_timers(node.clone(), shutdown.clone()));
+
+        let lanes = Lanes::spawn(config, node.clone(), response_tx, task_tracker);
+
         // `recv()` keeps the `rx` alive because it doesn't drop the value by ending the
         // execution of the thread. The thread is put to sleep until the channel is closed.
         //
         // You must explicitly drop `tx`, or close the thread after tracker.spawn() to close the
         // channel, and break the loop.
+        //
+        // Messages are routed to a lane by their affinity key; each lane handles its messages
+        // in order, and the lanes run in parallel. `dispatch` waits when a lane is full.
         while let Some(from_stdin) = rx.recv().await {
-            let response_reference = response_tx.clone();
-
-            // NOTE: node_clone must occur in the `while` loop (not outside of it), else the borrow checker
-            // complains because the spawned thread takes ownership of it.
-            //
-            // You can't clone in the spawned thread because the thread will own `node`.
-            let node_clone = node.clone();
-
-            task_tracker.spawn(async move {
-                match Node::handle_from_stdin(node_clone, &from_stdin) {
-                    Ok(Some(stringified_response)) => {
-                        eprintln!("Sending message: {:?}", stringified_response);
-                        response_reference.send(stringified_response).await.unwrap();
+            lanes.dispatch(from_stdin).await;
+        }
+
+        // Stdin is closed: finish the messages already read, then let the retry timer drain,
+        // for at most the drain timeout.
+        lanes.close().await;
+
+        // A hook that panics has been logged; shut down regardless.
+        let _ = node
+            .call(|node| {
+                for workload in node.registry.workloads() {
+                    let mut messages = workload.on_shutdown(node);
+                    messages.iter_mut().for_each(|message| node.stamp(message));
+
+                    node.send_all(messages);
+                }
+            })
+            .await;
+
+        shutdown.cancel();
+
+        let abort = timers.abort_handle();
+        let drained = tokio::time::timeout(drain.timeout, timers).await;
+
+        if drained.is_err() {
+            warn!("Stopped draining after {:?}.", drain.timeout);
+
+            abort.abort();
+        }
+
+        // Write out what's left, then release the response channel, so the writer finishes once
+        // the lanes do. What's held for the logs goes too, once they're fsynced.
+        let _ = node
+            .call(|node| {
+                match node.persistence.sync() {
+                    Ok(()) => node.outbox.hold(false),
+                    Err(err) => error!("Unable to save the node's state: {:?}", err),
+                }
+
+                node.outbox.close()
+            })
+            .await;
+        let _ = outbox.await;
+
+        info!("Shutting down...");
+    }
+
+    /// Handle every message in `from_stdin`, in order.
+    pub async fn process(node: NodeHandle, from_stdin: &str, response_tx: &Sender<String>) {
+        for document in state::documents(from_stdin) {
+            let envelope = state::envelope(document).ok();
+
+            Node::process_document(node.clone(), document, envelope, response_tx).await;
+        }
+    }
+
+    /// Handle one message, given its envelope if it has one, once the bootstrap and its
+    /// workload allow. Everything logged on its way through is in its `message` span.
+    pub async fn process_document(
+        node: NodeHandle,
+        document: &str,
+        envelope: Option<Envelope<'_>>,
+        response_tx: &Sender<String>,
+    ) {
+        let span = match &envelope {
+            Some(envelope) => info_span!(
+                "message",
+                msg_id = envelope.body.msg_id,
+                r#type = %envelope.body.kind,
+                src = envelope.src.as_deref(),
+            ),
+            None => Span::none(),
+        };
+
+        Node::admit(node, document, envelope, response_tx)
+            .instrument(span)
+            .await
+    }
+
+    async fn admit(
+        node: NodeHandle,
+        document: &str,
+        envelope: Option<Envelope<'_>>,
+        response_tx: &Sender<String>,
+    ) {
+        let kind = envelope
+            .as_ref()
+            .map(|envelope| envelope.body.kind.to_string());
+        let src = envelope.as_ref().and_then(|envelope| envelope.src.clone());
+        let src = src.map(Cow::into_owned);
+
+        let Ok(admission) = node
+            .call(move |node| Admission::new(node, kind.as_deref(), src.as_deref()))
+            .await
+        else {
+            return;
+        };
+
+        Metrics::increment(&admission.metrics.messages_in);
+
+        #[cfg(feature = "broadcast")]
+        if let Some(done) = &admission.bootstrap {
+            Node::wait_for_bootstrap(&node, done).await;
+        }
+
+        if let Some((workload, timeout)) = admission.gate {
+            if !Node::wait_until_ready(&node, workload, timeout, &admission.changed).await {
+                warn!(
+                    "The {:?} workload isn't ready; rejecting {:?}",
+                    workload, document
+                );
+
+                let unavailable = match &envelope {
+                    Some(envelope) => Node::unavailable(&node, envelope).await,
+                    None => Unsent::default(),
+                };
+
+                for unavailable in unavailable.lines() {
+                    match response_tx.send(unavailable).await {
+                        Ok(()) => Metrics::increment(&admission.metrics.messages_out),
+                        Err(_) => Metrics::increment(&admission.metrics.dropped),
                     }
-                    Ok(None) => {}
-                    Err(err) => {
-                        eprintln!(
-                            "Uh oh. Something went wrong handling stdin: {:?}, message: {:?}",
-                            err, &from_stdin
-                        );
+                }
+
+                return;
+            }
+        }
+
+        let handled = Node::handle_document(node, document, envelope).await;
+
+        // Whatever was just handled may have made a workload ready.
+        admission.changed.notify_waiters();
+
+        match handled {
+            Ok(stringified_responses) => {
+                for stringified_response in stringified_responses {
+                    debug!("Sending message: {:?}", stringified_response);
+                    match response_tx.send(stringified_response).await {
+                        Ok(()) => Metrics::increment(&admission.metrics.messages_out),
+                        Err(_) => Metrics::increment(&admission.metrics.dropped),
                     }
-                };
-            });
+                }
+            }
+            Err(err) => {
+                warn!(
+                    "Uh oh. Something went wrong handling stdin: {:?}, message: {:?}",
+                    err, document
+                );
+            }
+        };
+    }
+
+    /// Hold a client read until the bootstrap from neighbors completes, or is abandoned after a
+    /// timeout.
+    #[cfg(feature = "broadcast")]
+    async fn wait_for_bootstrap(node: &NodeHandle, done: &Notify) {
+        // Register for the notification before checking again, so completing in between isn't
+        // missed.
+        let notified = done.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        if node
+            .call(|node| node.broadcast.bootstrap.is_pending())
+            .await
+            != Ok(true)
+        {
+            return;
         }
 
-        eprintln!("{}", "Shutting down...");
+        if tokio::time::timeout(Bootstrap::TIMEOUT, notified)
+            .await
+            .is_err()
+        {
+            let _ = node.call(|node| node.broadcast.bootstrap.abandon()).await;
+        }
     }
 
-    pub fn handle_from_stdin(
-        node: Arc<Mutex<Node>>,
-        value: &String,
-    ) -> Result<Option<String>, String> {
-        let Ok(serialized_message) = serde_json::from_str(&value) else {
-            return Err("Uh-oh, unable to parse that message.".to_string());
+    /// Hold a client request until its workload is ready, returning false if it isn't within
+    /// the workload's timeout.
+    async fn wait_until_ready(
+        node: &NodeHandle,
+        workload: Workload,
+        timeout: Duration,
+        changed: &Notify,
+    ) -> bool {
+        let wait = async {
+            loop {
+                // Register for the notification before checking, so a change in between isn't
+                // missed.
+                let notified = changed.notified();
+
+                // A check that panics lets the request through, to be answered by its handler.
+                if node.call(move |node| workload.is_ready(node)).await != Ok(false) {
+                    return;
+                }
+
+                notified.await;
+            }
         };
 
-        let message = Message::new(serialized_message);
+        tokio::time::timeout(timeout, wait).await.is_ok()
+    }
 
-        Node::run_callback(&node, &message);
+    /// The `temporarily-unavailable` reply to a request whose workload isn't ready, unless the
+    /// reply was queued on the outbox.
+    async fn unavailable(node: &NodeHandle, envelope: &Envelope<'_>) -> Unsent {
+        let envelope = envelope.clone().into_owned();
 
-        // Lock the mutex after `run_callback`, or else you get a deadlock;
-        // `run_callback` locks the node.
-        let mut locked = node.lock().unwrap();
+        node.call(move |node| {
+            let Some(mut reply) = envelope.error_reply(
+                node.id.clone(),
+                ErrorCode::TemporarilyUnavailable,
+                "The node isn't ready yet.",
+            ) else {
+                return Unsent::default();
+            };
+            node.stamp(&mut reply);
 
-        let id = locked.next_message_id();
+            node.outgoing(vec![reply])
+        })
+        .await
+        .unwrap_or_default()
+    }
 
-        if let Some((response, _original_message)) = message.generate_response(&locked, id) {
-            let stringified_response =
-                serde_json::to_string(&response).expect("Couldn't parse response.");
+    /// Handle every message in `value`, whether one per line or concatenated. A document that
+    /// can't be parsed, or replied to, is an error only when no earlier message was replied to.
+    pub async fn handle_from_stdin(
+        node: NodeHandle,
+        value: &str,
+    ) -> Result<Vec<String>, NodeError> {
+        let mut responses = vec![];
 
-            Ok(Some(stringified_response))
-        } else {
-            Ok(None)
+        for document in state::documents(value) {
+            match Node::handle_document(node.clone(), document, None).await {
+                Ok(replies) => responses.extend(replies),
+                Err(err) if responses.is_empty() => return Err(err),
+                Err(err) => warn!("Unable to handle {:?}: {}", document, err),
+            }
         }
+
+        Ok(responses)
     }
 
-    pub fn generate_uuid(&self, client_id: &String) -> u64 {
-        let time = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap();
+    /// Handle one message, given its envelope if it was already read.
+    async fn handle_document(
+        node: NodeHandle,
+        value: &str,
+        envelope: Option<Envelope<'_>>,
+    ) -> Result<Vec<String>, NodeError> {
+        let message = match serde_json::from_str::<Message>(value) {
+            Ok(message) => message,
+            Err(err) => {
+                // Reply with an error when the sender can be made out. The envelope is only
+                // read here if the caller hadn't.
+                let envelope = envelope.or_else(|| state::envelope(value).ok());
+                let err = state::parse_error(err, envelope.as_ref());
+                let envelope = envelope.map(Envelope::into_owned);
+                let error = err.clone();
+                let reply = node
+                    .call(move |node| {
+                        let mut reply = envelope?.error_reply(
+                            node.id.clone(),
+                            error.code(),
+                            &error.to_string(),
+                        )?;
+                        node.stamp(&mut reply);
 
-        if let Some(ref id) = self.id {
-            UniqueId(format!("{}-{}-{}", id, client_id, time.as_nanos())).generate_hash()
-        } else {
-            panic!()
+                        Some(node.outgoing(vec![reply]))
+                    })
+                    .await
+                    .ok()
+                    .flatten();
+
+                return match reply {
+                    Some(unsent) => {
+                        warn!("Unable to parse message: {:?}", err);
+
+                        Ok(unsent.lines())
+                    }
+                    None => Err(err),
+                };
+            }
+        };
+
+        // Kept to answer the request with, should its handler panic.
+        let request = match message.body.in_reply_to() {
+            None => message.src.clone().map(|src| (src, message.body.msg_id())),
+            Some(_) => None,
+        };
+
+        // The node's task outlives the line, so the few strings borrowed from it are copied.
+        let message = message.into_owned();
+
+        let responses = match node
+            .call(move |node| {
+                let responses = node.dispatch(message);
+                node.outgoing(responses)
+            })
+            .await
+        {
+            Ok(responses) => responses,
+            Err(panicked) => {
+                error!("Unable to handle {:?}: {}", value, panicked);
+
+                let Some((src, in_reply_to)) = request else {
+                    return Ok(vec![]);
+                };
+
+                node.call(move |node| {
+                    let mut reply = Message {
+                        src: node.id.clone(),
+                        dest: src,
+                        body: MessageBody::Error(ErrorBody {
+                            code: ErrorCode::Crash,
+                            text: "The node failed handling this message.".into(),
+                            msg_id: None,
+                            in_reply_to,
+                        }),
+                        lamport: None,
+                    };
+                    node.stamp(&mut reply);
+
+                    node.outgoing(vec![reply])
+                })
+                .await
+                .unwrap_or_default()
+            }
+        };
+
+        // Serialize outside the node's task, so large replies never block other messages. The
+        // outbox's task does the same for the replies it took.
+        Ok(responses.lines())
+    }
+
+    /// Hand a reply to the request waiting on it, and the message to the handler registered for
+    /// its type, then run the node's tasks.
+    pub fn dispatch(&mut self, message: Message) -> Vec<Message<'static>> {
+        let _span = info_span!(
+            "message",
+            msg_id = message.body.msg_id(),
+            r#type = message.body.kind(),
+            src = message.src.as_deref(),
+        )
+        .entered();
+
+        if let Some(time) = message.lamport {
+            self.lamport.merge(time);
+        }
+
+        // A request with no sender can't be answered, and a client's request needs a `msg_id`
+        // for its reply to name.
+        if message.body.in_reply_to().is_none() {
+            let Some(src) = &message.src else {
+                warn!("Dropping a {} message with no src.", message.body.kind());
+                return vec![];
+            };
+
+            if !self.node_ids.contains(src) && message.body.msg_id().is_none() {
+                let mut error =
+                    NodeError::MissingField("msg_id").reply_to(&message, self.id.clone());
+                self.stamp(&mut error);
+
+                return vec![error];
+            }
+        }
+
+        // Anything from outside the cluster is a client request.
+        if let Some(src) = &message.src {
+            if !self.node_ids.contains(src) {
+                self.quiescence.record_activity();
+            }
         }
+
+        // A retransmitted client request gets the replies the first one did, without being
+        // handled again.
+        if let Some(key) = ReplyCache::key(&message, &self.node_ids) {
+            if let Some(mut replies) = self.replies.replay(&key) {
+                debug!("Replaying the replies to {:?}.", key);
+
+                replies.iter_mut().for_each(|reply| self.stamp(reply));
+                return replies;
+            }
+
+            self.replies.start(key);
+        }
+
+        let mut messages = vec![];
+
+        if let Some(in_reply_to) = message.body.in_reply_to() {
+            if let Some(src) = &message.src {
+                self.peer_health.record_reply(src);
+            }
+
+            // A reply to any attempt at a delivery answers whoever awaits any of them.
+            let correlated = match self.unacknowledged.get(in_reply_to) {
+                Some(delivery) => delivery.attempts.clone(),
+                None => vec![in_reply_to],
+            };
+
+            for msg_id in correlated {
+                self.rpc_deadlines.remove(&msg_id);
+
+                let reply = match &message.body {
+                    MessageBody::Error(body) => Err(RemoteError::from(body)),
+                    _ => Ok(message.clone().into_owned()),
+                };
+
+                // An error leaves the request's age, in case it's awaited again.
+                let reply_tx = match reply.is_ok() {
+                    true => self.correlations.remove(msg_id),
+                    false => self.correlations.take(msg_id),
+                };
+
+                if let Some(reply_tx) = reply_tx {
+                    // The task may have stopped waiting.
+                    let _ = reply_tx.send(reply);
+                }
+            }
+        }
+
+        let kind = message.body.kind();
+
+        match self.registry.get(kind) {
+            Some(handler) => {
+                let started = Instant::now();
+
+                messages.extend(handler.handle(self, message));
+                self.metrics.record_handled(kind, started.elapsed());
+            }
+            None if message.body.in_reply_to().is_some() => {}
+            None => messages.push(
+                NodeError::UnsupportedType(kind.to_string()).reply_to(&message, self.id.clone()),
+            ),
+        }
+
+        self.replies.record(&messages);
+        messages.iter_mut().for_each(|message| self.stamp(message));
+        messages.extend(self.run_tasks());
+
+        // The state is saved before the replies acknowledging it are sent. Within the group
+        // commit window, everything queued waits on the outbox until it's durable.
+        if let Err(err) = Persistence::save(self) {
+            error!("Unable to save the node's state: {:?}", err);
+        }
+        self.outbox.hold(!self.persistence.is_synced());
+
+        messages
+    }
+
+    /// Store a broadcast value, logging it to be saved if it's new. Returns whether it was.
+    #[cfg(feature = "broadcast")]
+    pub fn insert_message(&mut self, value: BroadcastValue) -> bool {
+        if !self.broadcast.messages.insert(value.clone()) {
+            return false;
+        }
+
+        self.persistence.record(|| Record::Broadcast(value));
+
+        true
+    }
+
+    /// Stamp a message that's about to be sent with the next Lamport time.
+    pub fn stamp(&mut self, message: &mut Message) {
+        message.lamport = Some(self.lamport.tick());
+    }
+
+    /// Every node in the cluster but this one, as listed in `init`.
+    pub fn other_nodes(&self) -> Vec<String> {
+        self.node_ids
+            .iter()
+            .filter(|node_id| Some(*node_id) != self.id.as_ref())
+            .cloned()
+            .collect()
+    }
+
+    /// The nodes to gossip to: the neighbors from the `topology` message, or every other node
+    /// until one arrives.
+    #[cfg(feature = "broadcast")]
+    pub fn peers(&self) -> Vec<String> {
+        match self.broadcast.topology.is_empty() {
+            true => self.other_nodes(),
+            false => self.broadcast.topology.clone(),
+        }
+    }
+
+    /// A Snowflake ID. The node's index is its position among `node_ids` in `tiebreak` order,
+    /// which every node agrees on. `None` before `init`, or when there are more nodes than the
+    /// layout has room for.
+    pub fn generate_id(&mut self) -> Option<u64> {
+        let id = self.id.as_ref()?;
+        let index = tiebreak::sorted(&self.node_ids)
+            .iter()
+            .position(|node_id| *node_id == id)?;
+
+        self.snowflake.next(index as u64)
     }
 
     pub fn next_message_id(&mut self) -> u32 {
@@ -128,204 +856,1256 @@ impl Node {
         self.current_message_id
     }
 
-    pub fn run_callback(mutex: &Arc<Mutex<Node>>, message: &MessageKind) {
-        let mut node = mutex.lock().unwrap();
+    /// Queue `body` for `peer` on the outbox.
+    pub fn send_to(&mut self, peer: &str, body: MessageBody<'static>) {
+        let mut message = Message {
+            src: self.id.clone(),
+            dest: peer.to_string(),
+            body,
+            lamport: None,
+        };
+        self.stamp(&mut message);
 
-        match message {
-            MessageKind::Init(message) => {
-                if let MessageBody::Init(body) = &message.body {
-                    node.id = Some(body.node_id.to_owned());
-                }
+        self.outbox.push(message);
+    }
+
+    /// Queue messages a task sends on the outbox, stamped, remembering those that reply to
+    /// clients as `dispatch` does.
+    pub fn send(&mut self, mut messages: Vec<Message<'static>>) {
+        self.replies.record(&messages);
+        messages.iter_mut().for_each(|message| self.stamp(message));
+
+        self.send_all(messages);
+    }
+
+    /// Queue messages that are already stamped on the outbox.
+    pub fn send_all(&mut self, messages: Vec<Message<'static>>) {
+        messages
+            .into_iter()
+            .for_each(|message| self.outbox.push(message));
+    }
+
+    /// While the node is running, queue messages on the outbox; otherwise hand them back for the
+    /// caller to send.
+    fn outgoing(&mut self, messages: Vec<Message<'static>>) -> Unsent {
+        if !self.outbox.is_open() {
+            return Unsent {
+                format: self.id_format,
+                messages,
+            };
+        }
+
+        self.send_all(messages);
+
+        Unsent::default()
+    }
+
+    /// A reply from this node to `message`, with the body's ids filled in.
+    pub fn reply<'b>(&mut self, message: &Message, body: MessageBody<'b>) -> Message<'b> {
+        let msg_id = self.next_message_id();
+
+        message.reply_with(self.id.clone(), Some(msg_id), body)
+    }
+
+    /// The reply to request `msg_id`, or a timeout error if none arrives within the configured
+    /// `rpc_timeout`. Dropped unanswered if the request is evicted or given up on.
+    pub fn expect_reply(
+        &mut self,
+        msg_id: u32,
+    ) -> oneshot::Receiver<Result<Message<'static>, RemoteError>> {
+        let (reply_tx, reply) = oneshot::channel();
+
+        self.rpc_deadlines
+            .insert(msg_id, Instant::now() + self.config.rpc_timeout);
+        self.correlations.insert(msg_id, reply_tx);
+
+        if self.correlations.len() > self.correlations.cap {
+            self.evict_requests();
+        }
+
+        reply
+    }
+
+    /// Drop the requests that have waited too long, or the oldest over the cap, along with
+    /// their deadlines and unacknowledged messages.
+    pub fn evict_requests(&mut self) {
+        for msg_id in self.correlations.evict(Instant::now()) {
+            debug!("Evicting the request for message {}.", msg_id);
+
+            self.rpc_deadlines.remove(&msg_id);
+            self.unacknowledged.remove(msg_id);
+            Metrics::increment(&self.metrics.evicted_callbacks);
+        }
+    }
+
+    /// Answer the requests that are past their deadline with a timeout error. What the tasks
+    /// awaiting them send comes out of `run_tasks`.
+    pub fn expire_rpcs(&mut self) -> Vec<Message<'static>> {
+        let now = Instant::now();
+        let mut expired: Vec<u32> = self
+            .rpc_deadlines
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(msg_id, _)| *msg_id)
+            .collect();
+        expired.sort();
+
+        for msg_id in expired {
+            self.rpc_deadlines.remove(&msg_id);
+
+            if let Some(reply_tx) = self.correlations.take(msg_id) {
+                let timeout = RemoteError {
+                    code: ErrorCode::Timeout,
+                    text: format!("No reply within {:?}.", self.config.rpc_timeout),
+                };
+
+                let _ = reply_tx.send(Err(timeout));
             }
-            MessageKind::BroadcastOk(message) => {
-                if message.dest != node.id.clone().unwrap() {
-                    eprintln!(
-                        "Skipping message because the destination is the same as node: {:?}",
-                        message
-                    );
+        }
 
-                    return;
-                }
+        self.evict_requests();
 
-                if let MessageBody::BroadcastOk(body) = &message.body {
-                    if let Some(response_callback) =
-                        node.response_callbacks.remove(&body.in_reply_to)
-                    {
-                        let ResponseCallback(callback) = response_callback;
-                        callback(node);
-
-                        eprintln!("Broadcast Ok received for message: {:?}", body.in_reply_to);
-                    } else {
-                        eprintln!(
-                            "Skipping Broadcast OK callback because the in reply to is not found in callbacks: {:?}",
-                            message
-                        );
-                    }
+        vec![]
+    }
+
+    /// Run work that waits on other nodes alongside the node's messages; see `Tasks`.
+    pub fn spawn(&mut self, task: impl Future<Output = ()> + Send + 'static) {
+        self.tasks.spawn(task);
+    }
+
+    /// A handle for the node's tasks to reach it with.
+    pub fn handle(&self) -> NodeHandle {
+        self.tasks.handle()
+    }
+
+    /// Run the node's tasks until they wait. What they send is on the outbox while the node is
+    /// running, and handed back otherwise.
+    pub fn run_tasks(&mut self) -> Vec<Message<'static>> {
+        Tasks::run(self);
+
+        match self.outbox.is_open() {
+            true => vec![],
+            false => self.outbox.take(),
+        }
+    }
+
+    /// Fire `event` once, `after` from now.
+    pub fn schedule(&mut self, after: Duration, event: TimerEvent) {
+        self.timers.schedule(Instant::now() + after, event, None);
+    }
+
+    /// Fire `event` every `every`, the first time after this node's startup jitter for it.
+    pub fn schedule_repeating(&mut self, every: Duration, event: TimerEvent) {
+        let delay = self.startup_jitter.delay(self.id.as_deref(), event.name());
+
+        self.timers
+            .schedule(Instant::now() + delay + every, event, Some(every));
+    }
+
+    /// Do a timer's work, returning the messages it sends; `run_tasks` sends those of the tasks it
+    /// woke, e.g. by expiring their RPCs.
+    pub fn fire(&mut self, event: &TimerEvent) -> Vec<Message<'static>> {
+        match event {
+            TimerEvent::Retry => self.resend_due(),
+            TimerEvent::ExpireRpcs => self.expire_rpcs(),
+            #[cfg(any(feature = "counter", feature = "kv"))]
+            TimerEvent::Replicate => self.replicate(),
+            TimerEvent::Heartbeat => self.heartbeats(),
+            #[cfg(feature = "broadcast")]
+            TimerEvent::AntiEntropy => self.anti_entropy_round(),
+            #[cfg(feature = "raft")]
+            TimerEvent::RaftTick => self.raft_tick(),
+            #[cfg(feature = "txn")]
+            TimerEvent::ExpireTransactions => handlers::expire_transactions(self),
+            // `dispatch` commits the logs after every message, this one included.
+            TimerEvent::CommitWal => vec![],
+            TimerEvent::WorkloadTick(name) => {
+                let workload = self
+                    .registry
+                    .workloads()
+                    .into_iter()
+                    .find(|workload| workload.name() == name);
+
+                match workload {
+                    Some(workload) => workload.on_tick(self),
+                    None => vec![],
                 }
             }
-            MessageKind::Broadcast(message) => {
-                if let MessageBody::Broadcast(body) = &message.body {
-                    let is_message_seen = node.messages.contains(&body.message);
-
-                    if !is_message_seen {
-                        node.messages.insert(body.message);
-
-                        // Generate message ID, and persist the message ID in the list of
-                        // unacknowledged messages before sending the first message.
-                        let mapped_messages = node
-                            .topology
-                            .clone()
-                            .into_iter()
-                            .filter_map(|node_id| {
-                                // Don't send the message back to the message's original src, even
-                                // if the src is a neighbor.
-                                if node_id == message.src.clone().unwrap() {
-                                    return None;
-                                }
-
-                                Some((node_id, node.next_message_id()))
-                            })
-                            .collect::<Vec<(String, u32)>>();
-
-                        // Store the message id's in the message list.
-                        for (_node_id, message_id) in mapped_messages.iter() {
-                            node.unacknowledged_messages
-                                .lock()
-                                .unwrap()
-                                .insert(*message_id);
-                        }
+        }
+    }
 
-                        let retry_node = mutex.clone();
-                        let body_clone = body.clone();
-
-                        // Spawn a thread to execute `Node::retry` method in a non-async function.
-                        // Calling `retry` asynchronously allows updates to unacknowledged_messages
-                        // in another thread to propogate without causing the `while` to block,
-                        // causing an infinite loop.
-                        //
-                        // NOTE: we are not `.await`ing the spawned thread; its possible the thread
-                        // is still processing messages after the main thread is closed.
-                        tokio::spawn(async move {
-                            while Node::retry(&retry_node) {
-                                let messages = Node::filter_messages(&retry_node, &mapped_messages);
-
-                                for (node_id, message_id) in messages.into_iter() {
-                                    Node::send_message(
-                                        &retry_node,
-                                        &body_clone,
-                                        node_id,
-                                        message_id,
-                                    );
-                                }
-
-                                // FIXME: Add a short delay before checking messages again. This
-                                // avoid blocking the thread with locks, causing net-timeouts.
-                                tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
-
-                                eprintln!("Messages sent. Waiting for acknowledgements...");
-                            }
-
-                            eprintln!("Acknowledged all messages.");
-                        });
-                    } else {
-                        // Log message to stderr.
-                        eprintln!(
-                            "Message seen {:?} - acknowledging it, but do nothing.",
-                            message
-                        );
-                    }
+    /// Fire each timer as it comes due, as an `internal_timer` message from the node to itself.
+    ///
+    /// Once the node starts shutting down only the retry timer is kept, and it keeps firing until
+    /// every message is acknowledged.
+    async fn run_timers(node: NodeHandle, shutdown: CancellationToken) {
+        let Ok(wake) = node.call(|node| node.timers.wake()).await else {
+            return;
+        };
+        let mut draining = false;
+
+        loop {
+            if shutdown.is_cancelled() && !draining {
+                draining = true;
+                let _ = node
+                    .call(|node| node.timers.retain(|event| *event == TimerEvent::Retry))
+                    .await;
+            }
+
+            let Ok((next, idle)) = node
+                .call(|node| (node.timers.next_due(), node.unacknowledged.is_empty()))
+                .await
+            else {
+                return;
+            };
+
+            if draining && (idle || next.is_none()) {
+                return;
+            }
+
+            let due = async {
+                match next {
+                    Some(next) => tokio::time::sleep_until(next.into()).await,
+                    None => std::future::pending().await,
                 }
+            };
+
+            tokio::select! {
+                _ = due => {}
+                // An earlier timer may have been scheduled.
+                _ = wake.notified() => continue,
+                _ = shutdown.cancelled(), if !draining => continue,
             }
-            MessageKind::Topology(message) => {
-                if let MessageBody::Topology(body) = &message.body {
-                    let body_topology = body.topology.to_owned();
-                    let node_id = node.id.to_owned().unwrap();
 
-                    if let Some(topology) = body_topology.get(&node_id) {
-                        node.topology = topology.to_vec();
+            // A timer whose handler panics has been logged; the others keep firing.
+            let _ = node
+                .call(|node| {
+                    let Some(id) = node.id.clone() else {
+                        node.timers.due(Instant::now());
+                        return;
+                    };
+
+                    for event in node.timers.due(Instant::now()) {
+                        let message = Message {
+                            src: Some(id.clone()),
+                            dest: id.clone(),
+                            body: MessageBody::Internal(InternalBody::Timer(TimerBody {
+                                event,
+                                msg_id: None,
+                            })),
+                            lamport: None,
+                        };
 
-                        eprintln!("My neighbors are: {:?}", node.topology);
+                        let messages = node.dispatch(message);
+                        node.send_all(messages);
                     }
+                })
+                .await;
+        }
+    }
+
+    /// Send the counter and the kv workload's LWW map to every other node. Nodes that haven't
+    /// counted or written anything have nothing to send.
+    #[cfg(any(feature = "counter", feature = "kv"))]
+    fn replicate(&mut self) -> Vec<Message<'static>> {
+        #[cfg(feature = "counter")]
+        let counted = !self.counter.is_empty();
+        #[cfg(not(feature = "counter"))]
+        let counted = false;
+        #[cfg(feature = "kv")]
+        let written = !self.kv.lww.is_empty();
+        #[cfg(not(feature = "kv"))]
+        let written = false;
+        #[cfg(feature = "kv")]
+        let session = self.config.kv_mode == KvMode::Session;
+
+        let body = ReplicateBody {
+            #[cfg(feature = "counter")]
+            increments: self.counter.increments().clone(),
+            #[cfg(feature = "counter")]
+            decrements: self.counter.decrements().clone(),
+            #[cfg(feature = "kv")]
+            entries: self.kv.lww.entries().clone(),
+            #[cfg(feature = "kv")]
+            sent_at: session.then(|| self.kv.hlc.now(self.id.as_deref().unwrap_or_default())),
+            #[cfg(feature = "kv")]
+            sessions: match session {
+                true => self.kv.sessions.versions().clone(),
+                false => BTreeMap::new(),
+            },
+            msg_id: None,
+        };
+
+        let messages = if !counted && !written {
+            vec![]
+        } else {
+            self.liveness
+                .alive(self.other_nodes())
+                .into_iter()
+                .map(|node_id| Message {
+                    src: self.id.clone(),
+                    dest: node_id,
+                    body: MessageBody::Internal(InternalBody::Replicate(body.clone())),
+                    lamport: None,
+                })
+                .collect::<Vec<Message<'static>>>()
+        };
+
+        #[cfg(feature = "kv")]
+        let messages = [messages, handlers::expire_session_reads(self)].concat();
+
+        messages
+    }
+
+    /// A heartbeat for every other node, for their failure detectors.
+    fn heartbeats(&mut self) -> Vec<Message<'static>> {
+        self.other_nodes()
+            .into_iter()
+            .map(|peer| Message {
+                src: self.id.clone(),
+                dest: peer,
+                body: MessageBody::Internal(InternalBody::Heartbeat(HeartbeatBody {
+                    msg_id: None,
+                })),
+                lamport: None,
+            })
+            .collect()
+    }
+
+    /// Advance Raft by one tick, in the kv workload's `raft` mode, returning the messages it
+    /// sends.
+    #[cfg(feature = "raft")]
+    pub fn raft_tick(&mut self) -> Vec<Message<'static>> {
+        let me = self.id.clone().unwrap_or_default();
+        let outgoing = self.kv.raft.tick(&me, &self.node_ids);
+
+        handlers::raft_messages(self, outgoing)
+    }
+
+    /// Send the node's whole set to a random neighbor, and merge the values it replies with.
+    /// Above the digest threshold, send a digest of the set instead, and exchange only the values
+    /// in the buckets the neighbor's set differs in.
+    #[cfg(feature = "broadcast")]
+    fn anti_entropy_round(&mut self) -> Vec<Message<'static>> {
+        let peers = self.liveness.alive(self.peers());
+        let Some(peer) = AntiEntropy::pick(&peers).cloned() else {
+            return vec![];
+        };
+
+        // The overflow's values are read back as the digest is taken, or the sync serialized.
+        let values = self.broadcast.messages.values();
+        let node = self.handle();
+
+        if self.broadcast.messages.len() > self.broadcast.anti_entropy.digest_above {
+            let digest = MessageBody::Internal(InternalBody::GossipDigest(GossipDigestBody {
+                digest: Digest::of(values.iter()),
+                msg_id: None,
+            }));
+
+            self.spawn(async move {
+                let Ok(Message {
+                    body: MessageBody::Internal(InternalBody::GossipPull(body)),
+                    ..
+                }) = rpc(&node, &peer, digest).await
+                else {
+                    return;
+                };
+
+                let _ = node
+                    .call(move |node| {
+                        let theirs: HashSet<BroadcastValue> = body.messages.into_iter().collect();
+                        let messages: Vec<BroadcastValue> = Digest::values_in(
+                            node.broadcast.messages.values().iter(),
+                            &body.buckets,
+                        )
+                        .into_iter()
+                        .filter(|value| !theirs.contains(value))
+                        .collect();
+
+                        handlers::merge(node, theirs);
+
+                        if !messages.is_empty() {
+                            node.send_to(
+                                &peer,
+                                MessageBody::Internal(InternalBody::Gossip(GossipBody {
+                                    messages,
+                                    msg_id: None,
+                                })),
+                            );
+                        }
+                    })
+                    .await;
+            });
+        } else {
+            let sync = MessageBody::Internal(InternalBody::Sync(SyncBody {
+                messages: values,
+                msg_id: None,
+            }));
+
+            self.spawn(async move {
+                if let Ok(Message {
+                    body: MessageBody::Internal(InternalBody::SyncOk(body)),
+                    ..
+                }) = rpc(&node, &peer, sync).await
+                {
+                    let _ = node
+                        .call(move |node| handlers::merge(node, body.messages))
+                        .await;
                 }
+            });
+        }
+
+        vec![]
+    }
+
+    /// Resend unacknowledged messages on the retry policy's schedule until they're acknowledged
+    /// or out of attempts, then schedule the next resend. While the node is quiescent no delay is
+    /// shorter than the quiescence interval, so an idle cluster doesn't keep resending to
+    /// unreachable neighbors.
+    fn resend_due(&mut self) -> Vec<Message<'static>> {
+        let floor = match self.quiescence.is_quiescent() {
+            true => self.quiescence.retry_interval(),
+            false => Duration::ZERO,
+        };
+
+        let (resend, expired) = self.retries.due(self.unacknowledged.ids(), floor);
+
+        for id in expired {
+            warn!("Giving up on message {} after every retry.", id);
+
+            let Some(delivery) = self.unacknowledged.remove(id) else {
+                continue;
+            };
+
+            for msg_id in delivery.attempts {
+                self.correlations.remove(msg_id);
+                self.rpc_deadlines.remove(&msg_id);
             }
-            MessageKind::Read(_message) => (),
-            MessageKind::Generate(_message) => (),
-            MessageKind::Invalid(_message) => (),
-            MessageKind::Echo(_message) => (),
         }
+
+        // Each resend is a new attempt with a fresh msg_id. It means the last attempt went
+        // unanswered; peers that keep timing out are retried less often.
+        let mut messages = vec![];
+
+        for id in resend {
+            let msg_id = self.next_message_id();
+
+            let Some(message) = self.unacknowledged.attempt(id, msg_id) else {
+                continue;
+            };
+
+            self.peer_health.record_timeout(&message.dest);
+            let backoff = self.peer_health.backoff(&message.dest);
+            self.retries.stretch(id, backoff);
+
+            messages.push(message);
+            Metrics::increment(&self.metrics.retries);
+        }
+
+        if !messages.is_empty() {
+            debug!("Unacknowledged messages: {:?}", messages.len());
+        }
+
+        let wake = self.retries.next_wake().max(Duration::from_millis(1));
+        self.schedule(wake, TimerEvent::Retry);
+
+        messages
     }
+}
 
-    fn retry(node: &Arc<Mutex<Node>>) -> bool {
-        let node = node.lock().unwrap();
-        let messages = node.unacknowledged_messages.lock().unwrap();
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::message::EchoOkBody;
+    #[cfg(feature = "broadcast")]
+    use crate::core::message::TopologyBody;
+    #[cfg(feature = "broadcast")]
+    use crate::gossip::GossipBatch;
+    #[cfg(feature = "broadcast")]
+    use crate::topology::Topology;
 
-        eprintln!("Unacknowledged messages: {:?}", messages);
-        !messages.is_empty()
+    struct ShoutHandler;
+
+    impl Handler for ShoutHandler {
+        fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+            let MessageBody::Echo(body) = &message.body else {
+                return vec![];
+            };
+
+            vec![Message {
+                src: node.id.clone(),
+                dest: message.src.clone().unwrap_or_default(),
+                body: MessageBody::EchoOk(EchoOkBody {
+                    msg_id: None,
+                    in_reply_to: body.msg_id,
+                    echo: body.echo.as_str().unwrap_or_default().to_uppercase().into(),
+                }),
+                lamport: None,
+            }]
+        }
     }
 
-    fn filter_messages(
-        node: &Arc<Mutex<Node>>,
-        mapped_messages: &Vec<(String, u32)>,
-    ) -> Vec<(String, u32)> {
-        let node = node.lock().unwrap();
-        let unacknowledged_messages = node.unacknowledged_messages.lock().unwrap();
+    fn parse(line: &str) -> Message<'static> {
+        state::parse(line).unwrap().into_owned()
+    }
 
-        mapped_messages
-            .clone()
-            .into_iter()
-            .filter_map(|message| {
-                let (_node_id, message_id) = &message;
+    #[test]
+    fn rejects_requests_it_cannot_answer() {
+        let mut node = Node {
+            id: Some("n1".to_string()),
+            ..Default::default()
+        };
 
-                if unacknowledged_messages.contains(&message_id) {
-                    Some(message)
-                } else {
-                    None
-                }
-            })
-            .collect()
+        let replies = node.dispatch(parse(
+            r#"{"dest": "n1", "body": {"type": "echo", "echo": "hi", "msg_id": 1}}"#,
+        ));
+        assert!(replies.is_empty());
+
+        let replies = node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": "hi"}}"#,
+        ));
+        assert_eq!(replies[0].dest, "c1");
+        assert!(matches!(
+            &replies[0].body,
+            MessageBody::Error(body) if body.code == ErrorCode::MalformedRequest
+        ));
     }
 
-    fn send_message(
-        node: &Arc<Mutex<Node>>,
-        body: &BroadcastBody,
-        node_id: String,
-        message_id: u32,
-    ) {
-        let mut node = node.lock().unwrap();
-        let stdout = io::stdout();
-        let mut lock = stdout.lock();
-
-        let message = Message {
-            src: node.id.clone(),
-            dest: node_id.to_owned(),
-            body: MessageBody::Broadcast(BroadcastBody {
-                r#type: "broadcast".to_owned(),
-                msg_id: Some(message_id),
-                in_reply_to: None,
-                message: body.message,
+    #[test]
+    fn dispatches_to_the_registered_handler() {
+        let mut registry = Registry::empty();
+        registry.register("echo", ShoutHandler);
+
+        let mut node = Node {
+            id: Some("n1".to_string()),
+            registry,
+            ..Default::default()
+        };
+
+        let replies = node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "msg_id": 1, "echo": "hi"}}"#,
+        ));
+        assert!(matches!(&replies[0].body, MessageBody::EchoOk(body) if body.echo == "HI"));
+
+        let replies = node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "read", "msg_id": 2}}"#,
+        ));
+        assert!(matches!(
+            &replies[0].body,
+            MessageBody::Error(body) if body.code == ErrorCode::NotSupported
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "counter")]
+    fn replays_the_reply_to_a_retransmitted_request() {
+        let mut node = Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string()],
+            replies: ReplyCache::new(1),
+            ..Default::default()
+        };
+
+        let add =
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "add", "delta": 5, "msg_id": 1}}"#;
+        let first = node.dispatch(parse(add));
+        let again = node.dispatch(parse(add));
+
+        assert_eq!(node.counter.value(), 5);
+        assert_eq!(again[0].body.in_reply_to(), Some(1));
+        assert_eq!(again[0].body.msg_id(), first[0].body.msg_id());
+
+        // Once forgotten, a request is handled again.
+        node.dispatch(parse(
+            r#"{"src": "c2", "dest": "n1", "body": {"type": "add", "delta": 1, "msg_id": 1}}"#,
+        ));
+        node.dispatch(parse(add));
+        assert_eq!(node.counter.value(), 11);
+    }
+
+    #[test]
+    #[cfg(feature = "broadcast")]
+    fn gossip_is_pending_until_acknowledged() {
+        let mut node = Node {
+            id: Some("n1".to_string()),
+            ..Default::default()
+        };
+
+        node.dispatch(Message {
+            src: Some("c1".to_string()),
+            dest: "n1".to_string(),
+            body: MessageBody::Topology(TopologyBody {
+                topology: Topology(HashMap::from([("n1".to_string(), vec!["n2".to_string()])])),
+                msg_id: Some(1),
             }),
+            lamport: None,
+        });
+
+        let replies = node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "broadcast", "message": 7, "msg_id": 2}}"#,
+        ));
+        let gossip = replies.iter().find(|reply| reply.dest == "n2").unwrap();
+
+        assert_eq!(node.unacknowledged.len(), 1);
+        assert_eq!(node.rpc_permits.outstanding(), 1);
+
+        // The resend is a new attempt, and acknowledging it completes the delivery.
+        let id = gossip.body.msg_id().unwrap();
+        let msg_id = node.next_message_id();
+        let resend = node.unacknowledged.attempt(id, msg_id).unwrap();
+        assert_ne!(resend.body.msg_id(), Some(id));
+
+        node.dispatch(parse(&format!(
+            r#"{{"src": "n2", "dest": "n1", "body": {{"type": "broadcast_ok", "in_reply_to": {}}}}}"#,
+            msg_id
+        )));
+
+        assert!(node.unacknowledged.is_empty());
+        assert!(node.correlations.is_empty());
+        assert_eq!(node.rpc_permits.outstanding(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "broadcast")]
+    fn batches_gossip_until_flushed() {
+        let mut node = Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string(), "n2".to_string(), "n3".to_string()],
+            broadcast: BroadcastState {
+                gossip_batch: GossipBatch::new(Duration::from_millis(100)),
+                ..Default::default()
+            },
+            ..Default::default()
         };
 
-        let message = serde_json::to_string(&message).expect("Couldn't parse message.");
+        node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "topology", "topology": {"n1": ["n2", "n3"]}, "msg_id": 1}}"#,
+        ));
 
-        // Log message to stderr.
-        eprintln!("Sent {:?}", message);
+        let replies = node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "broadcast", "message": 1, "msg_id": 2}}"#,
+        ));
+        assert_eq!(replies.len(), 1);
 
-        // Flush the message to stdout.
-        writeln!(lock, "{}", message).unwrap();
+        // A batch from a neighbor isn't sent back to it.
+        node.dispatch(parse(
+            r#"{"src": "n2", "dest": "n1", "body": {"type": "broadcast", "messages": [1, 2, 3], "msg_id": 1}}"#,
+        ));
 
-        if !node.response_callbacks.contains_key(&message_id) {
-            // Add a callback for the message, using the message id as the key.
-            node.response_callbacks.insert(
-                message_id,
-                ResponseCallback(Box::new(move |node| {
-                    let mut unlocked_messages = node.unacknowledged_messages.lock().unwrap();
+        let mut batches = node.broadcast.gossip_batch.take();
 
-                    unlocked_messages.remove(&message_id);
+        assert_eq!(batches.remove("n2").unwrap().len(), 1);
+        assert_eq!(batches.remove("n3").unwrap().len(), 3);
+        assert_eq!(node.broadcast.messages.len(), 3);
+    }
 
-                    eprintln!("Callback invoked for msg: {:?}", message);
-                })),
+    #[tokio::test]
+    async fn writes_replies_in_the_node_s_id_format() {
+        let node = |id_format| {
+            NodeHandle::spawn(Node {
+                id: Some("n1".to_string()),
+                id_format,
+                ..Default::default()
+            })
+        };
+        let echo = r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": 1, "msg_id": 1, "lamport": 9007199254740993}}"#;
+
+        let quoted = Node::handle_from_stdin(node(IdFormat::String), echo)
+            .await
+            .unwrap();
+        let plain = Node::handle_from_stdin(node(IdFormat::Number), echo)
+            .await
+            .unwrap();
+
+        assert!(quoted[0].contains(r#""lamport":"9007199254740995""#));
+        assert!(plain[0].contains(r#""lamport":9007199254740995"#));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "broadcast")]
+    async fn flushes_the_gossip_batch_when_stdin_closes() {
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let (response_tx, mut response_rx) = tokio::sync::mpsc::channel(8);
+
+        let node = NodeHandle::spawn(Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string(), "n2".to_string()],
+            broadcast: BroadcastState {
+                topology: vec!["n2".to_string()],
+                gossip_batch: GossipBatch::new(Duration::from_secs(60)),
+                ..Default::default()
+            },
+            drain: Drain {
+                timeout: Duration::from_millis(50),
+            },
+            ..Default::default()
+        });
+
+        tx.send(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "broadcast", "message": 1, "msg_id": 1}}"#
+                .to_string(),
+        )
+        .await
+        .unwrap();
+        drop(tx);
+
+        Node::run(node, rx, response_tx, &TaskTracker::new()).await;
+
+        let mut sent = vec![];
+        while let Ok(message) = response_rx.try_recv() {
+            sent.push(
+                serde_json::from_str::<Message>(&message)
+                    .unwrap()
+                    .into_owned(),
             );
         }
+
+        assert_eq!(sent[0].body.kind(), "broadcast_ok");
+        assert!(sent[1..].iter().any(|message| message.dest == "n2"));
+    }
+
+    #[test]
+    #[cfg(feature = "broadcast")]
+    fn gossips_to_every_node_until_a_topology_arrives() {
+        let mut node = Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string(), "n2".to_string(), "n3".to_string()],
+            ..Default::default()
+        };
+
+        assert_eq!(node.other_nodes(), vec!["n2", "n3"]);
+
+        let replies = node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "broadcast", "message": 1, "msg_id": 1}}"#,
+        ));
+        let gossip: Vec<_> = replies
+            .iter()
+            .map(|message| &message.dest)
+            .filter(|dest| *dest != "c1")
+            .collect();
+
+        assert_eq!(gossip, vec!["n2", "n3"]);
+
+        node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "topology", "topology": {"n1": ["n3"]}, "msg_id": 2}}"#,
+        ));
+
+        assert_eq!(node.peers(), vec!["n3"]);
+    }
+
+    #[test]
+    #[cfg(feature = "broadcast")]
+    fn syncs_missing_values_both_ways() {
+        let mut node = Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string(), "n2".to_string()],
+            ..Default::default()
+        };
+
+        node.broadcast.messages.insert(1.into());
+
+        let replies = node.dispatch(parse(
+            r#"{"src": "n2", "dest": "n1", "body": {"type": "internal_sync", "messages": [2], "msg_id": 1}}"#,
+        ));
+
+        let MessageBody::Internal(InternalBody::SyncOk(body)) = &replies[0].body else {
+            panic!("Expected a sync_ok reply.");
+        };
+
+        assert_eq!(body.messages, vec![1.into()]);
+        assert!(node.broadcast.messages.contains(&2.into()));
+    }
+
+    #[test]
+    #[cfg(feature = "broadcast")]
+    fn exchanges_only_the_buckets_a_digest_differs_in() {
+        let mut node = Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string(), "n2".to_string()],
+            ..Default::default()
+        };
+
+        let theirs: HashSet<BroadcastValue> = (0..100).map(BroadcastValue::from).collect();
+        theirs.iter().for_each(|value| {
+            node.broadcast.messages.insert(value.clone());
+        });
+        node.broadcast.messages.insert(500.into());
+
+        let digest = serde_json::to_string(&Digest::of(theirs.iter().cloned())).unwrap();
+        let replies = node.dispatch(parse(&format!(
+            r#"{{"src": "n2", "dest": "n1", "body": {{"type": "internal_gossip_digest", "digest": {digest}, "msg_id": 1}}}}"#
+        )));
+
+        let MessageBody::Internal(InternalBody::GossipPull(body)) = &replies[0].body else {
+            panic!("Expected a gossip_pull reply.");
+        };
+
+        assert_eq!(body.buckets.len(), 1);
+        assert!(body.messages.contains(&500.into()));
+        assert!(body.messages.len() < 10);
+
+        node.dispatch(parse(
+            r#"{"src": "n2", "dest": "n1", "body": {"type": "internal_gossip", "messages": [600]}}"#,
+        ));
+        assert!(node.broadcast.messages.contains(&600.into()));
+    }
+
+    #[test]
+    #[cfg(feature = "broadcast")]
+    fn strict_replies_wait_for_replication() {
+        let mut node = Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string(), "n2".to_string()],
+            broadcast: BroadcastState {
+                topology: vec!["n2".to_string()],
+                ..Default::default()
+            },
+            reply_modes: ReplyModes::strict(&[Workload::Broadcast]),
+            ..Default::default()
+        };
+
+        let replies = node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "broadcast", "message": 7, "msg_id": 1}}"#,
+        ));
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].dest, "n2");
+
+        let replies = node.dispatch(parse(&format!(
+            r#"{{"src": "n2", "dest": "n1", "body": {{"type": "broadcast_ok", "in_reply_to": {}}}}}"#,
+            replies[0].body.msg_id().unwrap()
+        )));
+        assert!(
+            matches!(&replies[0].body, MessageBody::BroadcastOk(body) if body.in_reply_to == 1)
+        );
+        assert_eq!(replies[0].dest, "c1");
+    }
+
+    #[test]
+    fn hands_error_replies_to_the_waiting_request() {
+        let mut node = Node::default();
+        let mut reply = node.expect_reply(3);
+
+        node.dispatch(parse(
+            r#"{"src": "lin-kv", "dest": "n1", "body": {"type": "error", "code": 22, "text": "expected 1", "in_reply_to": 3}}"#,
+        ));
+
+        let err = reply.try_recv().unwrap().unwrap_err();
+        assert_eq!(err.code, ErrorCode::PreconditionFailed);
+        assert!(err.is_definite());
+        assert!(node.correlations.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "kv")]
+    fn forwards_kv_requests_to_the_leader() {
+        let mut node = Node {
+            id: Some("n2".to_string()),
+            node_ids: vec!["n1".to_string(), "n2".to_string()],
+            registry: Registry::for_workloads(&[Workload::Kv]),
+            ..Default::default()
+        };
+
+        let forwarded = node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n2", "body": {"type": "write", "msg_id": 4, "key": 1, "value": 2}}"#,
+        ));
+        assert_eq!(forwarded[0].dest, "n1");
+
+        let relayed = node.dispatch(parse(&format!(
+            r#"{{"src": "n1", "dest": "n2", "body": {{"type": "write_ok", "msg_id": 9, "in_reply_to": {}}}}}"#,
+            forwarded[0].body.msg_id().unwrap()
+        )));
+        assert_eq!(relayed[0].dest, "c1");
+        assert_eq!(relayed[0].body.in_reply_to(), Some(4));
+    }
+
+    #[test]
+    #[cfg(feature = "kv")]
+    fn serves_kv_requests_from_the_service_in_service_mode() {
+        let mut node = Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string(), "n2".to_string()],
+            registry: Registry::for_workloads(&[Workload::Kv]),
+            config: Config {
+                kv_mode: KvMode::Service,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let request = node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "cas", "msg_id": 4, "key": 1, "from": 2, "to": 3}}"#,
+        ));
+        assert_eq!(request[0].dest, "lin-kv");
+        assert_eq!(request[0].body.kind(), "cas");
+
+        let relayed = node.dispatch(parse(&format!(
+            r#"{{"src": "lin-kv", "dest": "n1", "body": {{"type": "error", "code": 22, "text": "expected 2", "in_reply_to": {}}}}}"#,
+            request[0].body.msg_id().unwrap()
+        )));
+        assert_eq!(relayed[0].dest, "c1");
+        assert!(matches!(
+            &relayed[0].body,
+            MessageBody::Error(body) if body.code == ErrorCode::PreconditionFailed && body.in_reply_to == Some(4)
+        ));
+        assert!(node.kv.store.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "kv")]
+    fn times_out_forwarded_requests() {
+        let mut node = Node {
+            id: Some("n2".to_string()),
+            node_ids: vec!["n1".to_string(), "n2".to_string()],
+            registry: Registry::for_workloads(&[Workload::Kv]),
+            config: Config {
+                rpc_timeout: Duration::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let forwarded = node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n2", "body": {"type": "read", "msg_id": 4, "key": 1}}"#,
+        ));
+
+        let timeouts = node.dispatch(parse(
+            r#"{"src": "n2", "dest": "n2", "body": {"type": "internal_timer", "event": "expire_rpcs"}}"#,
+        ));
+        assert_eq!(timeouts[0].dest, "c1");
+        assert!(matches!(
+            &timeouts[0].body,
+            MessageBody::Error(body) if body.code == ErrorCode::Timeout && body.in_reply_to == Some(4)
+        ));
+
+        // A reply arriving after the deadline isn't relayed.
+        let late = node.dispatch(parse(&format!(
+            r#"{{"src": "n1", "dest": "n2", "body": {{"type": "read_ok", "value": 2, "in_reply_to": {}}}}}"#,
+            forwarded[0].body.msg_id().unwrap()
+        )));
+        assert!(late.iter().all(|message| message.dest != "c1"));
+        assert!(node.rpc_deadlines.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "raft")]
+    fn applies_kv_requests_once_raft_commits_them() {
+        let mut node = Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string()],
+            registry: Registry::for_workloads(&[Workload::Kv]),
+            config: Config {
+                kv_mode: KvMode::Raft,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let write = r#"{"src": "c1", "dest": "n1", "body": {"type": "write", "msg_id": 4, "key": 1, "value": 2}}"#;
+
+        let refused = node.dispatch(parse(write));
+        assert_eq!(refused[0].body.kind(), "error");
+
+        while node.kv.raft.leader.is_none() {
+            node.raft_tick();
+        }
+
+        let replies = node.dispatch(parse(write));
+        assert_eq!(replies[0].body.kind(), "write_ok");
+        assert_eq!(node.kv.raft.commit_index, 1);
+
+        let replies = node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "read", "msg_id": 5, "key": 1}}"#,
+        ));
+        assert!(matches!(
+            &replies[0].body,
+            MessageBody::ReadOk(body) if body.value == Some(2.into())
+        ));
+        // The read was served without going through the log.
+        assert_eq!(node.kv.raft.last_index(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "kv")]
+    fn forwards_kv_requests_to_the_key_owner_when_sharded() {
+        let mut node = Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string(), "n2".to_string()],
+            registry: Registry::for_workloads(&[Workload::Kv]),
+            config: Config {
+                kv_mode: KvMode::Sharded,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let ring = HashRing::new(&node.node_ids);
+        let owned_by = |owner: &str| {
+            (0..)
+                .find(|key: &u64| ring.owner(&key.to_string()).unwrap() == owner)
+                .unwrap()
+        };
+
+        let write = |key| {
+            parse(&format!(
+                r#"{{"src": "c1", "dest": "n1", "body": {{"type": "write", "msg_id": {key}, "key": {key}, "value": 2}}}}"#
+            ))
+        };
+
+        let local = node.dispatch(write(owned_by("n1")));
+        assert_eq!(local[0].body.kind(), "write_ok");
+
+        let forwarded = node.dispatch(write(owned_by("n2")));
+        assert_eq!(forwarded[0].dest, "n2");
+    }
+
+    #[test]
+    #[cfg(feature = "kafka")]
+    fn serves_each_kafka_key_from_its_owner() {
+        let node_ids = vec!["n1".to_string(), "n2".to_string()];
+        let mut nodes: HashMap<String, Node> = node_ids
+            .iter()
+            .map(|id| {
+                let node = Node {
+                    id: Some(id.clone()),
+                    node_ids: node_ids.clone(),
+                    registry: Registry::for_workloads(&[Workload::Kafka]),
+                    ..Default::default()
+                };
+
+                (id.clone(), node)
+            })
+            .collect();
+        let ring = HashRing::new(&node_ids);
+        let owned_by = |owner: &str| {
+            (0..)
+                .map(|key| format!("k{key}"))
+                .find(|key| ring.owner(key).unwrap() == owner)
+                .unwrap()
+        };
+        let (mine, theirs) = (owned_by("n1"), owned_by("n2"));
+
+        // Deliver a client's request to n1, and everything the nodes send each other after it,
+        // returning what reaches the client.
+        let mut request = |body: String| {
+            let mut in_flight = vec![parse(&format!(
+                r#"{{"src": "c1", "dest": "n1", "body": {body}}}"#
+            ))];
+            let mut replies = vec![];
+
+            while let Some(message) = in_flight.pop() {
+                match nodes.get_mut(&message.dest) {
+                    Some(node) => in_flight.extend(node.dispatch(message)),
+                    None => replies.push(message),
+                }
+            }
+
+            assert_eq!(replies.len(), 1, "{:?}", replies);
+            serde_json::to_value(&replies[0].body).unwrap()
+        };
+
+        for (msg_id, key) in [&mine, &theirs, &theirs].into_iter().enumerate() {
+            let reply = request(format!(
+                r#"{{"type": "send", "msg_id": {msg_id}, "key": "{key}", "msg": {msg_id}}}"#
+            ));
+            assert_eq!(reply["type"], "send_ok");
+            assert_eq!(reply["in_reply_to"], msg_id);
+        }
+
+        let reply = request(format!(
+            r#"{{"type": "poll", "msg_id": 3, "offsets": {{"{mine}": 0, "{theirs}": 1}}}}"#
+        ));
+        assert_eq!(reply["msgs"][&mine], serde_json::json!([[0, 0]]));
+        assert_eq!(reply["msgs"][&theirs], serde_json::json!([[1, 2]]));
+
+        let reply = request(format!(
+            r#"{{"type": "commit_offsets", "msg_id": 4, "offsets": {{"{mine}": 0, "{theirs}": 1}}}}"#
+        ));
+        assert_eq!(reply["type"], "commit_offsets_ok");
+
+        let reply = request(format!(
+            r#"{{"type": "list_committed_offsets", "msg_id": 5, "keys": ["{mine}", "{theirs}"]}}"#
+        ));
+        assert_eq!(reply["offsets"], serde_json::json!({&mine: 0, &theirs: 1}));
+        assert_eq!(nodes["n2"].kafka.logs.len(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "txn")]
+    fn commits_transactions_across_the_nodes_owning_their_keys() {
+        let mut nodes: HashMap<String, Node> = ["n1", "n2"]
+            .map(|id| {
+                let node = Node {
+                    id: Some(id.to_string()),
+                    node_ids: vec!["n1".to_string(), "n2".to_string()],
+                    registry: Registry::for_workloads(&[Workload::Txn]),
+                    ..Default::default()
+                };
+
+                (id.to_string(), node)
+            })
+            .into_iter()
+            .collect();
+
+        // Delivers every message between the nodes, returning the replies to the client.
+        let mut run = |dest: &str, request: &str| {
+            let mut in_flight = nodes.get_mut(dest).unwrap().dispatch(parse(request));
+            let mut replies = vec![];
+
+            while let Some(message) = in_flight.pop() {
+                match nodes.get_mut(&message.dest) {
+                    Some(node) => in_flight.extend(node.dispatch(message)),
+                    None => replies.push(message),
+                }
+            }
+
+            replies
+        };
+
+        let writes = (0..8)
+            .map(|key| format!(r#"["w", {key}, 1]"#))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let replies = run(
+            "n1",
+            &format!(
+                r#"{{"src": "c1", "dest": "n1", "body": {{"type": "txn", "msg_id": 1, "txn": [{writes}]}}}}"#
+            ),
+        );
+        assert_eq!(replies[0].body.kind(), "txn_ok");
+
+        let reads = (0..8)
+            .map(|key| format!(r#"["r", {key}, null]"#))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let replies = run(
+            "n2",
+            &format!(
+                r#"{{"src": "c1", "dest": "n2", "body": {{"type": "txn", "msg_id": 2, "txn": [{reads}]}}}}"#
+            ),
+        );
+
+        let MessageBody::TxnOk(body) = &replies[0].body else {
+            panic!("expected txn_ok, got {:?}", replies[0].body);
+        };
+        assert!(body.txn.iter().all(|op| op.2 == 1));
+    }
+
+    #[test]
+    #[cfg(feature = "kv")]
+    fn holds_session_reads_until_the_clients_writes_arrive() {
+        let node = |id: &str| Node {
+            id: Some(id.to_string()),
+            node_ids: vec!["n1".to_string(), "n2".to_string()],
+            registry: Registry::for_workloads(&[Workload::Kv]),
+            config: Config {
+                kv_mode: KvMode::Session,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (mut n1, mut n2) = (node("n1"), node("n2"));
+
+        n1.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "write", "msg_id": 1, "key": 1, "value": 2}}"#,
+        ));
+
+        // The client's session reaches n2 before n1's entries do.
+        n2.kv.sessions.merge(n1.kv.sessions.versions());
+
+        let held = n2.dispatch(parse(
+            r#"{"src": "c1", "dest": "n2", "body": {"type": "read", "msg_id": 2, "key": 1}}"#,
+        ));
+        assert!(held.is_empty());
+
+        let replies = n2.dispatch(Message {
+            src: Some("n1".to_string()),
+            dest: "n2".to_string(),
+            body: MessageBody::Internal(InternalBody::Replicate(ReplicateBody {
+                #[cfg(feature = "counter")]
+                increments: HashMap::new(),
+                #[cfg(feature = "counter")]
+                decrements: HashMap::new(),
+                entries: n1.kv.lww.entries().clone(),
+                sent_at: Some(n1.kv.hlc.now("n1")),
+                sessions: BTreeMap::new(),
+                msg_id: None,
+            })),
+            lamport: None,
+        });
+        assert!(matches!(
+            &replies[0].body,
+            MessageBody::ReadOk(body) if body.value == Some(2.into())
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "kv")]
+    fn serves_kv_requests_locally_in_lww_mode() {
+        let node = |id: &str| Node {
+            id: Some(id.to_string()),
+            node_ids: vec!["n1".to_string(), "n2".to_string()],
+            registry: Registry::for_workloads(&[Workload::Kv]),
+            config: Config {
+                kv_mode: KvMode::Lww,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (mut n1, mut n2) = (node("n1"), node("n2"));
+
+        let replies = n2.dispatch(parse(
+            r#"{"src": "c1", "dest": "n2", "body": {"type": "write", "msg_id": 4, "key": 1, "value": 2}}"#,
+        ));
+        assert_eq!(replies[0].body.kind(), "write_ok");
+
+        n1.dispatch(Message {
+            src: n2.id.clone(),
+            dest: "n1".to_string(),
+            body: MessageBody::Internal(InternalBody::Replicate(ReplicateBody {
+                #[cfg(feature = "counter")]
+                increments: HashMap::new(),
+                #[cfg(feature = "counter")]
+                decrements: HashMap::new(),
+                entries: n2.kv.lww.entries().clone(),
+                sent_at: None,
+                sessions: BTreeMap::new(),
+                msg_id: None,
+            })),
+            lamport: None,
+        });
+
+        let replies = n1.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "read", "msg_id": 5, "key": 1}}"#,
+        ));
+        assert!(matches!(
+            &replies[0].body,
+            MessageBody::ReadOk(body) if body.value == Some(2.into())
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "broadcast")]
+    fn dumps_what_the_node_thinks_is_happening() {
+        let mut node = Node {
+            registry: Registry::for_workloads(&[Workload::Broadcast]),
+            ..Default::default()
+        };
+
+        node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "init", "node_id": "n1", "node_ids": ["n1", "n2"], "msg_id": 1}}"#,
+        ));
+        node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "broadcast", "message": 7, "msg_id": 2}}"#,
+        ));
+
+        let replies = node.dispatch(parse(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "debug_state", "msg_id": 3}}"#,
+        ));
+
+        let MessageBody::DebugStateOk(body) = &replies[0].body else {
+            panic!("Expected a debug_state_ok, got {:?}.", replies[0].body);
+        };
+        assert_eq!(body.node_id.as_deref(), Some("n1"));
+        assert_eq!(body.messages, 1);
+        assert_eq!(body.pending_retries, 1);
+        assert_eq!(body.config["rpc_timeout"], 1000);
     }
 }
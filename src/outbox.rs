@@ -0,0 +1,191 @@
+//! The single path every message a running node sends takes. Handlers' replies, gossip, retries,
+//! and the timers' messages are queued in the order the node produced them, one FIFO
+//! queue per destination, and one task writes them out. Messages to the same peer therefore go
+//! out in the order they were queued.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Notify;
+use tracing::debug;
+
+use crate::actor::NodeHandle;
+use crate::core::message::Message;
+use crate::metrics::Metrics;
+
+#[derive(Debug, Default)]
+pub struct Outbox {
+    queues: BTreeMap<String, VecDeque<Message<'static>>>,
+    /// Signalled when messages are queued, or the outbox closes.
+    ready: Arc<Notify>,
+    open: bool,
+    closed: bool,
+    /// Whether `drain` leaves the queued messages be until the state they acknowledge is
+    /// durable; see `Persistence`.
+    held: bool,
+}
+
+impl Outbox {
+    /// Start queueing messages, for `drain` to write.
+    pub fn open(&mut self) {
+        self.open = true;
+        self.closed = false;
+    }
+
+    /// Stop queueing messages; `drain` returns once the queued ones are written.
+    pub fn close(&mut self) {
+        self.open = false;
+        self.closed = true;
+        self.ready.notify_one();
+    }
+
+    /// Whether messages are being queued, i.e. the node is running.
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Whether the node has stopped running, so nothing more it queues is written.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Whether the queued messages are held back from `drain`.
+    pub fn is_held(&self) -> bool {
+        self.held
+    }
+
+    /// Hold the queued messages back from `drain`, or release them.
+    pub fn hold(&mut self, held: bool) {
+        if self.held && !held {
+            self.ready.notify_one();
+        }
+
+        self.held = held;
+    }
+
+    pub fn push(&mut self, message: Message<'static>) {
+        self.queues
+            .entry(message.dest.clone())
+            .or_default()
+            .push_back(message);
+        self.ready.notify_one();
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    /// Every queued message, taking one from each destination's queue in turn so a long queue
+    /// doesn't hold up the others.
+    pub fn take(&mut self) -> Vec<Message<'static>> {
+        let mut messages = Vec::with_capacity(self.len());
+        let mut queues: Vec<VecDeque<Message<'static>>> =
+            std::mem::take(&mut self.queues).into_values().collect();
+
+        while !queues.is_empty() {
+            queues.retain_mut(|queue| match queue.pop_front() {
+                Some(message) => {
+                    messages.push(message);
+                    true
+                }
+                None => false,
+            });
+        }
+
+        messages
+    }
+}
+
+/// Write the node's queued messages to `response_tx`, unless they're held, until the outbox
+/// closes and is empty.
+pub async fn drain(node: NodeHandle, response_tx: Sender<String>) {
+    let Ok((ready, metrics, format)) = node
+        .call(|node| {
+            (
+                node.outbox.ready.clone(),
+                node.metrics.clone(),
+                node.id_format,
+            )
+        })
+        .await
+    else {
+        return;
+    };
+
+    loop {
+        let Ok((messages, open)) = node
+            .call(|node| match node.outbox.is_held() {
+                true => (vec![], node.outbox.is_open()),
+                false => (node.outbox.take(), node.outbox.is_open()),
+            })
+            .await
+        else {
+            return;
+        };
+
+        if messages.is_empty() {
+            if !open {
+                return;
+            }
+
+            // A push or a release since `take` has left a permit, so this doesn't miss it.
+            ready.notified().await;
+            continue;
+        }
+
+        for message in messages {
+            let message = format.to_json(&message).expect("Couldn't parse message.");
+
+            debug!("Sending message: {:?}", message);
+
+            match response_tx.send(message).await {
+                Ok(()) => Metrics::increment(&metrics.messages_out),
+                Err(_) => Metrics::increment(&metrics.dropped),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::message::{InitOkBody, MessageBody};
+
+    fn to(dest: &str, msg_id: u32) -> Message<'static> {
+        Message {
+            src: Some("n1".to_string()),
+            dest: dest.to_string(),
+            body: MessageBody::InitOk(InitOkBody {
+                msg_id: Some(msg_id),
+                in_reply_to: None,
+            }),
+            lamport: None,
+        }
+    }
+
+    #[test]
+    fn keeps_each_peers_messages_in_order() {
+        let mut outbox = Outbox::default();
+
+        for (dest, msg_id) in [("n2", 1), ("n2", 2), ("n2", 3), ("n3", 4)] {
+            outbox.push(to(dest, msg_id));
+        }
+
+        let sent: Vec<(String, Option<u32>)> = outbox
+            .take()
+            .into_iter()
+            .map(|message| (message.dest, message.body.msg_id()))
+            .collect();
+
+        assert_eq!(
+            sent,
+            [("n2", 1), ("n3", 4), ("n2", 2), ("n2", 3)]
+                .map(|(dest, msg_id)| (dest.to_string(), Some(msg_id)))
+        );
+        assert!(outbox.is_empty());
+    }
+}
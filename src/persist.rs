@@ -0,0 +1,502 @@
+//! Node state that survives a restart. With a state directory, every change to the broadcast
+//! values, the counter, and the kv store is appended to a write-ahead log at
+//! `<dir>/<node_id>.wal`, which is fsynced before the replies to the message that made the change
+//! are sent, and replayed when the node is initialized again. So a node killed by Maelstrom's kill
+//! nemesis comes back with everything it acknowledged.
+//!
+//! With a group commit window, appends are fsynced together once the oldest has waited for it,
+//! on a timer, and the node's outbox is held until they are; see `Wal::commit`.
+//!
+//! Once the log holds more records than the state has values, it's rewritten as a single
+//! snapshot of the state. The state is only read whole then, so the cost of reading it is spread
+//! over the records appended before.
+//!
+//! The Raft log is kept apart, in a write-ahead log at `<dir>/<node_id>.raft.wal`: its changes are
+//! appended as they're made, and the log is rewritten whenever Raft compacts its own.
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "counter")]
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{error, info};
+
+#[cfg(feature = "broadcast")]
+use crate::core::message::BroadcastValue;
+#[cfg(feature = "broadcast")]
+use crate::core::state::BroadcastValues;
+#[cfg(feature = "kv")]
+use crate::kv::{KvOp, KvStore};
+#[cfg(feature = "kv")]
+use crate::machine::StateMachine;
+use crate::node::Node;
+#[cfg(feature = "raft")]
+use crate::raft::RaftRecord;
+use crate::wal::Wal;
+
+/// The node's whole state, which a rewritten log starts with.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Serialized straight from the store, reading its overflow back as it's written.
+    #[cfg(feature = "broadcast")]
+    #[serde(default)]
+    pub messages: BroadcastValues,
+    #[cfg(feature = "counter")]
+    #[serde(default)]
+    pub increments: HashMap<String, u64>,
+    #[cfg(feature = "counter")]
+    #[serde(default)]
+    pub decrements: HashMap<String, u64>,
+    #[cfg(feature = "kv")]
+    #[serde(default)]
+    pub kv: KvStore,
+}
+
+impl Snapshot {
+    #[cfg_attr(
+        not(any(feature = "broadcast", feature = "counter", feature = "kv")),
+        allow(unused_variables)
+    )]
+    pub fn capture(node: &Node) -> Self {
+        Snapshot {
+            #[cfg(feature = "broadcast")]
+            messages: node.broadcast.messages.values(),
+            #[cfg(feature = "counter")]
+            increments: node.counter.increments().clone(),
+            #[cfg(feature = "counter")]
+            decrements: node.counter.decrements().clone(),
+            #[cfg(feature = "kv")]
+            kv: node.kv.store.clone(),
+        }
+    }
+
+    /// Merge the snapshot into the node's state.
+    #[cfg_attr(
+        not(any(feature = "broadcast", feature = "counter", feature = "kv")),
+        allow(unused_variables)
+    )]
+    pub fn restore(self, node: &mut Node) {
+        #[cfg(feature = "broadcast")]
+        for message in self.messages.iter() {
+            node.broadcast.messages.insert(message);
+        }
+
+        #[cfg(feature = "counter")]
+        node.counter.merge(&self.increments, &self.decrements);
+
+        #[cfg(feature = "kv")]
+        {
+            node.kv.store = self.kv;
+        }
+    }
+}
+
+/// A change to the node's state, as appended to the write-ahead log.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Record {
+    Snapshot(Snapshot),
+    #[cfg(feature = "broadcast")]
+    Broadcast(BroadcastValue),
+    /// The counter's counts after the change.
+    #[cfg(feature = "counter")]
+    Counter {
+        increments: HashMap<String, u64>,
+        decrements: HashMap<String, u64>,
+    },
+    /// An operation on the kv store, which replays to the same effect in the same order.
+    #[cfg(feature = "kv")]
+    Kv(KvOp),
+}
+
+impl Record {
+    fn replay(self, node: &mut Node) {
+        match self {
+            Record::Snapshot(snapshot) => snapshot.restore(node),
+            #[cfg(feature = "broadcast")]
+            Record::Broadcast(value) => {
+                node.broadcast.messages.insert(value);
+            }
+            #[cfg(feature = "counter")]
+            Record::Counter {
+                increments,
+                decrements,
+            } => {
+                node.counter.merge(&increments, &decrements);
+            }
+            #[cfg(feature = "kv")]
+            Record::Kv(op) => {
+                let _ = node.kv.store.apply(op);
+            }
+        }
+    }
+}
+
+/// What a previous run of the node left behind.
+#[derive(Debug, Default)]
+pub struct Saved {
+    pub records: Vec<Record>,
+    #[cfg(feature = "raft")]
+    pub raft: Vec<RaftRecord>,
+}
+
+impl Saved {
+    pub fn is_empty(&self) -> bool {
+        #[cfg(feature = "raft")]
+        if !self.raft.is_empty() {
+            return false;
+        }
+
+        self.records.is_empty()
+    }
+
+    /// Replay the saved changes into the node's state.
+    pub fn restore(self, node: &mut Node) {
+        for record in self.records {
+            record.replay(node);
+        }
+
+        // The store is rebuilt from Raft's snapshot and the entries committed after it, so the
+        // saved store's entries aren't applied twice.
+        #[cfg(feature = "raft")]
+        if !self.raft.is_empty() {
+            node.kv.store = KvStore::default();
+            node.kv.raft.recover(self.raft);
+        }
+    }
+}
+
+/// Where the node's state is written, if anywhere.
+#[derive(Debug, Default)]
+pub struct Persistence {
+    dir: Option<PathBuf>,
+    /// The node's write-ahead log, once its ID is known.
+    wal: Option<Wal<Record>>,
+    /// Records appended since the log was last rewritten.
+    appended: usize,
+    /// Whether an append failed, so the log must be rewritten from the state.
+    behind: bool,
+    /// The Raft log's write-ahead log, once the node's ID is known.
+    #[cfg(feature = "raft")]
+    raft_log: Option<Wal<RaftRecord>>,
+    /// Whether an append failed, so the Raft log must be rewritten whole.
+    #[cfg(feature = "raft")]
+    raft_log_behind: bool,
+    /// How long appends wait to be fsynced together.
+    window: Duration,
+}
+
+impl Persistence {
+    /// The log is never rewritten before it holds this many records.
+    const MIN_COMPACTION: usize = 1024;
+
+    pub fn new(dir: Option<PathBuf>, window: Duration) -> Self {
+        Persistence {
+            dir,
+            window,
+            ..Default::default()
+        }
+    }
+
+    /// How often the logs are committed, when appends wait to be fsynced together.
+    pub fn commit_interval(&self) -> Option<Duration> {
+        self.dir.as_ref()?;
+
+        Some(self.window).filter(|window| !window.is_zero())
+    }
+
+    /// Whether every change logged is durable.
+    pub fn is_synced(&self) -> bool {
+        #[cfg(feature = "raft")]
+        if self.raft_log.as_ref().is_some_and(|wal| !wal.is_synced()) {
+            return false;
+        }
+
+        self.wal.as_ref().is_none_or(Wal::is_synced)
+    }
+
+    /// Fsync every change logged now, e.g. before the node stops.
+    pub fn sync(&mut self) -> io::Result<()> {
+        #[cfg(feature = "raft")]
+        if let Some(wal) = &mut self.raft_log {
+            wal.sync()?;
+        }
+
+        match &mut self.wal {
+            Some(wal) => wal.sync(),
+            None => Ok(()),
+        }
+    }
+
+    fn path(&self, node_id: &str) -> Option<PathBuf> {
+        Some(self.dir.as_ref()?.join(format!("{}.wal", node_id)))
+    }
+
+    /// What a previous run of `node_id` left behind. Creates the directory on the first run.
+    pub fn load(&mut self, node_id: &str) -> io::Result<Option<Saved>> {
+        let Some(path) = self.path(node_id) else {
+            return Ok(None);
+        };
+
+        if let Some(dir) = &self.dir {
+            fs::create_dir_all(dir)?;
+        }
+
+        let (wal, records) = Wal::open(&path, self.window)?;
+        self.wal = Some(wal);
+        self.appended = records.len();
+        self.behind = false;
+
+        #[cfg(feature = "raft")]
+        let raft = {
+            let (wal, records) = Wal::open(path.with_extension("raft.wal"), self.window)?;
+            self.raft_log = Some(wal);
+            records
+        };
+
+        let saved = Saved {
+            records,
+            #[cfg(feature = "raft")]
+            raft,
+        };
+
+        Ok(Some(saved).filter(|saved| !saved.is_empty()))
+    }
+
+    /// Merge the state saved by a previous run of `node_id` into the node, if there is any.
+    pub fn recover(node: &mut Node, node_id: &str) {
+        match node.persistence.load(node_id) {
+            Ok(Some(saved)) => {
+                info!("Recovered the state saved by a previous run.");
+                saved.restore(node);
+            }
+            Ok(None) => {}
+            Err(err) => error!("Unable to read the saved state: {:?}", err),
+        }
+    }
+
+    /// Log a change to the node's state, to be made durable by the next `save`. The record is
+    /// only built when there's a log to append it to.
+    pub fn record(&mut self, record: impl FnOnce() -> Record) {
+        let Some(wal) = &mut self.wal else {
+            return;
+        };
+
+        match wal.append(&record()) {
+            Ok(()) => self.appended += 1,
+            Err(err) => {
+                error!("Unable to log a change to the node's state: {:?}", err);
+                self.behind = true;
+            }
+        }
+    }
+
+    /// Make the changes logged since the last save durable, once the group commit window has
+    /// passed. Nothing is written if nothing changed; the state is only read whole once the log is
+    /// due to be rewritten.
+    pub fn save(node: &mut Node) -> io::Result<()> {
+        #[cfg(feature = "raft")]
+        Self::save_raft(node)?;
+
+        #[cfg_attr(not(any(feature = "broadcast", feature = "kv")), allow(unused_mut))]
+        let mut size = 0;
+        #[cfg(feature = "broadcast")]
+        {
+            size += node.broadcast.messages.len();
+        }
+        #[cfg(feature = "kv")]
+        {
+            size += node.kv.store.len();
+        }
+
+        let persistence = &node.persistence;
+        let due = persistence.behind || persistence.appended > size.max(Self::MIN_COMPACTION);
+        let snapshot = due.then(|| Record::Snapshot(Snapshot::capture(node)));
+
+        let persistence = &mut node.persistence;
+
+        let Some(wal) = &mut persistence.wal else {
+            return Ok(());
+        };
+
+        let Some(snapshot) = snapshot else {
+            return wal.commit().map(drop);
+        };
+
+        let result = wal.rewrite(std::slice::from_ref(&snapshot));
+
+        persistence.appended = 1;
+        persistence.behind = result.is_err();
+
+        result
+    }
+
+    /// Append Raft's changes to its write-ahead log and commit them. Once Raft has compacted its
+    /// log into a snapshot, the write-ahead log is replaced with just the records rebuilding it.
+    #[cfg(feature = "raft")]
+    fn save_raft(node: &mut Node) -> io::Result<()> {
+        let records = node.kv.raft.take_records();
+        let persistence = &mut node.persistence;
+
+        let Some(wal) = &mut persistence.raft_log else {
+            return Ok(());
+        };
+
+        if records.is_empty() && !persistence.raft_log_behind {
+            return wal.commit().map(drop);
+        }
+
+        let compacted = records
+            .iter()
+            .any(|record| matches!(record, RaftRecord::Snapshot { .. }));
+
+        let result = match compacted || persistence.raft_log_behind {
+            true => wal.rewrite(&node.kv.raft.records()),
+            false => records
+                .iter()
+                .try_for_each(|record| wal.append(record))
+                .and_then(|()| wal.commit().map(drop)),
+        };
+
+        persistence.raft_log_behind = result.is_err();
+
+        result
+    }
+}
+
+#[cfg(all(test, any(feature = "broadcast", feature = "counter", feature = "kv")))]
+mod test {
+    use super::*;
+    #[cfg(feature = "broadcast")]
+    use crate::core::message::BroadcastValue;
+    #[cfg(any(feature = "broadcast", feature = "kv"))]
+    use serde_json::json;
+
+    #[test]
+    fn recovers_the_state_a_previous_run_saved() {
+        let dir = std::env::temp_dir().join(format!("tranquility-persist-{}", std::process::id()));
+
+        let mut node = Node {
+            id: Some("n1".to_string()),
+            persistence: Persistence::new(Some(dir.clone()), Duration::ZERO),
+            ..Default::default()
+        };
+        assert!(node.persistence.load("n1").unwrap().is_none());
+        #[cfg(feature = "broadcast")]
+        node.insert_message(BroadcastValue(json!(7)));
+        #[cfg(feature = "counter")]
+        {
+            node.counter.add("n1", 3);
+            node.persistence.record(|| Record::Counter {
+                increments: node.counter.increments().clone(),
+                decrements: node.counter.decrements().clone(),
+            });
+        }
+        #[cfg(feature = "kv")]
+        {
+            let op = KvOp::Write {
+                key: json!("x"),
+                value: json!(1),
+            };
+            node.persistence.record(|| Record::Kv(op.clone()));
+            node.kv.store.apply(op).unwrap();
+        }
+        #[cfg(feature = "raft")]
+        {
+            let nodes = ["n1".to_string()];
+            let request = json!({"src": "c1", "dest": "n1", "body": {"type": "read", "key": 1}});
+
+            while node.kv.raft.leader.is_none() {
+                node.kv.raft.tick("n1", &nodes);
+            }
+            node.kv
+                .raft
+                .propose("n1", &nodes, crate::Message::deserialize(request).unwrap());
+        }
+        Persistence::save(&mut node).unwrap();
+
+        let restart = || {
+            let mut restarted = Node {
+                persistence: Persistence::new(Some(dir.clone()), Duration::ZERO),
+                ..Default::default()
+            };
+            let saved = restarted.persistence.load("n1").unwrap().unwrap();
+            saved.restore(&mut restarted);
+            restarted
+        };
+
+        let restarted = restart();
+        assert!(restarted.persistence.wal.is_some());
+        #[cfg(feature = "broadcast")]
+        assert!(restarted
+            .broadcast
+            .messages
+            .contains(&BroadcastValue(json!(7))));
+        #[cfg(feature = "counter")]
+        assert_eq!(restarted.counter.value(), 3);
+        #[cfg(all(feature = "kv", not(feature = "raft")))]
+        assert_eq!(restarted.kv.store.read(&json!("x")), Ok(&json!(1)));
+        // The store comes back as Raft applies its log again.
+        #[cfg(feature = "raft")]
+        {
+            assert!(restarted.kv.store.is_empty());
+            assert_eq!(restarted.kv.raft.term, node.kv.raft.term);
+            assert_eq!(restarted.kv.raft.last_index(), 1);
+        }
+
+        // Rewritten as a snapshot, the log recovers the same state.
+        node.persistence.behind = true;
+        Persistence::save(&mut node).unwrap();
+        assert_eq!(node.persistence.appended, 1);
+
+        let restarted = restart();
+        #[cfg(feature = "broadcast")]
+        assert!(restarted
+            .broadcast
+            .messages
+            .contains(&BroadcastValue(json!(7))));
+        #[cfg(feature = "counter")]
+        assert_eq!(restarted.counter.value(), 3);
+        assert!(restarted.persistence.wal.is_some());
+
+        let mut other = Persistence::new(Some(dir.clone()), Duration::ZERO);
+        assert!(other.load("n2").unwrap().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "broadcast")]
+    fn holds_the_outbox_until_the_group_commit() {
+        let dir =
+            std::env::temp_dir().join(format!("tranquility-persist-{}-window", std::process::id()));
+        let window = Duration::from_millis(20);
+        let echo = |msg_id| {
+            crate::Message::deserialize(json!({"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": 1, "msg_id": msg_id}}))
+                .unwrap()
+        };
+
+        let mut node = Node {
+            id: Some("n1".to_string()),
+            persistence: Persistence::new(Some(dir.clone()), window),
+            ..Default::default()
+        };
+        node.persistence.load("n1").unwrap();
+        node.outbox.open();
+        assert_eq!(node.persistence.commit_interval(), Some(window));
+
+        node.insert_message(BroadcastValue(json!(7)));
+        node.dispatch(echo(1));
+        assert!(!node.persistence.is_synced());
+        assert!(node.outbox.is_held());
+
+        std::thread::sleep(window);
+        node.dispatch(echo(2));
+        assert!(node.persistence.is_synced());
+        assert!(!node.outbox.is_held());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,377 @@
+//! Python bindings, behind the `pyo3` feature (or its alias, `python`): `Message` builds and parses protocol messages,
+//! and `Client` drives a node in-process, as `testing::NodeTestFixture` does, so a notebook can
+//! talk to a node without a Maelstrom harness or string-munging JSON.
+//!
+//! ```python
+//! from tranquility import Client, Message
+//!
+//! client = Client("n1", ["n1", "n2"])
+//! client.broadcast(1)
+//! client.read()  # [1]
+//! Message.parse('{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": 1}}').kind
+//! ```
+//!
+//! Bodies cross to and from Python as dicts, through the `json` module, so they take whatever
+//! values the wire format does.
+
+// pyo3's generated wrappers convert `PyErr` into itself.
+#![allow(clippy::useless_conversion)]
+
+use pyo3::exceptions::{PyLookupError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::core::message::Message;
+use crate::core::state;
+use crate::node::Node;
+
+/// A protocol message.
+#[pyclass(name = "Message", module = "tranquility")]
+#[derive(Clone, Debug)]
+pub struct PyMessage(pub Message<'static>);
+
+#[pymethods]
+impl PyMessage {
+    /// A message from `src` to `dest`; `body` is a dict with the body's `type` among its fields.
+    #[new]
+    #[pyo3(signature = (src, dest, body))]
+    fn new(src: Option<String>, dest: String, body: &Bound<'_, PyAny>) -> PyResult<Self> {
+        let body = to_value(body)?;
+
+        message(json!({"src": src, "dest": dest, "body": body}))
+    }
+
+    /// Parse a message as it's written on the wire.
+    #[staticmethod]
+    fn parse(line: &str) -> PyResult<Self> {
+        state::parse(line)
+            .map(|message| PyMessage(message.into_owned()))
+            .map_err(|err| PyValueError::new_err(err.to_string()))
+    }
+
+    /// The message as it's written on the wire.
+    fn to_json(&self) -> PyResult<String> {
+        serde_json::to_string(&self.0).map_err(|err| PyValueError::new_err(err.to_string()))
+    }
+
+    #[getter]
+    fn src(&self) -> Option<String> {
+        self.0.src.clone()
+    }
+
+    #[getter]
+    fn dest(&self) -> String {
+        self.0.dest.clone()
+    }
+
+    /// The body's `type`.
+    #[getter]
+    fn kind(&self) -> &'static str {
+        self.0.body.kind()
+    }
+
+    #[getter]
+    fn msg_id(&self) -> Option<u32> {
+        self.0.body.msg_id()
+    }
+
+    #[getter]
+    fn in_reply_to(&self) -> Option<u32> {
+        self.0.body.in_reply_to()
+    }
+
+    #[getter]
+    fn lamport(&self) -> Option<u64> {
+        self.0.lamport
+    }
+
+    /// The body as a dict, `type` included.
+    #[getter]
+    fn body(&self, py: Python<'_>) -> PyResult<PyObject> {
+        to_python(py, &self.body_value()?)
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!("Message({})", self.to_json()?))
+    }
+}
+
+impl PyMessage {
+    fn body_value(&self) -> PyResult<Value> {
+        serde_json::to_value(&self.0.body).map_err(|err| PyValueError::new_err(err.to_string()))
+    }
+}
+
+/// A node in-process, and a client sending it requests. Each request is handed to the node
+/// directly, and answered with what the node sent back to the client.
+#[pyclass(name = "Client", module = "tranquility")]
+#[derive(Debug)]
+pub struct PyClient {
+    node: Node,
+    /// The client requests are sent from.
+    #[pyo3(get)]
+    client: String,
+    /// The `msg_id` of the last request.
+    #[pyo3(get)]
+    msg_id: u32,
+}
+
+#[pymethods]
+impl PyClient {
+    /// A node initialized as `node_id`, in a cluster of `node_ids`, just itself by default.
+    #[new]
+    #[pyo3(signature = (node_id = "n1", node_ids = None, client = "c1"))]
+    fn new(node_id: &str, node_ids: Option<Vec<String>>, client: &str) -> PyResult<Self> {
+        let node_ids = node_ids.unwrap_or_else(|| vec![node_id.to_string()]);
+        let mut client = PyClient {
+            node: Node::default(),
+            client: client.to_string(),
+            msg_id: 0,
+        };
+
+        client.call(json!({"type": "init", "node_id": node_id, "node_ids": node_ids}))?;
+
+        Ok(client)
+    }
+
+    /// The node's id.
+    #[getter]
+    fn node_id(&self) -> Option<String> {
+        self.node.id.clone()
+    }
+
+    /// Send a request body, with the next `msg_id` unless it has one, and return everything the
+    /// node sent in response, to clients and peers alike.
+    fn send(&mut self, body: &Bound<'_, PyAny>) -> PyResult<Vec<PyMessage>> {
+        let body = to_value(body)?;
+
+        self.send_value(body)
+    }
+
+    /// Send a request body and return the node's reply to it. An `error` reply is raised as a
+    /// `RuntimeError` with the error's code and text as its arguments.
+    fn request(&mut self, body: &Bound<'_, PyAny>) -> PyResult<PyMessage> {
+        let body = to_value(body)?;
+
+        self.call(body)
+    }
+
+    /// Deliver a whole message, e.g. one from a peer, and return what the node sent in response.
+    fn receive(&mut self, message: &PyMessage) -> Vec<PyMessage> {
+        self.dispatch(message.0.clone())
+    }
+
+    /// Echo `value` back.
+    fn echo(&mut self, py: Python<'_>, value: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+        let value = to_value(value)?;
+        let reply = self.call(json!({"type": "echo", "echo": value}))?;
+
+        to_python(py, &reply.body_value()?["echo"])
+    }
+
+    /// A new unique id.
+    fn generate(&mut self, py: Python<'_>) -> PyResult<PyObject> {
+        let reply = self.call(json!({"type": "generate"}))?;
+
+        to_python(py, &reply.body_value()?["id"])
+    }
+
+    /// Give the node its neighbors, a dict from node ids to theirs.
+    fn topology(&mut self, topology: &Bound<'_, PyAny>) -> PyResult<()> {
+        let topology = to_value(topology)?;
+
+        self.call(json!({"type": "topology", "topology": topology}))
+            .map(drop)
+    }
+
+    /// Broadcast `value`.
+    fn broadcast(&mut self, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        let value = to_value(value)?;
+
+        self.call(json!({"type": "broadcast", "message": value}))
+            .map(drop)
+    }
+
+    /// Add `delta` to the counter.
+    fn add(&mut self, delta: i64) -> PyResult<()> {
+        self.call(json!({"type": "add", "delta": delta})).map(drop)
+    }
+
+    /// Read the broadcast values, the counter, or with `key`, a key in the kv store.
+    #[pyo3(signature = (key = None))]
+    fn read(&mut self, py: Python<'_>, key: Option<&Bound<'_, PyAny>>) -> PyResult<PyObject> {
+        let body = match key {
+            Some(key) => json!({"type": "read", "key": to_value(key)?}),
+            None => json!({"type": "read"}),
+        };
+        let body = self.call(body)?.body_value()?;
+
+        match body.get("messages") {
+            Some(messages) => to_python(py, messages),
+            None => to_python(py, &body["value"]),
+        }
+    }
+
+    /// Write `value` to `key` in the kv store.
+    fn write(&mut self, key: &Bound<'_, PyAny>, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        let (key, value) = (to_value(key)?, to_value(value)?);
+
+        self.call(json!({"type": "write", "key": key, "value": value}))
+            .map(drop)
+    }
+
+    /// Set `key` in the kv store to `to` if it's `from`.
+    #[pyo3(signature = (key, from, to, create_if_not_exists = false))]
+    fn cas(
+        &mut self,
+        key: &Bound<'_, PyAny>,
+        from: &Bound<'_, PyAny>,
+        to: &Bound<'_, PyAny>,
+        create_if_not_exists: bool,
+    ) -> PyResult<()> {
+        let (key, from, to) = (to_value(key)?, to_value(from)?, to_value(to)?);
+        let body = json!({
+            "type": "cas",
+            "key": key,
+            "from": from,
+            "to": to,
+            "create_if_not_exists": create_if_not_exists,
+        });
+
+        self.call(body).map(drop)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Client({:?}, {:?})", self.node.id, self.client)
+    }
+}
+
+impl PyClient {
+    fn send_value(&mut self, mut body: Value) -> PyResult<Vec<PyMessage>> {
+        self.msg_id += 1;
+
+        match body.get("msg_id").and_then(Value::as_u64) {
+            Some(msg_id) => {
+                self.msg_id = u32::try_from(msg_id).map_err(|_| {
+                    PyValueError::new_err(format!("msg_id {} doesn't fit in a u32", msg_id))
+                })?
+            }
+            None => body["msg_id"] = json!(self.msg_id),
+        }
+
+        let dest = self.node.id.clone().unwrap_or_else(|| "n1".to_string());
+        let message = message(json!({"src": self.client, "dest": dest, "body": body}))?;
+
+        Ok(self.dispatch(message.0))
+    }
+
+    /// Send a request body and return the node's one reply to it.
+    fn call(&mut self, body: Value) -> PyResult<PyMessage> {
+        let sent = self.send_value(body)?;
+        let reply = sent
+            .into_iter()
+            .find(|message| {
+                message.0.dest == self.client && message.0.body.in_reply_to() == Some(self.msg_id)
+            })
+            .ok_or_else(|| PyLookupError::new_err(format!("no reply to {}", self.msg_id)))?;
+
+        if reply.kind() != "error" {
+            return Ok(reply);
+        }
+
+        let body = reply.body_value()?;
+
+        let code = body["code"].as_u64().unwrap_or_default();
+        let text = body["text"].as_str().unwrap_or_default().to_string();
+
+        Err(PyRuntimeError::new_err((code, text)))
+    }
+
+    fn dispatch(&mut self, message: Message<'static>) -> Vec<PyMessage> {
+        self.node
+            .dispatch(message)
+            .into_iter()
+            .map(PyMessage)
+            .collect()
+    }
+}
+
+fn message(value: Value) -> PyResult<PyMessage> {
+    Message::deserialize(value)
+        .map(|message| PyMessage(message.into_owned()))
+        .map_err(|err| PyValueError::new_err(err.to_string()))
+}
+
+fn to_value(object: &Bound<'_, PyAny>) -> PyResult<Value> {
+    let json = object.py().import_bound("json")?;
+    let text: String = json.call_method1("dumps", (object,))?.extract()?;
+
+    serde_json::from_str(&text).map_err(|err| PyValueError::new_err(err.to_string()))
+}
+
+fn to_python(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    let json = py.import_bound("json")?;
+
+    Ok(json.call_method1("loads", (value.to_string(),))?.unbind())
+}
+
+#[pymodule]
+fn tranquility(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyMessage>()?;
+    module.add_class::<PyClient>()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn drives_a_node_from_python() {
+        pyo3::prepare_freethreaded_python();
+
+        Python::with_gil(|py| {
+            let module = PyModule::new_bound(py, "tranquility").unwrap();
+            tranquility(&module).unwrap();
+
+            let locals = PyDict::new_bound(py);
+            locals.set_item("tranquility", module).unwrap();
+
+            let code = r#"
+client = tranquility.Client("n1", ["n1"])
+assert client.echo({"a": [1, 2]}) == {"a": [1, 2]}
+
+message = tranquility.Message("c1", "n1", {"type": "echo", "echo": "hi", "msg_id": 7})
+assert message.kind == "echo" and message.msg_id == 7
+assert tranquility.Message.parse(message.to_json()).body["echo"] == "hi"
+
+[reply] = client.receive(message)
+assert reply.kind == "echo_ok" and reply.in_reply_to == 7 and reply.dest == "c1"
+
+try:
+    tranquility.Message.parse("{")
+    assert False
+except ValueError:
+    pass
+
+try:
+    client.send({"type": "echo", "echo": 1, "msg_id": 2**32})
+    assert False
+except ValueError:
+    pass
+"#;
+            py.run_bound(code, None, Some(&locals)).unwrap();
+
+            #[cfg(feature = "broadcast")]
+            py.run_bound(
+                "client.broadcast(3); assert client.read() == [3]",
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+        });
+    }
+}
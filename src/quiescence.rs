@@ -0,0 +1,88 @@
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Tracks when the node last saw new work, i.e. a client request or a new value. Once it has
+/// been idle for a while, retry intervals are stretched so an idle cluster doesn't keep
+/// resending, and they shrink back as soon as work arrives.
+#[derive(Debug)]
+pub struct Quiescence {
+    last_activity: Instant,
+    base: Duration,
+    max: Duration,
+    idle_after: Duration,
+}
+
+impl Default for Quiescence {
+    fn default() -> Self {
+        Quiescence::new(
+            Duration::from_millis(1000),
+            Duration::from_millis(8000),
+            Duration::from_millis(2000),
+        )
+    }
+}
+
+impl Quiescence {
+    pub fn new(base: Duration, max: Duration, idle_after: Duration) -> Self {
+        Quiescence {
+            last_activity: Instant::now(),
+            base,
+            max,
+            idle_after,
+        }
+    }
+
+    pub fn record_activity(&mut self) {
+        if self.is_quiescent() {
+            info!("Activity after {:?} idle.", self.last_activity.elapsed());
+        }
+
+        self.last_activity = Instant::now();
+    }
+
+    pub fn is_quiescent(&self) -> bool {
+        self.last_activity.elapsed() >= self.idle_after
+    }
+
+    /// The base interval while active; once quiescent, doubled for every further `idle_after`
+    /// without activity, up to the maximum.
+    pub fn retry_interval(&self) -> Duration {
+        if !self.is_quiescent() || self.idle_after.is_zero() {
+            return self.base;
+        }
+
+        let idle_periods = self.last_activity.elapsed().as_millis() / self.idle_after.as_millis();
+        let multiplier = 1u32 << idle_periods.min(16);
+
+        self.base.saturating_mul(multiplier).min(self.max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stretches_the_retry_interval_while_idle() {
+        let mut quiescence = Quiescence::new(
+            Duration::from_millis(100),
+            Duration::from_millis(1000),
+            Duration::from_secs(1),
+        );
+
+        assert_eq!(quiescence.retry_interval(), Duration::from_millis(100));
+
+        quiescence.last_activity = Instant::now() - Duration::from_secs(2);
+
+        assert!(quiescence.is_quiescent());
+        assert_eq!(quiescence.retry_interval(), Duration::from_millis(400));
+
+        quiescence.last_activity = Instant::now() - Duration::from_secs(60);
+
+        assert_eq!(quiescence.retry_interval(), Duration::from_millis(1000));
+
+        quiescence.record_activity();
+
+        assert_eq!(quiescence.retry_interval(), Duration::from_millis(100));
+    }
+}
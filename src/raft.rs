@@ -0,0 +1,1011 @@
+//! Raft consensus: leader election, log replication, and commitment, as in "In Search of an
+//! Understandable Consensus Algorithm". The log's entries are client requests; every node applies
+//! committed entries in log order, so a request is applied once, at the same point, everywhere.
+//!
+//! `Raft` only tracks the protocol's state. It's driven by `tick`, called every `Raft::TICK`, and
+//! by `handle` for Raft's messages, and returns the messages to send rather than sending them.
+//!
+//! Reads needn't go through the log. The leader notes its commit index when a read arrives and
+//! serves the read once a majority has acknowledged a round of heartbeats sent after it, which
+//! proves no other leader had been elected ("read index"). With a lease, the leader skips that
+//! round while a majority acknowledged one recently enough that none can have been.
+//!
+//! Once the log grows past a limit, the applied entries are compacted into a snapshot of the
+//! state machine. A follower too far behind for the leader's log is sent the snapshot instead.
+//!
+//! The vote, the log, and the snapshot must survive a restart. `take_records` hands out their
+//! changes as `RaftRecord`s for the node to make durable before it sends the messages that
+//! reflect them, and `recover` replays them.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tracing::info;
+
+use crate::core::message::{
+    AppendEntriesBody, AppendEntriesResBody, InstallSnapshotBody, InternalBody, Message,
+    MessageBody, RequestVoteBody, RequestVoteResBody,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LogEntry {
+    pub term: u64,
+    /// The client's request, as the leader received it.
+    #[serde(deserialize_with = "Message::deserialize_owned")]
+    pub request: Message<'static>,
+}
+
+/// A change to the state Raft keeps across a restart. Replaying them in order rebuilds it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RaftRecord {
+    Vote {
+        term: u64,
+        voted_for: Option<String>,
+    },
+    /// The log's entries from `index` on, replacing any it had there.
+    Entries {
+        index: u64,
+        entries: Vec<LogEntry>,
+    },
+    Snapshot {
+        index: u64,
+        term: u64,
+        state: Value,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Role {
+    #[default]
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// How the kv workload's `raft` mode serves reads.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RaftReads {
+    /// The leader confirms it's still the leader with a round of heartbeats, then reads.
+    #[default]
+    ReadIndex,
+    /// Like `ReadIndex`, but no round is needed while the leader holds a lease. Relies on every
+    /// node's ticks taking about as long.
+    Lease,
+    /// Reads are committed to the log like writes.
+    Log,
+}
+
+impl RaftReads {
+    pub fn parse(reads: &str) -> Option<Self> {
+        match reads {
+            "read-index" => Some(RaftReads::ReadIndex),
+            "lease" => Some(RaftReads::Lease),
+            "log" => Some(RaftReads::Log),
+            _ => None,
+        }
+    }
+}
+
+/// Messages to send, by destination.
+pub type Outgoing = Vec<(String, MessageBody<'static>)>;
+
+/// A read waiting for the leader to confirm round `round` and apply the log up to `index`.
+#[derive(Debug)]
+struct PendingRead {
+    index: u64,
+    round: u64,
+    request: Message<'static>,
+}
+
+#[derive(Debug, Default)]
+pub struct Raft {
+    pub term: u64,
+    pub role: Role,
+    /// The leader of the current term, once known.
+    pub leader: Option<String>,
+    voted_for: Option<String>,
+    votes: HashSet<String>,
+    /// Entry `i` is at `log[i - snapshot_index - 1]`; indexes start at 1.
+    log: Vec<LogEntry>,
+    /// The state machine's state after applying every entry up to `snapshot_index`, which the
+    /// log no longer has.
+    snapshot_index: u64,
+    snapshot_term: u64,
+    snapshot: Value,
+    /// A snapshot from the leader, waiting to replace the state machine's state.
+    restore: Option<Value>,
+    pub commit_index: u64,
+    last_applied: u64,
+    /// The leader's next entry to send to each follower, and the last it knows each has.
+    next_index: HashMap<String, u64>,
+    match_index: HashMap<String, u64>,
+    /// Ticks since the last heartbeat, sent as leader or received otherwise.
+    elapsed: u32,
+    election_timeout: u32,
+    /// Requests proposed on this node, by log index, waiting to be committed.
+    pub waiting: HashMap<u64, Message<'static>>,
+    ticks: u64,
+    /// The leader's latest round of `append_entries`, and the latest a majority has acknowledged.
+    round: u64,
+    confirmed_round: u64,
+    /// The tick each unconfirmed round was sent at.
+    rounds: BTreeMap<u64, u64>,
+    acked_round: HashMap<String, u64>,
+    /// The leader's lease lasts until this tick.
+    lease_until: u64,
+    reads: Vec<PendingRead>,
+    /// Reads that were waiting when this node stopped being the leader.
+    abandoned: Vec<Message<'static>>,
+    /// The vote as of the last `take_records`.
+    recorded_vote: (u64, Option<String>),
+    /// The first log index changed since the last `take_records`, and whether the snapshot was.
+    unrecorded_from: Option<u64>,
+    unrecorded_snapshot: bool,
+}
+
+impl Raft {
+    pub const TICK: Duration = Duration::from_millis(20);
+    /// A leader sends heartbeats this many ticks apart.
+    const HEARTBEAT_TICKS: u32 = 3;
+    /// Followers wait between this and twice this many ticks for a heartbeat before they stand
+    /// for election.
+    const ELECTION_TICKS: u32 = 10;
+    /// How many ticks short of the election timeout a lease ends, in case ticks run long.
+    const LEASE_MARGIN: u32 = 2;
+
+    pub fn last_index(&self) -> u64 {
+        self.snapshot_index + self.log.len() as u64
+    }
+
+    /// How many entries the log holds since the last snapshot.
+    pub fn log_len(&self) -> usize {
+        self.log.len()
+    }
+
+    /// The term of entry `index`, or 0 if the log doesn't have it.
+    fn term_at(&self, index: u64) -> u64 {
+        match index.checked_sub(self.snapshot_index) {
+            Some(0) => self.snapshot_term,
+            Some(offset) => self
+                .log
+                .get(offset as usize - 1)
+                .map_or(0, |entry| entry.term),
+            None => 0,
+        }
+    }
+
+    fn entries_from(&self, index: u64) -> &[LogEntry] {
+        let start = (index - self.snapshot_index - 1) as usize;
+
+        self.log.get(start..).unwrap_or_default()
+    }
+
+    /// Advance time by one tick, as node `me` of `nodes`.
+    pub fn tick(&mut self, me: &str, nodes: &[String]) -> Outgoing {
+        self.ticks += 1;
+        self.elapsed += 1;
+
+        if self.election_timeout == 0 {
+            self.reset_election_timeout();
+        }
+
+        match self.role {
+            Role::Leader if self.elapsed >= Self::HEARTBEAT_TICKS => self.replicate(me, nodes),
+            Role::Leader => vec![],
+            _ if self.elapsed >= self.election_timeout => self.start_election(me, nodes),
+            _ => vec![],
+        }
+    }
+
+    /// Append a request to the log, if this node is the leader. Returns its index.
+    pub fn propose(
+        &mut self,
+        me: &str,
+        nodes: &[String],
+        request: Message<'static>,
+    ) -> Option<u64> {
+        if self.role != Role::Leader {
+            return None;
+        }
+
+        self.log.push(LogEntry {
+            term: self.term,
+            request: request.clone(),
+        });
+
+        let index = self.last_index();
+        self.changed_from(index);
+        self.waiting.insert(index, request);
+        self.advance_commit_index(me, nodes);
+
+        Some(index)
+    }
+
+    /// Whether the leader can serve reads from its own state. Until it's committed an entry from
+    /// its own term, it can't be sure which entries before it were committed.
+    pub fn knows_commit_index(&self) -> bool {
+        self.role == Role::Leader && self.term_at(self.commit_index) == self.term
+    }
+
+    /// Queue a read on the leader, to be served once it's safe to. Returns whether it waits on a
+    /// new round of heartbeats, rather than the lease or the log alone.
+    pub fn read(&mut self, request: Message<'static>, lease: bool) -> bool {
+        let round = match lease && self.ticks < self.lease_until {
+            true => self.confirmed_round,
+            false => self.round + 1,
+        };
+
+        self.reads.push(PendingRead {
+            index: self.commit_index,
+            round,
+            request,
+        });
+
+        round > self.confirmed_round
+    }
+
+    /// Reads that are safe to serve now, and reads abandoned since the node stopped leading.
+    pub fn take_reads(&mut self) -> (Vec<Message<'static>>, Vec<Message<'static>>) {
+        let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.reads)
+            .into_iter()
+            .partition(|read| {
+                read.round <= self.confirmed_round && read.index <= self.last_applied
+            });
+
+        self.reads = waiting;
+
+        (
+            ready.into_iter().map(|read| read.request).collect(),
+            std::mem::take(&mut self.abandoned),
+        )
+    }
+
+    /// Send every follower the entries it's missing, or a heartbeat if it has them all.
+    pub fn replicate(&mut self, me: &str, nodes: &[String]) -> Outgoing {
+        self.elapsed = 0;
+        self.round += 1;
+        self.rounds.insert(self.round, self.ticks);
+        self.confirm_round(me, nodes);
+
+        peers(me, nodes)
+            .map(|peer| {
+                let next = self.next_index.get(peer).copied().unwrap_or(1).max(1);
+                let prev_log_index = next - 1;
+
+                if prev_log_index < self.snapshot_index {
+                    let body =
+                        MessageBody::Internal(InternalBody::InstallSnapshot(InstallSnapshotBody {
+                            term: self.term,
+                            last_included_index: self.snapshot_index,
+                            last_included_term: self.snapshot_term,
+                            data: self.snapshot.clone(),
+                            round: self.round,
+                            msg_id: None,
+                        }));
+
+                    return (peer.clone(), body);
+                }
+
+                let body = MessageBody::Internal(InternalBody::AppendEntries(AppendEntriesBody {
+                    term: self.term,
+                    prev_log_index,
+                    prev_log_term: self.term_at(prev_log_index),
+                    entries: self.entries_from(next).to_vec(),
+                    leader_commit: self.commit_index,
+                    round: self.round,
+                    msg_id: None,
+                }));
+
+                (peer.clone(), body)
+            })
+            .collect()
+    }
+
+    /// Handle one of Raft's messages from `from`.
+    pub fn handle(
+        &mut self,
+        me: &str,
+        nodes: &[String],
+        from: &str,
+        body: &MessageBody,
+    ) -> Outgoing {
+        let term = match body {
+            MessageBody::Internal(InternalBody::RequestVote(body)) => body.term,
+            MessageBody::Internal(InternalBody::RequestVoteRes(body)) => body.term,
+            MessageBody::Internal(InternalBody::AppendEntries(body)) => body.term,
+            MessageBody::Internal(InternalBody::AppendEntriesRes(body)) => body.term,
+            MessageBody::Internal(InternalBody::InstallSnapshot(body)) => body.term,
+            _ => return vec![],
+        };
+
+        // A follower that's heard from its leader within the shortest election timeout ignores
+        // candidates, so none can be elected while the leader's lease lasts.
+        if let MessageBody::Internal(InternalBody::RequestVote(_)) = body {
+            if self.role == Role::Follower
+                && self.leader.is_some()
+                && self.elapsed < Self::ELECTION_TICKS
+            {
+                let refusal =
+                    MessageBody::Internal(InternalBody::RequestVoteRes(RequestVoteResBody {
+                        term: self.term,
+                        vote_granted: false,
+                        msg_id: None,
+                    }));
+
+                return vec![(from.to_string(), refusal)];
+            }
+        }
+
+        if term > self.term {
+            self.become_follower(term, None);
+        }
+
+        match body {
+            MessageBody::Internal(InternalBody::RequestVote(body)) => {
+                vec![(from.to_string(), self.vote(from, body))]
+            }
+            MessageBody::Internal(InternalBody::RequestVoteRes(body)) => {
+                if self.role == Role::Candidate && body.term == self.term && body.vote_granted {
+                    self.votes.insert(from.to_string());
+
+                    if self.votes.len() > nodes.len() / 2 {
+                        return self.become_leader(me, nodes);
+                    }
+                }
+
+                vec![]
+            }
+            MessageBody::Internal(InternalBody::AppendEntries(body)) => {
+                vec![(from.to_string(), self.append(from, body))]
+            }
+            MessageBody::Internal(InternalBody::InstallSnapshot(body)) => {
+                vec![(from.to_string(), self.install_snapshot(from, body))]
+            }
+            MessageBody::Internal(InternalBody::AppendEntriesRes(body)) => {
+                if self.role != Role::Leader || body.term != self.term {
+                    return vec![];
+                }
+
+                // Even a failed append acknowledges this node as the leader.
+                let acked = self.acked_round.entry(from.to_string()).or_default();
+                *acked = (*acked).max(body.round);
+                self.confirm_round(me, nodes);
+
+                self.next_index
+                    .insert(from.to_string(), body.match_index + 1);
+
+                if body.success {
+                    let matched = self.match_index.entry(from.to_string()).or_default();
+                    *matched = (*matched).max(body.match_index);
+
+                    self.advance_commit_index(me, nodes);
+                }
+
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// A snapshot installed from the leader, which the state machine should load before it
+    /// applies any more committed entries.
+    pub fn take_restore(&mut self) -> Option<Value> {
+        self.restore.take()
+    }
+
+    /// Committed entries that haven't been applied yet, with their indexes, in log order.
+    pub fn take_committed(&mut self) -> Vec<(u64, LogEntry)> {
+        let committed = (self.last_applied + 1..=self.commit_index)
+            .map(|index| (index, self.entries_from(index)[0].clone()))
+            .collect();
+
+        self.last_applied = self.commit_index;
+
+        committed
+    }
+
+    fn start_election(&mut self, me: &str, nodes: &[String]) -> Outgoing {
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.voted_for = Some(me.to_string());
+        self.votes = HashSet::from([me.to_string()]);
+        self.elapsed = 0;
+        self.reset_election_timeout();
+
+        if self.votes.len() > nodes.len() / 2 {
+            return self.become_leader(me, nodes);
+        }
+
+        let body = MessageBody::Internal(InternalBody::RequestVote(RequestVoteBody {
+            term: self.term,
+            last_log_index: self.last_index(),
+            last_log_term: self.term_at(self.last_index()),
+            msg_id: None,
+        }));
+
+        peers(me, nodes)
+            .map(|peer| (peer.clone(), body.clone()))
+            .collect()
+    }
+
+    fn become_leader(&mut self, me: &str, nodes: &[String]) -> Outgoing {
+        info!("Elected leader for term {}.", self.term);
+
+        self.role = Role::Leader;
+        self.leader = Some(me.to_string());
+        self.next_index = peers(me, nodes)
+            .map(|peer| (peer.clone(), self.last_index() + 1))
+            .collect();
+        self.match_index = peers(me, nodes).map(|peer| (peer.clone(), 0)).collect();
+        self.acked_round.clear();
+        self.rounds.clear();
+        self.confirmed_round = self.round;
+
+        self.replicate(me, nodes)
+    }
+
+    fn become_follower(&mut self, term: u64, leader: Option<String>) {
+        if self.role == Role::Leader {
+            self.abandoned
+                .extend(self.reads.drain(..).map(|read| read.request));
+        }
+
+        self.lease_until = 0;
+        self.term = term;
+        self.role = Role::Follower;
+        self.leader = leader;
+        self.voted_for = None;
+        self.votes.clear();
+    }
+
+    fn vote(&mut self, candidate: &str, body: &RequestVoteBody) -> MessageBody<'static> {
+        let last_term = self.term_at(self.last_index());
+        let up_to_date =
+            (body.last_log_term, body.last_log_index) >= (last_term, self.last_index());
+        let free = self
+            .voted_for
+            .as_ref()
+            .is_none_or(|voted_for| voted_for == candidate);
+
+        let vote_granted = body.term == self.term && free && up_to_date;
+
+        if vote_granted {
+            self.voted_for = Some(candidate.to_string());
+            self.elapsed = 0;
+        }
+
+        MessageBody::Internal(InternalBody::RequestVoteRes(RequestVoteResBody {
+            term: self.term,
+            vote_granted,
+            msg_id: None,
+        }))
+    }
+
+    fn append(&mut self, leader: &str, body: &AppendEntriesBody) -> MessageBody<'static> {
+        let reply = |term, success, match_index| {
+            MessageBody::Internal(InternalBody::AppendEntriesRes(AppendEntriesResBody {
+                term,
+                success,
+                match_index,
+                round: body.round,
+                msg_id: None,
+            }))
+        };
+
+        if body.term < self.term {
+            return reply(self.term, false, 0);
+        }
+
+        // A candidate that hears from this term's leader has lost the election.
+        self.become_follower(body.term, Some(leader.to_string()));
+        self.voted_for = Some(leader.to_string());
+        self.elapsed = 0;
+
+        let prev = body.prev_log_index;
+
+        // Entries up to the snapshot are committed, so they agree with the leader's.
+        if prev > self.last_index()
+            || (prev >= self.snapshot_index && self.term_at(prev) != body.prev_log_term)
+        {
+            return reply(
+                self.term,
+                false,
+                prev.saturating_sub(1).min(self.last_index()),
+            );
+        }
+
+        for (offset, entry) in body.entries.iter().enumerate() {
+            let index = prev + 1 + offset as u64;
+
+            if index <= self.snapshot_index {
+                continue;
+            }
+
+            if index <= self.last_index() {
+                if self.term_at(index) == entry.term {
+                    continue;
+                }
+
+                // A conflicting entry, and everything after it, was never committed.
+                self.log
+                    .truncate((index - self.snapshot_index - 1) as usize);
+            }
+
+            self.changed_from(index);
+            self.log.push(entry.clone());
+        }
+
+        let matched = prev + body.entries.len() as u64;
+
+        self.commit_index = self.commit_index.max(body.leader_commit.min(matched));
+
+        reply(self.term, true, matched)
+    }
+
+    /// Replace the log up to the leader's snapshot, keeping any entries after it.
+    fn install_snapshot(
+        &mut self,
+        leader: &str,
+        body: &InstallSnapshotBody,
+    ) -> MessageBody<'static> {
+        let reply = |term, success, match_index| {
+            MessageBody::Internal(InternalBody::AppendEntriesRes(AppendEntriesResBody {
+                term,
+                success,
+                match_index,
+                round: body.round,
+                msg_id: None,
+            }))
+        };
+
+        if body.term < self.term {
+            return reply(self.term, false, 0);
+        }
+
+        self.become_follower(body.term, Some(leader.to_string()));
+        self.voted_for = Some(leader.to_string());
+        self.elapsed = 0;
+
+        let index = body.last_included_index;
+
+        if index <= self.commit_index {
+            return reply(self.term, true, self.commit_index.min(self.last_index()));
+        }
+
+        self.load_snapshot(index, body.last_included_term, body.data.clone());
+        self.restore = Some(body.data.clone());
+        self.commit_index = index;
+        self.last_applied = index;
+
+        reply(self.term, true, index)
+    }
+
+    /// Compact the applied entries into `state`, the state machine's state after applying them.
+    pub fn compact(&mut self, state: Value) {
+        self.load_snapshot(self.last_applied, self.term_at(self.last_applied), state);
+    }
+
+    /// Replace the log up to `index` with a snapshot, keeping the entries after it if the log
+    /// agrees with the snapshot.
+    fn load_snapshot(&mut self, index: u64, term: u64, state: Value) {
+        if index <= self.last_index() && self.term_at(index) == term {
+            self.log.drain(..(index - self.snapshot_index) as usize);
+        } else {
+            self.log.clear();
+        }
+
+        self.snapshot_index = index;
+        self.snapshot_term = term;
+        self.snapshot = state;
+        self.unrecorded_snapshot = true;
+    }
+
+    fn changed_from(&mut self, index: u64) {
+        self.unrecorded_from = Some(self.unrecorded_from.map_or(index, |from| from.min(index)));
+    }
+
+    /// The changes to the vote, the log, and the snapshot since they were last taken.
+    pub fn take_records(&mut self) -> Vec<RaftRecord> {
+        let mut records = vec![];
+
+        if (self.term, &self.voted_for) != (self.recorded_vote.0, &self.recorded_vote.1) {
+            self.recorded_vote = (self.term, self.voted_for.clone());
+            records.push(RaftRecord::Vote {
+                term: self.term,
+                voted_for: self.voted_for.clone(),
+            });
+        }
+
+        if std::mem::take(&mut self.unrecorded_snapshot) {
+            records.push(RaftRecord::Snapshot {
+                index: self.snapshot_index,
+                term: self.snapshot_term,
+                state: self.snapshot.clone(),
+            });
+        }
+
+        // Entries the snapshot has since replaced needn't be recorded.
+        if let Some(from) = self.unrecorded_from.take() {
+            let index = from.max(self.snapshot_index + 1);
+
+            records.push(RaftRecord::Entries {
+                index,
+                entries: self.entries_from(index).to_vec(),
+            });
+        }
+
+        records
+    }
+
+    /// The records that rebuild the current state on their own, e.g. to replace a long run of
+    /// earlier ones.
+    pub fn records(&self) -> Vec<RaftRecord> {
+        vec![
+            RaftRecord::Vote {
+                term: self.term,
+                voted_for: self.voted_for.clone(),
+            },
+            RaftRecord::Snapshot {
+                index: self.snapshot_index,
+                term: self.snapshot_term,
+                state: self.snapshot.clone(),
+            },
+            RaftRecord::Entries {
+                index: self.snapshot_index + 1,
+                entries: self.log.clone(),
+            },
+        ]
+    }
+
+    /// Rebuild the state a previous run recorded. Only the snapshot's entries are known to be
+    /// committed; the rest are committed again once a leader says so.
+    pub fn recover(&mut self, records: impl IntoIterator<Item = RaftRecord>) {
+        for record in records {
+            match record {
+                RaftRecord::Vote { term, voted_for } => {
+                    self.term = term;
+                    self.voted_for = voted_for;
+                }
+                RaftRecord::Entries { index, entries } => {
+                    let start = index.max(self.snapshot_index + 1);
+                    let skip = (start - index) as usize;
+
+                    self.log
+                        .truncate((start - self.snapshot_index - 1) as usize);
+                    self.log.extend(entries.into_iter().skip(skip));
+                }
+                RaftRecord::Snapshot { index, term, state } => {
+                    self.load_snapshot(index, term, state)
+                }
+            }
+        }
+
+        self.commit_index = self.snapshot_index;
+        self.last_applied = self.snapshot_index;
+
+        if self.snapshot_index > 0 {
+            self.restore = Some(self.snapshot.clone());
+        }
+
+        self.recorded_vote = (self.term, self.voted_for.clone());
+        self.unrecorded_from = None;
+        self.unrecorded_snapshot = false;
+    }
+
+    /// Confirm the latest round a majority has acknowledged, counting this node, and extend the
+    /// lease from when it was sent: no follower that acknowledged it votes for another candidate
+    /// for an election timeout after.
+    fn confirm_round(&mut self, me: &str, nodes: &[String]) {
+        let mut acked: Vec<u64> = peers(me, nodes)
+            .map(|peer| self.acked_round.get(peer).copied().unwrap_or(0))
+            .chain([self.round])
+            .collect();
+        acked.sort_unstable_by(|a, b| b.cmp(a));
+
+        let confirmed = acked[acked.len() / 2];
+
+        if confirmed <= self.confirmed_round {
+            return;
+        }
+
+        if let Some(sent) = self.rounds.get(&confirmed) {
+            let lease = (Self::ELECTION_TICKS - Self::LEASE_MARGIN) as u64;
+            self.lease_until = self.lease_until.max(sent + lease);
+        }
+
+        self.confirmed_round = confirmed;
+        self.rounds = self.rounds.split_off(&(confirmed + 1));
+    }
+
+    /// Commit the latest entry from this term that a majority has.
+    fn advance_commit_index(&mut self, me: &str, nodes: &[String]) {
+        for index in (self.commit_index + 1..=self.last_index()).rev() {
+            if self.term_at(index) != self.term {
+                break;
+            }
+
+            let replicas = 1 + peers(me, nodes)
+                .filter(|peer| self.match_index.get(*peer).copied().unwrap_or(0) >= index)
+                .count();
+
+            if replicas > nodes.len() / 2 {
+                self.commit_index = index;
+                break;
+            }
+        }
+    }
+
+    fn reset_election_timeout(&mut self) {
+        let random = RandomState::new().build_hasher().finish() as u32;
+
+        self.election_timeout = Self::ELECTION_TICKS + random % Self::ELECTION_TICKS;
+    }
+}
+
+fn peers<'a>(me: &'a str, nodes: &'a [String]) -> impl Iterator<Item = &'a String> {
+    nodes.iter().filter(move |node_id| *node_id != me)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn cluster() -> (Vec<String>, BTreeMap<String, Raft>) {
+        let nodes: Vec<String> = ["n1", "n2", "n3"].map(String::from).to_vec();
+        let rafts = nodes
+            .iter()
+            .map(|node_id| (node_id.clone(), Raft::default()))
+            .collect();
+
+        (nodes, rafts)
+    }
+
+    /// Tick every node once, delivering every message until there are none left. Messages to
+    /// `down` are lost.
+    fn round(nodes: &[String], rafts: &mut BTreeMap<String, Raft>, down: Option<&str>) {
+        let mut in_flight = vec![];
+
+        for (node_id, raft) in rafts.iter_mut() {
+            if Some(node_id.as_str()) != down {
+                in_flight.extend(
+                    raft.tick(node_id, nodes)
+                        .into_iter()
+                        .map(|(dest, body)| (node_id.clone(), dest, body)),
+                );
+            }
+        }
+
+        while let Some((src, dest, body)) = in_flight.pop() {
+            if Some(dest.as_str()) == down {
+                continue;
+            }
+
+            let raft = rafts.get_mut(&dest).unwrap();
+
+            in_flight.extend(
+                raft.handle(&dest, nodes, &src, &body)
+                    .into_iter()
+                    .map(|(next, body)| (dest.clone(), next, body)),
+            );
+        }
+    }
+
+    fn leader(rafts: &BTreeMap<String, Raft>) -> Option<String> {
+        let leaders: Vec<_> = rafts
+            .iter()
+            .filter(|(_, raft)| raft.role == Role::Leader)
+            .map(|(node_id, _)| node_id.clone())
+            .collect();
+
+        assert!(leaders.len() <= 1, "more than one leader: {:?}", leaders);
+
+        leaders.first().cloned()
+    }
+
+    fn request(msg_id: u32) -> Message<'static> {
+        serde_json::from_str(&format!(
+            r#"{{"src": "c1", "dest": "n1", "body": {{"type": "write", "key": 1, "value": {msg_id}, "msg_id": {msg_id}}}}}"#
+        ))
+        .map(Message::into_owned)
+        .unwrap()
+    }
+
+    #[test]
+    fn elects_a_leader_and_commits_on_a_majority() {
+        let (nodes, mut rafts) = cluster();
+
+        while leader(&rafts).is_none() {
+            round(&nodes, &mut rafts, None);
+        }
+
+        let first = leader(&rafts).unwrap();
+        let index = rafts
+            .get_mut(&first)
+            .unwrap()
+            .propose(&first, &nodes, request(1));
+        assert_eq!(index, Some(1));
+
+        // The leader goes down; the others elect a new one, which commits the entry a majority
+        // had along with its own.
+        for _ in 0..Raft::HEARTBEAT_TICKS {
+            round(&nodes, &mut rafts, None);
+        }
+
+        // It would find out it had been deposed once it was back; until then it's ignored.
+        rafts.get_mut(&first).unwrap().role = Role::Follower;
+
+        let second = loop {
+            round(&nodes, &mut rafts, Some(&first));
+
+            match leader(&rafts) {
+                Some(leader) if leader != first => break leader,
+                _ => {}
+            }
+        };
+
+        rafts
+            .get_mut(&second)
+            .unwrap()
+            .propose(&second, &nodes, request(2));
+
+        for _ in 0..Raft::HEARTBEAT_TICKS * 2 {
+            round(&nodes, &mut rafts, Some(&first));
+        }
+
+        for (node_id, raft) in rafts.iter_mut().filter(|(node_id, _)| **node_id != first) {
+            let committed: Vec<_> = raft
+                .take_committed()
+                .into_iter()
+                .map(|(_, entry)| entry.request.body.msg_id())
+                .collect();
+
+            assert_eq!(committed, vec![Some(1), Some(2)], "{node_id}");
+        }
+    }
+
+    #[test]
+    fn serves_reads_once_a_majority_confirms_the_leader() {
+        let (nodes, mut rafts) = cluster();
+
+        while leader(&rafts).is_none() {
+            round(&nodes, &mut rafts, None);
+        }
+
+        let first = leader(&rafts).unwrap();
+        let raft = rafts.get_mut(&first).unwrap();
+        raft.propose(&first, &nodes, request(1));
+
+        for _ in 0..Raft::HEARTBEAT_TICKS {
+            round(&nodes, &mut rafts, None);
+        }
+
+        let raft = rafts.get_mut(&first).unwrap();
+        raft.take_committed();
+        assert!(raft.knows_commit_index());
+
+        // Without a lease, the read waits for the next round of heartbeats.
+        assert!(raft.read(request(2), false));
+        assert!(raft.take_reads().0.is_empty());
+
+        for _ in 0..Raft::HEARTBEAT_TICKS {
+            round(&nodes, &mut rafts, None);
+        }
+
+        let raft = rafts.get_mut(&first).unwrap();
+        assert_eq!(raft.take_reads().0.len(), 1);
+
+        // That round earned a lease, so the next read is served right away.
+        assert!(!raft.read(request(3), true));
+        assert_eq!(raft.take_reads().0.len(), 1);
+    }
+
+    #[test]
+    fn sends_a_snapshot_to_a_follower_behind_the_log() {
+        let (nodes, mut rafts) = cluster();
+
+        while leader(&rafts).is_none() {
+            round(&nodes, &mut rafts, None);
+        }
+
+        let first = leader(&rafts).unwrap();
+        let down = nodes.iter().find(|node_id| **node_id != first).unwrap();
+
+        for msg_id in 1..=3 {
+            rafts
+                .get_mut(&first)
+                .unwrap()
+                .propose(&first, &nodes, request(msg_id));
+        }
+
+        for _ in 0..Raft::HEARTBEAT_TICKS {
+            round(&nodes, &mut rafts, Some(down));
+        }
+
+        let raft = rafts.get_mut(&first).unwrap();
+        assert_eq!(raft.take_committed().len(), 3);
+        raft.compact(Value::from("state"));
+        assert_eq!(raft.log_len(), 0);
+
+        for _ in 0..Raft::HEARTBEAT_TICKS {
+            round(&nodes, &mut rafts, None);
+        }
+
+        let follower = rafts.get_mut(down).unwrap();
+        assert_eq!(follower.take_restore(), Some(Value::from("state")));
+        assert_eq!(follower.last_index(), 3);
+        assert!(follower.take_committed().is_empty());
+    }
+
+    #[test]
+    fn recovers_the_vote_log_and_snapshot_from_its_records() {
+        let (nodes, mut rafts) = cluster();
+        let mut records: BTreeMap<String, Vec<RaftRecord>> = BTreeMap::new();
+
+        let mut record = |rafts: &mut BTreeMap<String, Raft>| {
+            for (node_id, raft) in rafts.iter_mut() {
+                records
+                    .entry(node_id.clone())
+                    .or_default()
+                    .extend(raft.take_records());
+            }
+        };
+
+        while leader(&rafts).is_none() {
+            round(&nodes, &mut rafts, None);
+            record(&mut rafts);
+        }
+
+        let first = leader(&rafts).unwrap();
+
+        for msg_id in 1..=4 {
+            let raft = rafts.get_mut(&first).unwrap();
+            raft.propose(&first, &nodes, request(msg_id));
+
+            if msg_id == 3 {
+                for _ in 0..Raft::HEARTBEAT_TICKS {
+                    round(&nodes, &mut rafts, None);
+                }
+
+                let raft = rafts.get_mut(&first).unwrap();
+                raft.take_committed();
+                raft.compact(Value::from("state"));
+            }
+
+            record(&mut rafts);
+        }
+
+        for _ in 0..Raft::HEARTBEAT_TICKS {
+            round(&nodes, &mut rafts, None);
+        }
+        record(&mut rafts);
+
+        for (node_id, raft) in &rafts {
+            let mut recovered = Raft::default();
+            recovered.recover(records[node_id].clone());
+
+            assert_eq!(recovered.term, raft.term, "{node_id}");
+            assert_eq!(recovered.voted_for, raft.voted_for, "{node_id}");
+            assert_eq!(recovered.last_index(), 4, "{node_id}");
+            assert_eq!(recovered.term_at(4), raft.term_at(4), "{node_id}");
+
+            // Replacing the records with `records` rebuilds the same state.
+            let mut compacted = Raft::default();
+            compacted.recover(raft.records());
+            assert_eq!(compacted.snapshot_index, raft.snapshot_index, "{node_id}");
+            assert_eq!(compacted.log_len(), raft.log_len(), "{node_id}");
+        }
+
+        let mut leader = Raft::default();
+        leader.recover(records[&first].clone());
+        assert_eq!(leader.snapshot_index, 3);
+        assert_eq!(leader.take_restore(), Some(Value::from("state")));
+        assert_eq!(leader.role, Role::Follower);
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::workload::Workload;
+
+/// Holds client requests for a workload until the node can answer them, e.g. broadcasts that
+/// arrive before `topology`, for at most the workload's timeout.
+#[derive(Debug, Default)]
+pub struct Readiness {
+    /// The workload handling each gated request type, and how long its requests are held.
+    gates: HashMap<String, (Workload, Duration)>,
+    changed: Arc<Notify>,
+}
+
+impl Readiness {
+    /// Gate the given workloads' requests, when they're among the active `workloads`. A request
+    /// type shared by several workloads belongs to the one listed first, as in the registry, but
+    /// a shared `read` is routed by the node's state, so it isn't held.
+    pub fn new(workloads: &[Workload], timeouts: &[(Workload, Duration)]) -> Self {
+        let mut gates = HashMap::new();
+
+        for workload in workloads.iter().rev() {
+            for kind in workload.requests() {
+                gates.insert(kind.to_string(), *workload);
+            }
+        }
+
+        let readers = workloads
+            .iter()
+            .filter(|workload| workload.is_built() && workload.requests().contains(&"read"))
+            .count();
+
+        if readers > 1 {
+            gates.remove("read");
+        }
+
+        let gates = gates
+            .into_iter()
+            .filter_map(|(kind, workload)| {
+                timeouts
+                    .iter()
+                    .find(|(gated, _)| *gated == workload)
+                    .map(|(_, timeout)| (kind, (workload, *timeout)))
+            })
+            .collect();
+
+        Readiness {
+            gates,
+            ..Default::default()
+        }
+    }
+
+    pub fn gate(&self, kind: &str) -> Option<(Workload, Duration)> {
+        self.gates.get(kind).copied()
+    }
+
+    /// Notified after every message the node handles, since any of them may make a workload
+    /// ready.
+    pub fn changed(&self) -> Arc<Notify> {
+        self.changed.clone()
+    }
+}
+
+#[cfg(all(test, feature = "broadcast"))]
+mod test {
+    use super::*;
+    use crate::actor::NodeHandle;
+    use crate::core::message::Message;
+    use crate::node::Node;
+    use tokio::sync::mpsc;
+
+    fn gated_node() -> NodeHandle {
+        let workloads = [Workload::Broadcast];
+
+        NodeHandle::spawn(Node {
+            id: Some("n1".to_string()),
+            node_ids: vec!["n1".to_string()],
+            readiness: Readiness::new(
+                &workloads,
+                &[(Workload::Broadcast, Duration::from_millis(50))],
+            ),
+            ..Default::default()
+        })
+    }
+
+    async fn reply_type(replies: &mut mpsc::Receiver<String>) -> String {
+        let reply = replies.recv().await.unwrap();
+        let reply: Message = serde_json::from_str(&reply).unwrap();
+
+        reply.body.kind().to_string()
+    }
+
+    #[tokio::test]
+    async fn holds_requests_until_the_workload_is_ready() {
+        let (response_tx, mut replies) = mpsc::channel(4);
+        let broadcast = r#"{"src": "c1", "dest": "n1", "body": {"type": "broadcast", "message": 1, "msg_id": 1}}"#;
+        let topology = r#"{"src": "c2", "dest": "n1", "body": {"type": "topology", "topology": {"n1": []}, "msg_id": 1}}"#;
+
+        // Not ready in time.
+        let node = gated_node();
+        Node::process(node.clone(), broadcast, &response_tx).await;
+        assert_eq!(reply_type(&mut replies).await, "error");
+
+        // Ready once the topology arrives.
+        let node = gated_node();
+        let held = tokio::spawn({
+            let node = node.clone();
+            let response_tx = response_tx.clone();
+
+            async move { Node::process(node, broadcast, &response_tx).await }
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        Node::process(node, topology, &response_tx).await;
+        held.await.unwrap();
+
+        assert_eq!(reply_type(&mut replies).await, "topology_ok");
+        assert_eq!(reply_type(&mut replies).await, "broadcast_ok");
+    }
+}
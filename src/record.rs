@@ -0,0 +1,115 @@
+//! `--record <path>`: append every message the node receives and sends to a JSON lines file, each
+//! with when and which way it went, for `--replay` and for building regression fixtures. Messages
+//! are recorded on their way between the transport and the node, so the recording is the same
+//! over stdin/stdout, TCP, or a replay.
+
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::mpsc::{self, Receiver};
+use tracing::error;
+
+use crate::core::state;
+use crate::replay::{Direction, Recorded};
+
+#[derive(Debug)]
+pub struct Recorder {
+    file: Mutex<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Append to the file at `path`, creating it if need be.
+    pub fn open(path: &Path) -> io::Result<Arc<Recorder>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Arc::new(Recorder {
+            file: Mutex::new(file),
+            start: Instant::now(),
+        }))
+    }
+
+    /// Write a line, one entry per message when it holds several, as the node handles them. A
+    /// document that isn't JSON is recorded as a string, which replay skips.
+    pub fn record(&self, direction: Direction, line: &str) {
+        let at = self.start.elapsed().as_millis() as u64;
+        let mut file = self.file.lock().unwrap();
+
+        for document in state::documents(line) {
+            let recorded = Recorded {
+                at,
+                direction,
+                message: serde_json::from_str(document).unwrap_or_else(|_| Value::from(document)),
+            };
+
+            if let Err(err) = writeln!(file, "{}", serde_json::to_string(&recorded).unwrap()) {
+                error!("Unable to record a message: {:?}", err);
+            }
+        }
+    }
+
+    /// Pass the lines from `rx` on to the returned channel, recording each. The returned channel
+    /// closes once `rx` does.
+    pub fn relay(
+        self: Arc<Self>,
+        direction: Direction,
+        mut rx: Receiver<String>,
+        capacity: usize,
+    ) -> Receiver<String> {
+        let (tx, relayed) = mpsc::channel(capacity.max(1));
+
+        tokio::spawn(async move {
+            while let Some(line) = rx.recv().await {
+                self.record(direction, &line);
+
+                if tx.send(line).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        relayed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::replay::Trace;
+
+    #[tokio::test]
+    async fn records_messages_in_a_form_replay_reads() {
+        let path = std::env::temp_dir().join(format!("tranquility-record-{}", std::process::id()));
+        let recorder = Recorder::open(&path).unwrap();
+
+        let (tx, rx) = mpsc::channel(4);
+        let mut rx = recorder.clone().relay(Direction::In, rx, 4);
+
+        let echo =
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": 1, "msg_id": 1}}"#;
+        tx.send(echo.to_string()).await.unwrap();
+        assert_eq!(rx.recv().await.as_deref(), Some(echo));
+
+        recorder.record(
+            Direction::Out,
+            r#"{"src": "n1", "dest": "c1", "body": {"type": "echo_ok", "echo": 1, "in_reply_to": 1}}"#,
+        );
+        recorder.record(Direction::In, "not json");
+        // Several messages on one line, as the node accepts them.
+        recorder.record(
+            Direction::In,
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": 2, "msg_id": 2}}{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": 3, "msg_id": 3}}"#,
+        );
+
+        let trace = Trace::read(&path).unwrap();
+        assert_eq!(trace.messages.len(), 3);
+        assert!(trace.messages[0].1.contains(r#""echo":1"#));
+        assert!(trace.messages[1].1.contains(r#""echo":2"#));
+        assert!(trace.messages[2].1.contains(r#""echo":3"#));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
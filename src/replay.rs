@@ -0,0 +1,146 @@
+//! `--replay <file>`: feed a recorded trace through the node in place of stdin, printing its
+//! replies as usual, to reproduce a checker failure offline.
+//!
+//! A trace is read line by line, and each line's JSON, from its first `{`, is one of:
+//! - a recorded message, `{"at": 12, "direction": "in", "message": {...}}`, received `at`
+//!   milliseconds into the recording. Only received messages are replayed.
+//! - a bare message, as in Maelstrom's logs or a node's stderr. Messages sent by the node being
+//!   replayed, i.e. from the `node_id` of the first `init`, are skipped, so a node's own `Sent:`
+//!   lines are ignored, and so are messages to other nodes, in a log of the whole cluster.
+//!
+//! Lines that are neither are skipped. Recorded messages are replayed at their original timing,
+//! divided by the speed; bare messages, and every message at speed 0, as fast as the node takes
+//! them.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Which way a recorded message went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    In,
+    Out,
+}
+
+/// A line of a recording.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Recorded {
+    /// Milliseconds since recording started.
+    pub at: u64,
+    pub direction: Direction,
+    pub message: Value,
+}
+
+/// The messages to replay, in order, with when each was received, if known.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trace {
+    pub messages: Vec<(Option<Duration>, String)>,
+}
+
+impl Trace {
+    pub fn read(path: &Path) -> io::Result<Trace> {
+        Ok(Trace::parse(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn parse(contents: &str) -> Trace {
+        let mut trace = Trace::default();
+        let mut node_id: Option<Value> = None;
+
+        for line in contents.lines() {
+            let Some(start) = line.find('{') else {
+                continue;
+            };
+            let Ok(value) = serde_json::from_str::<Value>(&line[start..]) else {
+                continue;
+            };
+
+            let (at, message) = match serde_json::from_value::<Recorded>(value.clone()) {
+                Ok(recorded) if recorded.direction == Direction::In => {
+                    (Some(Duration::from_millis(recorded.at)), recorded.message)
+                }
+                Ok(_) => continue,
+                Err(_) => (None, value),
+            };
+
+            if message.get("body").is_none() {
+                continue;
+            }
+
+            // Skip what the node sent, and in a log of the whole cluster, what other nodes got.
+            if let Some(node_id) = &node_id {
+                if message.get("src") == Some(node_id) || message.get("dest") != Some(node_id) {
+                    continue;
+                }
+            }
+
+            if message["body"]["type"] == "init" && node_id.is_none() {
+                node_id = message["body"].get("node_id").cloned();
+            }
+
+            trace.messages.push((at, message.to_string()));
+        }
+
+        trace
+    }
+
+    /// Send the messages to the node at `speed` times their original pace, or without waiting
+    /// at speed 0. Closing `tx` once they're sent shuts the node down.
+    pub async fn feed(self, speed: f64, tx: Sender<String>) {
+        let start = Instant::now();
+        let first = self.messages.iter().find_map(|(at, _)| *at);
+        let count = self.messages.len();
+
+        for (at, message) in self.messages {
+            if let (Some(at), Some(first)) = (at, first) {
+                if speed > 0.0 {
+                    tokio::time::sleep_until(start + at.saturating_sub(first).div_f64(speed)).await;
+                }
+            }
+
+            if tx.send(message).await.is_err() {
+                warn!("The node stopped; no longer replaying.");
+                return;
+            }
+        }
+
+        info!("Replayed {} messages.", count);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replays_only_the_messages_the_node_received() {
+        let trace = Trace::parse(concat!(
+            r#"{"at": 5, "direction": "in", "message": {"src": "c1", "dest": "n1", "body": {"type": "init", "node_id": "n1", "node_ids": ["n1"], "msg_id": 1}}}"#,
+            "\n",
+            r#"{"at": 6, "direction": "out", "message": {"src": "n1", "dest": "c1", "body": {"type": "init_ok", "in_reply_to": 1}}}"#,
+            "\n",
+            r#"Sent: {"src": "n1", "dest": "c1", "body": {"type": "init_ok", "in_reply_to": 1}}"#,
+            "\n",
+            "Shutting down...\n",
+            r#"metrics {"dropped": 0}"#,
+            "\n",
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": 1, "msg_id": 2}}"#,
+            "\n",
+            r#"{"src": "n2", "dest": "n3", "body": {"type": "echo", "echo": 2, "msg_id": 3}}"#,
+            "\n",
+            r#"{"src": "c2", "dest": "n2", "body": {"type": "init", "node_id": "n2", "node_ids": ["n2"], "msg_id": 1}}"#,
+            "\n",
+        ));
+
+        assert_eq!(trace.messages.len(), 2);
+        assert_eq!(trace.messages[0].0, Some(Duration::from_millis(5)));
+        assert_eq!(trace.messages[1].0, None);
+        assert!(trace.messages[1].1.contains("echo"));
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+/// How unacknowledged messages are resent: after `initial`, then after delays growing by
+/// `multiplier` up to `max_delay`, each spread by up to `jitter` of itself either way so peers
+/// that lost messages at the same time don't resend in lockstep.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub initial: Duration,
+    pub multiplier: f64,
+    pub max_delay: Duration,
+    /// Give up on a message after this many resends; `None` retries until it's acknowledged.
+    pub max_attempts: Option<u32>,
+    /// A fraction between 0 and 1.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            initial: Duration::from_millis(1000),
+            multiplier: 2.0,
+            max_delay: Duration::from_millis(8000),
+            max_attempts: None,
+            jitter: 0.1,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before the resend following `attempts` earlier ones.
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let backoff = self.initial.as_secs_f64() * self.multiplier.max(1.0).powi(attempts as i32);
+        let backoff = backoff.min(self.max_delay.as_secs_f64());
+
+        // A random factor in [1 - jitter, 1 + jitter].
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        let factor = 1.0 + self.jitter * (2.0 * random - 1.0);
+
+        Duration::from_secs_f64(backoff * factor)
+    }
+}
+
+#[derive(Debug)]
+struct Schedule {
+    attempts: u32,
+    due: Instant,
+}
+
+/// The resend schedule of each unacknowledged message, by `msg_id`.
+#[derive(Debug, Default)]
+pub struct Retries {
+    pub policy: RetryPolicy,
+    schedules: HashMap<u32, Schedule>,
+}
+
+impl Retries {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Retries {
+            policy,
+            schedules: HashMap::new(),
+        }
+    }
+
+    /// Sort the unacknowledged messages into those due a resend and those out of attempts.
+    /// Messages seen for the first time are scheduled, and acknowledged ones forgotten. No delay
+    /// is shorter than `floor`.
+    pub fn due(
+        &mut self,
+        unacknowledged: impl IntoIterator<Item = u32>,
+        floor: Duration,
+    ) -> (Vec<u32>, Vec<u32>) {
+        let now = Instant::now();
+        let mut schedules = HashMap::new();
+        let mut resend = vec![];
+        let mut expired = vec![];
+
+        for msg_id in unacknowledged {
+            let mut schedule = self.schedules.remove(&msg_id).unwrap_or_else(|| Schedule {
+                attempts: 0,
+                due: now + self.policy.delay(0).max(floor),
+            });
+
+            if schedule.due <= now {
+                if self
+                    .policy
+                    .max_attempts
+                    .is_some_and(|max| schedule.attempts >= max)
+                {
+                    expired.push(msg_id);
+                    continue;
+                }
+
+                schedule.attempts += 1;
+                schedule.due = now + self.policy.delay(schedule.attempts).max(floor);
+                resend.push(msg_id);
+            }
+
+            schedules.insert(msg_id, schedule);
+        }
+
+        self.schedules = schedules;
+
+        (resend, expired)
+    }
+
+    /// Wait `factor` times as long as scheduled for the next resend of `msg_id`.
+    pub fn stretch(&mut self, msg_id: u32, factor: u32) {
+        if let Some(schedule) = self.schedules.get_mut(&msg_id) {
+            let now = Instant::now();
+            schedule.due = now + schedule.due.saturating_duration_since(now) * factor;
+        }
+    }
+
+    /// How long until the next resend is due. Never longer than the initial delay, so newly
+    /// unacknowledged messages are scheduled promptly.
+    pub fn next_wake(&self) -> Duration {
+        let now = Instant::now();
+
+        self.schedules
+            .values()
+            .map(|schedule| schedule.due.saturating_duration_since(now))
+            .min()
+            .unwrap_or(self.policy.initial)
+            .min(self.policy.initial)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backs_off_up_to_the_maximum_then_gives_up() {
+        let policy = RetryPolicy {
+            initial: Duration::ZERO,
+            max_attempts: Some(2),
+            jitter: 0.0,
+            ..Default::default()
+        };
+
+        let backoff = RetryPolicy {
+            initial: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+            jitter: 0.0,
+            ..Default::default()
+        };
+
+        assert_eq!(backoff.delay(0), Duration::from_millis(100));
+        assert_eq!(backoff.delay(1), Duration::from_millis(200));
+        assert_eq!(backoff.delay(5), Duration::from_millis(300));
+
+        let mut retries = Retries::new(policy);
+
+        assert_eq!(retries.due([1], Duration::ZERO), (vec![1], vec![]));
+        assert_eq!(retries.due([1], Duration::ZERO), (vec![1], vec![]));
+        assert_eq!(retries.due([1], Duration::ZERO), (vec![], vec![1]));
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::actor::NodeHandle;
+use crate::core::message::{ErrorCode, Message, MessageBody, RemoteError};
+use crate::node::Node;
+
+/// Caps on the number of RPCs awaiting a reply, across every peer and to any one peer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RpcLimits {
+    pub global: usize,
+    pub per_peer: usize,
+}
+
+impl Default for RpcLimits {
+    fn default() -> Self {
+        RpcLimits {
+            global: 1024,
+            per_peer: 256,
+        }
+    }
+}
+
+/// Hands out permits for outstanding RPCs. Callers await a permit once a cap is reached, so
+/// during a partition senders slow down instead of the pending tables growing without limit.
+#[derive(Debug)]
+pub struct RpcPermits {
+    limits: RpcLimits,
+    global: Arc<Semaphore>,
+    per_peer: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+/// Held for as long as an RPC is outstanding; dropping it frees the slot.
+#[derive(Debug)]
+pub struct RpcPermit {
+    _global: OwnedSemaphorePermit,
+    _peer: OwnedSemaphorePermit,
+}
+
+impl Default for RpcPermits {
+    fn default() -> Self {
+        RpcPermits::new(RpcLimits::default())
+    }
+}
+
+impl RpcPermits {
+    pub fn new(limits: RpcLimits) -> Self {
+        RpcPermits {
+            limits,
+            global: Arc::new(Semaphore::new(limits.global.max(1))),
+            per_peer: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wait for a slot for an RPC to `peer`. The peer's slot is taken first, so a single slow
+    /// peer can't hold global slots while it waits.
+    pub async fn acquire(&self, peer: &str) -> RpcPermit {
+        let peer = self
+            .per_peer
+            .lock()
+            .unwrap()
+            .entry(peer.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.limits.per_peer.max(1))))
+            .clone();
+
+        // The semaphores are never closed, so acquiring can't fail.
+        let peer = peer.acquire_owned().await.unwrap();
+        let global = self.global.clone().acquire_owned().await.unwrap();
+
+        RpcPermit {
+            _global: global,
+            _peer: peer,
+        }
+    }
+
+    /// The number of RPCs currently holding a permit.
+    pub fn outstanding(&self) -> usize {
+        self.limits.global.max(1) - self.global.available_permits()
+    }
+}
+
+/// Send `body` to `dest` and wait for the reply. A reply of type `error` resolves to a
+/// `RemoteError`.
+///
+/// Waits for a permit first once the outstanding-RPC caps are reached. A reply that doesn't arrive
+/// within the node's `rpc_timeout` resolves to a `Timeout` error, while the node is running.
+pub async fn rpc(
+    node: &NodeHandle,
+    dest: &str,
+    mut body: MessageBody<'static>,
+) -> Result<Message<'static>, RemoteError> {
+    let permits = node.call(|node| node.rpc_permits.clone()).await?;
+    let _permit = permits.acquire(dest).await;
+
+    let dest = dest.to_string();
+
+    let reply = node
+        .call(move |node| {
+            if node.outbox.is_closed() {
+                return None;
+            }
+
+            let msg_id = node.next_message_id();
+            let reply = node.expect_reply(msg_id);
+
+            body.set_ids(Some(msg_id), None);
+            node.send_to(&dest, body);
+
+            Some(reply)
+        })
+        .await?
+        .ok_or_else(|| unavailable("The node isn't running."))?;
+
+    reply
+        .await
+        .map_err(|_| unavailable("The request was abandoned."))?
+}
+
+/// Send `body` to `dest` as a delivery the node resends on the retry policy's schedule, and wait
+/// for `dest` to acknowledge it; see `Deliveries`. Each error, timeouts included, is handed to
+/// `on_error` on the node while the wait goes on. `None` once the delivery is given up on or
+/// evicted, or the node stops.
+///
+/// The delivery holds its permit until it's settled, like any other RPC.
+pub async fn deliver(
+    node: &NodeHandle,
+    dest: &str,
+    mut body: MessageBody<'static>,
+    on_error: impl Fn(&mut Node, RemoteError) + Send + Sync + 'static,
+) -> Option<Message<'static>> {
+    let permits = node.call(|node| node.rpc_permits.clone()).await.ok()?;
+    let _permit = permits.acquire(dest).await;
+
+    let dest = dest.to_string();
+
+    let (msg_id, mut reply) = node
+        .call(move |node| {
+            if node.outbox.is_closed() {
+                return None;
+            }
+
+            let msg_id = node.next_message_id();
+            body.set_ids(Some(msg_id), None);
+
+            let message = Message {
+                src: node.id.clone(),
+                dest,
+                body,
+                lamport: None,
+            };
+
+            node.unacknowledged.insert(message.clone());
+            let reply = node.expect_reply(msg_id);
+            node.send(vec![message]);
+
+            Some((msg_id, reply))
+        })
+        .await
+        .ok()??;
+
+    let on_error = Arc::new(on_error);
+
+    loop {
+        let err = match reply.await.ok()? {
+            Ok(acknowledgement) => {
+                let _ = node
+                    .call(move |node| node.unacknowledged.remove(msg_id))
+                    .await;
+
+                return Some(acknowledgement);
+            }
+            Err(err) => err,
+        };
+
+        let on_error = on_error.clone();
+
+        reply = node
+            .call(move |node| {
+                on_error(node, err);
+                node.expect_reply(msg_id)
+            })
+            .await
+            .ok()?;
+    }
+}
+
+fn unavailable(text: &str) -> RemoteError {
+    RemoteError {
+        code: ErrorCode::TemporarilyUnavailable,
+        text: text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn waits_for_a_permit_once_a_peer_is_at_its_cap() {
+        let permits = RpcPermits::new(RpcLimits {
+            global: 4,
+            per_peer: 1,
+        });
+
+        let first = permits.acquire("n2").await;
+        let _other_peer = permits.acquire("n3").await;
+
+        assert_eq!(permits.outstanding(), 2);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), permits.acquire("n2"))
+                .await
+                .is_err()
+        );
+
+        drop(first);
+        permits.acquire("n2").await;
+    }
+
+    #[tokio::test]
+    async fn resolves_with_the_reply() {
+        let (outbound, mut sent) = mpsc::channel(1);
+        let node = NodeHandle::spawn(Node {
+            id: Some("n1".to_string()),
+            ..Default::default()
+        });
+        node.call(|node| node.outbox.open()).await.unwrap();
+        tokio::spawn(crate::outbox::drain(node.clone(), outbound));
+
+        let peer = {
+            let node = node.clone();
+
+            tokio::spawn(async move {
+                let request = sent.recv().await.unwrap();
+                let request = serde_json::from_str::<Message>(&request).unwrap();
+                let reply = format!(
+                    r#"{{"src": "n2", "dest": "n1", "body": {{"type": "error", "code": 20, "text": "missing", "in_reply_to": {}}}}}"#,
+                    request.body.msg_id().unwrap()
+                );
+
+                let reply = serde_json::from_str::<Message>(&reply)
+                    .unwrap()
+                    .into_owned();
+
+                node.call(move |node| node.dispatch(reply)).await.unwrap();
+            })
+        };
+
+        let reply = rpc(
+            &node,
+            "n2",
+            serde_json::from_str(r#"{"type": "read", "key": 1}"#).unwrap(),
+        )
+        .await;
+
+        peer.await.unwrap();
+        assert_eq!(reply.unwrap_err().code, ErrorCode::KeyDoesNotExist);
+    }
+}
@@ -0,0 +1,11 @@
+use schemars::schema_for;
+
+use crate::core::message::Message;
+
+/// JSON Schema for every message the node accepts and every response it emits, generated from
+/// the types in `message`, so external tooling can stay in sync with the wire format.
+pub fn export() -> serde_json::Value {
+    serde_json::json!({
+        "message": schema_for!(Message),
+    })
+}
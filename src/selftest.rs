@@ -0,0 +1,148 @@
+//! `tranquility self-test`: run a node in-process and check its replies to a scripted session,
+//! as a quick smoke test of a build.
+
+#[cfg(feature = "broadcast")]
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio_util::task::TaskTracker;
+
+use crate::actor::NodeHandle;
+#[cfg(feature = "broadcast")]
+use crate::core::message::BroadcastValue;
+use crate::core::message::{Message, MessageBody};
+use crate::node::Node;
+
+/// How long to wait for the node's reply to each step.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A step of the session: its name, the line sent, and what the reply to it must satisfy.
+type Check = (&'static str, &'static str, Box<dyn Fn(&Message) -> bool>);
+
+struct Session {
+    tx: Sender<String>,
+    response_rx: Receiver<String>,
+}
+
+impl Session {
+    /// Send a line to the node and wait for a message that satisfies `check`, skipping others.
+    async fn expect(&mut self, line: &str, check: impl Fn(&Message) -> bool) -> Result<(), String> {
+        self.tx
+            .send(line.to_string())
+            .await
+            .map_err(|_| "the node stopped".to_string())?;
+
+        let wait = async {
+            while let Some(response) = self.response_rx.recv().await {
+                if let Ok(message) = serde_json::from_str::<Message>(&response) {
+                    if check(&message) {
+                        return Ok(());
+                    }
+                }
+            }
+
+            Err("the node stopped".to_string())
+        };
+
+        tokio::time::timeout(TIMEOUT, wait)
+            .await
+            .unwrap_or_else(|_| Err("no matching reply".to_string()))
+    }
+}
+
+/// Run the scripted session, printing a line per check. Returns whether every check passed.
+pub async fn run() -> bool {
+    let (tx, rx) = mpsc::channel(32);
+    let (response_tx, response_rx) = mpsc::channel(32);
+
+    let tracker = TaskTracker::new();
+    let node = NodeHandle::spawn(Node::default());
+
+    let handler = {
+        let tracker = tracker.clone();
+
+        tokio::spawn(async move { Node::run(node, rx, response_tx, &tracker).await })
+    };
+
+    let mut session = Session { tx, response_rx };
+    let mut passed = true;
+
+    #[cfg_attr(not(feature = "broadcast"), allow(unused_mut))]
+    let mut checks: Vec<Check> = vec![
+        (
+            "init",
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}}"#,
+            Box::new(|message: &Message| matches!(message.body, MessageBody::InitOk(_))),
+        ),
+        (
+            "echo",
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "msg_id": 2, "echo": "hello"}}"#,
+            Box::new(
+                |message: &Message| matches!(&message.body, MessageBody::EchoOk(body) if body.echo == "hello"),
+            ),
+        ),
+        (
+            "generate",
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "generate", "msg_id": 3}}"#,
+            Box::new(|message: &Message| matches!(message.body, MessageBody::GenerateOk(_))),
+        ),
+    ];
+    #[cfg(feature = "broadcast")]
+    checks.extend(broadcast_checks());
+
+    for (name, line, check) in checks {
+        match session.expect(line, check).await {
+            Ok(()) => println!("pass {}", name),
+            Err(err) => {
+                println!("FAIL {}: {}", name, err);
+                passed = false;
+            }
+        }
+    }
+
+    drop(session);
+    let _ = handler.await;
+
+    tracker.close();
+    tracker.wait().await;
+
+    passed
+}
+
+/// The topology, broadcast, and read steps, after `init` has named the node `n1` of `n1` and `n2`.
+#[cfg(feature = "broadcast")]
+fn broadcast_checks() -> Vec<Check> {
+    let value = |n: u32| BroadcastValue::from(n);
+
+    vec![
+        (
+            "topology",
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "topology", "msg_id": 4, "topology": {"n1": ["n2"], "n2": ["n1"]}}}"#,
+            Box::new(|message: &Message| matches!(message.body, MessageBody::TopologyOk(_))),
+        ),
+        (
+            "broadcast fan-out",
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "broadcast", "msg_id": 5, "message": 42}}"#,
+            Box::new(|message: &Message| {
+                message.dest == "n2" && matches!(message.body, MessageBody::Broadcast(_))
+            }),
+        ),
+        (
+            "broadcast fan-in",
+            r#"{"src": "n2", "dest": "n1", "body": {"type": "broadcast", "msg_id": 1, "message": 43}}"#,
+            Box::new(|message: &Message| {
+                message.dest == "n2" && matches!(message.body, MessageBody::BroadcastOk(_))
+            }),
+        ),
+        (
+            "read",
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "read", "msg_id": 6}}"#,
+            Box::new(move |message: &Message| {
+                matches!(&message.body, MessageBody::ReadOk(body) if body.messages.as_ref().is_some_and(|messages| {
+                    let messages: HashSet<_> = messages.iter().collect();
+                    messages.contains(&value(42)) && messages.contains(&value(43))
+                }))
+            }),
+        ),
+    ]
+}
@@ -0,0 +1,154 @@
+//! Clients for Maelstrom's built-in services. Workloads can keep their state in a key-value
+//! service instead of replicating it themselves, as the Gossip Glomers challenges suggest for the
+//! g-counter.
+
+use serde_json::Value;
+
+use crate::actor::NodeHandle;
+use crate::core::message::{CasBody, ErrorCode, MessageBody, ReadBody, RemoteError, WriteBody};
+use crate::rpc::rpc;
+
+/// Maelstrom's key-value services, which differ only in their consistency.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KvService {
+    /// Sequentially consistent.
+    Seq,
+    /// Linearizable.
+    Lin,
+    /// Last-writer-wins: reads may be stale, and writes may be lost to concurrent ones.
+    Lww,
+}
+
+impl KvService {
+    /// The node id the service is addressed by.
+    pub fn address(&self) -> &'static str {
+        match self {
+            KvService::Seq => "seq-kv",
+            KvService::Lin => "lin-kv",
+            KvService::Lww => "lww-kv",
+        }
+    }
+}
+
+/// An async client for a key-value service, sending its requests as RPCs from `node`.
+#[derive(Clone, Debug)]
+pub struct KvClient {
+    node: NodeHandle,
+    service: KvService,
+}
+
+impl KvClient {
+    pub fn new(node: NodeHandle, service: KvService) -> Self {
+        KvClient { node, service }
+    }
+
+    /// The value of `key`. A missing key is a `KeyDoesNotExist` error.
+    pub async fn kv_read(&self, key: impl Into<Value>) -> Result<Value, RemoteError> {
+        let body = MessageBody::Read(ReadBody {
+            key: Some(key.into()),
+            msg_id: None,
+        });
+
+        match self.call(body).await? {
+            MessageBody::ReadOk(body) => Ok(body.value.unwrap_or_default()),
+            reply => Err(unexpected(&reply)),
+        }
+    }
+
+    pub async fn kv_write(
+        &self,
+        key: impl Into<Value>,
+        value: impl Into<Value>,
+    ) -> Result<(), RemoteError> {
+        let body = MessageBody::Write(WriteBody {
+            key: key.into(),
+            value: value.into(),
+            msg_id: None,
+        });
+
+        match self.call(body).await? {
+            MessageBody::WriteOk(_) => Ok(()),
+            reply => Err(unexpected(&reply)),
+        }
+    }
+
+    /// Set `key` to `to` if it's `from`. A different value is a `PreconditionFailed` error, and a
+    /// missing key a `KeyDoesNotExist` one unless `create_if_not_exists` is set.
+    pub async fn kv_cas(
+        &self,
+        key: impl Into<Value>,
+        from: impl Into<Value>,
+        to: impl Into<Value>,
+        create_if_not_exists: bool,
+    ) -> Result<(), RemoteError> {
+        let body = MessageBody::Cas(CasBody {
+            key: key.into(),
+            from: from.into(),
+            to: to.into(),
+            create_if_not_exists,
+            msg_id: None,
+        });
+
+        match self.call(body).await? {
+            MessageBody::CasOk(_) => Ok(()),
+            reply => Err(unexpected(&reply)),
+        }
+    }
+
+    async fn call(&self, body: MessageBody<'static>) -> Result<MessageBody<'static>, RemoteError> {
+        rpc(&self.node, self.service.address(), body)
+            .await
+            .map(|reply| reply.body)
+    }
+}
+
+fn unexpected(reply: &MessageBody<'_>) -> RemoteError {
+    RemoteError {
+        code: ErrorCode::MalformedRequest,
+        text: format!("Unexpected {} reply from the service.", reply.kind()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::message::Message;
+    use crate::node::Node;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn reads_from_the_service() {
+        let (outbound, mut sent) = mpsc::channel(1);
+        let node = NodeHandle::spawn(Node {
+            id: Some("n1".to_string()),
+            ..Default::default()
+        });
+        node.call(|node| node.outbox.open()).await.unwrap();
+        tokio::spawn(crate::outbox::drain(node.clone(), outbound));
+
+        let service = {
+            let node = node.clone();
+
+            tokio::spawn(async move {
+                let request = sent.recv().await.unwrap();
+                let request = serde_json::from_str::<Message>(&request).unwrap();
+                assert_eq!(request.dest, "seq-kv");
+
+                let reply = format!(
+                    r#"{{"src": "seq-kv", "dest": "n1", "body": {{"type": "read_ok", "value": 7, "in_reply_to": {}}}}}"#,
+                    request.body.msg_id().unwrap()
+                );
+                let reply = serde_json::from_str::<Message>(&reply)
+                    .unwrap()
+                    .into_owned();
+
+                node.call(move |node| node.dispatch(reply)).await.unwrap();
+            })
+        };
+
+        let value = KvClient::new(node, KvService::Seq).kv_read("counter").await;
+
+        service.await.unwrap();
+        assert_eq!(value.unwrap(), 7);
+    }
+}
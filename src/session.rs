@@ -0,0 +1,152 @@
+//! Session guarantees for the kv workload's `session` mode: a client reads its own writes, and
+//! never reads older entries than it has already read, even from a replica that's behind.
+//!
+//! A client's session version is the latest timestamp, per node that wrote them, of the entries
+//! it has written or read. A replica only serves a client's read once it has every entry the
+//! session depends on. Until then the read waits for replication, and after `Sessions::WAIT` it's
+//! forwarded to a node that wrote one of the missing entries.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::clock::HybridTimestamp;
+use crate::core::message::Message;
+
+/// The latest timestamp of the entries a session depends on, by the node that wrote them.
+pub type SessionVersion = BTreeMap<String, HybridTimestamp>;
+
+#[derive(Debug, Default)]
+pub struct Sessions {
+    versions: BTreeMap<String, SessionVersion>,
+    /// Each node's clock when it sent the entries this node last merged from it. Every entry it
+    /// wrote before then has been merged, or superseded.
+    replicated: HashMap<String, HybridTimestamp>,
+    waiting: Vec<(Instant, Message<'static>)>,
+}
+
+impl Sessions {
+    pub const WAIT: Duration = Duration::from_millis(1000);
+
+    /// Record that `client` wrote or read the entry written at `timestamp`.
+    pub fn observe(&mut self, client: &str, timestamp: &HybridTimestamp) {
+        let version = self.versions.entry(client.to_string()).or_default();
+
+        if version
+            .get(&timestamp.node_id)
+            .is_none_or(|latest| latest < timestamp)
+        {
+            version.insert(timestamp.node_id.clone(), timestamp.clone());
+        }
+    }
+
+    /// Record that the entries from `node_id` have been merged up to its clock `sent_at`.
+    pub fn replicated(&mut self, node_id: &str, sent_at: HybridTimestamp) {
+        let latest = self.replicated.entry(node_id.to_string()).or_default();
+
+        if *latest < sent_at {
+            *latest = sent_at;
+        }
+    }
+
+    pub fn versions(&self) -> &BTreeMap<String, SessionVersion> {
+        &self.versions
+    }
+
+    /// Merge the session versions another node has seen, so a client that moves to this node
+    /// keeps its guarantees once they've arrived.
+    pub fn merge(&mut self, versions: &BTreeMap<String, SessionVersion>) {
+        for (client, version) in versions {
+            for timestamp in version.values() {
+                self.observe(client, timestamp);
+            }
+        }
+    }
+
+    /// A node that wrote an entry `client`'s session depends on which this node, `me`, hasn't
+    /// merged yet.
+    pub fn missing(&self, me: &str, client: &str) -> Option<&String> {
+        self.versions
+            .get(client)?
+            .iter()
+            .find(|(node_id, timestamp)| {
+                *node_id != me
+                    && self
+                        .replicated
+                        .get(*node_id)
+                        .is_none_or(|latest| latest < *timestamp)
+            })
+            .map(|(node_id, _)| node_id)
+    }
+
+    /// Hold a read until this node has caught up with its client's session.
+    pub fn wait(&mut self, read: Message<'static>) {
+        self.waiting.push((Instant::now(), read));
+    }
+
+    /// The held reads whose sessions this node has now caught up with.
+    pub fn take_ready(&mut self, me: &str) -> Vec<Message<'static>> {
+        let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.waiting)
+            .into_iter()
+            .partition(|(_, read)| {
+                self.missing(me, read.src.as_deref().unwrap_or_default())
+                    .is_none()
+            });
+
+        self.waiting = waiting;
+
+        ready.into_iter().map(|(_, read)| read).collect()
+    }
+
+    /// The held reads that have waited longer than `WAIT`, with a node to forward each to.
+    pub fn take_expired(&mut self, me: &str) -> Vec<(Message<'static>, String)> {
+        let (expired, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.waiting)
+            .into_iter()
+            .partition(|(since, _)| since.elapsed() >= Self::WAIT);
+
+        self.waiting = waiting;
+
+        expired
+            .into_iter()
+            .filter_map(|(_, read)| {
+                let node_id = self
+                    .missing(me, read.src.as_deref().unwrap_or_default())?
+                    .clone();
+
+                Some((read, node_id))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(wall: u64, node_id: &str) -> HybridTimestamp {
+        HybridTimestamp {
+            wall,
+            logical: 0,
+            node_id: node_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn waits_for_the_entries_a_session_depends_on() {
+        let mut sessions = Sessions::default();
+
+        // The client wrote on n2, and read an entry n3 wrote.
+        sessions.observe("c1", &at(5, "n2"));
+        sessions.observe("c1", &at(3, "n3"));
+        sessions.observe("c1", &at(1, "n3"));
+
+        assert_eq!(sessions.missing("n2", "c2"), None);
+        assert_eq!(sessions.missing("n2", "c1").unwrap(), "n3");
+
+        sessions.replicated("n3", at(3, "n3"));
+        assert_eq!(sessions.missing("n2", "c1"), None);
+        assert_eq!(sessions.missing("n1", "c1").unwrap(), "n2");
+
+        sessions.replicated("n2", at(4, "n2"));
+        assert_eq!(sessions.missing("n1", "c1").unwrap(), "n2");
+    }
+}
@@ -0,0 +1,101 @@
+//! Partitioning of the keyspace across the nodes by consistent hashing. Each node is hashed to
+//! many points on a ring, and a key belongs to the first node point at or after the key's hash,
+//! so a node joining or leaving only moves the keys next to its points.
+
+use crate::tiebreak::stable_hash;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HashRing {
+    node_ids: Vec<String>,
+    /// Node points, sorted by hash.
+    points: Vec<(u64, String)>,
+}
+
+impl HashRing {
+    /// Points per node. More even out how many keys each node owns.
+    pub const VIRTUAL_NODES: usize = 64;
+
+    pub fn new(node_ids: &[String]) -> Self {
+        let mut points: Vec<(u64, String)> = node_ids
+            .iter()
+            .flat_map(|node_id| {
+                (0..Self::VIRTUAL_NODES)
+                    .map(move |point| (position(&[node_id, &point.to_string()]), node_id.clone()))
+            })
+            .collect();
+        points.sort();
+
+        HashRing {
+            node_ids: node_ids.to_vec(),
+            points,
+        }
+    }
+
+    /// Rebuild the ring if the cluster's nodes have changed.
+    pub fn update(&mut self, node_ids: &[String]) {
+        if self.node_ids != node_ids {
+            *self = HashRing::new(node_ids);
+        }
+    }
+
+    /// The node that owns `key`, or `None` on an empty ring.
+    pub fn owner(&self, key: &str) -> Option<&String> {
+        let hash = position(&[key]);
+        let index = self.points.partition_point(|(point, _)| *point < hash);
+
+        self.points
+            .get(index)
+            .or_else(|| self.points.first())
+            .map(|(_, node_id)| node_id)
+    }
+}
+
+/// Where the parts hash to on the ring. FNV-1a barely changes the high bits of short inputs,
+/// which would bunch similar keys together, so its hash is mixed with MurmurHash3's finalizer.
+fn position(parts: &[&str]) -> u64 {
+    let mut hash = stable_hash(parts);
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_moves_keys_to_a_joining_node() {
+        let node_ids = ["n1", "n2", "n3"].map(String::from).to_vec();
+        let ring = HashRing::new(&node_ids);
+
+        let mut grown = node_ids.clone();
+        grown.push("n4".to_string());
+        let grown = HashRing::new(&grown);
+
+        let keys: Vec<String> = (0..1000).map(|key| key.to_string()).collect();
+
+        for key in &keys {
+            let (before, after) = (ring.owner(key).unwrap(), grown.owner(key).unwrap());
+
+            assert!(
+                before == after || after == "n4",
+                "{key} moved from {before} to {after}"
+            );
+        }
+
+        // Every node owns a fair share.
+        for node_id in &node_ids {
+            let owned = keys
+                .iter()
+                .filter(|key| ring.owner(key) == Some(node_id))
+                .count();
+
+            assert!(owned > 200, "{node_id} owns {owned} keys");
+        }
+
+        assert_eq!(HashRing::default().owner("key"), None);
+    }
+}
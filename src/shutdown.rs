@@ -0,0 +1,138 @@
+use serde::Serialize;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tracing::{error, info};
+
+use crate::node::Node;
+
+/// How long a node keeps retrying and flushing gossip after stdin closes, before the tasks still
+/// running are cut off.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Drain {
+    pub timeout: Duration,
+}
+
+impl Default for Drain {
+    fn default() -> Self {
+        Drain {
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A gossip message no neighbor acknowledged.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Unacknowledged {
+    pub msg_id: u32,
+    pub dest: String,
+}
+
+/// The work a node left unfinished when it shut down, so scripts run after a test can tell a
+/// cluster that converged from one that was cut off.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ShutdownReport {
+    pub node_id: Option<String>,
+    /// Whether nothing was left unfinished.
+    pub clean: bool,
+    pub unacknowledged: Vec<Unacknowledged>,
+    /// Message ids of requests still waiting on a reply.
+    pub pending_rpcs: Vec<u32>,
+    /// Message ids of client replies held back in strict mode.
+    pub held_replies: Vec<u32>,
+    /// Broadcast values collected for a gossip batch that was never sent.
+    pub batched: usize,
+    /// Outbound messages that couldn't be written.
+    pub dropped: u64,
+}
+
+impl ShutdownReport {
+    pub fn capture(node: &Node) -> Self {
+        let mut unacknowledged: Vec<_> = node
+            .unacknowledged
+            .iter()
+            .map(|delivery| Unacknowledged {
+                msg_id: delivery.id,
+                dest: delivery.message.dest.clone(),
+            })
+            .collect();
+        unacknowledged.sort_by_key(|message| message.msg_id);
+
+        let mut pending_rpcs: Vec<_> = node.correlations.keys().copied().collect();
+        pending_rpcs.sort();
+
+        #[cfg(feature = "broadcast")]
+        let (mut held_replies, batched): (Vec<_>, _) = (
+            node.broadcast.held_replies.keys().copied().collect(),
+            node.broadcast.gossip_batch.len(),
+        );
+        #[cfg(not(feature = "broadcast"))]
+        let (mut held_replies, batched) = (Vec::new(), 0);
+        held_replies.sort();
+
+        let dropped = node.metrics.dropped.load(Ordering::Relaxed);
+
+        ShutdownReport {
+            node_id: node.id.clone(),
+            clean: unacknowledged.is_empty()
+                && pending_rpcs.is_empty()
+                && held_replies.is_empty()
+                && batched == 0
+                && dropped == 0,
+            unacknowledged,
+            pending_rpcs,
+            held_replies,
+            batched,
+            dropped,
+        }
+    }
+
+    /// Write the report as a line of JSON to `path`, or to stderr, prefixed with `shutdown `,
+    /// without one.
+    pub fn emit(&self, path: Option<&Path>) {
+        let json = serde_json::to_string(self).expect("Couldn't serialize the shutdown report.");
+
+        match path {
+            Some(path) => {
+                if let Err(err) = std::fs::write(path, json + "\n") {
+                    error!(
+                        "Couldn't write the shutdown report to {}: {}",
+                        path.display(),
+                        err
+                    );
+                }
+            }
+            None => info!("shutdown {}", json),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "broadcast"))]
+mod test {
+    use super::*;
+    use crate::core::message::Message;
+
+    #[test]
+    fn lists_unfinished_work() {
+        let mut node = Node::default();
+
+        assert!(ShutdownReport::capture(&node).clean);
+
+        let gossip: Message = serde_json::from_str(
+            r#"{"src": "n1", "dest": "n2", "body": {"type": "broadcast", "message": 1, "msg_id": 3}}"#,
+        )
+        .unwrap();
+        node.unacknowledged.insert(gossip);
+
+        let report = ShutdownReport::capture(&node);
+
+        assert!(!report.clean);
+        assert_eq!(
+            report.unacknowledged,
+            vec![Unacknowledged {
+                msg_id: 3,
+                dest: "n2".to_string()
+            }]
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! A deterministic, in-process network for tests. Nodes are driven through `Node::dispatch` on a
+//! virtual clock, and every delay, drop, and partition comes from a seeded generator, so a run
+//! that fails can be replayed exactly from its seed.
+
+use std::collections::{BTreeMap, HashSet};
+
+use crate::core::message::Message;
+use crate::node::Node;
+
+/// SplitMix64: small, fast, and the same sequence for a seed on every platform.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        match n {
+            0 => 0,
+            n => self.next_u64() % n,
+        }
+    }
+
+    /// True with probability `p`.
+    pub fn chance(&mut self, p: f64) -> bool {
+        (self.next_u64() as f64 / u64::MAX as f64) < p
+    }
+}
+
+/// Cuts `nodes` off from the rest of the cluster from tick `from` until tick `until`.
+#[derive(Clone, Debug)]
+pub struct Partition {
+    pub from: u64,
+    pub until: u64,
+    pub nodes: HashSet<String>,
+}
+
+impl Partition {
+    fn separates(&self, now: u64, a: &str, b: &str) -> bool {
+        (self.from..self.until).contains(&now) && self.nodes.contains(a) != self.nodes.contains(b)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    pub seed: u64,
+    /// Every message between nodes takes between one and this many ticks to arrive.
+    pub max_delay: u64,
+    /// The chance a message between nodes is lost.
+    pub drop_rate: f64,
+    pub partitions: Vec<Partition>,
+    /// How often nodes resend what hasn't been acknowledged, in ticks.
+    pub retry_every: u64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            seed: 0,
+            max_delay: 5,
+            drop_rate: 0.0,
+            partitions: vec![],
+            retry_every: 20,
+        }
+    }
+}
+
+pub struct Simulation {
+    pub nodes: BTreeMap<String, Node>,
+    /// Replies to anything that isn't a node, in the order they were sent.
+    pub client_replies: Vec<Message<'static>>,
+    pub dropped: u64,
+    pub now: u64,
+    config: SimulationConfig,
+    rng: Rng,
+    /// Messages on their way, by delivery tick and then send order.
+    in_flight: BTreeMap<(u64, u64), Message<'static>>,
+    sent: u64,
+    next_retry: u64,
+}
+
+impl Simulation {
+    /// A cluster of default nodes, already initialized.
+    pub fn new(config: SimulationConfig, node_ids: &[&str]) -> Self {
+        let node_ids: Vec<String> = node_ids.iter().map(|node_id| node_id.to_string()).collect();
+
+        let nodes = node_ids
+            .iter()
+            .map(|node_id| {
+                let mut node = Node {
+                    id: Some(node_id.clone()),
+                    node_ids: node_ids.clone(),
+                    ..Default::default()
+                };
+
+                // Advance past `init`'s message id, as if it had been received.
+                node.next_message_id();
+
+                (node_id.clone(), node)
+            })
+            .collect();
+
+        Simulation {
+            nodes,
+            client_replies: vec![],
+            dropped: 0,
+            now: 0,
+            rng: Rng::new(config.seed),
+            next_retry: config.retry_every,
+            config,
+            in_flight: BTreeMap::new(),
+            sent: 0,
+        }
+    }
+
+    /// Send a message from outside the cluster. Clients' messages are delayed, but never lost.
+    pub fn send(&mut self, message: Message<'static>) {
+        let delay = 1 + self.rng.below(self.config.max_delay);
+
+        self.schedule(self.now + delay, message);
+    }
+
+    fn schedule(&mut self, at: u64, message: Message<'static>) {
+        self.sent += 1;
+        self.in_flight.insert((at, self.sent), message);
+    }
+
+    /// Send a node's output on its way, subject to drops and partitions.
+    fn route(&mut self, message: Message<'static>) {
+        if !self.nodes.contains_key(&message.dest) {
+            self.client_replies.push(message);
+            return;
+        }
+
+        let src = message.src.clone().unwrap_or_default();
+        let partitioned = self
+            .config
+            .partitions
+            .iter()
+            .any(|partition| partition.separates(self.now, &src, &message.dest));
+
+        if partitioned || self.rng.chance(self.config.drop_rate) {
+            self.dropped += 1;
+            return;
+        }
+
+        let delay = 1 + self.rng.below(self.config.max_delay);
+
+        self.schedule(self.now + delay, message);
+    }
+
+    /// Resend every node's unacknowledged messages, in id order, each with a fresh msg_id.
+    fn retry(&mut self) {
+        let mut resend = vec![];
+
+        for node in self.nodes.values_mut() {
+            let mut pending = node.unacknowledged.ids();
+            pending.sort();
+
+            for id in pending {
+                let msg_id = node.next_message_id();
+                resend.extend(node.unacknowledged.attempt(id, msg_id));
+            }
+        }
+
+        resend.into_iter().for_each(|message| self.route(message));
+    }
+
+    fn is_settled(&self) -> bool {
+        self.in_flight.is_empty()
+            && self
+                .nodes
+                .values()
+                .all(|node| node.unacknowledged.is_empty())
+    }
+
+    /// Deliver the next message, retrying on schedule along the way. Returns false once nothing
+    /// is in flight or waiting on an acknowledgement.
+    pub fn step(&mut self) -> bool {
+        if self.is_settled() {
+            return false;
+        }
+
+        let next = self.in_flight.keys().next().map(|(at, _)| *at);
+
+        if next.is_none_or(|at| at >= self.next_retry) {
+            self.now = self.next_retry;
+            self.next_retry += self.config.retry_every.max(1);
+            self.retry();
+
+            return true;
+        }
+
+        let Some(((at, _), message)) = self.in_flight.pop_first() else {
+            return true;
+        };
+
+        self.now = at;
+
+        let node = self
+            .nodes
+            .get_mut(&message.dest)
+            .expect("Only messages for nodes are scheduled.");
+
+        for output in node.dispatch(message) {
+            self.route(output);
+        }
+
+        true
+    }
+
+    /// Step until the cluster settles or `max_ticks` pass. Returns whether it settled.
+    pub fn run(&mut self, max_ticks: u64) -> bool {
+        while self.now < max_ticks {
+            if !self.step() {
+                return true;
+            }
+        }
+
+        self.is_settled()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replays_the_same_run_from_a_seed() {
+        let run = |seed| {
+            let mut rng = Rng::new(seed);
+
+            (0..5).map(|_| rng.below(100)).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+}
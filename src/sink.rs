@@ -0,0 +1,83 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::log::{Record, Sink};
+
+/// Mirrors committed kafka records to a file, one JSON object with `key`, `offset`, and `msg` per
+/// line. The file is appended to, so a restarted node carries on where it left off, and each
+/// commit is synced to disk before it's acknowledged.
+#[derive(Debug)]
+pub struct NdjsonSink {
+    file: BufWriter<File>,
+}
+
+impl NdjsonSink {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(NdjsonSink {
+            file: BufWriter::new(file),
+        })
+    }
+}
+
+impl Sink for NdjsonSink {
+    fn mirror(&mut self, records: &[Record]) -> io::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        for record in records {
+            serde_json::to_writer(&mut self.file, record)?;
+            self.file.write_all(b"\n")?;
+        }
+
+        self.file.flush()?;
+        self.file.get_ref().sync_data()
+    }
+}
+
+#[cfg(all(test, feature = "runtime"))]
+mod test {
+    use super::*;
+    use crate::testing::NodeTestFixture;
+    use serde_json::json;
+
+    #[test]
+    fn mirrors_records_once_they_are_committed() {
+        let path = std::env::temp_dir().join(format!("tranquility-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut fixture = NodeTestFixture::new().init("n1", ["n1"]);
+        fixture.node.kafka.sink = Some(Box::new(NdjsonSink::open(&path).unwrap()));
+
+        fixture
+            .send(json!({"type": "send", "key": "k1", "msg": 10}))
+            .expect_reply("send_ok")
+            .send(json!({"type": "send", "key": "k1", "msg": 11}))
+            .expect_reply("send_ok")
+            .send(json!({"type": "send", "key": "k2", "msg": 20}))
+            .expect_reply("send_ok")
+            .send(json!({"type": "commit_offsets", "offsets": {"k1": 0, "k2": 0}}))
+            .expect_reply("commit_offsets_ok")
+            .send(json!({"type": "commit_offsets", "offsets": {"k1": 1}}))
+            .expect_reply("commit_offsets_ok");
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(
+            lines,
+            vec![
+                json!({"key": "k1", "offset": 0, "msg": 10}),
+                json!({"key": "k2", "offset": 0, "msg": 20}),
+                json!({"key": "k1", "offset": 1, "msg": 11}),
+            ]
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use std::time::{Duration, SystemTime};
+
+/// How a Snowflake ID's bits are split between its timestamp, node index, and sequence, most
+/// significant first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub timestamp_bits: u32,
+    pub node_bits: u32,
+    pub sequence_bits: u32,
+}
+
+impl Layout {
+    /// 64 bits: milliseconds for roughly 69 years, 1024 nodes, and 4096 IDs per millisecond.
+    pub const WIDE: Layout = Layout {
+        timestamp_bits: 41,
+        node_bits: 10,
+        sequence_bits: 12,
+    };
+
+    /// 53 bits, so every ID is exactly representable as a double: milliseconds for roughly 17
+    /// years, 64 nodes, and 256 IDs per millisecond.
+    pub const SAFE: Layout = Layout {
+        timestamp_bits: 39,
+        node_bits: 6,
+        sequence_bits: 8,
+    };
+
+    pub fn max_nodes(&self) -> u64 {
+        1 << self.node_bits
+    }
+
+    /// The milliseconds since `Snowflake::EPOCH` encoded in `id`.
+    pub fn millis(&self, id: u64) -> u64 {
+        id >> (self.node_bits + self.sequence_bits)
+    }
+
+    /// The node index encoded in `id`.
+    pub fn node_index(&self, id: u64) -> u64 {
+        (id >> self.sequence_bits) & (self.max_nodes() - 1)
+    }
+
+    /// The sequence encoded in `id`.
+    pub fn sequence(&self, id: u64) -> u64 {
+        id & ((1 << self.sequence_bits) - 1)
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::WIDE
+    }
+}
+
+/// Generates IDs laid out as `timestamp | node index | sequence`, per its `Layout`. IDs from one
+/// node are strictly increasing, and two nodes never generate the same ID as long as their
+/// indexes differ.
+#[derive(Debug, Default)]
+pub struct Snowflake {
+    layout: Layout,
+    last_millis: u64,
+    sequence: u64,
+}
+
+impl Snowflake {
+    /// 2024-01-01T00:00:00Z, in milliseconds since the Unix epoch.
+    pub const EPOCH: u64 = 1_704_067_200_000;
+
+    pub fn new(layout: Layout) -> Self {
+        Snowflake {
+            layout,
+            ..Snowflake::default()
+        }
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// The next ID for the node at `node_index`, or `None` when the index doesn't fit.
+    ///
+    /// Timestamps never go backwards, even when the clock does: the last one is reused instead.
+    /// Once a millisecond's sequence is exhausted, the next millisecond is borrowed rather than
+    /// waited for.
+    pub fn next(&mut self, node_index: u64) -> Option<u64> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_millis() as u64;
+
+        self.next_at(now, node_index)
+    }
+
+    /// The next ID, with the clock reading `now` milliseconds since the Unix epoch.
+    pub fn next_at(&mut self, now: u64, node_index: u64) -> Option<u64> {
+        let layout = self.layout;
+
+        if node_index >= layout.max_nodes() {
+            return None;
+        }
+
+        let millis = now.saturating_sub(Snowflake::EPOCH);
+
+        if millis > self.last_millis {
+            self.last_millis = millis;
+            self.sequence = 0;
+        } else if self.sequence + 1 < 1 << layout.sequence_bits {
+            self.sequence += 1;
+        } else {
+            self.last_millis += 1;
+            self.sequence = 0;
+        }
+
+        let timestamp = self.last_millis & ((1 << layout.timestamp_bits) - 1);
+
+        Some(
+            timestamp << (layout.node_bits + layout.sequence_bits)
+                | node_index << layout.sequence_bits
+                | self.sequence,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn generates_increasing_ids_past_the_sequence_limit() {
+        let mut snowflake = Snowflake::default();
+
+        let ids: Vec<u64> = (0..10_000).map(|_| snowflake.next(3).unwrap()).collect();
+
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids.iter().all(|id| Layout::WIDE.node_index(*id) == 3));
+        assert_eq!(snowflake.next(Layout::WIDE.max_nodes()), None);
+    }
+
+    #[test]
+    fn keeps_safe_ids_within_53_bits() {
+        let mut snowflake = Snowflake::new(Layout::SAFE);
+        let now = Snowflake::EPOCH + (1 << 38);
+
+        let ids: Vec<u64> = (0..1_000)
+            .map(|_| snowflake.next_at(now, 63).unwrap())
+            .collect();
+
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids.iter().all(|id| *id < 1 << 53));
+        assert!(ids.iter().all(|id| Layout::SAFE.node_index(*id) == 63));
+        assert_eq!(Layout::SAFE.millis(ids[0]), 1 << 38);
+        assert_eq!(snowflake.next(64), None);
+    }
+
+    #[test]
+    fn never_collides_across_nodes() {
+        let mut first = Snowflake::default();
+        let mut second = Snowflake::default();
+
+        let ids: HashSet<u64> = (0..1_000)
+            .flat_map(|_| [first.next(0).unwrap(), second.next(1).unwrap()])
+            .collect();
+
+        assert_eq!(ids.len(), 2_000);
+    }
+
+    #[test]
+    fn keeps_increasing_when_the_clock_goes_backwards() {
+        let mut snowflake = Snowflake::default();
+        let now = Snowflake::EPOCH + 1_000;
+
+        let ids: Vec<u64> = [now, now, now - 500, now - 1, now, now + 1]
+            .into_iter()
+            .map(|now| snowflake.next_at(now, 2).unwrap())
+            .collect();
+
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
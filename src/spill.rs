@@ -0,0 +1,201 @@
+use std::collections::hash_map::{Entry, RandomState};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::hash::BuildHasher;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::core::message::BroadcastValue;
+use crate::core::state::{Overflow, SpillReader};
+
+static SEGMENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// An append-only file of broadcast values, written one JSON value per line. The file is removed
+/// when the segment is dropped; it only exists to keep a long run's values out of memory.
+///
+/// Lookups go through an in-memory index of each value's fingerprint and line offset, so only
+/// values with a matching fingerprint are read back.
+#[derive(Debug)]
+pub struct SpillSegment {
+    path: PathBuf,
+    file: File,
+    /// A second handle for lookups, so seeking doesn't move the write position.
+    reader: File,
+    len: usize,
+    /// The end of the last complete append.
+    end: u64,
+    hasher: RandomState,
+    index: HashMap<u64, u64>,
+    /// Offsets of values whose fingerprint was already in `index`; almost always empty.
+    collisions: HashMap<u64, Vec<u64>>,
+}
+
+impl SpillSegment {
+    pub fn create(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(format!(
+            "tranquility-{}-{}.spill",
+            std::process::id(),
+            SEGMENTS.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(&path)?;
+        let reader = File::open(&path)?;
+
+        Ok(SpillSegment {
+            path,
+            file,
+            reader,
+            len: 0,
+            end: 0,
+            hasher: RandomState::new(),
+            index: HashMap::new(),
+            collisions: HashMap::new(),
+        })
+    }
+
+    fn read_at(&self, offset: u64) -> io::Result<BroadcastValue> {
+        let mut reader = BufReader::new(&self.reader);
+        let mut line = String::new();
+
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_line(&mut line)?;
+
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+impl Overflow for SpillSegment {
+    fn append(&mut self, messages: &HashSet<BroadcastValue>) -> io::Result<()> {
+        // Drop whatever a failed append left behind, so every line up to the end is complete.
+        self.file.set_len(self.end)?;
+        (&self.file).seek(SeekFrom::Start(self.end))?;
+
+        let mut writer = BufWriter::new(&self.file);
+        let mut offsets = Vec::with_capacity(messages.len());
+        let mut end = self.end;
+
+        for message in messages {
+            let line = serde_json::to_vec(message)?;
+
+            writer.write_all(&line)?;
+            writer.write_all(b"\n")?;
+            offsets.push((self.hasher.hash_one(message), end));
+            end += line.len() as u64 + 1;
+        }
+
+        writer.flush()?;
+
+        for (fingerprint, offset) in offsets {
+            match self.index.entry(fingerprint) {
+                Entry::Vacant(entry) => {
+                    entry.insert(offset);
+                }
+                Entry::Occupied(_) => self.collisions.entry(fingerprint).or_default().push(offset),
+            }
+        }
+
+        self.len += messages.len();
+        self.end = end;
+
+        Ok(())
+    }
+
+    fn contains(&self, message: &BroadcastValue) -> io::Result<bool> {
+        let fingerprint = self.hasher.hash_one(message);
+        let offsets = self
+            .index
+            .get(&fingerprint)
+            .into_iter()
+            .chain(self.collisions.get(&fingerprint).into_iter().flatten());
+
+        for &offset in offsets {
+            if self.read_at(offset)? == *message {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn reader(&self) -> SpillReader {
+        let (path, end) = (self.path.clone(), self.end);
+
+        Arc::new(move || {
+            let reader = BufReader::new(File::open(&path)?).take(end);
+
+            Ok(Box::new(
+                reader.lines().map(|line| Ok(serde_json::from_str(&line?)?)),
+            ))
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Drop for SpillSegment {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::state::BroadcastStore;
+
+    #[test]
+    fn spills_values_past_the_limit_to_disk() {
+        let segment = SpillSegment::create(&std::env::temp_dir()).unwrap();
+        let path = segment.path.clone();
+        let mut store = BroadcastStore::with_overflow(2, Box::new(segment));
+
+        for message in 1..=5 {
+            assert!(store.insert(message.into()));
+        }
+
+        assert!(!store.insert(1.into()));
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.len_in_memory(), 2);
+        assert_eq!(
+            store.values().iter().collect::<HashSet<_>>(),
+            (1..=5).map(BroadcastValue::from).collect::<HashSet<_>>()
+        );
+
+        drop(store);
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn streams_the_values_spilled_before_a_read() {
+        let segment = SpillSegment::create(&std::env::temp_dir()).unwrap();
+        let mut store = BroadcastStore::with_overflow(2, Box::new(segment));
+
+        for message in 1..=6 {
+            store.insert(message.into());
+        }
+
+        let values = store.values();
+
+        for message in 7..=9 {
+            store.insert(message.into());
+        }
+
+        assert!((1..=9).all(|message| !store.insert(message.into())));
+
+        let mut read: Vec<u32> =
+            serde_json::from_value(serde_json::to_value(&values).unwrap()).unwrap();
+        read.sort();
+
+        assert_eq!(read, (1..=6).collect::<Vec<_>>());
+    }
+}
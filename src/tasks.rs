@@ -0,0 +1,202 @@
+//! Work the node does that waits on other nodes, e.g. gossip waiting for an acknowledgement, as
+//! futures the node polls itself. A task reaches the node through a `NodeHandle` whose commands
+//! the node runs along with the task, so a task can `rpc().await` like any other caller.
+//!
+//! Tasks make progress wherever the node does: `Node::dispatch` runs them before it returns, so
+//! a node driven without a runtime, as in the tests and the simulation, runs them too. A node in
+//! its own task is also woken to run them when they're woken from elsewhere, e.g. by an RPC permit
+//! freed on another thread.
+
+use futures_util::future::{poll_fn, BoxFuture};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use std::fmt;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use tokio::sync::mpsc;
+use tokio::task;
+
+use crate::actor::{self, Command, NodeHandle};
+use crate::node::Node;
+
+pub struct Tasks {
+    handle: NodeHandle,
+    commands: mpsc::UnboundedReceiver<Command>,
+    running: FuturesUnordered<BoxFuture<'static, ()>>,
+    wake: Arc<Wakeup>,
+}
+
+/// Whether a task was woken since the node last polled them, and how to wake the node's task.
+#[derive(Default)]
+struct Wakeup {
+    woken: AtomicBool,
+    polling: AtomicBool,
+    /// The node's own task, once it has one. Weak, so a waiting task doesn't keep it running.
+    actor: Mutex<Option<mpsc::WeakUnboundedSender<Command>>>,
+}
+
+impl Wake for Wakeup {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::SeqCst);
+
+        // While polling, the node notices the wake itself.
+        if self.polling.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let actor = self.actor.lock().unwrap();
+
+        if let Some(commands) = actor.as_ref().and_then(|actor| actor.upgrade()) {
+            // The node runs its tasks after every command.
+            let _ = commands.send(Box::new(|_| {}));
+        }
+    }
+}
+
+/// Clears `polling` however polling ends, panics included.
+struct Polling(Arc<Wakeup>);
+
+impl Drop for Polling {
+    fn drop(&mut self) {
+        self.0.polling.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Default for Tasks {
+    fn default() -> Self {
+        let (handle, commands) = actor::channel();
+
+        Tasks {
+            handle,
+            commands,
+            running: FuturesUnordered::new(),
+            wake: Arc::default(),
+        }
+    }
+}
+
+impl fmt::Debug for Tasks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tasks")
+            .field("running", &self.running.len())
+            .finish()
+    }
+}
+
+impl Tasks {
+    /// A handle for tasks to reach the node through.
+    pub fn handle(&self) -> NodeHandle {
+        self.handle.clone()
+    }
+
+    pub fn spawn(&mut self, task: impl Future<Output = ()> + Send + 'static) {
+        self.running.push(Box::pin(task));
+        self.wake.woken.store(true, Ordering::SeqCst);
+    }
+
+    /// Wake the node's task, through `commands`, when a task is woken from elsewhere.
+    pub(crate) fn attach(&self, commands: mpsc::WeakUnboundedSender<Command>) {
+        *self.wake.actor.lock().unwrap() = Some(commands);
+    }
+
+    pub fn len(&self) -> usize {
+        self.running.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+
+    /// Poll the tasks and run the commands they send until none can make progress.
+    pub fn run(node: &mut Node) {
+        let wake = node.tasks.wake.clone();
+        wake.polling.store(true, Ordering::SeqCst);
+        let _polling = Polling(wake.clone());
+
+        let waker = Waker::from(wake.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            let woken = wake.woken.swap(false, Ordering::SeqCst);
+            let mut ran = false;
+
+            while let Ok(command) = node.tasks.commands.try_recv() {
+                command(node);
+                ran = true;
+            }
+
+            if !ran && !woken {
+                break;
+            }
+
+            // Once the node's task has spent tokio's cooperative budget, what the tasks wait on
+            // wakes them straight away instead of making progress, so polling would never stop.
+            let running = &mut node.tasks.running;
+            let poll = pin!(task::unconstrained(poll_fn(|cx| {
+                while let Poll::Ready(Some(())) = running.poll_next_unpin(cx) {}
+                Poll::Ready(())
+            })));
+            let _ = poll.poll(&mut cx);
+        }
+
+        wake.polling.store(false, Ordering::SeqCst);
+
+        // A wake that came as polling stopped may not have reached the node's task.
+        if wake.woken.load(Ordering::SeqCst) {
+            Waker::from(wake).wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn runs_a_task_woken_from_elsewhere() {
+        let node = NodeHandle::spawn(Node::default());
+        let (tx, rx) = oneshot::channel();
+        let (done_tx, done) = oneshot::channel();
+
+        node.call(move |node| {
+            let handle = node.handle();
+
+            node.spawn(async move {
+                let value: u32 = rx.await.unwrap();
+                let msg_id = handle.call(|node| node.next_message_id()).await;
+
+                let _ = done_tx.send((value, msg_id));
+            });
+        })
+        .await
+        .unwrap();
+
+        tx.send(7).unwrap();
+
+        assert_eq!(done.await.unwrap(), (7, Ok(1)));
+    }
+
+    /// Gossip to peers that never answer leaves tasks waiting on RPC permits, which a node
+    /// that has spent its cooperative budget would otherwise poll forever, hanging the test.
+    #[cfg(feature = "broadcast")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn keeps_handling_messages_while_tasks_wait_on_permits() {
+        let node = NodeHandle::spawn(Node::default());
+        let init = r#"{"src": "c0", "dest": "n1", "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}}"#;
+        Node::handle_from_stdin(node.clone(), init).await.unwrap();
+
+        for i in 0..200 {
+            let line = format!(
+                r#"{{"src": "c1", "dest": "n1", "body": {{"type": "broadcast", "message": {i}, "msg_id": {i}}}}}"#
+            );
+            Node::handle_from_stdin(node.clone(), &line).await.unwrap();
+        }
+    }
+}
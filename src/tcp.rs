@@ -0,0 +1,613 @@
+//! The node as a standalone service: the same newline-delimited JSON as on stdin/stdout, over TCP.
+//!
+//! The node's transport is the set of its connections. Replies go back over the connection the
+//! recipient last sent from. A message for a node that hasn't connected is sent over a
+//! connection the node dials itself when its id is a `host:port`
+//! address, e.g. an id found through DNS discovery. Dialed connections are made on the first
+//! message, redialed with backoff when they fail, and hold a bounded buffer of lines meanwhile.
+//!
+//! With `Tls`, connections are accepted and dialed over TLS; see the `tls` module.
+
+use futures_util::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedSender, WeakSender};
+use tokio_util::codec::{FramedRead, LinesCodec};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::core::state;
+use crate::retry::RetryPolicy;
+use crate::tls::Tls;
+use crate::transport::{self, LineTransport};
+
+/// A connection's bytes, over TCP or over TLS.
+trait ByteStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> ByteStream for T {}
+
+/// The connection to write each node or client's messages to, by id.
+#[derive(Clone, Default)]
+struct Routes(Arc<Mutex<HashMap<String, UnboundedSender<String>>>>);
+
+impl Routes {
+    fn get(&self, id: &str) -> Option<UnboundedSender<String>> {
+        self.0.lock().unwrap().get(id).cloned()
+    }
+
+    fn insert(&self, id: String, connection: UnboundedSender<String>) {
+        self.0.lock().unwrap().insert(id, connection);
+    }
+
+    fn remove(&self, id: &str) {
+        self.0.lock().unwrap().remove(id);
+    }
+}
+
+/// How the node dials peers: failed dials and writes are retried after `backoff`'s delays, with
+/// up to `buffer` lines for the peer held until they can be written. Lines past the buffer are
+/// dropped, as they would be by a partition.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dialing {
+    pub backoff: RetryPolicy,
+    pub buffer: usize,
+}
+
+impl Default for Dialing {
+    fn default() -> Self {
+        Dialing {
+            backoff: RetryPolicy {
+                initial: Duration::from_millis(100),
+                max_delay: Duration::from_millis(5000),
+                ..Default::default()
+            },
+            buffer: 1024,
+        }
+    }
+}
+
+/// Accept connections and feed every line read to `tx`, and write every line from `response_rx`
+/// to the connection for its `dest`, dialed as `dialing` says, until `shutdown` is cancelled.
+/// With `tls`, connections are accepted and dialed over TLS.
+///
+/// Connections only hold `tx` weakly, so the node sees its input close once this returns, while
+/// the node's remaining output is still delivered.
+pub async fn serve(
+    listener: TcpListener,
+    tx: Sender<String>,
+    response_rx: Receiver<String>,
+    dialing: Dialing,
+    tls: Option<Tls>,
+    shutdown: CancellationToken,
+) -> io::Result<()> {
+    info!("Listening on {}", listener.local_addr()?);
+
+    let routes = Routes::default();
+    let peers = Peers::new(routes.clone(), tx.downgrade(), dialing, tls.clone());
+    let router = tokio::spawn(transport::forward(response_rx, peers));
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    info!("Accepted a connection from {}", peer);
+
+                    match &tls {
+                        Some(tls) => accept(tls.clone(), stream, peer, routes.clone(), tx.downgrade()),
+                        None => {
+                            connect(Box::new(stream), peer, routes.clone(), tx.downgrade());
+                        }
+                    }
+                }
+                Err(err) => warn!("Unable to accept a connection: {:?}", err),
+            },
+            _ = shutdown.cancelled() => break,
+        }
+    }
+
+    drop(tx);
+    let _ = router.await;
+
+    Ok(())
+}
+
+/// The node's connections, as its transport: each line goes to its `dest` over the connection
+/// `dest` last sent from, or else over one dialed to it when it's a `host:port` address.
+struct Peers {
+    routes: Routes,
+    dialed: HashMap<String, Sender<String>>,
+    dialing: Dialing,
+    tls: Option<Tls>,
+    /// Where dialed connections feed the lines they read.
+    inbound: WeakSender<String>,
+}
+
+impl Peers {
+    fn new(
+        routes: Routes,
+        inbound: WeakSender<String>,
+        dialing: Dialing,
+        tls: Option<Tls>,
+    ) -> Self {
+        Peers {
+            routes,
+            dialed: HashMap::new(),
+            dialing,
+            tls,
+            inbound,
+        }
+    }
+}
+
+/// Lines that can't be delivered are dropped, as they would be by a partition, so sending never
+/// fails.
+impl transport::Transport for Peers {
+    async fn send(&mut self, dest: &str, line: String) -> io::Result<()> {
+        let line = match self.routes.get(dest) {
+            Some(connection) => match connection.send(line) {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    info!("The connection from {} closed.", dest);
+                    self.routes.remove(dest);
+                    err.0
+                }
+            },
+            None => line,
+        };
+
+        let Ok(address) = dest.parse::<SocketAddr>() else {
+            warn!("No connection to {}; dropping {:?}.", dest, line);
+            return Ok(());
+        };
+
+        let peer = self.dialed.entry(dest.to_string()).or_insert_with(|| {
+            let tls = self.tls.clone();
+            dial(
+                dest.to_string(),
+                address,
+                self.dialing.clone(),
+                tls,
+                self.inbound.clone(),
+            )
+        });
+
+        match peer.try_send(line) {
+            Ok(()) => {}
+            Err(TrySendError::Full(line)) => {
+                warn!("The buffer for {} is full; dropping {:?}.", dest, line);
+            }
+            Err(TrySendError::Closed(line)) => {
+                warn!("The connection to {} closed; dropping {:?}.", dest, line);
+                self.dialed.remove(dest);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Spawn the task writing to a peer the node dials, returning where to send its lines. The peer
+/// is dialed once there's a line for it, and redialed with backoff whenever dialing or writing
+/// fails, with the line that failed written first once it's back.
+fn dial(
+    dest: String,
+    address: SocketAddr,
+    dialing: Dialing,
+    tls: Option<Tls>,
+    inbound: WeakSender<String>,
+) -> Sender<String> {
+    let (connection, mut outbound) = mpsc::channel::<String>(dialing.buffer.max(1));
+    let span = info_span!("peer", peer = %dest);
+
+    tokio::spawn(
+        async move {
+            let mut pending = None;
+            let mut failures = 0;
+
+            loop {
+                let Some(mut line) = (match pending.take() {
+                    Some(line) => Some(line),
+                    None => outbound.recv().await,
+                }) else {
+                    return;
+                };
+
+                let stream = match open(address, tls.as_ref()).await {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        warn!("Unable to connect to {}: {:?}", dest, err);
+                        tokio::time::sleep(dialing.backoff.delay(failures)).await;
+
+                        failures += 1;
+                        pending = Some(line);
+                        continue;
+                    }
+                };
+
+                let (reader, writer) = tokio::io::split(stream);
+                let (lines, mut writer) = LineTransport::new(reader, writer).split();
+                let reading = tokio::spawn(read(lines, None, inbound.clone()).in_current_span());
+
+                failures = 0;
+
+                loop {
+                    if let Err(err) = writer.send(line.clone()).await {
+                        warn!("Unable to write to {}; redialing: {:?}", dest, err);
+                        pending = Some(line);
+                        break;
+                    }
+
+                    match outbound.recv().await {
+                        Some(next) => line = next,
+                        None => {
+                            reading.abort();
+                            return;
+                        }
+                    }
+                }
+
+                reading.abort();
+            }
+        }
+        .instrument(span),
+    );
+
+    connection
+}
+
+/// Dial `address`, completing the TLS handshake over the connection with `tls`.
+async fn open(address: SocketAddr, tls: Option<&Tls>) -> io::Result<Box<dyn ByteStream>> {
+    let stream = TcpStream::connect(address).await?;
+
+    Ok(match tls {
+        Some(tls) => Box::new(tls.connect(address, stream).await?),
+        None => Box::new(stream),
+    })
+}
+
+/// Spawn the task completing the TLS handshake with a peer that connected, then reading and
+/// writing its connection. The handshake doesn't hold up accepting other connections.
+fn accept(
+    tls: Tls,
+    stream: TcpStream,
+    peer: SocketAddr,
+    routes: Routes,
+    inbound: WeakSender<String>,
+) {
+    tokio::spawn(async move {
+        match tls.accept(stream).await {
+            Ok(stream) => {
+                connect(Box::new(stream), peer, routes, inbound);
+            }
+            Err(err) => warn!(
+                "Unable to complete the TLS handshake with {}: {:?}",
+                peer, err
+            ),
+        }
+    });
+}
+
+/// Spawn the tasks reading and writing an accepted connection, returning where to send its
+/// lines.
+fn connect(
+    stream: Box<dyn ByteStream>,
+    peer: SocketAddr,
+    routes: Routes,
+    inbound: WeakSender<String>,
+) -> UnboundedSender<String> {
+    let (reader, writer) = tokio::io::split(stream);
+    let (lines, mut writer) = LineTransport::new(reader, writer).split();
+    let (connection, mut outbound) = mpsc::unbounded_channel::<String>();
+    let span = info_span!("peer", peer = %peer);
+
+    tokio::spawn(
+        async move {
+            while let Some(line) = outbound.recv().await {
+                if let Err(err) = writer.send(line).await {
+                    warn!("Unable to write to a connection: {:?}", err);
+                    break;
+                }
+            }
+        }
+        .instrument(span.clone()),
+    );
+
+    tokio::spawn(read(lines, Some((routes, connection.clone())), inbound).instrument(span));
+
+    connection
+}
+
+/// Feed every line read from a connection to the node. With `replies`, whoever sends over the
+/// connection is answered over it.
+async fn read(
+    mut lines: FramedRead<ReadHalf<Box<dyn ByteStream>>, LinesCodec>,
+    replies: Option<(Routes, UnboundedSender<String>)>,
+    inbound: WeakSender<String>,
+) {
+    while let Some(Ok(line)) = lines.next().await {
+        if let Some((routes, connection)) = &replies {
+            for document in state::documents(&line) {
+                if let Ok(envelope) = state::envelope(document) {
+                    if let Some(src) = envelope.src {
+                        routes.insert(src.into_owned(), connection.clone());
+                    }
+                }
+            }
+        }
+
+        let Some(tx) = inbound.upgrade() else {
+            break;
+        };
+
+        if tx.send(line).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actor::NodeHandle;
+    use crate::node::Node;
+    use crate::tls::TlsConfig;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, ServerName};
+    use rustls::{ClientConfig, RootCertStore};
+    use std::path::Path;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio_rustls::TlsConnector;
+    use tokio_util::task::TaskTracker;
+
+    const ECHO: &[u8] =
+        b"{\"src\": \"c1\", \"dest\": \"n1\", \"body\": {\"type\": \"echo\", \"echo\": \"hi\", \"msg_id\": 1}}\n";
+
+    /// A CA, and a certificate it signed for 127.0.0.1, written to `dir`.
+    fn certificates(dir: &Path, mutual: bool) -> TlsConfig {
+        std::fs::create_dir_all(dir).unwrap();
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca = CertificateParams::new(vec![]).unwrap();
+        ca.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca.self_signed(&ca_key).unwrap();
+
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["127.0.0.1".to_string()])
+            .unwrap()
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+
+        std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+        std::fs::write(dir.join("node.pem"), cert.pem()).unwrap();
+        std::fs::write(dir.join("node.key"), key.serialize_pem()).unwrap();
+
+        TlsConfig {
+            cert: Some(dir.join("node.pem")),
+            key: Some(dir.join("node.key")),
+            ca: Some(dir.join("ca.pem")),
+            mutual,
+        }
+    }
+
+    #[tokio::test]
+    async fn replies_over_the_clients_connection() {
+        let (tx, rx) = mpsc::channel(8);
+        let (response_tx, response_rx) = mpsc::channel(8);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+
+        let node = NodeHandle::spawn(Node {
+            id: Some("n1".to_string()),
+            ..Default::default()
+        });
+        let node = tokio::spawn(async move {
+            Node::run(node, rx, response_tx, &TaskTracker::new()).await;
+        });
+        let server = tokio::spawn(serve(
+            listener,
+            tx,
+            response_rx,
+            Dialing::default(),
+            None,
+            shutdown.clone(),
+        ));
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        client
+            .write_all(
+                b"{\"src\": \"c1\", \"dest\": \"n1\", \"body\": {\"type\": \"echo\", \"echo\": \"hi\", \"msg_id\": 1}}\n",
+            )
+            .await
+            .unwrap();
+
+        let mut reply = String::new();
+        BufReader::new(&mut client)
+            .read_line(&mut reply)
+            .await
+            .unwrap();
+
+        assert!(reply.contains(r#""type":"echo_ok""#));
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+        node.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn serves_only_peers_with_a_certificate_under_mutual_tls() {
+        let dir = std::env::temp_dir().join(format!("tranquility-tls-{}", std::process::id()));
+        let config = certificates(&dir, true);
+        let tls = Tls::new(&config).unwrap().unwrap();
+
+        let (tx, rx) = mpsc::channel(8);
+        let (response_tx, response_rx) = mpsc::channel(8);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+
+        let node = NodeHandle::spawn(Node {
+            id: Some("n1".to_string()),
+            ..Default::default()
+        });
+        let node = tokio::spawn(async move {
+            Node::run(node, rx, response_tx, &TaskTracker::new()).await;
+        });
+        let server = tokio::spawn(serve(
+            listener,
+            tx,
+            response_rx,
+            Dialing::default(),
+            Some(tls.clone()),
+            shutdown.clone(),
+        ));
+
+        // A peer with a certificate the CA signed is answered.
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut client = tls.connect(address, stream).await.unwrap();
+        client.write_all(ECHO).await.unwrap();
+
+        let mut reply = String::new();
+        BufReader::new(&mut client)
+            .read_line(&mut reply)
+            .await
+            .unwrap();
+
+        assert!(reply.contains(r#""type":"echo_ok""#));
+
+        // One without a certificate is turned away during the handshake.
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from_pem_file(dir.join("ca.pem")).unwrap())
+            .unwrap();
+        let anonymous =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+
+        let stream = TcpStream::connect(address).await.unwrap();
+        let server_name = ServerName::from(address.ip());
+        let mut client = TlsConnector::from(Arc::new(anonymous))
+            .connect(server_name, stream)
+            .await
+            .unwrap();
+        let _ = client.write_all(ECHO).await;
+
+        let mut reply = String::new();
+        let read = BufReader::new(&mut client).read_line(&mut reply).await;
+
+        assert!(read.is_err() || reply.is_empty(), "{reply}");
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+        node.await.unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dials_peers_over_tls() {
+        let dir = std::env::temp_dir().join(format!("tranquility-dial-{}", std::process::id()));
+        let tls = Tls::new(&certificates(&dir, false)).unwrap().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let (inbound, _) = mpsc::channel(1);
+        let (response_tx, response_rx) = mpsc::channel(8);
+        let peers = Peers::new(
+            Routes::default(),
+            inbound.downgrade(),
+            Dialing::default(),
+            Some(tls.clone()),
+        );
+        let router = tokio::spawn(transport::forward(response_rx, peers));
+
+        let line = format!(r#"{{"src": "n1", "dest": "{address}", "body": {{"type": "echo"}}}}"#);
+        response_tx.send(line.clone()).await.unwrap();
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let stream = tls.accept(stream).await.unwrap();
+        let mut received = String::new();
+        BufReader::new(stream)
+            .read_line(&mut received)
+            .await
+            .unwrap();
+
+        assert_eq!(received.trim_end(), line);
+
+        drop(response_tx);
+        router.await.unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn buffers_lines_until_a_peer_can_be_dialed() {
+        // A free port, with nothing listening on it yet.
+        let address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let (inbound, _) = mpsc::channel(1);
+        let (response_tx, response_rx) = mpsc::channel(8);
+        let dialing = Dialing {
+            backoff: RetryPolicy {
+                initial: Duration::from_millis(10),
+                max_delay: Duration::from_millis(50),
+                ..Default::default()
+            },
+            buffer: 2,
+        };
+        let peers = Peers::new(Routes::default(), inbound.downgrade(), dialing, None);
+        let router = tokio::spawn(transport::forward(response_rx, peers));
+
+        for id in 1..=4 {
+            let line = format!(
+                r#"{{"src": "n1", "dest": "{}", "body": {{"type": "echo", "msg_id": {}}}}}"#,
+                address, id
+            );
+            response_tx.send(line).await.unwrap();
+
+            // The first line is taken up by the dialer before the rest arrive.
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let listener = TcpListener::bind(address).await.unwrap();
+        let (stream, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        drop(response_tx);
+        router.await.unwrap();
+
+        let mut lines = BufReader::new(stream).lines();
+        let mut received = vec![];
+
+        while let Some(line) = lines.next_line().await.unwrap() {
+            received.push(line);
+        }
+
+        let msg_ids: Vec<_> = received
+            .iter()
+            .map(|line| state::envelope(line).unwrap().body.msg_id)
+            .collect();
+
+        // The first line is held by the dialer and the next two by the buffer; the last is
+        // dropped.
+        assert_eq!(msg_ids, [Some(1), Some(2), Some(3)]);
+    }
+}
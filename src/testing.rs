@@ -0,0 +1,179 @@
+//! A fixture for testing handlers without channels or a task tracker: messages are handed to a
+//! `Node` through `Node::dispatch`, and what it sends back is checked in place.
+//!
+//! ```
+//! # #[cfg(feature = "broadcast")] {
+//! use serde_json::json;
+//! use tranquility::testing::NodeTestFixture;
+//!
+//! NodeTestFixture::new()
+//!     .init("n1", ["n1", "n2"])
+//!     .send(json!({"type": "broadcast", "message": 1}))
+//!     .expect_reply("broadcast_ok")
+//!     .send(json!({"type": "read"}))
+//!     .expect_reply_with("read_ok", |body| assert_eq!(body["messages"], json!([1])));
+//! # }
+//! ```
+
+use serde_json::{json, Value};
+
+use crate::core::message::Message;
+use crate::core::state;
+use crate::node::Node;
+use crate::timer::TimerEvent;
+
+/// A node, a client talking to it, and what the node sent in response to the last message.
+/// Failed expectations panic, naming the caller's line.
+#[derive(Debug)]
+pub struct NodeTestFixture {
+    pub node: Node,
+    /// The client requests are sent from.
+    pub client: String,
+    /// The `msg_id` of the last request.
+    pub msg_id: u32,
+    /// Everything the node sent in response to the last message, to clients and peers alike.
+    pub sent: Vec<Message<'static>>,
+}
+
+impl Default for NodeTestFixture {
+    fn default() -> Self {
+        NodeTestFixture::with_node(Node::default())
+    }
+}
+
+impl NodeTestFixture {
+    pub fn new() -> Self {
+        NodeTestFixture::default()
+    }
+
+    /// A fixture around a node built with a particular config or state.
+    pub fn with_node(node: Node) -> Self {
+        NodeTestFixture {
+            node,
+            client: "c1".to_string(),
+            msg_id: 0,
+            sent: vec![],
+        }
+    }
+
+    /// Initialize the node as Maelstrom does, and expect it to acknowledge.
+    #[track_caller]
+    pub fn init<'a>(self, node_id: &str, node_ids: impl IntoIterator<Item = &'a str>) -> Self {
+        let node_ids: Vec<&str> = node_ids.into_iter().collect();
+
+        self.send(json!({"type": "init", "node_id": node_id, "node_ids": node_ids}))
+            .expect_reply("init_ok")
+    }
+
+    /// Send a request body from the client, with the next `msg_id` unless it has one.
+    #[track_caller]
+    pub fn send(mut self, mut body: Value) -> Self {
+        self.msg_id += 1;
+
+        match body.get("msg_id").and_then(Value::as_u64) {
+            Some(msg_id) => self.msg_id = msg_id as u32,
+            None => body["msg_id"] = json!(self.msg_id),
+        }
+
+        let dest = self.node.id.clone().unwrap_or_else(|| "n1".to_string());
+        let client = self.client.clone();
+
+        self.receive(json!({"src": client, "dest": dest, "body": body}))
+    }
+
+    /// Deliver a whole message, e.g. one from a peer. Its replies replace `sent`.
+    #[track_caller]
+    pub fn receive(mut self, message: Value) -> Self {
+        let line = message.to_string();
+        let message = state::parse(&line).unwrap_or_else(|err| panic!("{}: {}", message, err));
+
+        self.sent = self.node.dispatch(message);
+        self
+    }
+
+    /// Fire a timer. What it sends replaces `sent`.
+    pub fn fire(mut self, event: TimerEvent) -> Self {
+        self.sent = self.node.fire(&event);
+        self.sent.extend(self.node.run_tasks());
+        self
+    }
+
+    /// The client's replies to the last request.
+    pub fn replies(&self) -> Vec<&Message<'static>> {
+        self.sent
+            .iter()
+            .filter(|message| {
+                message.dest == self.client && message.body.in_reply_to() == Some(self.msg_id)
+            })
+            .collect()
+    }
+
+    /// Expect exactly one reply to the last request, of type `kind`.
+    #[track_caller]
+    pub fn expect_reply(self, kind: &str) -> Self {
+        self.expect_reply_with(kind, |_| ())
+    }
+
+    /// Expect exactly one reply to the last request, of type `kind`, and check its body as JSON.
+    #[track_caller]
+    pub fn expect_reply_with(self, kind: &str, check: impl FnOnce(&Value)) -> Self {
+        let replies = self.replies();
+
+        assert_eq!(
+            replies.len(),
+            1,
+            "expected a {} in reply to {}, got {:?}",
+            kind,
+            self.msg_id,
+            self.sent
+        );
+        assert_eq!(replies[0].body.kind(), kind, "{:?}", replies[0]);
+
+        check(&serde_json::to_value(&replies[0].body).unwrap());
+        self
+    }
+
+    /// Expect the node to have sent a message of type `kind` to `dest`, e.g. gossip to a peer.
+    #[track_caller]
+    pub fn expect_sent(self, dest: &str, kind: &str) -> Self {
+        assert!(
+            self.sent
+                .iter()
+                .any(|message| message.dest == dest && message.body.kind() == kind),
+            "expected a {} to {}, got {:?}",
+            kind,
+            dest,
+            self.sent
+        );
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "broadcast")]
+    fn checks_replies_and_gossip() {
+        NodeTestFixture::new()
+            .init("n1", ["n1", "n2"])
+            .send(json!({"type": "echo", "echo": "hello"}))
+            .expect_reply_with("echo_ok", |body| assert_eq!(body["echo"], "hello"))
+            .send(json!({"type": "broadcast", "message": 7}))
+            .expect_reply("broadcast_ok")
+            .expect_sent("n2", "broadcast")
+            .send(json!({"type": "read"}))
+            .expect_reply_with("read_ok", |body| assert_eq!(body["messages"], json!([7])));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a read_ok")]
+    fn fails_without_a_reply() {
+        NodeTestFixture::new()
+            .init("n1", ["n1"])
+            .fire(TimerEvent::Heartbeat)
+            .expect_reply("read_ok");
+    }
+}
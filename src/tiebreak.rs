@@ -0,0 +1,85 @@
+//! Deterministic choices derived from node ids alone, so every node makes the same choice
+//! without asking the others, and runs are reproducible.
+
+use std::cmp::Ordering;
+
+/// Order node ids by their prefix, then by their number, so `n2` comes before `n10`. Ids
+/// without a numeric suffix, e.g. `host:port` addresses, fall back to comparing the whole id.
+pub fn compare(a: &str, b: &str) -> Ordering {
+    fn split(id: &str) -> (&str, Option<u64>) {
+        let prefix = id.trim_end_matches(|c: char| c.is_ascii_digit());
+
+        (prefix, id[prefix.len()..].parse().ok())
+    }
+
+    let (a_prefix, a_number) = split(a);
+    let (b_prefix, b_number) = split(b);
+
+    a_prefix
+        .cmp(b_prefix)
+        .then(a_number.cmp(&b_number))
+        .then(a.cmp(b))
+}
+
+/// The node ids in `compare` order.
+pub fn sorted(node_ids: &[String]) -> Vec<&String> {
+    let mut sorted: Vec<_> = node_ids.iter().collect();
+    sorted.sort_by(|a, b| compare(a, b));
+
+    sorted
+}
+
+/// The node preferred when one node must act for the cluster: the first in `compare` order.
+/// Also the winner of a tie between concurrent updates from different nodes.
+pub fn leader(node_ids: &[String]) -> Option<&String> {
+    node_ids.iter().min_by(|a, b| compare(a, b))
+}
+
+/// The node that sequences operations during `term`, rotating through the nodes in order.
+pub fn sequencer(node_ids: &[String], term: u64) -> Option<&String> {
+    let sorted = sorted(node_ids);
+
+    match sorted.len() {
+        0 => None,
+        len => Some(sorted[(term % len as u64) as usize]),
+    }
+}
+
+/// The node a key is assigned to, by rendezvous hashing: adding or removing a node only moves
+/// the keys assigned to it.
+pub fn owner<'a>(key: &str, node_ids: &'a [String]) -> Option<&'a String> {
+    node_ids.iter().max_by(|a, b| {
+        stable_hash(&[key, a])
+            .cmp(&stable_hash(&[key, b]))
+            .then(compare(b, a))
+    })
+}
+
+/// FNV-1a over the parts, which, unlike the standard library's hashers, is the same on every
+/// node, build, and run.
+pub(crate) fn stable_hash(parts: &[&str]) -> u64 {
+    parts
+        .iter()
+        .flat_map(|part| part.bytes().chain([0xff]))
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn agrees_regardless_of_the_order_of_node_ids() {
+        let node_ids = ["n10", "n2", "n1"].map(String::from);
+        let mut reversed = node_ids.clone();
+        reversed.reverse();
+
+        assert_eq!(sorted(&node_ids), ["n1", "n2", "n10"]);
+        assert_eq!(leader(&node_ids).unwrap(), "n1");
+        assert_eq!(sequencer(&node_ids, 4).unwrap(), "n2");
+        assert_eq!(owner("key", &node_ids), owner("key", &reversed));
+        assert_eq!(leader(&[]), None);
+    }
+}
@@ -0,0 +1,175 @@
+//! Timers for the node's periodic work. A timer fires as an `internal_timer` message the node
+//! sends itself, handled by `dispatch` like any other message, so gossip, retries, Raft's election
+//! timeouts, and the rest all run on the node's one handler path instead of in their own tasks.
+//! One task sleeps until the next timer is due; scheduling an earlier one wakes it.
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "runtime")]
+use std::collections::BTreeMap;
+#[cfg(feature = "runtime")]
+use std::sync::Arc;
+#[cfg(feature = "runtime")]
+use std::time::{Duration, Instant};
+#[cfg(feature = "runtime")]
+use tokio::sync::Notify;
+
+/// What a timer does when it fires.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum TimerEvent {
+    /// Resend unacknowledged messages that are due.
+    Retry,
+    /// Time out requests past their deadline.
+    ExpireRpcs,
+    /// Send the counter and LWW map to the other nodes.
+    #[cfg(any(feature = "counter", feature = "kv"))]
+    Replicate,
+    /// Send heartbeats to the other nodes.
+    Heartbeat,
+    /// Exchange broadcast values with a random neighbor.
+    #[cfg(feature = "broadcast")]
+    AntiEntropy,
+    /// Advance Raft by one tick.
+    #[cfg(feature = "raft")]
+    RaftTick,
+    /// Settle transactions that have waited too long.
+    #[cfg(feature = "txn")]
+    ExpireTransactions,
+    /// Fsync the write-ahead logs' appends once they've waited for the group commit window.
+    CommitWal,
+    /// Run a hosted workload's `on_tick`, by name.
+    WorkloadTick(String),
+}
+
+impl TimerEvent {
+    /// The name a repeating timer's startup jitter is derived from.
+    pub fn name(&self) -> &str {
+        match self {
+            TimerEvent::Retry => "retry",
+            TimerEvent::ExpireRpcs => "expire_rpcs",
+            #[cfg(any(feature = "counter", feature = "kv"))]
+            TimerEvent::Replicate => "replicate",
+            TimerEvent::Heartbeat => "heartbeat",
+            #[cfg(feature = "broadcast")]
+            TimerEvent::AntiEntropy => "anti_entropy",
+            #[cfg(feature = "raft")]
+            TimerEvent::RaftTick => "raft_tick",
+            #[cfg(feature = "txn")]
+            TimerEvent::ExpireTransactions => "expire_transactions",
+            TimerEvent::CommitWal => "commit_wal",
+            TimerEvent::WorkloadTick(name) => name,
+        }
+    }
+}
+
+#[cfg(feature = "runtime")]
+#[derive(Debug)]
+struct Timer {
+    event: TimerEvent,
+    every: Option<Duration>,
+}
+
+/// The scheduled timers, by when they're due.
+#[cfg(feature = "runtime")]
+#[derive(Debug, Default)]
+pub struct Timers {
+    /// Keyed by due time, then by the order they were scheduled in.
+    timers: BTreeMap<(Instant, u64), Timer>,
+    scheduled: u64,
+    wake: Arc<Notify>,
+}
+
+#[cfg(feature = "runtime")]
+impl Timers {
+    /// Fire `event` at `at`, and then every `every`, if set.
+    pub fn schedule(&mut self, at: Instant, event: TimerEvent, every: Option<Duration>) {
+        self.scheduled += 1;
+        self.timers
+            .insert((at, self.scheduled), Timer { event, every });
+        self.wake.notify_one();
+    }
+
+    /// When the next timer is due.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.timers.keys().next().map(|(at, _)| *at)
+    }
+
+    /// Notified whenever a timer is scheduled, so the timer task can wake for an earlier one.
+    pub fn wake(&self) -> Arc<Notify> {
+        self.wake.clone()
+    }
+
+    /// Remove the timers due by `now`, in order, rescheduling the repeating ones. A repeating
+    /// timer that fell behind fires once, not once per interval missed.
+    pub fn due(&mut self, now: Instant) -> Vec<TimerEvent> {
+        let mut events = vec![];
+
+        while let Some(entry) = self.timers.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+
+            let ((at, _), timer) = entry.remove_entry();
+
+            if let Some(every) = timer.every {
+                let next = match at + every > now {
+                    true => at + every,
+                    false => now + every,
+                };
+
+                self.scheduled += 1;
+                self.timers.insert(
+                    (next, self.scheduled),
+                    Timer {
+                        event: timer.event.clone(),
+                        every: Some(every),
+                    },
+                );
+            }
+
+            events.push(timer.event);
+        }
+
+        events
+    }
+
+    /// Keep only the timers whose events match.
+    pub fn retain(&mut self, keep: impl Fn(&TimerEvent) -> bool) {
+        self.timers.retain(|_, timer| keep(&timer.event));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+}
+
+#[cfg(all(test, feature = "runtime"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fires_due_timers_in_order_and_repeats() {
+        let mut timers = Timers::default();
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+
+        timers.schedule(start + second * 2, TimerEvent::Retry, None);
+        timers.schedule(start + second, TimerEvent::Heartbeat, Some(second));
+
+        assert_eq!(timers.next_due(), Some(start + second));
+        assert!(timers.due(start).is_empty());
+        assert_eq!(timers.due(start + second), [TimerEvent::Heartbeat]);
+        assert_eq!(
+            timers.due(start + second * 2),
+            [TimerEvent::Retry, TimerEvent::Heartbeat]
+        );
+
+        // Far behind, a repeating timer fires once.
+        assert_eq!(timers.due(start + second * 10), [TimerEvent::Heartbeat]);
+        assert_eq!(timers.next_due(), Some(start + second * 11));
+
+        timers.retain(|event| *event == TimerEvent::Retry);
+        assert!(timers.is_empty());
+    }
+}
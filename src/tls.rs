@@ -0,0 +1,129 @@
+//! TLS for the TCP transport. With a certificate configured, every connection the node accepts or
+//! dials is TLS, and the other end's certificate must chain to one of the configured CAs: always
+//! for peers the node dials, and for connecting peers too with `mutual`.
+//!
+//! Peers are dialed by their `host:port` ids, so a peer's certificate must name its IP address.
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::{client, server, TlsAcceptor, TlsConnector};
+
+/// Where the node's certificate and the CAs it trusts are read from, all PEM files.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TlsConfig {
+    /// The node's certificate chain; connections are plaintext without one.
+    pub cert: Option<PathBuf>,
+    /// The certificate's private key.
+    pub key: Option<PathBuf>,
+    /// The CA certificates the other end's certificate must chain to.
+    pub ca: Option<PathBuf>,
+    /// Whether connecting peers must present a certificate too.
+    pub mutual: bool,
+}
+
+/// The TLS settings in effect, for the listener and the dialer alike.
+#[derive(Clone)]
+pub struct Tls {
+    acceptor: TlsAcceptor,
+    connector: TlsConnector,
+}
+
+impl Tls {
+    /// The TLS `config` sets up, if it sets up any.
+    pub fn new(config: &TlsConfig) -> io::Result<Option<Tls>> {
+        let Some(cert) = &config.cert else {
+            return Ok(None);
+        };
+        let key = config
+            .key
+            .as_ref()
+            .ok_or_else(|| invalid("A TLS certificate needs its key."))?;
+        let ca = config
+            .ca
+            .as_ref()
+            .ok_or_else(|| invalid("A TLS certificate needs the CAs to check peers against."))?;
+
+        let certs = certificates(cert)?;
+        let key = PrivateKeyDer::from_pem_file(key).map_err(|err| in_file(key, err))?;
+        let mut roots = RootCertStore::empty();
+
+        for ca in certificates(ca)? {
+            roots.add(ca).map_err(invalid)?;
+        }
+
+        let roots = Arc::new(roots);
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+        let server = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(invalid)?;
+        let server = match config.mutual {
+            true => server.with_client_cert_verifier(
+                WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone())
+                    .build()
+                    .map_err(invalid)?,
+            ),
+            false => server.with_no_client_auth(),
+        }
+        .with_single_cert(certs.clone(), key.clone_key())
+        .map_err(invalid)?;
+
+        // The certificate is only sent to peers that ask for one.
+        let client = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(invalid)?
+            .with_root_certificates(roots)
+            .with_client_auth_cert(certs, key)
+            .map_err(invalid)?;
+
+        Ok(Some(Tls {
+            acceptor: TlsAcceptor::from(Arc::new(server)),
+            connector: TlsConnector::from(Arc::new(client)),
+        }))
+    }
+
+    /// Complete the handshake with a peer that connected.
+    pub async fn accept(&self, stream: TcpStream) -> io::Result<server::TlsStream<TcpStream>> {
+        self.acceptor.accept(stream).await
+    }
+
+    /// Complete the handshake with the peer dialed at `address`.
+    pub async fn connect(
+        &self,
+        address: SocketAddr,
+        stream: TcpStream,
+    ) -> io::Result<client::TlsStream<TcpStream>> {
+        self.connector
+            .connect(ServerName::from(address.ip()), stream)
+            .await
+    }
+}
+
+fn certificates(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .map_err(|err| in_file(path, err))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| in_file(path, err))?;
+
+    match certs.is_empty() {
+        true => Err(in_file(path, "no certificates")),
+        false => Ok(certs),
+    }
+}
+
+fn in_file(path: &Path, err: impl fmt::Display) -> io::Error {
+    invalid(format!("{}: {}", path.display(), err))
+}
+
+fn invalid(err: impl Into<Box<dyn Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+}
@@ -0,0 +1,363 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use crate::tiebreak;
+
+/// The overlay declared by a `topology` message: each node's neighbors.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct Topology(pub HashMap<String, Vec<String>>);
+
+/// A problem with a declared overlay. None of them stop the node from using it, but each makes
+/// gossip slower or incomplete.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TopologyWarning {
+    /// A neighbor that isn't one of the cluster's `node_ids`.
+    UnknownNeighbor { node: String, neighbor: String },
+    /// `from` lists `to` as a neighbor, but not the other way around.
+    Asymmetric { from: String, to: String },
+    /// The overlay falls apart into these groups of nodes, which can't reach each other.
+    Partitioned { components: Vec<Vec<String>> },
+}
+
+impl std::fmt::Display for TopologyWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TopologyWarning::UnknownNeighbor { node, neighbor } => {
+                write!(f, "{} has unknown neighbor {}", node, neighbor)
+            }
+            TopologyWarning::Asymmetric { from, to } => {
+                write!(
+                    f,
+                    "{} lists {} as a neighbor, but not the reverse",
+                    from, to
+                )
+            }
+            TopologyWarning::Partitioned { components } => {
+                write!(f, "the overlay is partitioned into {:?}", components)
+            }
+        }
+    }
+}
+
+/// A summary of the overlay, for the `topology_report` debug message.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TopologyReport {
+    pub nodes: usize,
+    pub edges: usize,
+    /// The longest shortest path between two nodes, or `None` when the overlay is partitioned.
+    pub diameter: Option<usize>,
+    /// How many nodes have each number of neighbors.
+    #[serde(deserialize_with = "degrees_from_strings")]
+    #[cfg_attr(feature = "schema", schemars(with = "BTreeMap<usize, usize>"))]
+    pub degrees: BTreeMap<usize, usize>,
+    pub warnings: Vec<String>,
+}
+
+/// Where the node's overlay comes from.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OverlayStrategy {
+    /// The topology Maelstrom sends.
+    #[default]
+    Given,
+    /// Every node neighbors the root, the first node in `tiebreak` order: two hops between any
+    /// two nodes, with all the load on the root.
+    Star,
+    /// A tree rooted at the first node, where each node has up to `fanout` children, filled in
+    /// `tiebreak` order.
+    Tree { fanout: usize },
+}
+
+impl OverlayStrategy {
+    /// Parse `given`, `star`, `tree`, or `tree:<fanout>`, or `kary:<k>` for a tree with fanout
+    /// `k`. A tree has a fanout of 4 unless given one.
+    pub fn parse(strategy: &str) -> Option<Self> {
+        match strategy {
+            "given" => Some(OverlayStrategy::Given),
+            "star" => Some(OverlayStrategy::Star),
+            "tree" => Some(OverlayStrategy::Tree { fanout: 4 }),
+            _ => strategy
+                .strip_prefix("tree:")
+                .or_else(|| strategy.strip_prefix("kary:"))
+                .map(|fanout| OverlayStrategy::Tree {
+                    fanout: fanout.parse().unwrap_or(4).max(1),
+                }),
+        }
+    }
+
+    /// The overlay to use in place of `given`. Built overlays span `node_ids`, or the nodes in
+    /// `given` before `init`.
+    pub fn build(&self, given: &Topology, node_ids: &[String]) -> Topology {
+        let fanout = match self {
+            OverlayStrategy::Given => return given.clone(),
+            OverlayStrategy::Star => usize::MAX,
+            OverlayStrategy::Tree { fanout } => *fanout,
+        };
+
+        let nodes = match node_ids.is_empty() {
+            true => given.0.keys().cloned().collect(),
+            false => node_ids.to_vec(),
+        };
+        let nodes = tiebreak::sorted(&nodes);
+
+        let mut overlay: HashMap<String, Vec<String>> = nodes
+            .iter()
+            .map(|node| (node.to_string(), vec![]))
+            .collect();
+
+        // Nodes are numbered breadth-first, so each node's parent is the one `fanout` times
+        // closer to the root.
+        for (index, node) in nodes.iter().enumerate().skip(1) {
+            let parent = nodes[(index - 1) / fanout];
+
+            overlay
+                .get_mut(parent.as_str())
+                .unwrap()
+                .push(node.to_string());
+            overlay
+                .get_mut(node.as_str())
+                .unwrap()
+                .push(parent.to_string());
+        }
+
+        Topology(overlay)
+    }
+}
+
+/// JSON object keys are strings, and once the report is flattened into a reply serde no longer
+/// parses them back into numbers itself.
+fn degrees_from_strings<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<usize, usize>, D::Error> {
+    BTreeMap::<String, usize>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(degree, nodes)| {
+            degree
+                .parse()
+                .map(|degree| (degree, nodes))
+                .map_err(serde::de::Error::custom)
+        })
+        .collect()
+}
+
+impl Topology {
+    pub fn neighbors(&self, node_id: &str) -> Option<&[String]> {
+        self.0.get(node_id).map(Vec::as_slice)
+    }
+
+    /// Every node in `node_ids` or in the overlay, with its neighbors in either direction.
+    fn undirected<'a>(&'a self, node_ids: &'a [String]) -> BTreeMap<&'a str, BTreeSet<&'a str>> {
+        let mut graph: BTreeMap<&str, BTreeSet<&str>> = node_ids
+            .iter()
+            .map(|node_id| (node_id.as_str(), BTreeSet::new()))
+            .collect();
+
+        for (node, neighbors) in &self.0 {
+            for neighbor in neighbors {
+                graph.entry(node).or_default().insert(neighbor);
+                graph.entry(neighbor).or_default().insert(node);
+            }
+        }
+
+        graph
+    }
+
+    pub fn validate(&self, node_ids: &[String]) -> Vec<TopologyWarning> {
+        let mut warnings = vec![];
+        let mut nodes = self.0.keys().collect::<Vec<&String>>();
+        nodes.sort();
+
+        for node in nodes {
+            for neighbor in &self.0[node] {
+                if !node_ids.is_empty() && !node_ids.contains(neighbor) {
+                    warnings.push(TopologyWarning::UnknownNeighbor {
+                        node: node.clone(),
+                        neighbor: neighbor.clone(),
+                    });
+                }
+
+                if !self
+                    .neighbors(neighbor)
+                    .is_some_and(|reverse| reverse.contains(node))
+                {
+                    warnings.push(TopologyWarning::Asymmetric {
+                        from: node.clone(),
+                        to: neighbor.clone(),
+                    });
+                }
+            }
+        }
+
+        let components = Topology::components(&self.undirected(node_ids));
+
+        if components.len() > 1 {
+            warnings.push(TopologyWarning::Partitioned { components });
+        }
+
+        warnings
+    }
+
+    fn components(graph: &BTreeMap<&str, BTreeSet<&str>>) -> Vec<Vec<String>> {
+        let mut seen = BTreeSet::new();
+        let mut components = vec![];
+
+        for node in graph.keys() {
+            if seen.contains(node) {
+                continue;
+            }
+
+            let component = Topology::distances(graph, node)
+                .into_keys()
+                .collect::<Vec<&str>>();
+
+            seen.extend(component.iter().copied());
+            components.push(component.into_iter().map(String::from).collect());
+        }
+
+        components
+    }
+
+    /// Breadth-first distances from `start` to every node it can reach.
+    fn distances<'a>(
+        graph: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+        start: &'a str,
+    ) -> BTreeMap<&'a str, usize> {
+        let mut distances = BTreeMap::from([(start, 0)]);
+        let mut queue = VecDeque::from([start]);
+
+        while let Some(node) = queue.pop_front() {
+            let distance = distances[node];
+
+            for neighbor in graph.get(node).into_iter().flatten() {
+                if !distances.contains_key(neighbor) {
+                    distances.insert(neighbor, distance + 1);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        distances
+    }
+
+    pub fn report(&self, node_ids: &[String]) -> TopologyReport {
+        let graph = self.undirected(node_ids);
+        let warnings = self.validate(node_ids);
+
+        let mut degrees = BTreeMap::new();
+
+        for node in graph.keys() {
+            let degree = self.neighbors(node).map_or(0, <[String]>::len);
+
+            *degrees.entry(degree).or_default() += 1;
+        }
+
+        let partitioned = warnings
+            .iter()
+            .any(|warning| matches!(warning, TopologyWarning::Partitioned { .. }));
+
+        let diameter = (!partitioned).then(|| {
+            graph
+                .keys()
+                .filter_map(|node| Topology::distances(&graph, node).into_values().max())
+                .max()
+                .unwrap_or_default()
+        });
+
+        TopologyReport {
+            nodes: graph.len(),
+            edges: self.0.values().map(Vec::len).sum(),
+            diameter,
+            degrees,
+            warnings: warnings.iter().map(ToString::to_string).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn topology(edges: &[(&str, &[&str])]) -> Topology {
+        Topology(
+            edges
+                .iter()
+                .map(|(node, neighbors)| {
+                    (
+                        node.to_string(),
+                        neighbors
+                            .iter()
+                            .map(|neighbor| neighbor.to_string())
+                            .collect(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn builds_a_tree_rooted_at_the_first_node() {
+        let node_ids = ["n1", "n2", "n3", "n4", "n5", "n10"]
+            .map(String::from)
+            .to_vec();
+        let tree = OverlayStrategy::Tree { fanout: 2 }.build(&Topology::default(), &node_ids);
+
+        assert_eq!(tree.neighbors("n1").unwrap(), ["n2", "n3"]);
+        assert_eq!(tree.neighbors("n2").unwrap(), ["n1", "n4", "n5"]);
+        assert_eq!(tree.neighbors("n3").unwrap(), ["n1", "n10"]);
+        assert!(tree.validate(&node_ids).is_empty());
+
+        let star = OverlayStrategy::Star.build(&Topology::default(), &node_ids);
+
+        assert_eq!(star.neighbors("n1").unwrap().len(), 5);
+        assert_eq!(star.neighbors("n10").unwrap(), ["n1"]);
+
+        assert_eq!(
+            OverlayStrategy::parse("kary:2"),
+            Some(OverlayStrategy::Tree { fanout: 2 })
+        );
+        assert_eq!(
+            OverlayStrategy::parse("tree:0"),
+            Some(OverlayStrategy::Tree { fanout: 1 })
+        );
+        assert_eq!(OverlayStrategy::parse("bush:2"), None);
+    }
+
+    #[test]
+    fn reports_problems_with_the_overlay() {
+        let node_ids = ["n1", "n2", "n3", "n4"].map(String::from).to_vec();
+        let line = topology(&[
+            ("n1", &["n2"]),
+            ("n2", &["n1", "n3"]),
+            ("n3", &["n2", "n5"]),
+        ]);
+
+        assert_eq!(
+            line.validate(&node_ids),
+            vec![
+                TopologyWarning::UnknownNeighbor {
+                    node: "n3".into(),
+                    neighbor: "n5".into()
+                },
+                TopologyWarning::Asymmetric {
+                    from: "n3".into(),
+                    to: "n5".into()
+                },
+                TopologyWarning::Partitioned {
+                    components: vec![
+                        vec!["n1".into(), "n2".into(), "n3".into(), "n5".into()],
+                        vec!["n4".into()]
+                    ]
+                },
+            ]
+        );
+
+        let report = topology(&[("n1", &["n2"]), ("n2", &["n1", "n3"]), ("n3", &["n2"])])
+            .report(&node_ids[..3]);
+
+        assert_eq!(report.diameter, Some(2));
+        assert_eq!(report.degrees, BTreeMap::from([(1, 2), (2, 1)]));
+        assert!(report.warnings.is_empty());
+    }
+}
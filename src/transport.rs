@@ -0,0 +1,194 @@
+//! How messages get on and off the wire. Maelstrom talks to a node over stdin and stdout, and
+//! nodes can talk to each other over TCP; both carry one JSON message per line, framed here.
+//!
+//! Everything a node sends, whether a handler's reply, an RPC, gossip, or a timer's message,
+//! leaves through one [`Transport`], which is handed each message with the node or client it's
+//! for. Over stdio that's Maelstrom, which delivers by `dest` itself; over TCP it's the
+//! connection manager in the `tcp` module, which dials peers as needed.
+
+use futures_core::Stream;
+use futures_sink::Sink;
+use futures_util::SinkExt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, Stdin, Stdout};
+use tokio::sync::mpsc::Receiver;
+use tokio_util::codec::{FramedRead, FramedWrite, LinesCodec, LinesCodecError};
+use tracing::{debug, error, warn};
+
+use crate::core::state;
+
+/// Where a node's messages go once it has sent them, in the order it sent them.
+pub trait Transport: Send {
+    /// Send one message, serialized as a line, to `dest`. An error means nothing more can be
+    /// sent.
+    fn send(&mut self, dest: &str, line: String) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+/// Over stdio every line goes to Maelstrom, which delivers it by its `dest`.
+impl<W: AsyncWrite + Send + Unpin> Transport for FramedWrite<W, LinesCodec> {
+    async fn send(&mut self, _dest: &str, line: String) -> io::Result<()> {
+        SinkExt::send(self, line).await.map_err(into_io)
+    }
+}
+
+/// Hand every line the node sends to `transport`, addressed to the line's `dest`, until the node
+/// stops or the transport fails.
+pub async fn forward(mut lines: Receiver<String>, mut transport: impl Transport) {
+    while let Some(line) = lines.recv().await {
+        debug!("Sent: {}", line);
+
+        let Some(dest) = state::envelope(&line)
+            .ok()
+            .map(|envelope| envelope.dest.into_owned())
+        else {
+            warn!("No dest to send {:?} to.", line);
+            continue;
+        };
+
+        if let Err(err) = transport.send(&dest, line).await {
+            error!("Unable to send to {}: {:?}", dest, err);
+            break;
+        }
+    }
+}
+
+/// Newline-delimited messages over a pair of byte streams: every line read is one frame, however
+/// the bytes were chunked, and every frame written is one line.
+///
+/// Frames are the raw lines rather than parsed messages, so the node can still reply to a line
+/// that doesn't parse and route lines to lanes without parsing them twice.
+pub struct LineTransport<R, W> {
+    reader: FramedRead<R, LinesCodec>,
+    writer: FramedWrite<W, LinesCodec>,
+}
+
+impl LineTransport<Stdin, Stdout> {
+    /// Maelstrom's transport: messages in on stdin, out on stdout.
+    pub fn stdio() -> Self {
+        LineTransport::new(tokio::io::stdin(), tokio::io::stdout())
+    }
+}
+
+impl<R: AsyncRead, W: AsyncWrite> LineTransport<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        LineTransport {
+            reader: FramedRead::new(reader, LinesCodec::new()),
+            writer: FramedWrite::new(writer, LinesCodec::new()),
+        }
+    }
+
+    /// The reading and writing halves, so they can be driven from separate tasks.
+    pub fn split(self) -> (FramedRead<R, LinesCodec>, FramedWrite<W, LinesCodec>) {
+        (self.reader, self.writer)
+    }
+}
+
+fn into_io(err: LinesCodecError) -> io::Error {
+    match err {
+        LinesCodecError::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+impl<R: AsyncRead + Unpin, W: Unpin> Stream for LineTransport<R, W> {
+    type Item = io::Result<String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.reader)
+            .poll_next(cx)
+            .map(|line| line.map(|line| line.map_err(into_io)))
+    }
+}
+
+impl<R: Unpin, W: AsyncWrite + Unpin> Sink<String> for LineTransport<R, W> {
+    type Error = io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Sink::<String>::poll_ready(Pin::new(&mut self.writer), cx).map_err(into_io)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, line: String) -> io::Result<()> {
+        Pin::new(&mut self.writer).start_send(line).map_err(into_io)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Sink::<String>::poll_flush(Pin::new(&mut self.writer), cx).map_err(into_io)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Sink::<String>::poll_close(Pin::new(&mut self.writer), cx).map_err(into_io)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_util::StreamExt;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn frames_every_line_in_a_chunk() {
+        let chunk = concat!(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": "1", "msg_id": 1}}"#,
+            "\n",
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": "2", "msg_id": 2}}"#,
+            "\n",
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": "3", "msg_id": 3}}"#,
+        );
+
+        let mut transport = LineTransport::new(chunk.as_bytes(), Vec::new());
+        let mut lines = vec![];
+
+        while let Some(line) = transport.next().await {
+            lines.push(
+                crate::core::state::parse(&line.unwrap())
+                    .unwrap()
+                    .into_owned(),
+            );
+        }
+
+        assert_eq!(lines.len(), 3);
+
+        transport.send("one".to_string()).await.unwrap();
+        transport.send("two".to_string()).await.unwrap();
+
+        let (_, writer) = transport.split();
+
+        assert_eq!(writer.get_ref(), b"one\ntwo\n");
+    }
+
+    impl Transport for mpsc::UnboundedSender<(String, String)> {
+        async fn send(&mut self, dest: &str, line: String) -> io::Result<()> {
+            mpsc::UnboundedSender::send(self, (dest.to_string(), line))
+                .map_err(|_| io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    #[tokio::test]
+    async fn forwards_each_line_to_its_dest() {
+        let (tx, rx) = mpsc::channel(4);
+        let to_n2 = r#"{"src": "n1", "dest": "n2", "body": {"type": "echo"}}"#;
+        let to_c1 = r#"{"src": "n1", "dest": "c1", "body": {"type": "echo_ok"}}"#;
+
+        for line in [to_n2, "not json", to_c1] {
+            tx.send(line.to_string()).await.unwrap();
+        }
+        drop(tx);
+
+        let (transport, mut sent_rx) = mpsc::unbounded_channel();
+        forward(rx, transport).await;
+
+        let mut sent = vec![];
+        while let Ok(message) = sent_rx.try_recv() {
+            sent.push(message);
+        }
+
+        assert_eq!(
+            sent,
+            [("n2", to_n2), ("c1", to_c1)].map(|(dest, line)| (dest.to_string(), line.to_string()))
+        );
+    }
+}
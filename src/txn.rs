@@ -0,0 +1,332 @@
+//! Multi-key transactions over keys owned by different nodes, committed with two-phase commit.
+//!
+//! The node a client sends `txn` to coordinates it: it asks each node owning some of the keys on
+//! the `HashRing`, its participants, to prepare their part, and commits once every one has voted
+//! yes. Any no vote, or a participant that doesn't answer within `Transactions::TIMEOUT`, aborts
+//! it.
+//!
+//! Only commits are remembered ("presumed abort"): a participant left prepared without hearing
+//! the outcome asks the coordinator, which answers that anything it has no commit for was
+//! aborted.
+
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::core::message::{ErrorCode, Message, TxnOp};
+use crate::kv::KvStore;
+use crate::sharding::HashRing;
+
+#[derive(Debug)]
+struct Prepared {
+    coordinator: String,
+    /// The transaction's reads, with their values, as the vote.
+    reads: Vec<TxnOp>,
+    writes: Vec<(Value, Value)>,
+    since: Instant,
+}
+
+#[derive(Debug)]
+struct Coordinating {
+    request: Message<'static>,
+    ops: Vec<TxnOp>,
+    /// The positions in `ops` of each participant's part.
+    plan: BTreeMap<String, Vec<usize>>,
+    waiting: HashSet<String>,
+    started: Instant,
+}
+
+/// A transaction every participant voted to commit.
+#[derive(Debug)]
+pub struct Commit {
+    pub request: Message<'static>,
+    /// The transaction's ops, with the values read.
+    pub ops: Vec<TxnOp>,
+    pub participants: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Transactions {
+    store: KvStore,
+    /// The transaction holding each key, by the key's serialization.
+    locks: HashMap<String, String>,
+    prepared: HashMap<String, Prepared>,
+    coordinating: HashMap<String, Coordinating>,
+    /// Committed transactions, with the participants yet to acknowledge the commit.
+    committed: HashMap<String, HashSet<String>>,
+}
+
+impl Transactions {
+    pub const TIMEOUT: Duration = Duration::from_millis(1000);
+
+    /// Split a transaction's ops by the node that owns each key.
+    pub fn plan(ops: &[TxnOp], ring: &HashRing) -> BTreeMap<String, Vec<usize>> {
+        let mut plan: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+
+        for (index, TxnOp(_, key, _)) in ops.iter().enumerate() {
+            if let Some(owner) = ring.owner(&key.to_string()) {
+                plan.entry(owner.clone()).or_default().push(index);
+            }
+        }
+
+        plan
+    }
+
+    /// Start coordinating a transaction, waiting on a vote from every node in `plan`.
+    pub fn begin(
+        &mut self,
+        txn_id: &str,
+        request: Message<'static>,
+        ops: Vec<TxnOp>,
+        plan: BTreeMap<String, Vec<usize>>,
+    ) {
+        let waiting = plan.keys().cloned().collect();
+
+        self.coordinating.insert(
+            txn_id.to_string(),
+            Coordinating {
+                request,
+                ops,
+                plan,
+                waiting,
+                started: Instant::now(),
+            },
+        );
+    }
+
+    /// Count a participant's yes vote, with its part of the transaction as it read it. Returns
+    /// the commit once every participant has voted.
+    pub fn vote(&mut self, txn_id: &str, participant: &str, part: Vec<TxnOp>) -> Option<Commit> {
+        let txn = self.coordinating.get_mut(txn_id)?;
+
+        if let Some(indexes) = txn.plan.get(participant) {
+            for (index, op) in indexes.iter().zip(part) {
+                txn.ops[*index] = op;
+            }
+        }
+
+        txn.waiting.remove(participant);
+
+        if !txn.waiting.is_empty() {
+            return None;
+        }
+
+        let txn = self.coordinating.remove(txn_id)?;
+        let participants: Vec<String> = txn.plan.into_keys().collect();
+
+        self.committed
+            .insert(txn_id.to_string(), participants.iter().cloned().collect());
+
+        Some(Commit {
+            request: txn.request,
+            ops: txn.ops,
+            participants,
+        })
+    }
+
+    /// Stop coordinating a transaction that hasn't committed, returning the client's request and
+    /// the participants to tell.
+    pub fn abort_coordinating(&mut self, txn_id: &str) -> Option<(Message<'static>, Vec<String>)> {
+        let txn = self.coordinating.remove(txn_id)?;
+
+        Some((txn.request, txn.plan.into_keys().collect()))
+    }
+
+    pub fn is_coordinating(&self, txn_id: &str) -> bool {
+        self.coordinating.contains_key(txn_id)
+    }
+
+    pub fn is_committed(&self, txn_id: &str) -> bool {
+        self.committed.contains_key(txn_id)
+    }
+
+    /// A participant has applied the commit; once all have, it needn't be remembered.
+    pub fn acknowledge(&mut self, txn_id: &str, participant: &str) {
+        if let Some(waiting) = self.committed.get_mut(txn_id) {
+            waiting.remove(participant);
+
+            if waiting.is_empty() {
+                self.committed.remove(txn_id);
+            }
+        }
+    }
+
+    /// Transactions this node coordinates that have waited too long for their votes.
+    pub fn expired(&self) -> Vec<String> {
+        self.coordinating
+            .iter()
+            .filter(|(_, txn)| txn.started.elapsed() >= Self::TIMEOUT)
+            .map(|(txn_id, _)| txn_id.clone())
+            .collect()
+    }
+
+    /// Lock this node's keys for a transaction and read them, voting yes with the reads. A key
+    /// another transaction holds is a no vote.
+    pub fn prepare(
+        &mut self,
+        txn_id: &str,
+        coordinator: &str,
+        ops: &[TxnOp],
+    ) -> Result<Vec<TxnOp>, ErrorCode> {
+        if let Some(prepared) = self.prepared.get(txn_id) {
+            return Ok(prepared.reads.clone());
+        }
+
+        let keys: HashSet<String> = ops.iter().map(|op| op.1.to_string()).collect();
+
+        if keys
+            .iter()
+            .any(|key| self.locks.get(key).is_some_and(|holder| holder != txn_id))
+        {
+            return Err(ErrorCode::TxnConflict);
+        }
+
+        let mut staged: HashMap<String, Value> = HashMap::new();
+        let mut reads = vec![];
+        let mut writes = vec![];
+
+        for TxnOp(f, key, value) in ops {
+            match f.as_str() {
+                "r" => {
+                    let read = staged
+                        .get(&key.to_string())
+                        .cloned()
+                        .or_else(|| self.store.read(key).ok().cloned())
+                        .unwrap_or(Value::Null);
+
+                    reads.push(TxnOp(f.clone(), key.clone(), read));
+                }
+                "w" => {
+                    staged.insert(key.to_string(), value.clone());
+                    writes.push((key.clone(), value.clone()));
+                    reads.push(TxnOp(f.clone(), key.clone(), value.clone()));
+                }
+                _ => return Err(ErrorCode::MalformedRequest),
+            }
+        }
+
+        for key in keys {
+            self.locks.insert(key, txn_id.to_string());
+        }
+
+        self.prepared.insert(
+            txn_id.to_string(),
+            Prepared {
+                coordinator: coordinator.to_string(),
+                reads: reads.clone(),
+                writes,
+                since: Instant::now(),
+            },
+        );
+
+        Ok(reads)
+    }
+
+    /// Apply a prepared transaction's writes and release its keys.
+    pub fn commit(&mut self, txn_id: &str) {
+        if let Some(prepared) = self.release(txn_id) {
+            for (key, value) in prepared.writes {
+                self.store.write(&key, value);
+            }
+        }
+    }
+
+    /// Drop a prepared transaction's writes and release its keys.
+    pub fn abort(&mut self, txn_id: &str) {
+        self.release(txn_id);
+    }
+
+    fn release(&mut self, txn_id: &str) -> Option<Prepared> {
+        let prepared = self.prepared.remove(txn_id)?;
+
+        self.locks.retain(|_, holder| holder != txn_id);
+
+        Some(prepared)
+    }
+
+    /// Transactions prepared for longer than the timeout without an outcome, with their
+    /// coordinators to ask. Each is only due again after another timeout.
+    pub fn in_doubt(&mut self) -> Vec<(String, String)> {
+        self.prepared
+            .iter_mut()
+            .filter(|(_, prepared)| prepared.since.elapsed() >= Self::TIMEOUT)
+            .map(|(txn_id, prepared)| {
+                prepared.since = Instant::now();
+
+                (txn_id.clone(), prepared.coordinator.clone())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn op(f: &str, key: u64, value: Value) -> TxnOp {
+        TxnOp(f.to_string(), key.into(), value)
+    }
+
+    #[test]
+    fn locks_keys_until_the_outcome() {
+        let mut txns = Transactions::default();
+        let ops = [op("w", 1, 5.into()), op("r", 1, Value::Null)];
+
+        let reads = txns.prepare("t1", "n1", &ops).unwrap();
+        assert_eq!(reads[1], op("r", 1, 5.into()));
+
+        assert_eq!(
+            txns.prepare("t2", "n1", &[op("r", 1, Value::Null)]),
+            Err(ErrorCode::TxnConflict)
+        );
+
+        txns.commit("t1");
+
+        let reads = txns
+            .prepare("t2", "n1", &[op("r", 1, Value::Null)])
+            .unwrap();
+        assert_eq!(reads, vec![op("r", 1, 5.into())]);
+
+        txns.abort("t2");
+        assert!(txns.locks.is_empty());
+    }
+
+    #[test]
+    fn commits_once_every_participant_votes() {
+        let mut txns = Transactions::default();
+        let node_ids = vec!["n1".to_string(), "n2".to_string()];
+        let request: Message = serde_json::from_str(
+            r#"{"src": "c1", "dest": "n1", "body": {"type": "txn", "msg_id": 1, "txn": []}}"#,
+        )
+        .unwrap();
+
+        let ops: Vec<TxnOp> = (0..8).map(|key| op("r", key, Value::Null)).collect();
+        let plan = Transactions::plan(&ops, &HashRing::new(&node_ids));
+        assert_eq!(plan.len(), 2);
+
+        txns.begin("t1", request, ops, plan.clone());
+
+        let mut commit = None;
+
+        for (participant, indexes) in &plan {
+            assert!(commit.is_none());
+
+            let part = indexes
+                .iter()
+                .map(|key| op("r", *key as u64, 1.into()))
+                .collect();
+            commit = txns.vote("t1", participant, part);
+        }
+
+        let commit = commit.unwrap();
+        assert!(commit.ops.iter().all(|op| op.2 == 1));
+        assert!(txns.is_committed("t1"));
+
+        for participant in &commit.participants {
+            txns.acknowledge("t1", participant);
+        }
+
+        // Presumed abort: once forgotten, a transaction reads as aborted.
+        assert!(!txns.is_committed("t1"));
+    }
+}
@@ -0,0 +1,276 @@
+//! A write-ahead log: records appended to a file and fsynced in groups. Each record is framed
+//! with its length and a CRC-32 of its contents, so a torn write or a corrupted record is detected
+//! on replay instead of being applied.
+//!
+//! Records are anything serializable, e.g. the kv store's writes or the Raft log's entries.
+//! Appends are buffered; `commit` fsyncs them once the oldest has waited for the group commit
+//! window, so a burst of appends costs one fsync. A caller that must not acknowledge a record
+//! before it's durable calls `sync`, or holds the acknowledgement until `is_synced`.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// The length and checksum before each record.
+const HEADER: usize = 8;
+
+#[derive(Debug)]
+pub struct Wal<T> {
+    path: PathBuf,
+    file: BufWriter<File>,
+    window: Duration,
+    /// When the oldest record that isn't fsynced yet was appended.
+    unsynced: Option<Instant>,
+    records: PhantomData<fn(T) -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned> Wal<T> {
+    /// Open the log at `path`, creating it if needed, with the records already in it. A torn or
+    /// corrupted record ends the log: it and everything after it are truncated.
+    pub fn open(path: impl AsRef<Path>, window: Duration) -> io::Result<(Wal<T>, Vec<T>)> {
+        let path = path.as_ref().to_path_buf();
+        let created = !path.exists();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&path)?;
+
+        // Until its directory entry is durable, a crash could lose the new file and all of it.
+        if created {
+            sync_dir(&path)?;
+        }
+
+        let mut contents = vec![];
+        file.read_to_end(&mut contents)?;
+
+        let (records, valid) = Self::decode(&contents);
+
+        if valid < contents.len() {
+            warn!(
+                "Truncating {} corrupt bytes at the end of {:?}.",
+                contents.len() - valid,
+                path
+            );
+            file.set_len(valid as u64)?;
+            file.sync_data()?;
+        }
+
+        file.seek(SeekFrom::Start(valid as u64))?;
+
+        let wal = Wal {
+            path,
+            file: BufWriter::new(file),
+            window,
+            unsynced: None,
+            records: PhantomData,
+        };
+
+        Ok((wal, records))
+    }
+
+    /// The records up to the first one that's incomplete or fails its checksum, and the length
+    /// of the log they take up.
+    fn decode(mut contents: &[u8]) -> (Vec<T>, usize) {
+        let mut records = vec![];
+        let mut valid = 0;
+
+        while contents.len() >= HEADER {
+            let len = u32::from_le_bytes(contents[0..4].try_into().unwrap()) as usize;
+            let checksum = u32::from_le_bytes(contents[4..8].try_into().unwrap());
+
+            let Some(payload) = contents.get(HEADER..HEADER + len) else {
+                break;
+            };
+
+            if crc32(payload) != checksum {
+                break;
+            }
+
+            let Ok(record) = serde_json::from_slice(payload) else {
+                break;
+            };
+
+            records.push(record);
+            valid += HEADER + len;
+            contents = &contents[HEADER + len..];
+        }
+
+        (records, valid)
+    }
+
+    /// Buffer a record. It's durable once `commit` or `sync` fsyncs it.
+    pub fn append(&mut self, record: &T) -> io::Result<()> {
+        let payload = serde_json::to_vec(record)?;
+        let len = u32::try_from(payload.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Record is too large."))?;
+
+        self.file.write_all(&len.to_le_bytes())?;
+        self.file.write_all(&crc32(&payload).to_le_bytes())?;
+        self.file.write_all(&payload)?;
+
+        self.unsynced.get_or_insert_with(Instant::now);
+
+        Ok(())
+    }
+
+    /// Fsync the buffered records if the oldest has waited for the group commit window, returning
+    /// whether they were.
+    pub fn commit(&mut self) -> io::Result<bool> {
+        match self.unsynced {
+            Some(since) if since.elapsed() >= self.window => self.sync().map(|()| true),
+            _ => Ok(false),
+        }
+    }
+
+    /// Fsync the buffered records now.
+    pub fn sync(&mut self) -> io::Result<()> {
+        if self.unsynced.is_none() {
+            return Ok(());
+        }
+
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        self.unsynced = None;
+
+        Ok(())
+    }
+
+    /// Whether every appended record is durable.
+    pub fn is_synced(&self) -> bool {
+        self.unsynced.is_none()
+    }
+
+    /// Replace the log with `records`, e.g. once older ones are compacted into a snapshot. The
+    /// new log is written beside the old one and renamed over it, so a crash leaves one or the
+    /// other.
+    pub fn rewrite(&mut self, records: &[T]) -> io::Result<()> {
+        self.sync()?;
+
+        let partial = self.path.with_extension("wal.tmp");
+        let _ = std::fs::remove_file(&partial);
+        let (mut wal, _) = Wal::<T>::open(&partial, self.window)?;
+
+        for record in records {
+            wal.append(record)?;
+        }
+
+        wal.sync()?;
+        std::fs::rename(&partial, &self.path)?;
+        sync_dir(&self.path)?;
+
+        wal.path = self.path.clone();
+        *self = wal;
+
+        Ok(())
+    }
+}
+
+/// Fsync the directory `path` is in, so a file just created or renamed there survives a crash.
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    File::open(dir)?.sync_all()
+}
+
+/// CRC-32 (IEEE), as used by zlib and Ethernet.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in bytes {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Record {
+        term: u64,
+        key: String,
+    }
+
+    fn record(term: u64) -> Record {
+        Record {
+            term,
+            key: format!("key-{term}"),
+        }
+    }
+
+    #[test]
+    fn replays_records_up_to_a_corrupt_one() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let path = std::env::temp_dir().join(format!("tranquility-{}.wal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let (mut wal, records) = Wal::<Record>::open(&path, Duration::ZERO).unwrap();
+        assert!(records.is_empty());
+
+        for term in 1..=3 {
+            wal.append(&record(term)).unwrap();
+        }
+        assert!(wal.commit().unwrap());
+        assert!(wal.is_synced());
+        drop(wal);
+
+        let (_, records) = Wal::<Record>::open(&path, Duration::ZERO).unwrap();
+        assert_eq!(records, [record(1), record(2), record(3)]);
+
+        // Flip a byte in the last record's payload.
+        let mut contents = std::fs::read(&path).unwrap();
+        let last = contents.len() - 2;
+        contents[last] ^= 0xFF;
+        std::fs::write(&path, contents).unwrap();
+
+        let (mut wal, records) = Wal::<Record>::open(&path, Duration::ZERO).unwrap();
+        assert_eq!(records, [record(1), record(2)]);
+
+        wal.rewrite(&[record(5)]).unwrap();
+        wal.append(&record(6)).unwrap();
+        wal.sync().unwrap();
+        drop(wal);
+
+        let (_, records) = Wal::<Record>::open(&path, Duration::ZERO).unwrap();
+        assert_eq!(records, [record(5), record(6)]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn waits_for_the_group_commit_window() {
+        let path =
+            std::env::temp_dir().join(format!("tranquility-{}-window.wal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let (mut wal, _) = Wal::<Record>::open(&path, Duration::from_secs(60)).unwrap();
+        wal.append(&record(1)).unwrap();
+        wal.append(&record(2)).unwrap();
+        assert!(!wal.commit().unwrap());
+        assert!(!wal.is_synced());
+
+        wal.sync().unwrap();
+        assert!(wal.is_synced());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,297 @@
+use std::sync::Arc;
+
+#[cfg(feature = "broadcast")]
+use crate::broadcast::BroadcastWorkload;
+use crate::core::message::{Message, MessageBody, ReadBody};
+#[cfg(feature = "kv")]
+use crate::handlers::KvHandler;
+#[cfg(feature = "raft")]
+use crate::handlers::RaftHandler;
+#[cfg(any(feature = "counter", feature = "kv"))]
+use crate::handlers::ReplicateHandler;
+#[cfg(feature = "counter")]
+use crate::handlers::{AddHandler, CounterReadHandler};
+#[cfg(feature = "kafka")]
+use crate::handlers::{
+    CommitOffsetsHandler, ListCommittedOffsetsHandler, PollHandler, SendHandler,
+};
+use crate::handlers::{
+    CrashHandler, DebugStateHandler, EchoHandler, ErrorHandler, GenerateHandler, HeartbeatHandler,
+    InitHandler, MetricsHandler, PeerStatusHandler, TimerHandler,
+};
+#[cfg(feature = "txn")]
+use crate::handlers::{
+    TxnAbortHandler, TxnCommitHandler, TxnHandler, TxnPrepareHandler, TxnStatusHandler,
+};
+use crate::node::{Handler, Node, Registry};
+
+/// A Maelstrom workload the node can serve. Several can be active at once; each registers the
+/// handlers for its own message types and keeps its own state on the node.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Workload {
+    Echo,
+    UniqueIds,
+    Broadcast,
+    GCounter,
+    PnCounter,
+    Kafka,
+    Kv,
+    Txn,
+}
+
+impl Workload {
+    pub const ALL: [Workload; 8] = [
+        Workload::Echo,
+        Workload::UniqueIds,
+        Workload::Broadcast,
+        Workload::GCounter,
+        Workload::PnCounter,
+        Workload::Kafka,
+        Workload::Kv,
+        Workload::Txn,
+    ];
+
+    pub fn parse(name: &str) -> Option<Workload> {
+        match name {
+            "echo" => Some(Workload::Echo),
+            "unique-ids" => Some(Workload::UniqueIds),
+            "broadcast" => Some(Workload::Broadcast),
+            "g-counter" => Some(Workload::GCounter),
+            "pn-counter" => Some(Workload::PnCounter),
+            "kafka" => Some(Workload::Kafka),
+            "kv" => Some(Workload::Kv),
+            "txn" => Some(Workload::Txn),
+            _ => None,
+        }
+    }
+
+    /// Whether the binary was built with the workload's cargo feature. Echo and unique-ids are
+    /// always built.
+    pub fn is_built(&self) -> bool {
+        match self {
+            Workload::Broadcast => cfg!(feature = "broadcast"),
+            Workload::GCounter | Workload::PnCounter => cfg!(feature = "counter"),
+            Workload::Kafka => cfg!(feature = "kafka"),
+            Workload::Kv => cfg!(feature = "kv"),
+            Workload::Txn => cfg!(feature = "txn"),
+            Workload::Echo | Workload::UniqueIds => true,
+        }
+    }
+
+    /// The request types clients send to this workload.
+    pub fn requests(&self) -> &'static [&'static str] {
+        match self {
+            Workload::Echo => &["echo"],
+            Workload::UniqueIds => &["generate"],
+            Workload::Broadcast => &["broadcast", "read"],
+            Workload::GCounter | Workload::PnCounter => &["add", "read"],
+            Workload::Kafka => &["send", "poll", "commit_offsets", "list_committed_offsets"],
+            Workload::Kv => &["read", "write", "cas"],
+            Workload::Txn => &["txn"],
+        }
+    }
+
+    /// Whether the node has what it needs to answer this workload's requests: broadcast needs
+    /// the topology, and every workload needs `init`.
+    pub fn is_ready(&self, node: &Node) -> bool {
+        match self {
+            #[cfg(feature = "broadcast")]
+            Workload::Broadcast => node.id.is_some() && !node.broadcast.overlay.0.is_empty(),
+            _ => node.id.is_some(),
+        }
+    }
+
+    /// Whether a `read` several workloads share is meant for this one: kv reads name a key, and
+    /// the broadcast and counter reads don't.
+    pub fn claims_read(&self, body: &ReadBody) -> bool {
+        match self {
+            Workload::Kv => body.key.is_some(),
+            _ => body.key.is_none(),
+        }
+    }
+
+    /// Register the workload's handlers. A workload whose feature is off registers none, so its
+    /// requests are answered `not-supported`.
+    #[allow(unreachable_patterns)]
+    fn register(&self, registry: &mut Registry) {
+        match self {
+            Workload::Echo => registry.register("echo", EchoHandler),
+            Workload::UniqueIds => registry.register("generate", GenerateHandler),
+            #[cfg(feature = "broadcast")]
+            Workload::Broadcast => registry.host(BroadcastWorkload),
+            // Both counters share the node's PN-counter; a g-counter never decrements it.
+            #[cfg(feature = "counter")]
+            Workload::GCounter | Workload::PnCounter => {
+                registry.register("add", AddHandler);
+                registry.register("read", CounterReadHandler);
+                registry.register("internal_replicate", ReplicateHandler);
+            }
+            #[cfg(feature = "kafka")]
+            Workload::Kafka => {
+                registry.register("send", SendHandler);
+                registry.register("poll", PollHandler);
+                registry.register("commit_offsets", CommitOffsetsHandler);
+                registry.register("list_committed_offsets", ListCommittedOffsetsHandler);
+            }
+            #[cfg(feature = "kv")]
+            Workload::Kv => {
+                registry.register("read", KvHandler);
+                registry.register("write", KvHandler);
+                registry.register("cas", KvHandler);
+                // Entries arrive this way in the `lww` mode.
+                registry.register("internal_replicate", ReplicateHandler);
+
+                #[cfg(feature = "raft")]
+                {
+                    registry.register("internal_request_vote", RaftHandler);
+                    registry.register("internal_request_vote_res", RaftHandler);
+                    registry.register("internal_append_entries", RaftHandler);
+                    registry.register("internal_append_entries_res", RaftHandler);
+                    registry.register("internal_install_snapshot", RaftHandler);
+                }
+            }
+            #[cfg(feature = "txn")]
+            Workload::Txn => {
+                registry.register("txn", TxnHandler);
+                registry.register("internal_txn_prepare", TxnPrepareHandler);
+                registry.register("internal_txn_commit", TxnCommitHandler);
+                registry.register("internal_txn_abort", TxnAbortHandler);
+                registry.register("internal_txn_status", TxnStatusHandler);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Routes a `read` to the first of the workloads sharing it, in the configured order, that claims
+/// it, or else to the first of them.
+struct SharedRead(Vec<(Workload, Arc<dyn Handler>)>);
+
+impl Handler for SharedRead {
+    fn handle(&self, node: &mut Node, message: Message) -> Vec<Message<'static>> {
+        let MessageBody::Read(body) = &message.body else {
+            return vec![];
+        };
+
+        let handler = self
+            .0
+            .iter()
+            .find(|(workload, _)| workload.claims_read(body))
+            .or(self.0.first())
+            .map(|(_, handler)| handler.clone());
+
+        handler.map_or_else(Vec::new, |handler| handler.handle(node, message))
+    }
+}
+
+/// Whether a workload replies as soon as a request is handled locally, or only once it has been
+/// replicated. Strict replies trade latency for the guarantee that an acknowledged write survives
+/// the node.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplyModes {
+    strict: Vec<Workload>,
+}
+
+impl ReplyModes {
+    pub fn strict(workloads: &[Workload]) -> Self {
+        ReplyModes {
+            strict: workloads.to_vec(),
+        }
+    }
+
+    pub fn is_strict(&self, workload: Workload) -> bool {
+        self.strict.contains(&workload)
+    }
+}
+
+impl Registry {
+    /// The handlers shared by every workload, plus each workload's own. When workloads claim the
+    /// same message type, the one listed first keeps it, except for `read`, which goes to the
+    /// workload that `Workload::claims_read` picks.
+    pub fn for_workloads(workloads: &[Workload]) -> Self {
+        let mut registry = Registry::empty();
+
+        for workload in workloads.iter().rev() {
+            workload.register(&mut registry);
+        }
+
+        let readers: Vec<_> = workloads
+            .iter()
+            .filter_map(|workload| {
+                let mut own = Registry::empty();
+                workload.register(&mut own);
+
+                Some((*workload, own.get("read")?))
+            })
+            .collect();
+
+        if readers.len() > 1 {
+            registry.register("read", SharedRead(readers));
+        }
+
+        registry.register("init", InitHandler);
+        registry.register("error", ErrorHandler);
+        registry.register("metrics", MetricsHandler);
+        registry.register("peer_status", PeerStatusHandler);
+        registry.register("crash", CrashHandler);
+        registry.register("debug_state", DebugStateHandler);
+        registry.register("internal_heartbeat", HeartbeatHandler);
+        registry.register("internal_timer", TimerHandler);
+
+        registry
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(all(feature = "broadcast", feature = "counter", feature = "kv"))]
+    use crate::testing::NodeTestFixture;
+    #[cfg(all(feature = "broadcast", feature = "counter", feature = "kv"))]
+    use serde_json::json;
+
+    #[test]
+    fn registers_only_the_active_workloads() {
+        let registry = Registry::for_workloads(&[Workload::Echo]);
+
+        assert!(registry.get("init").is_some());
+        assert!(registry.get("echo").is_some());
+        assert!(registry.get("broadcast").is_none());
+        assert_eq!(Workload::parse("unique-ids"), Some(Workload::UniqueIds));
+    }
+
+    #[test]
+    #[cfg(all(feature = "broadcast", feature = "counter", feature = "kv"))]
+    fn routes_shared_reads_by_the_configured_workloads() {
+        let counters_first = Node {
+            registry: Registry::for_workloads(&[
+                Workload::GCounter,
+                Workload::Broadcast,
+                Workload::Kv,
+            ]),
+            ..Default::default()
+        };
+
+        NodeTestFixture::with_node(counters_first)
+            .init("n1", ["n1"])
+            .send(json!({"type": "add", "delta": 3}))
+            .expect_reply("add_ok")
+            .send(json!({"type": "read"}))
+            .expect_reply_with("read_ok", |body| {
+                assert_eq!(body["value"], 3);
+                assert!(body.get("messages").is_none());
+            })
+            .send(json!({"type": "read", "key": 1}))
+            .expect_reply_with("error", |body| assert_eq!(body["code"], 20));
+
+        NodeTestFixture::new()
+            .init("n1", ["n1"])
+            .send(json!({"type": "add", "delta": 3}))
+            .expect_reply("add_ok")
+            .send(json!({"type": "read"}))
+            .expect_reply_with("read_ok", |body| {
+                assert_eq!(body["messages"], json!([]));
+                assert!(body.get("value").is_none());
+            });
+    }
+}
@@ -0,0 +1,170 @@
+//! Replays the captured Maelstrom sessions in `tests/corpus`, one file per workload, and checks
+//! that every message parses and every reply follows the protocol. The same sessions, mutated at
+//! random, seed a quick fuzz pass; `fuzz/` has the coverage-guided one.
+
+use std::fs;
+use std::panic;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tranquility::actor::NodeHandle;
+use tranquility::core::message::Message;
+use tranquility::core::state;
+use tranquility::simulation::Rng;
+use tranquility::workload::Workload;
+use tranquility::{Node, Registry};
+
+async fn replay(path: &Path) {
+    let name = path.file_stem().unwrap().to_str().unwrap();
+    let workload = Workload::parse(name).unwrap_or_else(|| panic!("{name} isn't a workload."));
+
+    if !workload.is_built() {
+        return;
+    }
+
+    let node = NodeHandle::spawn(Node {
+        registry: Registry::for_workloads(&[workload]),
+        ..Default::default()
+    });
+
+    let session = fs::read_to_string(path).unwrap();
+
+    for (line, request) in session.lines().enumerate() {
+        let context = format!("{}:{}", path.display(), line + 1);
+        let parsed = state::parse(request).unwrap_or_else(|err| panic!("{context}: {err}"));
+        let replies = Node::handle_from_stdin(node.clone(), request)
+            .await
+            .unwrap();
+
+        let (node_id, node_ids) = node
+            .call(|node| (node.id.clone(), node.node_ids.clone()))
+            .await
+            .unwrap();
+        let client = !node_ids.contains(parsed.src.as_ref().unwrap());
+        let mut answered = 0;
+
+        for reply in replies {
+            let reply: Message = serde_json::from_str(&reply).unwrap();
+
+            assert_eq!(reply.src, node_id, "{context}: {reply:?}");
+
+            // Anything that isn't a reply is gossip to a peer.
+            let Some(in_reply_to) = reply.body.in_reply_to() else {
+                assert!(node_ids.contains(&reply.dest), "{context}: {reply:?}");
+                continue;
+            };
+
+            let expected = format!("{}_ok", parsed.body.kind());
+            let kind = reply.body.kind();
+
+            assert_eq!(
+                Some(&reply.dest),
+                parsed.src.as_ref(),
+                "{context}: {reply:?}"
+            );
+            assert_eq!(
+                Some(in_reply_to),
+                parsed.body.msg_id(),
+                "{context}: {reply:?}"
+            );
+            assert!(kind == expected || kind == "error", "{context}: {reply:?}");
+
+            answered += 1;
+        }
+
+        // Clients wait on every request they send.
+        if client && parsed.body.msg_id().is_some() {
+            assert_eq!(answered, 1, "{context}: expected exactly one reply");
+        }
+    }
+}
+
+/// Set by any panic in this test binary, including those the node's task catches.
+static PANICKED: AtomicBool = AtomicBool::new(false);
+
+fn corpus() -> Vec<std::path::PathBuf> {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut files: Vec<_> = fs::read_dir(corpus)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+
+    files.sort();
+    assert!(!files.is_empty());
+
+    files
+}
+
+/// `line` with a few random bytes flipped, dropped, duplicated, or cut off.
+fn mutate(rng: &mut Rng, line: &[u8]) -> Vec<u8> {
+    const INTERESTING: &[&[u8]] = &[
+        b"{", b"}", b"[", b"\"", b"\\", b"null", b"-1", b"1e999", b"\n",
+    ];
+
+    let mut bytes = line.to_vec();
+
+    for _ in 0..1 + rng.below(4) {
+        let at = rng.below(bytes.len() as u64 + 1) as usize;
+
+        match rng.below(5) {
+            0 if at < bytes.len() => bytes[at] = rng.next_u64() as u8,
+            1 if at < bytes.len() => {
+                bytes.remove(at);
+            }
+            2 => bytes.truncate(at),
+            3 => {
+                let token = INTERESTING[rng.below(INTERESTING.len() as u64) as usize];
+                bytes.splice(at..at, token.iter().copied());
+            }
+            _ => {
+                let from = rng.below(bytes.len() as u64 + 1) as usize;
+                let chunk = bytes[from.min(at)..from.max(at)].to_vec();
+                bytes.splice(at..at, chunk);
+            }
+        }
+    }
+
+    bytes
+}
+
+#[tokio::test]
+async fn replays_the_corpus() {
+    for path in &corpus() {
+        replay(path).await;
+    }
+}
+
+#[tokio::test]
+async fn survives_mutated_sessions() {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        PANICKED.store(true, Ordering::Relaxed);
+        default(info);
+    }));
+
+    let mut rng = Rng::new(824);
+
+    for path in &corpus() {
+        let session = fs::read(path).unwrap();
+        let lines: Vec<&[u8]> = session.split(|&byte| byte == b'\n').collect();
+
+        for _ in 0..200 {
+            let node = NodeHandle::spawn(Node::default());
+
+            // An intact init now and then, so mutations reach the handlers past it.
+            if rng.chance(0.5) {
+                let _ =
+                    Node::handle_from_stdin(node.clone(), std::str::from_utf8(lines[0]).unwrap())
+                        .await;
+            }
+
+            let line = lines[rng.below(lines.len() as u64) as usize];
+            let input = String::from_utf8_lossy(&mutate(&mut rng, line)).into_owned();
+
+            // An error is fine; a panic isn't, even one the node's task catches.
+            let _ = Node::handle_from_stdin(node.clone(), &input).await;
+            node.call(|_| ()).await.unwrap();
+
+            assert!(!PANICKED.load(Ordering::Relaxed), "{input:?}");
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use tokio::sync::mpsc;
+use tokio_util::task::TaskTracker;
+use tranquility::actor::NodeHandle;
+#[cfg(feature = "broadcast")]
+use tranquility::broadcast::BroadcastState;
+use tranquility::core::message::{Message, MessageBody};
+#[cfg(feature = "broadcast")]
+use tranquility::core::state::BroadcastStore;
+use tranquility::Node;
+
+/// Run a node over channels, send it `input`, close stdin, and collect everything it sends until
+/// it shuts down.
+async fn run(input: &str) -> Vec<Message<'static>> {
+    let (tx, rx) = mpsc::channel(10);
+    let (response_tx, mut response_rx) = mpsc::channel(10);
+
+    let tracker = TaskTracker::new();
+    let node = NodeHandle::spawn(Node {
+        id: None,
+        ..Default::default()
+    });
+
+    let handler = {
+        let tracker = tracker.clone();
+
+        tokio::spawn(async move { Node::run(node, rx, response_tx, &tracker).await })
+    };
+
+    // Read responses while the node runs, so it never waits on a full channel.
+    let collector = tokio::spawn(async move {
+        let mut responses = vec![];
+
+        while let Some(response) = response_rx.recv().await {
+            let message = serde_json::from_str::<Message>(&response)
+                .unwrap_or_else(|err| panic!("{response}: {err}"));
+            responses.push(message.into_owned());
+        }
+
+        responses
+    });
+
+    tx.send(input.to_string()).await.unwrap();
+    drop(tx);
+
+    handler.await.unwrap();
+    collector.await.unwrap()
+}
+
+/// The one reply of type `kind` to the client's request `msg_id`.
+fn reply<'a>(responses: &'a [Message<'static>], kind: &str, msg_id: u32) -> &'a Message<'static> {
+    let replies: Vec<&Message> = responses
+        .iter()
+        .filter(|message| message.dest == "c1" && message.body.in_reply_to() == Some(msg_id))
+        .collect();
+
+    assert_eq!(replies.len(), 1, "{responses:?}");
+    assert_eq!(replies[0].body.kind(), kind, "{:?}", replies[0]);
+
+    replies[0]
+}
+
+const INIT: &str = r#"{"id": 508799, "src": "c1", "dest": "n3", "body": {"type": "init", "msg_id": 1, "in_reply_to": null, "node_id": "n1", "node_ids": ["n1", "n2", "n3"]}}"#;
+
+#[tokio::test]
+async fn responds_with_init_message() {
+    let responses = run(INIT).await;
+
+    let init_ok = reply(&responses, "init_ok", 1);
+    assert_eq!(init_ok.src.as_deref(), Some("n1"));
+}
+
+#[tokio::test]
+async fn responds_to_generate_message() {
+    let message = concat!(
+        r#"{"id": 500005, "src": "c1", "dest": "n3", "body": {"type": "generate", "msg_id": 2 }}"#,
+        "\n",
+        r#"{"id": 500006, "src": "c1", "dest": "n3", "body": {"type": "generate", "msg_id": 3 }}"#,
+    );
+
+    let responses = run(&format!("{INIT}\n{message}")).await;
+
+    let ids = [2, 3].map(
+        |msg_id| match &reply(&responses, "generate_ok", msg_id).body {
+            MessageBody::GenerateOk(body) => body.id.clone(),
+            _ => unreachable!(),
+        },
+    );
+    assert_ne!(ids[0], ids[1]);
+}
+
+#[tokio::test]
+#[cfg(feature = "broadcast")]
+async fn responds_to_broadcast_message() {
+    let message = r#"{"id": 508799, "src": "c1", "dest": "n3", "body": {"type": "init", "msg_id": 1, "in_reply_to": null, "node_id": "n1", "node_ids": ["n1", "n2", "n3"]}}
+        {"id": 100000, "src": "c1", "dest": "n3", "body": {"type": "topology", "msg_id": 2, "topology": { "n1": ["n2", "n3"], "n2": ["n1"], "n3": ["n1"] } }}
+        {"id": 100000, "src": "c1", "dest": "n3", "body": {"type": "broadcast", "message": 1000, "msg_id": 3 }}"#;
+
+    let responses = run(message).await;
+
+    reply(&responses, "broadcast_ok", 3);
+
+    // The value is gossiped to both neighbors.
+    for neighbor in ["n2", "n3"] {
+        assert!(
+            responses.iter().any(|message| message.dest == neighbor
+                && matches!(&message.body, MessageBody::Broadcast(body) if body.message == Some(1000u32.into()))),
+            "{neighbor}: {responses:?}"
+        );
+    }
+}
+
+#[tokio::test]
+#[cfg(feature = "broadcast")]
+async fn responds_to_read_message() {
+    let message = concat!(
+        r#"{"id": 100000, "src": "c1", "dest": "n3", "body": { "type": "broadcast", "message": 1000, "msg_id": 2 }}"#,
+        "\n",
+        r#"{"id": 100001, "src": "c1", "dest": "n3", "body": { "type": "read", "msg_id": 3 }}"#,
+    );
+
+    let responses = run(&format!("{INIT}\n{message}")).await;
+
+    let MessageBody::ReadOk(body) = &reply(&responses, "read_ok", 3).body else {
+        unreachable!();
+    };
+    let messages: Vec<_> = body.messages.as_ref().unwrap().iter().collect();
+    assert_eq!(messages, [1000u32.into()]);
+}
+
+#[tokio::test]
+#[cfg(feature = "broadcast")]
+async fn responds_to_topology_message() {
+    let message = r#"{"id": 100000, "src": "c1", "dest": "n3", "body": {"type": "topology", "msg_id": 2, "topology": { "n1": ["n2", "n3"], "n2": ["n1"], "n3": ["n1"] } }}"#;
+
+    let responses = run(&format!("{INIT}\n{message}")).await;
+
+    reply(&responses, "topology_ok", 2);
+}
+
+#[tokio::test]
+async fn handles_every_message_in_a_chunk() {
+    let node = NodeHandle::spawn(Node {
+        id: Some("n1".to_string()),
+        ..Default::default()
+    });
+
+    let chunk = concat!(
+        r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": "a", "msg_id": 1}}"#,
+        r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": "b", "msg_id": 2}}"#,
+        "\n",
+        r#"{"src": "c1", "dest": "n1", "body": {"type": "echo", "echo": "c", "msg_id": 3}}"#,
+    );
+
+    let responses = Node::handle_from_stdin(node, chunk).await.unwrap();
+
+    assert_eq!(responses.len(), 3);
+    assert!(responses[2].contains(r#""in_reply_to":3"#));
+}
+
+#[tokio::test]
+async fn echoes_any_json_payload() {
+    let node = NodeHandle::spawn(Node {
+        id: Some("n1".to_string()),
+        ..Default::default()
+    });
+
+    let echo = r#"{"flag":true,"nested":{"list":[1,"two",null,{"three":3.5}]}}"#;
+    let message = format!(
+        r#"{{"src": "c1", "dest": "n1", "body": {{"type": "echo", "echo": {echo}, "msg_id": 1}}}}"#
+    );
+
+    let responses = Node::handle_from_stdin(node, &message).await.unwrap();
+
+    assert!(responses[0].contains(&format!(r#""echo":{echo}"#)));
+}
+
+#[tokio::test]
+#[cfg(feature = "broadcast")]
+async fn reads_from_a_snapshot_of_the_store() {
+    let node = NodeHandle::spawn(Node {
+        id: Some("n1".to_string()),
+        broadcast: BroadcastState {
+            messages: BroadcastStore::from([1000]),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    let message =
+        r#"{"src": "c1", "dest": "n1", "body": { "type": "read", "msg_id": 1 }}"#.to_string();
+
+    let responses = Node::handle_from_stdin(node.clone(), &message)
+        .await
+        .unwrap();
+    let response = &responses[0];
+
+    assert!(response.contains(r#""type":"read_ok""#));
+    assert!(response.contains(r#""messages":[1000]"#));
+    assert_eq!(node.call(|node| node.current_message_id).await, Ok(1));
+}
+
+#[tokio::test]
+#[cfg(feature = "broadcast")]
+async fn passes_the_self_test() {
+    assert!(tranquility::selftest::run().await);
+}
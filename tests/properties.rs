@@ -0,0 +1,268 @@
+//! Property tests: random client requests are fed to a small cluster, and every message that
+//! crosses the wire, between nodes or back to a client, is checked to survive a
+//! serialize→deserialize round trip unchanged, and every reply to a client to answer a request it
+//! made. Requests, timers, and delivery order are proptest strategies, so a failing run is shrunk
+//! to the fewest steps that still fail, and saved for the next run to try first.
+
+use proptest::prelude::*;
+use proptest::sample::{select, Index};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use tranquility::config::Config;
+use tranquility::core::message::{Message, MessageBody};
+use tranquility::core::state;
+use tranquility::kv::KvMode;
+use tranquility::timer::TimerEvent;
+use tranquility::Node;
+
+const NODES: [&str; 3] = ["n1", "n2", "n3"];
+const CASES: u32 = 32;
+const STEPS: std::ops::Range<usize> = 150..250;
+const KV_MODES: [KvMode; 5] = [
+    KvMode::Linearizable,
+    KvMode::Lww,
+    KvMode::Session,
+    KvMode::Sharded,
+    KvMode::Raft,
+];
+const TIMERS: [TimerEvent; 6] = [
+    TimerEvent::RaftTick,
+    TimerEvent::Retry,
+    TimerEvent::Replicate,
+    TimerEvent::AntiEntropy,
+    TimerEvent::Heartbeat,
+    TimerEvent::ExpireTransactions,
+];
+
+/// Something that happens to the cluster.
+#[derive(Clone, Debug)]
+enum Step {
+    /// A client sends a request body to a node; its `msg_id` is given as it's sent.
+    Request {
+        client: &'static str,
+        dest: &'static str,
+        body: Value,
+    },
+    /// A timer fires, for elections, resends, replication, and anti-entropy.
+    Fire {
+        node: &'static str,
+        event: TimerEvent,
+    },
+    /// One of the messages in flight is delivered.
+    Deliver(Index),
+}
+
+/// A small key, so requests for the same key meet.
+fn key() -> impl Strategy<Value = Value> {
+    (0..4u64).prop_map(|key| json!(key))
+}
+
+fn value() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        (0..1000u64).prop_map(|n| json!(n)),
+        (0..1000u64).prop_map(|n| json!(format!("v{n}"))),
+        (0..10u64).prop_map(|n| json!({"nested": [n, null, true]})),
+        (0..1000i64).prop_map(|n| json!(-n)),
+    ]
+}
+
+fn offsets() -> impl Strategy<Value = Value> {
+    (0..5u64, 0..5u64).prop_map(|(k1, k2)| json!({ "k1": k1, "k2": k2 }))
+}
+
+/// The body of a random request a Maelstrom client could send.
+fn request() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        value().prop_map(|value| json!({"type": "echo", "echo": value})),
+        value().prop_map(|value| json!({"type": "broadcast", "message": value})),
+        Just(json!({"type": "read"})),
+        key().prop_map(|key| json!({"type": "read", "key": key})),
+        Just(
+            json!({"type": "topology", "topology": {"n1": ["n2"], "n2": ["n1", "n3"], "n3": ["n2"]}})
+        ),
+        Just(json!({"type": "generate"})),
+        (-3..7i64).prop_map(|delta| json!({"type": "add", "delta": delta})),
+        (select(vec!["k1", "k2"]), value())
+            .prop_map(|(key, msg)| json!({"type": "send", "key": key, "msg": msg})),
+        offsets().prop_map(|offsets| json!({"type": "poll", "offsets": offsets})),
+        offsets().prop_map(|offsets| json!({"type": "commit_offsets", "offsets": offsets})),
+        Just(json!({"type": "list_committed_offsets", "keys": ["k1", "k2"]})),
+        (key(), value())
+            .prop_map(|(key, value)| json!({"type": "write", "key": key, "value": value})),
+        (key(), value(), value())
+            .prop_map(|(key, from, to)| json!({"type": "cas", "key": key, "from": from, "to": to})),
+        (key(), key(), 0..100u64).prop_map(|(read, write, value)| {
+            json!({"type": "txn", "txn": [["r", read, null], ["w", write, value]]})
+        }),
+        Just(json!({"type": "metrics"})),
+        Just(json!({"type": "peer_status"})),
+        Just(json!({"type": "debug_state"})),
+        Just(json!({"type": "topology_report"})),
+    ]
+}
+
+/// Requests and timers each come about a third as often as deliveries.
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        3 => (select(vec!["c1", "c2", "c3"]), select(NODES.to_vec()), request())
+            .prop_map(|(client, dest, body)| Step::Request { client, dest, body }),
+        3 => (select(NODES.to_vec()), select(TIMERS.to_vec()))
+            .prop_map(|(node, event)| Step::Fire { node, event }),
+        10 => any::<Index>().prop_map(Step::Deliver),
+    ]
+}
+
+fn parse(message: Value) -> Message<'static> {
+    state::parse(&message.to_string())
+        .map(Message::into_owned)
+        .unwrap_or_else(|err| panic!("{message}: {err}"))
+}
+
+/// A message as JSON, with the broadcast value sets `read_ok` and `internal_sync` carry sorted;
+/// they're written in whatever order the set iterates in.
+fn canonical(message: &Message) -> Value {
+    let mut value = serde_json::to_value(message).unwrap();
+
+    if matches!(message.body.kind(), "read_ok" | "internal_sync") {
+        if let Some(Value::Array(values)) = value["body"].get_mut("messages") {
+            values.sort_by_key(|value| value.to_string());
+        }
+    }
+
+    value
+}
+
+fn assert_round_trips(message: &Message) {
+    let json = serde_json::to_string(message).unwrap();
+    let parsed: Message = serde_json::from_str(&json).unwrap_or_else(|err| panic!("{json}: {err}"));
+
+    assert_eq!(parsed.body.kind(), message.body.kind(), "{json}");
+    assert_eq!(canonical(&parsed), canonical(message), "{json}");
+}
+
+fn run(kv_mode: KvMode, steps: Vec<Step>) {
+    let mut nodes: HashMap<String, Node> = NODES
+        .iter()
+        .map(|id| {
+            let node = Node {
+                config: Config {
+                    kv_mode,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            (id.to_string(), node)
+        })
+        .collect();
+
+    // What each client asked, and which node it asked.
+    let mut asked: HashMap<(String, u32), String> = HashMap::new();
+    let mut in_flight: Vec<Message<'static>> = vec![];
+    let mut kinds = HashSet::new();
+
+    // Maelstrom initializes every node before sending anything else.
+    for (msg_id, id) in NODES.iter().enumerate() {
+        let init = parse(json!({
+            "src": "c0", "dest": id,
+            "body": {"type": "init", "node_id": id, "node_ids": NODES, "msg_id": msg_id},
+        }));
+        assert_round_trips(&init);
+        asked.insert(("c0".to_string(), msg_id as u32), id.to_string());
+
+        in_flight.extend(nodes.get_mut(*id).unwrap().dispatch(init));
+    }
+
+    let mut requests = 0;
+    let mut steps = steps.into_iter();
+
+    loop {
+        let message = match steps.next() {
+            Some(Step::Request {
+                client,
+                dest,
+                mut body,
+            }) => {
+                requests += 1;
+
+                let msg_id = requests as u32;
+                body["msg_id"] = json!(msg_id);
+
+                in_flight.push(parse(json!({"src": client, "dest": dest, "body": body})));
+                asked.insert((client.to_string(), msg_id), dest.to_string());
+                continue;
+            }
+            Some(Step::Fire { node, event }) => {
+                let node = nodes.get_mut(node).unwrap();
+                in_flight.extend(node.fire(&event));
+                in_flight.extend(node.run_tasks());
+                continue;
+            }
+            Some(Step::Deliver(_)) if in_flight.is_empty() => continue,
+            Some(Step::Deliver(index)) => in_flight.swap_remove(index.index(in_flight.len())),
+            // Once the steps run out, what's still in flight is delivered in order.
+            None if in_flight.is_empty() => break,
+            None => in_flight.remove(0),
+        };
+
+        assert_round_trips(&message);
+        kinds.insert(message.body.kind());
+
+        let Some(node) = nodes.get_mut(&message.dest) else {
+            // A reply to a client.
+            let Some(in_reply_to) = message.body.in_reply_to() else {
+                panic!("a client got a message that isn't a reply: {message:?}");
+            };
+
+            let asked_node = asked.get(&(message.dest.clone(), in_reply_to));
+            assert_eq!(
+                asked_node,
+                message.src.as_ref(),
+                "{message:?} doesn't answer a request sent to its src"
+            );
+            continue;
+        };
+
+        let request = message.body.msg_id();
+        let immediate = matches!(
+            message.body,
+            MessageBody::Echo(_) | MessageBody::Generate(_) | MessageBody::Topology(_)
+        );
+        let client = message.src.clone();
+
+        let replies = node.dispatch(message);
+
+        if immediate {
+            let answers: Vec<_> = replies
+                .iter()
+                .filter(|reply| reply.dest == *client.as_ref().unwrap())
+                .collect();
+
+            assert_eq!(answers.len(), 1, "{replies:?}");
+            assert_eq!(answers[0].body.in_reply_to(), request);
+        }
+
+        in_flight.extend(replies);
+    }
+
+    // Make sure a run with a client's worth of requests exercised node-to-node traffic, not just
+    // clients; a shrunk run may be too short to.
+    if requests >= 20 {
+        assert!(
+            kinds.iter().any(|kind| kind.starts_with("internal_")),
+            "{kinds:?}"
+        );
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    #[test]
+    fn messages_round_trip_and_replies_answer_their_requests(
+        kv_mode in select(KV_MODES.to_vec()),
+        steps in prop::collection::vec(step(), STEPS),
+    ) {
+        run(kv_mode, steps);
+    }
+}
@@ -0,0 +1,89 @@
+//! Broadcast convergence under seeded delays, drops, and partitions. A failure names its seed;
+//! rerun just that seed with `TRANQUILITY_SIM_SEED=<seed> cargo test --test simulation`.
+
+use std::collections::HashSet;
+use tranquility::causal::CausalBroadcast;
+use tranquility::core::message::Message;
+use tranquility::simulation::{Partition, Rng, Simulation, SimulationConfig};
+
+fn message(json: &str) -> Message<'static> {
+    serde_json::from_str::<Message>(json).unwrap().into_owned()
+}
+
+fn converges(seed: u64, causal: bool) {
+    let node_ids = ["n1", "n2", "n3", "n4", "n5"];
+
+    let mut simulation = Simulation::new(
+        SimulationConfig {
+            seed,
+            drop_rate: 0.2,
+            partitions: vec![Partition {
+                from: 0,
+                until: 100,
+                nodes: HashSet::from(["n1".to_string(), "n2".to_string()]),
+            }],
+            ..Default::default()
+        },
+        &node_ids,
+    );
+
+    if causal {
+        for node in simulation.nodes.values_mut() {
+            node.broadcast.causal = Some(CausalBroadcast::default());
+        }
+    }
+
+    // A line: n1 - n2 - n3 - n4 - n5.
+    for node_id in node_ids {
+        simulation.send(message(&format!(
+            r#"{{"src": "c0", "dest": "{node_id}", "body": {{"type": "topology", "topology": {{"n1": ["n2"], "n2": ["n1", "n3"], "n3": ["n2", "n4"], "n4": ["n3", "n5"], "n5": ["n4"]}}, "msg_id": 1}}}}"#
+        )));
+    }
+
+    let mut rng = Rng::new(seed);
+
+    for value in 0..10 {
+        let dest = node_ids[rng.below(node_ids.len() as u64) as usize];
+
+        simulation.send(message(&format!(
+            r#"{{"src": "c1", "dest": "{dest}", "body": {{"type": "broadcast", "message": {value}, "msg_id": {}}}}}"#,
+            value + 1
+        )));
+    }
+
+    assert!(
+        simulation.run(10_000),
+        "seed {seed}: the cluster didn't settle"
+    );
+
+    for (node_id, node) in &simulation.nodes {
+        assert_eq!(
+            node.broadcast.messages.len(),
+            10,
+            "seed {seed}: {node_id} is missing values"
+        );
+    }
+}
+
+fn seeds() -> Vec<u64> {
+    match std::env::var("TRANQUILITY_SIM_SEED") {
+        Ok(seed) => vec![seed
+            .parse()
+            .expect("TRANQUILITY_SIM_SEED must be a number.")],
+        Err(_) => (0..20).collect(),
+    }
+}
+
+#[test]
+fn broadcast_converges_despite_drops_and_partitions() {
+    for seed in seeds() {
+        converges(seed, false);
+    }
+}
+
+#[test]
+fn causal_broadcast_converges_despite_drops_and_partitions() {
+    for seed in seeds() {
+        converges(seed, true);
+    }
+}